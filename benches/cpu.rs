@@ -0,0 +1,79 @@
+// Benchmarks for the CPU's hot loop, to tell whether a change to dispatch or
+// logging made it faster: instructions per second for a few mixes of
+// instructions run by the CPU on its own, and frames per second with the
+// PPU and APU running too (59.7 is real time). Criterion keeps the last run
+// and reports the change from it.
+//
+//   cargo bench
+//   cargo bench -- cpu/loads
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use rusty_gameboy::cartridge::Cartridge;
+use rusty_gameboy::mmu::Mmu;
+use rusty_gameboy::{Cpu, GameBoy};
+
+/// Instructions in each iteration of a mix
+const INSTRUCTIONS: u64 = 10_000;
+
+/// A ROM running `body` from 0x0100 in a loop
+fn looping(body: &[u8]) -> Vec<u8> {
+    assert!(
+        body.len() <= 126,
+        "JR can't reach back over {} bytes",
+        body.len()
+    );
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + body.len()].copy_from_slice(body);
+    // JR back to 0x0100
+    let back = -(body.len() as i8 + 2);
+    rom[0x100 + body.len()..0x102 + body.len()].copy_from_slice(&[0x18, back as u8]);
+    rom
+}
+
+/// The instruction mixes, by name
+fn mixes() -> Vec<(&'static str, Vec<u8>)> {
+    // LD HL, $C0C0, then LD (HL+), A and LD A, (HL-) back and forth
+    let mut loads = vec![0x21, 0xC0, 0xC0];
+    loads.extend([0x22, 0x3A].repeat(60));
+    vec![
+        ("nop", looping(&[0x00; 120])),
+        ("loads", looping(&loads)),
+        // ADD HL with BC, DE, HL and SP
+        ("alu", looping(&[0x09, 0x19, 0x29, 0x39].repeat(30))),
+        // JR to the next instruction, and JR NZ, which isn't taken
+        ("jumps", looping(&[0x18, 0x00, 0x20, 0x00].repeat(30))),
+    ]
+}
+
+fn bench_cpu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    for (name, rom) in mixes() {
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(false);
+        let mut mmu = Mmu::new(Cartridge::new(rom));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for _ in 0..INSTRUCTIONS {
+                    cpu.step(&mut mmu);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    group.throughput(Throughput::Elements(1));
+    for (name, rom) in mixes() {
+        let mut gameboy = GameBoy::new(rom);
+        gameboy.skip_boot();
+        group.bench_function(name, |b| b.iter(|| gameboy.step_frame()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_cpu, bench_frames);
+criterion_main!(benches);
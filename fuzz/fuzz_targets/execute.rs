@@ -0,0 +1,22 @@
+// Runs random bytes as a ROM for a bounded number of instructions, to find
+// panics, out of bounds reads and hangs in the decoder and executor.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_gameboy::GameBoy;
+
+/// Enough for a few frames, so a run that loops forever still ends
+const MAX_INSTRUCTIONS: usize = 50_000;
+
+fuzz_target!(|rom: &[u8]| {
+    let mut gameboy = GameBoy::new(rom.to_vec());
+    gameboy.skip_boot();
+    for _ in 0..MAX_INSTRUCTIONS {
+        // Opcodes the CPU doesn't run yet, or that are illegal, come back
+        // as errors, which end the run; anything else going wrong panics
+        if gameboy.try_step_instruction().is_err() {
+            return;
+        }
+    }
+});
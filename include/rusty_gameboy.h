@@ -0,0 +1,55 @@
+/*
+ * The C API of rusty-gameboy, built with `cargo build --release --features ffi`
+ * into target/release/librusty_gameboy.so (.dylib on macOS, .dll on Windows).
+ */
+
+#ifndef RUSTY_GAMEBOY_H
+#define RUSTY_GAMEBOY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define GB_SCREEN_WIDTH 160
+#define GB_SCREEN_HEIGHT 144
+
+/* Bits for gb_set_input() */
+#define GB_RIGHT (1 << 0)
+#define GB_LEFT (1 << 1)
+#define GB_UP (1 << 2)
+#define GB_DOWN (1 << 3)
+#define GB_A (1 << 4)
+#define GB_B (1 << 5)
+#define GB_SELECT (1 << 6)
+#define GB_START (1 << 7)
+
+typedef struct Emulator GB;
+
+/* A new emulator with no cartridge in it */
+GB *gb_create(void);
+
+/* Load a ROM and start it after the boot ROM. Returns 0, or -1 on failure. */
+int32_t gb_load_rom(GB *gb, const uint8_t *rom, size_t size);
+
+/* Run until the next frame is drawn. Returns 0, or -1 without a cartridge
+ * or if the emulator failed, which unloads the cartridge. */
+int32_t gb_step_frame(GB *gb);
+
+/* GB_SCREEN_WIDTH * GB_SCREEN_HEIGHT 0x00RRGGBB pixels, row by row, or NULL
+ * without a cartridge. Valid until the next call with this emulator. */
+const uint32_t *gb_get_framebuffer(const GB *gb);
+
+/* Set which buttons are held, GB_A | GB_START and so on */
+void gb_set_input(GB *gb, uint8_t buttons);
+
+/* Free an emulator. NULL is ignored. */
+void gb_destroy(GB *gb);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
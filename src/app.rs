@@ -0,0 +1,428 @@
+// What the command line tool does with its arguments: the subcommands that
+// only read the ROM, setting up a GameBoy from the flags, and running it in
+// a frontend, the debugger or the benchmark. main.rs parses the command line
+// and exits with the code run() returns.
+
+use crate::cartridge::header;
+use crate::cartridge::info::Info;
+use crate::cli::{CommandLineArgs, Subcommand};
+use crate::debugger::script::Script;
+use crate::debugger::tui::Tui;
+use crate::debugger::Debugger;
+use crate::disassembler::{json, Format, Location};
+use crate::gameboy::GameBoy;
+use crate::ppu::Renderer;
+use crate::symbols::Symbols;
+use crate::test_rom::{Suite, Summary};
+use crate::{bench, disassembler, doctor, frontend, serial, test_rom};
+use log::{debug, info};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// Print the result of verifying the ROM's header, with the problems on
+/// stderr.
+/// Returns true if no problems were found.
+fn verify_rom(rom_path: &Path) -> bool {
+    let rom = match fs::read(rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Could not read {:?}: {}", rom_path, e);
+            return false;
+        }
+    };
+
+    let errors = header::verify(&rom);
+    for error in errors.iter() {
+        eprintln!("{}", error);
+    }
+    if errors.is_empty() {
+        println!("{:?}: OK", rom_path);
+    }
+    errors.is_empty()
+}
+
+/// Print what the ROM's header says, returning false if it couldn't be read
+fn print_info(rom_path: &Path, format: Format) -> bool {
+    let info = fs::read(rom_path)
+        .map_err(|e| e.to_string())
+        .and_then(|rom| Info::parse(&rom));
+    match (info, format) {
+        (Ok(info), Format::Text) => print!("{}", info.table()),
+        (Ok(info), Format::Json) => println!("{}", info.json()),
+        (Err(e), _) => {
+            eprintln!("Could not read the header of {:?}: {}", rom_path, e);
+            return false;
+        }
+    }
+    true
+}
+
+/// The extra entry points and the range of ROM offsets to disassemble,
+/// from the command line
+fn disassembly_region(
+    args: &CommandLineArgs,
+    symbols: &Symbols,
+    rom_size: usize,
+) -> Result<(Vec<Location>, Range<usize>), String> {
+    let offset = |text: &str| {
+        let (bank, address) = disassembler::parse_location(text, symbols)?;
+        match disassembler::rom_offset(bank, address) {
+            offset if offset < rom_size => Ok(offset),
+            _ => Err(format!("{:?} is past the end of the ROM", text)),
+        }
+    };
+    let entries = args
+        .entries
+        .iter()
+        .map(|entry| disassembler::parse_location(entry, symbols))
+        .collect::<Result<Vec<_>, String>>()?;
+    let start = args.start.as_deref().map_or(Ok(0), offset)?;
+    let end = args
+        .end
+        .as_deref()
+        .map_or(Ok(rom_size), |end| offset(end).map(|end| end + 1))?;
+    if end <= start {
+        return Err(String::from("The end is before the start"));
+    }
+    Ok((entries, start..end))
+}
+
+/// Print the code in the ROM, with each instruction's bank, address and
+/// bytes, and the data in between.
+/// Returns true if it could be read.
+fn disassemble_rom(args: &CommandLineArgs, symbols: &Symbols) -> bool {
+    let rom = match fs::read(&args.rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Could not read {:?}: {}", args.rom_path, e);
+            return false;
+        }
+    };
+    let (entries, range) = match disassembly_region(args, symbols, rom.len()) {
+        Ok(region) => region,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+
+    let lines = disassembler::disassemble(&rom, &entries, range, symbols);
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let written = match args.format {
+        Format::Text => lines.iter().try_for_each(|line| writeln!(out, "{}", line)),
+        Format::Json => json::write(&lines, &mut out),
+    };
+    // Stop quietly if the output is closed, eg by head
+    written.and_then(|_| out.flush()).ok();
+    true
+}
+
+/// The symbols in `path`, or in the symbol file next to the ROM if there is one
+fn load_symbols(path: Option<&Path>, rom_path: &Path) -> Result<Symbols, String> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match Symbols::path_for(rom_path) {
+            path if path.exists() => path,
+            _ => return Ok(Symbols::default()),
+        },
+    };
+    let symbols = Symbols::load(&path)
+        .map_err(|e| format!("Could not load the symbols {:?}: {}", path, e))?;
+    info!("Loaded the symbols in {:?}", path);
+    Ok(symbols)
+}
+
+/// Run against a reference log.
+/// Returns true if every line matched.
+fn compare_trace(gameboy: &mut GameBoy, path: &Path) -> bool {
+    let reference = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            eprintln!("Could not read the reference log {:?}: {}", path, e);
+            return false;
+        }
+    };
+    match doctor::compare(gameboy, reference) {
+        Ok(lines) => {
+            println!("All {} lines match the reference log", lines);
+            true
+        }
+        Err(e) => {
+            eprint!("{}", e);
+            false
+        }
+    }
+}
+
+/// Run a suite's test ROMs from `dir` and print how each did.
+/// Returns true if they all passed.
+fn run_test_roms(suite: Suite, dir: &Path) -> bool {
+    let roms = match suite.roms(dir) {
+        Ok(roms) => roms,
+        Err(e) => {
+            eprintln!(
+                "Could not find the {:?} test ROMs in {:?}: {}",
+                suite, dir, e
+            );
+            return false;
+        }
+    };
+    let width = roms
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut summary = Summary::default();
+    for (name, path, frames) in &roms {
+        match fs::read(path) {
+            Ok(rom) => {
+                let (outcome, ran) = test_rom::run(rom, *frames);
+                println!(
+                    "{:<width$}  {} ({} frames)",
+                    name,
+                    outcome,
+                    ran,
+                    width = width
+                );
+                summary.add(&outcome);
+            }
+            Err(e) => {
+                println!("{:<width$}  Could not read it: {}", name, e, width = width);
+                summary.missing += 1;
+            }
+        }
+    }
+    println!("{}", summary);
+    summary.passed == roms.len()
+}
+
+/// The exit code for a subcommand that succeeded if `ok`
+fn exit_code(ok: bool) -> i32 {
+    if ok {
+        0
+    } else {
+        1
+    }
+}
+
+/// A GameBoy for the ROM, set up as the flags say
+fn set_up(args: &CommandLineArgs) -> Result<GameBoy, String> {
+    let mut gameboy = GameBoy::new_from_path(args.rom_path.clone(), args.seed)
+        .map_err(|e| format!("Could not read {:?}: {}", args.rom_path, e))?;
+    debug!("Created a GameBoy {}", gameboy);
+    match &args.boot_rom {
+        Some(path) => gameboy
+            .load_boot_rom(path)
+            .map_err(|e| format!("Could not load the boot ROM {:?}: {}", path, e))?,
+        None => gameboy.skip_boot(),
+    }
+    if let Some(path) = &args.trace_instructions {
+        gameboy
+            .trace_instructions(path)
+            .map_err(|e| format!("Could not write the trace to {:?}: {}", path, e))?;
+        gameboy.trace_between(args.trace_start, args.trace_stop);
+    }
+    if let Some(ranges) = &args.trace_memory {
+        gameboy.enable_memory_trace(ranges.clone());
+    }
+    if args.pixel_fifo {
+        gameboy.set_renderer(Renderer::Fifo);
+    }
+    if let Some(shades) = args.palette {
+        gameboy.set_shades(shades);
+    }
+    if let Some(frame) = args.screenshot_frame {
+        gameboy.set_screenshot_frame(frame);
+    }
+    gameboy.set_resampling(args.resampling);
+    for &channel in &args.mute {
+        gameboy.set_muted(channel, true);
+    }
+    for &channel in &args.solo {
+        gameboy.set_soloed(channel, true);
+    }
+    if let Some(path) = &args.serial_output {
+        gameboy
+            .copy_serial_output(path)
+            .map_err(|e| format!("Could not write the serial output to {:?}: {}", path, e))?;
+    }
+    if let Some(link) = &args.link {
+        let device =
+            serial::open_link(link).map_err(|e| format!("Could not plug in {:?}: {}", link, e))?;
+        gameboy.plug_in_serial(device);
+    }
+    if let Some(path) = &args.record_movie {
+        gameboy
+            .record_movie(path)
+            .map_err(|e| format!("Could not record a movie to {:?}: {}", path, e))?;
+    }
+    if let Some(path) = &args.play_movie {
+        gameboy
+            .play_movie(path)
+            .map_err(|e| format!("Could not play the movie: {}", e))?;
+    }
+    if let Some(path) = &args.record_audio {
+        gameboy
+            .record_audio(path)
+            .map_err(|e| format!("Could not record audio to {:?}: {}", path, e))?;
+    }
+    for code in &args.cheats {
+        gameboy
+            .cheats_mut()
+            .add(code)
+            .map_err(|e| format!("Could not apply the cheat: {}", e))?;
+    }
+    if let Some(path) = &args.load_state {
+        gameboy
+            .load_state(path)
+            .map_err(|e| format!("Could not load the save state {:?}: {}", path, e))?;
+    }
+    if args.profile.is_some() {
+        gameboy.enable_profiling();
+    }
+    if args.coverage.is_some() {
+        gameboy.enable_coverage();
+    }
+    if let Some(path) = &args.script {
+        let script = Script::load(path)
+            .map_err(|e| format!("Could not load the script {:?}: {}", path, e))?;
+        gameboy.set_script(script);
+    }
+    if let Subcommand::Run = args.subcommand {
+        gameboy.watch_test_result();
+    }
+    Ok(gameboy)
+}
+
+/// Write the profile and coverage, if they were asked for
+fn write_reports(
+    gameboy: &GameBoy,
+    profile: Option<&Path>,
+    coverage: Option<&Path>,
+) -> Result<(), String> {
+    if let Some(path) = profile {
+        gameboy
+            .write_profile(path)
+            .map_err(|e| format!("Could not write the profile to {:?}: {}", path, e))?;
+    }
+    if let Some(path) = coverage {
+        gameboy
+            .write_coverage(path)
+            .map_err(|e| format!("Could not write the coverage to {:?}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+/// Do what the command line asked for.
+/// Returns the code to exit with.
+pub fn run(args: CommandLineArgs) -> i32 {
+    match &args.subcommand {
+        Subcommand::Verify => return exit_code(verify_rom(&args.rom_path)),
+        Subcommand::TestRoms(dir) => return exit_code(run_test_roms(args.suite, dir)),
+        Subcommand::Info => return exit_code(print_info(&args.rom_path, args.format)),
+        _ => {}
+    }
+    let symbols = match args.subcommand {
+        Subcommand::Debug | Subcommand::Disassemble => {
+            load_symbols(args.symbols.as_deref(), &args.rom_path)
+        }
+        _ => Ok(Symbols::default()),
+    };
+    let symbols = match symbols {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    if let Subcommand::Disassemble = args.subcommand {
+        return exit_code(disassemble_rom(&args, &symbols));
+    }
+
+    let mut gameboy = match set_up(&args) {
+        Ok(gameboy) => gameboy,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    if let Some(path) = &args.compare_trace {
+        return exit_code(compare_trace(&mut gameboy, path));
+    }
+    match args.subcommand {
+        Subcommand::Run => frontend::create_frontend(
+            args.frontend,
+            args.headless,
+            args.scaler,
+            args.keymap,
+            args.pacer,
+        )
+        .run(&mut gameboy),
+        Subcommand::Debug if args.tui => {
+            if let Err(e) = Tui::new(&mut gameboy, symbols).run(&mut gameboy) {
+                eprintln!("Could not run the terminal UI: {}", e);
+                return 1;
+            }
+        }
+        Subcommand::Debug => Debugger::with_symbols(symbols).run(&mut gameboy),
+        Subcommand::Bench => {
+            let frames = args.headless.frames.unwrap_or(bench::DEFAULT_FRAMES);
+            print!("{}", bench::run(&mut gameboy, frames));
+        }
+        Subcommand::Verify
+        | Subcommand::Info
+        | Subcommand::Disassemble
+        | Subcommand::TestRoms(_) => {
+            unreachable!("{:?} returns before the GameBoy starts", args.subcommand)
+        }
+    }
+    if let Err(e) = write_reports(&gameboy, args.profile.as_deref(), args.coverage.as_deref()) {
+        eprintln!("{}", e);
+        return 1;
+    }
+    match gameboy.test_verdict() {
+        Some(verdict) => {
+            eprintln!("Test ROM {:?}", verdict);
+            verdict.exit_code()
+        }
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_rom() {
+        let path = std::env::temp_dir().join("rusty-gameboy-test-verify.gb");
+        fs::write(&path, vec![0; 0x8000]).unwrap();
+        assert!(!verify_rom(&path));
+        assert!(!verify_rom(Path::new("missing.gb")));
+    }
+
+    #[test]
+    fn test_load_symbols() {
+        let rom_path = Path::new("missing.gb");
+        assert!(load_symbols(None, rom_path).is_ok());
+        let e = load_symbols(Some(Path::new("missing.sym")), rom_path).unwrap_err();
+        assert!(
+            e.starts_with("Could not load the symbols \"missing.sym\""),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_compare_trace() {
+        let mut gameboy = GameBoy::new(vec![0; 0x8000]);
+        gameboy.skip_boot();
+        let path = std::env::temp_dir().join("rusty-gameboy-test-compare.log");
+        fs::write(&path, gameboy.trace_line() + "\n").unwrap();
+        assert!(compare_trace(&mut gameboy, &path));
+        assert!(!compare_trace(&mut gameboy, Path::new("missing.log")));
+    }
+}
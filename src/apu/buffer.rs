@@ -0,0 +1,73 @@
+use alloc::collections::VecDeque;
+
+/// One sample for each speaker, from -1.0 to 1.0
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StereoSample {
+    pub left: f32,
+    pub right: f32,
+}
+
+/// A fixed-size queue of samples between the APU and the audio output.
+/// If the output falls behind, the oldest samples are dropped so the
+/// delay never grows past the capacity.
+pub struct SampleBuffer {
+    samples: VecDeque<StereoSample>,
+    capacity: usize,
+}
+
+impl SampleBuffer {
+    pub fn new(capacity: usize) -> SampleBuffer {
+        SampleBuffer {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, sample: StereoSample) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Take every sample queued so far, oldest first
+    pub fn drain(&mut self) -> impl Iterator<Item = StereoSample> + '_ {
+        self.samples.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(value: f32) -> StereoSample {
+        StereoSample {
+            left: value,
+            right: -value,
+        }
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut buffer = SampleBuffer::new(4);
+        buffer.push(sample(0.1));
+        buffer.push(sample(0.2));
+        assert_eq!(
+            buffer.drain().collect::<Vec<_>>(),
+            vec![sample(0.1), sample(0.2)]
+        );
+        assert_eq!(buffer.drain().count(), 0);
+    }
+
+    #[test]
+    fn test_overflow_drops_oldest() {
+        let mut buffer = SampleBuffer::new(2);
+        for value in [0.1, 0.2, 0.3] {
+            buffer.push(sample(value));
+        }
+        assert_eq!(
+            buffer.drain().collect::<Vec<_>>(),
+            vec![sample(0.2), sample(0.3)]
+        );
+    }
+}
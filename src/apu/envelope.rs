@@ -0,0 +1,111 @@
+use crate::savestate::save_state_fields;
+
+/// The volume envelope of channels 1, 2 and 4, set through NRx2.
+/// Every `period` envelope clocks the volume steps up or down by one.
+#[derive(Debug, Default)]
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    /// 0-15
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn write(&mut self, nrx2: u8) {
+        self.initial_volume = nrx2 >> 4;
+        self.increase = nrx2 & 0b1000 != 0;
+        self.period = nrx2 & 0b111;
+    }
+
+    /// Whether NRx2 turns the channel's DAC on: any of the top 5 bits set
+    pub fn dac_enabled(nrx2: u8) -> bool {
+        nrx2 & 0xF8 != 0
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    /// Called at 64 Hz by the frame sequencer
+    pub fn clock(&mut self) {
+        // A period of 0 stops the envelope
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period;
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+save_state_fields!(Envelope {
+    initial_volume,
+    increase,
+    period,
+    volume,
+    timer,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decrease() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x22); // volume 2, decrease, period 2
+        envelope.trigger();
+        assert_eq!(envelope.volume(), 2);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 2);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 1);
+        for _ in 0..4 {
+            envelope.clock();
+        }
+        // Stops at 0
+        assert_eq!(envelope.volume(), 0);
+    }
+
+    #[test]
+    fn test_increase() {
+        let mut envelope = Envelope::default();
+        envelope.write(0xE9); // volume 14, increase, period 1
+        envelope.trigger();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+    }
+
+    #[test]
+    fn test_period_zero() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x58);
+        envelope.trigger();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 5);
+    }
+
+    #[test]
+    fn test_dac_enabled() {
+        assert!(!Envelope::dac_enabled(0x00));
+        assert!(!Envelope::dac_enabled(0x07));
+        assert!(Envelope::dac_enabled(0x08));
+        assert!(Envelope::dac_enabled(0x10));
+    }
+}
@@ -0,0 +1,84 @@
+use crate::apu::buffer::StereoSample;
+use crate::gameboy::CLOCK_HZ;
+use crate::savestate::save_state_fields;
+
+/// How much charge the capacitor keeps per T-cycle on the DMG
+const CHARGE_PER_CYCLE: f64 = 0.999958;
+
+/// The capacitor between the mixer and the speaker, which blocks the DC
+/// offset the DACs put out, so silence settles at 0 rather than wherever
+/// the channels were left
+#[derive(Debug)]
+pub struct HighPass {
+    left: f32,
+    right: f32,
+    /// How much charge is kept between two samples
+    charge_factor: f32,
+}
+
+impl HighPass {
+    pub fn new(sample_rate: u32) -> HighPass {
+        let mut high_pass = HighPass {
+            left: 0.0,
+            right: 0.0,
+            charge_factor: 0.0,
+        };
+        high_pass.set_sample_rate(sample_rate);
+        high_pass
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        // libm, as core has no powf
+        self.charge_factor =
+            libm::pow(CHARGE_PER_CYCLE, CLOCK_HZ as f64 / sample_rate as f64) as f32;
+    }
+
+    /// Filter one sample. With every DAC off, the capacitor is left alone.
+    pub fn filter(&mut self, sample: StereoSample, dacs_enabled: bool) -> StereoSample {
+        if !dacs_enabled {
+            return StereoSample::default();
+        }
+        let left = sample.left - self.left;
+        self.left = sample.left - left * self.charge_factor;
+        let right = sample.right - self.right;
+        self.right = sample.right - right * self.charge_factor;
+        StereoSample { left, right }
+    }
+}
+
+// The charge factor comes from the sample rate, which isn't saved
+save_state_fields!(HighPass { left, right });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dc_decays() {
+        let mut high_pass = HighPass::new(48_000);
+        let dc = StereoSample {
+            left: 0.5,
+            right: -0.5,
+        };
+        let first = high_pass.filter(dc, true);
+        assert_eq!(first, dc);
+        // A constant level fades to nothing within a fraction of a second
+        let settled = (0..48_000 / 10)
+            .map(|_| high_pass.filter(dc, true))
+            .last()
+            .unwrap();
+        assert!(settled.left.abs() < 0.01 && settled.right.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_dacs_off() {
+        let mut high_pass = HighPass::new(48_000);
+        let sample = StereoSample {
+            left: 0.5,
+            right: 0.5,
+        };
+        assert_eq!(high_pass.filter(sample, false), StereoSample::default());
+        // The capacitor didn't charge
+        assert_eq!(high_pass.filter(sample, true), sample);
+    }
+}
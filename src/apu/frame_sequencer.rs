@@ -0,0 +1,93 @@
+use crate::gameboy::CLOCK_HZ;
+use crate::savestate::save_state_fields;
+
+/// The frame sequencer steps at 512 Hz
+pub const SEQUENCER_PERIOD: u32 = CLOCK_HZ / 512;
+
+/// What a step of the frame sequencer clocks
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    pub length: bool,
+    pub sweep: bool,
+    pub envelope: bool,
+}
+
+/// Clocks the length counters at 256 Hz, the sweep at 128 Hz
+/// and the envelopes at 64 Hz, over a cycle of 8 steps:
+///
+/// ```text
+/// Step    0   1   2   3   4   5   6   7
+/// Length  x       x       x       x
+/// Sweep           x               x
+/// Envelope                            x
+/// ```
+#[derive(Debug, Default)]
+pub struct FrameSequencer {
+    /// T-cycles since the last step
+    clock: u32,
+    /// The step that runs next, 0-7
+    step: u8,
+}
+
+impl FrameSequencer {
+    /// T-cycles until the next step
+    pub fn until_step(&self) -> u32 {
+        SEQUENCER_PERIOD - self.clock
+    }
+
+    /// Advance by at most `until_step` T-cycles, returning the step if one ran
+    pub fn tick(&mut self, cycles: u32) -> Option<Step> {
+        self.clock += cycles;
+        if self.clock < SEQUENCER_PERIOD {
+            return None;
+        }
+        self.clock = 0;
+        let step = self.step;
+        self.step = (self.step + 1) % 8;
+        Some(Step {
+            length: step & 1 == 0,
+            sweep: step == 2 || step == 6,
+            envelope: step == 7,
+        })
+    }
+
+    /// Whether the next step leaves the length counters alone. Enabling
+    /// a length counter, or triggering its channel, during this half of
+    /// the length period clocks it an extra time.
+    pub fn extra_length_clock(&self) -> bool {
+        self.step & 1 == 1
+    }
+}
+
+save_state_fields!(FrameSequencer { clock, step });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps() {
+        let mut sequencer = FrameSequencer::default();
+        assert_eq!(sequencer.tick(SEQUENCER_PERIOD - 1), None);
+        let steps: Vec<Step> = (0..8)
+            .map(|_| sequencer.tick(sequencer.until_step()).unwrap())
+            .collect();
+        let clocked = |clocks: fn(&Step) -> bool| -> Vec<usize> {
+            (0..8).filter(|step| clocks(&steps[*step])).collect()
+        };
+        assert_eq!(clocked(|step| step.length), vec![0, 2, 4, 6]);
+        assert_eq!(clocked(|step| step.sweep), vec![2, 6]);
+        assert_eq!(clocked(|step| step.envelope), vec![7]);
+    }
+
+    #[test]
+    fn test_extra_length_clock() {
+        let mut sequencer = FrameSequencer::default();
+        assert!(!sequencer.extra_length_clock());
+        // Step 0 clocked length, so step 1 won't
+        sequencer.tick(SEQUENCER_PERIOD);
+        assert!(sequencer.extra_length_clock());
+        sequencer.tick(SEQUENCER_PERIOD);
+        assert!(!sequencer.extra_length_clock());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::savestate::save_state_fields;
+
+/// Turns a channel off after a set time, when enabled by NRx4 bit 6
+#[derive(Debug)]
+pub struct LengthCounter {
+    /// 64 for most channels, 256 for the wave channel
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// Load the length from NRx1. The channel plays for `max - length` clocks.
+    pub fn load(&mut self, length: u8) {
+        self.counter = self.max - length as u16;
+    }
+
+    /// The counter after the APU is turned off, which clears the enable bit.
+    /// The DMG keeps the count; the CGB clears it too.
+    pub fn powered_off(&self, keep_count: bool) -> LengthCounter {
+        LengthCounter {
+            max: self.max,
+            counter: if keep_count { self.counter } else { 0 },
+            enabled: false,
+        }
+    }
+
+    /// Set from NRx4 bit 6. Enabling the counter when the frame sequencer's
+    /// next step won't clock it clocks it straight away.
+    /// Returns true when that runs it out.
+    pub fn set_enabled(&mut self, enabled: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+        !was_enabled && enabled && extra_clock && self.clock()
+    }
+
+    /// A length of 0 becomes the maximum when the channel is triggered,
+    /// less the extra clock if there is one
+    pub fn trigger(&mut self, extra_clock: bool) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+    }
+
+    /// Called at 256 Hz by the frame sequencer.
+    /// Returns true when the length runs out and the channel should stop.
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+}
+
+save_state_fields!(LengthCounter {
+    max,
+    counter,
+    enabled
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length() {
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+        length.set_enabled(true, false);
+        assert!(!length.clock());
+        assert!(length.clock());
+        // Nothing more once it has run out
+        assert!(!length.clock());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut length = LengthCounter::new(64);
+        length.load(63);
+        assert!(!length.clock());
+        length.set_enabled(true, false);
+        assert!(length.clock());
+    }
+
+    #[test]
+    fn test_trigger_reloads_zero() {
+        let mut length = LengthCounter::new(256);
+        length.trigger(false);
+        length.set_enabled(true, false);
+        for _ in 0..255 {
+            assert!(!length.clock());
+        }
+        assert!(length.clock());
+    }
+
+    #[test]
+    fn test_powered_off() {
+        let mut length = LengthCounter::new(64);
+        length.load(60);
+        length.set_enabled(true, false);
+
+        let mut kept = length.powered_off(true);
+        assert!(!kept.clock());
+        kept.set_enabled(true, false);
+        for _ in 0..3 {
+            assert!(!kept.clock());
+        }
+        assert!(kept.clock());
+
+        let mut cleared = length.powered_off(false);
+        cleared.set_enabled(true, false);
+        assert!(!cleared.clock());
+    }
+
+    #[test]
+    fn test_extra_clock() {
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+        assert!(!length.set_enabled(true, true));
+        assert!(length.clock());
+
+        // Runs out on the extra clock
+        let mut length = LengthCounter::new(64);
+        length.load(63);
+        assert!(length.set_enabled(true, true));
+        // Only when the counter is newly enabled
+        length.load(63);
+        assert!(!length.set_enabled(true, true));
+    }
+
+    #[test]
+    fn test_trigger_extra_clock() {
+        let mut length = LengthCounter::new(64);
+        length.set_enabled(true, false);
+        length.trigger(true);
+        for _ in 0..62 {
+            assert!(!length.clock());
+        }
+        assert!(length.clock());
+    }
+}
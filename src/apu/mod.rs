@@ -0,0 +1,651 @@
+pub mod buffer;
+pub mod envelope;
+pub mod filter;
+pub mod frame_sequencer;
+pub mod length;
+pub mod noise;
+pub mod resampler;
+pub mod square;
+pub mod sweep;
+pub mod wave;
+
+use log::debug;
+
+use crate::apu::buffer::{SampleBuffer, StereoSample};
+use crate::apu::filter::HighPass;
+use crate::apu::frame_sequencer::{FrameSequencer, Step};
+use crate::apu::noise::NoiseChannel;
+use crate::apu::resampler::{Resampler, Resampling};
+use crate::apu::square::SquareChannel;
+use crate::apu::wave::WaveChannel;
+use crate::gameboy::CLOCK_HZ;
+use crate::prelude::*;
+use crate::savestate::save_state_fields;
+
+/// Sound registers. Each channel has 5 registers, NRx0-NRx4,
+/// although channels 2 and 4 don't use their first one.
+/// Then NR50-NR52 control the output as a whole.
+pub const NR10: u16 = 0xFF10;
+pub const NR11: u16 = 0xFF11;
+pub const NR14: u16 = 0xFF14;
+pub const NR21: u16 = 0xFF16;
+pub const NR24: u16 = 0xFF19;
+pub const NR30: u16 = 0xFF1A;
+pub const NR31: u16 = 0xFF1B;
+pub const NR34: u16 = 0xFF1E;
+pub const NR41: u16 = 0xFF20;
+pub const NR44: u16 = 0xFF23;
+/// Master volume for each speaker
+pub const NR50: u16 = 0xFF24;
+/// Which channels go to which speaker
+pub const NR51: u16 = 0xFF25;
+/// Sound on/off, and which channels are playing
+pub const NR52: u16 = 0xFF26;
+/// 32 4-bit samples played by channel 3
+pub const WAVE_RAM_START: u16 = 0xFF30;
+pub const WAVE_RAM_END: u16 = 0xFF3F;
+
+/// NR52 bit 7 turns the whole APU on or off
+const NR52_POWER: u8 = 1 << 7;
+
+/// The bits of each register in 0xFF10-0xFF26 that read back as 1:
+/// unused bits, and write-only ones like the frequencies and trigger bits
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+/// The rate samples are produced at
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+/// T-cycles between each look at the output when averaging it: one M-cycle,
+/// the fastest any channel but the wave channel changes
+const MIX_CYCLES: u32 = 4;
+/// Enough for a few frames, so a slow audio output doesn't stall emulation
+const BUFFER_SAMPLES: usize = 8192;
+
+/// The four sound channels, numbered 1-4 as in the docs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+impl Channel {
+    pub fn from_number(number: u8) -> Option<Channel> {
+        match number {
+            1 => Some(Channel::Square1),
+            2 => Some(Channel::Square2),
+            3 => Some(Channel::Wave),
+            4 => Some(Channel::Noise),
+            _ => None,
+        }
+    }
+
+    pub fn number(self) -> u8 {
+        self as u8 + 1
+    }
+}
+
+/// Parse a comma-separated list of channel numbers, eg `1,3`
+pub fn parse_channels(value: &str) -> Result<Vec<Channel>, String> {
+    value
+        .split(',')
+        .map(|number| {
+            number
+                .trim()
+                .parse()
+                .ok()
+                .and_then(Channel::from_number)
+                .ok_or_else(|| format!("Unknown channel {:?}, expected 1-4", number.trim()))
+        })
+        .collect()
+}
+
+/// The Audio Processing Unit: four sound channels mixed into stereo samples.
+/// The samples are queued in a buffer for the frontend to play.
+pub struct Apu {
+    /// NR10-NR51 as last written
+    registers: [u8; READ_MASKS.len()],
+    /// NR52 bit 7. While off, the sound registers are cleared and ignore writes.
+    enabled: bool,
+    /// The CGB clears the length counters too when the APU is turned off
+    cgb_mode: bool,
+    square1: SquareChannel,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    /// Channels left out of the mix, indexed by channel number - 1
+    muted: [bool; 4],
+    /// When any channel is soloed, only soloed channels are mixed
+    soloed: [bool; 4],
+    sequencer: FrameSequencer,
+    sample_rate: u32,
+    /// T-cycles since the last sample, multiplied by the sample rate
+    /// so no time is lost to rounding
+    sample_clock: u64,
+    resampler: Resampler,
+    high_pass: HighPass,
+    samples: SampleBuffer,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl Apu {
+    pub fn new(sample_rate: u32) -> Apu {
+        Apu {
+            registers: [0; READ_MASKS.len()],
+            enabled: true,
+            cgb_mode: false,
+            square1: SquareChannel::new(true),
+            square2: SquareChannel::new(false),
+            wave: WaveChannel::default(),
+            noise: NoiseChannel::default(),
+            muted: [false; 4],
+            soloed: [false; 4],
+            sequencer: FrameSequencer::default(),
+            sample_rate,
+            sample_clock: 0,
+            resampler: Resampler::default(),
+            high_pass: HighPass::new(sample_rate),
+            samples: SampleBuffer::new(BUFFER_SAMPLES),
+        }
+    }
+
+    pub fn new_cgb() -> Apu {
+        Apu {
+            cgb_mode: true,
+            ..Apu::default()
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Change the sample rate, eg to keep up with the audio output
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.high_pass.set_sample_rate(sample_rate);
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    pub fn set_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.soloed[channel as usize] = soloed;
+    }
+
+    pub fn is_soloed(&self, channel: Channel) -> bool {
+        self.soloed[channel as usize]
+    }
+
+    /// Whether a channel is mixed in, going by the mute and solo settings
+    fn audible(&self, index: usize) -> bool {
+        let any_soloed = self.soloed.iter().any(|soloed| *soloed);
+        !self.muted[index] && (!any_soloed || self.soloed[index])
+    }
+
+    pub fn set_resampling(&mut self, resampling: Resampling) {
+        self.resampler.resampling = resampling;
+    }
+
+    /// The samples produced since the last call, oldest first
+    pub fn samples(&mut self) -> &mut SampleBuffer {
+        &mut self.samples
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            NR52 => {
+                let power = if self.enabled { NR52_POWER } else { 0 };
+                let playing = [
+                    self.square1.enabled(),
+                    self.square2.enabled(),
+                    self.wave.enabled(),
+                    self.noise.enabled(),
+                ]
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (channel, playing)| {
+                    bits | ((*playing as u8) << channel)
+                });
+                READ_MASKS[(NR52 - NR10) as usize] | power | playing
+            }
+            NR10..=NR51 => {
+                let index = (address - NR10) as usize;
+                READ_MASKS[index] | self.registers[index]
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                self.wave.read_wave_ram((address - WAVE_RAM_START) as usize)
+            }
+            _ => unreachable!("{:#06x} is not an APU register", address),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            NR52 => {
+                let enabled = value & NR52_POWER != 0;
+                if self.enabled && !enabled {
+                    self.power_off();
+                } else if !self.enabled && enabled {
+                    // The frame sequencer starts again from step 0
+                    self.sequencer = FrameSequencer::default();
+                }
+                self.enabled = enabled;
+                debug!("APU {}", if self.enabled { "on" } else { "off" });
+            }
+            NR10..=NR51 if !self.enabled => {
+                // Only the lengths can be written on the DMG, and nothing on the CGB
+                if !self.cgb_mode {
+                    match address {
+                        NR11 => self.square1.write_length(value),
+                        NR21 => self.square2.write_length(value),
+                        NR31 => self.wave.write_length(value),
+                        NR41 => self.noise.write_length(value),
+                        _ => {}
+                    }
+                }
+            }
+            NR10..=NR51 => {
+                self.registers[(address - NR10) as usize] = value;
+                let extra_length_clock = self.sequencer.extra_length_clock();
+                match address {
+                    NR14 => self.square1.write_control(value, extra_length_clock),
+                    NR24 => self.square2.write_control(value, extra_length_clock),
+                    NR34 => self.wave.write_control(value, extra_length_clock),
+                    NR44 => self.noise.write_control(value, extra_length_clock),
+                    NR10..=NR14 => self.square1.write(address - NR10, value),
+                    NR21..=NR24 => self.square2.write(address - NR21 + 1, value),
+                    NR30..=NR34 => self.wave.write(address - NR30, value),
+                    NR41..=NR44 => self.noise.write(address - NR41 + 1, value),
+                    _ => {}
+                }
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => self
+                .wave
+                .write_wave_ram((address - WAVE_RAM_START) as usize, value),
+            _ => unreachable!("{:#06x} is not an APU register", address),
+        }
+    }
+
+    /// Clear every sound register and stop every channel
+    fn power_off(&mut self) {
+        self.registers = [0; READ_MASKS.len()];
+        let keep_length = !self.cgb_mode;
+        self.square1.power_off(keep_length);
+        self.square2.power_off(keep_length);
+        self.wave.power_off(keep_length);
+        self.noise.power_off(keep_length);
+    }
+
+    fn register(&self, address: u16) -> u8 {
+        self.registers[(address - NR10) as usize]
+    }
+
+    /// Advance by a number of T-cycles, queueing the samples produced in that time
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles > 0 {
+            // Run the channels up to the next sample or frame sequencer step
+            let rate = self.sample_rate as u64;
+            let until_sample = (CLOCK_HZ as u64 - self.sample_clock).div_ceil(rate);
+            let until_step = self.sequencer.until_step();
+            let mut chunk = cycles.min(until_step).min(until_sample as u32);
+            if self.resampler.averages() {
+                chunk = chunk.min(MIX_CYCLES);
+                let output = self.mix();
+                self.resampler.add(output, chunk);
+            }
+
+            self.square1.tick(chunk);
+            self.square2.tick(chunk);
+            self.wave.tick(chunk);
+            self.noise.tick(chunk);
+            cycles -= chunk;
+
+            if let Some(step) = self.sequencer.tick(chunk) {
+                self.step_sequencer(step);
+            }
+
+            self.sample_clock += chunk as u64 * rate;
+            if self.sample_clock >= CLOCK_HZ as u64 {
+                self.sample_clock -= CLOCK_HZ as u64;
+                let sample = self.resampler.take(self.mix());
+                let sample = self.high_pass.filter(sample, self.any_dac_enabled());
+                self.samples.push(sample);
+            }
+        }
+    }
+
+    /// Clock the length counters, envelopes and sweep for this step
+    fn step_sequencer(&mut self, step: Step) {
+        if step.length {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if step.sweep {
+            self.square1.clock_sweep();
+        }
+        if step.envelope {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+    }
+
+    fn any_dac_enabled(&self) -> bool {
+        self.square1.dac_enabled()
+            || self.square2.dac_enabled()
+            || self.wave.dac_enabled()
+            || self.noise.dac_enabled()
+    }
+
+    /// The output of every channel, mixed into one sample per speaker
+    fn mix(&self) -> StereoSample {
+        // Each DAC turns the 0-15 output into -1.0 to 1.0. A DAC that's off outputs nothing.
+        let dac = |output: u8, enabled: bool| {
+            if enabled {
+                output as f32 / 7.5 - 1.0
+            } else {
+                0.0
+            }
+        };
+        let outputs = [
+            dac(self.square1.output(), self.square1.dac_enabled()),
+            dac(self.square2.output(), self.square2.dac_enabled()),
+            dac(self.wave.output(), self.wave.dac_enabled()),
+            dac(self.noise.output(), self.noise.dac_enabled()),
+        ];
+
+        // NR51 bits 0-3 send a channel to the right speaker, bits 4-7 to the left
+        let panning = self.register(NR51);
+        let (mut left, mut right) = (0.0, 0.0);
+        for (channel, output) in outputs.iter().enumerate() {
+            if !self.audible(channel) {
+                continue;
+            }
+            if panning & (0x10 << channel) != 0 {
+                left += output;
+            }
+            if panning & (0x01 << channel) != 0 {
+                right += output;
+            }
+        }
+
+        // NR50 sets each speaker's volume from 1/8 to 8/8
+        let volume = self.register(NR50);
+        let left_volume = (((volume >> 4) & 0b111) + 1) as f32 / 8.0;
+        let right_volume = ((volume & 0b111) + 1) as f32 / 8.0;
+        StereoSample {
+            left: left / 4.0 * left_volume,
+            right: right / 4.0 * right_volume,
+        }
+    }
+}
+
+// Muting, soloing and the sample rate are settings rather than hardware,
+// and the samples not yet played are dropped
+save_state_fields!(Apu {
+    registers,
+    enabled,
+    cgb_mode,
+    square1,
+    square2,
+    wave,
+    noise,
+    sequencer,
+    sample_clock,
+    resampler,
+    high_pass,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::frame_sequencer::SEQUENCER_PERIOD;
+    use test_case::test_case;
+
+    #[test_case(0xFF10, 0xFF, 0xFF; "nr10 unused bit")]
+    #[test_case(0xFF11, 0x80, 0xBF; "nr11 length is write-only")]
+    #[test_case(0xFF12, 0xF3, 0xF3; "nr12 envelope")]
+    #[test_case(0xFF13, 0x42, 0xFF; "nr13 write-only")]
+    #[test_case(0xFF14, 0x40, 0xFF; "nr14 length enable")]
+    #[test_case(0xFF1A, 0x80, 0xFF; "nr30 dac on")]
+    #[test_case(0xFF1C, 0x60, 0xFF; "nr32 volume")]
+    #[test_case(0xFF24, 0x77, 0x77; "nr50")]
+    #[test_case(NR51, 0xF0, 0xF0; "nr51")]
+    fn test_read_masks(address: u16, value: u8, expected: u8) {
+        let mut apu = Apu::default();
+        apu.write(address, value);
+        assert_eq!(apu.read(address), expected);
+    }
+
+    #[test]
+    fn test_nr52() {
+        let mut apu = Apu::default();
+        assert_eq!(apu.read(NR52), 0xF0);
+        apu.write(NR52, 0x00);
+        assert_eq!(apu.read(NR52), 0x70);
+    }
+
+    #[test]
+    fn test_power_off() {
+        let mut apu = Apu::default();
+        apu.write(0xFF12, 0xF3);
+        apu.write(NR51, 0xFF);
+        apu.write(NR14, 0x80);
+        apu.write(WAVE_RAM_START, 0x12);
+        apu.write(NR52, 0x00);
+        assert_eq!(apu.read(0xFF12), 0x00);
+        assert_eq!(apu.read(NR51), 0x00);
+        assert_eq!(apu.read(NR52), 0x70);
+
+        // Read-only until turned back on, apart from wave RAM
+        apu.write(0xFF12, 0xF3);
+        apu.write(WAVE_RAM_START + 1, 0x34);
+        apu.write(NR52, 0x80);
+        assert_eq!(apu.read(0xFF12), 0x00);
+        assert_eq!(apu.read(WAVE_RAM_START), 0x12);
+        assert_eq!(apu.read(WAVE_RAM_START + 1), 0x34);
+        apu.write(0xFF12, 0xF3);
+        assert_eq!(apu.read(0xFF12), 0xF3);
+    }
+
+    /// Load a length of 1 for channel 2 while the APU is off,
+    /// then see whether it runs out after one clock
+    fn length_written_while_off(mut apu: Apu) -> bool {
+        apu.write(NR52, 0x00);
+        apu.write(NR21, 0x3F);
+        apu.write(NR52, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(NR24, 0xC0);
+        apu.tick(SEQUENCER_PERIOD);
+        apu.read(NR52) & 0b10 == 0
+    }
+
+    #[test]
+    fn test_power_off_length() {
+        assert!(length_written_while_off(Apu::default()));
+        // On the CGB the write is ignored, so the length is 64 on trigger
+        assert!(!length_written_while_off(Apu::new_cgb()));
+    }
+
+    #[test]
+    fn test_nr52_channels() {
+        let mut apu = Apu::default();
+        apu.write(0xFF17, 0xF0); // NR22: DAC on
+        apu.write(NR24, 0x80);
+        assert_eq!(apu.read(NR52), 0xF2);
+        apu.write(0xFF12, 0xF0);
+        apu.write(NR14, 0x80);
+        assert_eq!(apu.read(NR52), 0xF3);
+    }
+
+    #[test]
+    fn test_length_runs_out() {
+        let mut apu = Apu::default();
+        apu.write(0xFF16, 0x3F); // NR21: 1 length clock
+        apu.write(0xFF17, 0xF0);
+        apu.write(NR24, 0xC0);
+        assert_eq!(apu.read(NR52) & 0b10, 0b10);
+        // Length is clocked on the first step of the frame sequencer
+        apu.tick(SEQUENCER_PERIOD);
+        assert_eq!(apu.read(NR52) & 0b10, 0);
+    }
+
+    #[test]
+    fn test_length_extra_clock() {
+        let mut apu = Apu::default();
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF16, 0x3F); // 1 length clock
+        apu.write(NR24, 0x80);
+        // After step 0, the next step doesn't clock length, so enabling
+        // length clocks it straight away and stops the channel
+        apu.tick(SEQUENCER_PERIOD);
+        apu.write(NR24, 0x40);
+        assert_eq!(apu.read(NR52) & 0b10, 0);
+
+        // Triggering with length 0 and length enabled loads 63 instead of 64
+        apu.write(NR24, 0xC0);
+        assert_eq!(apu.read(NR52) & 0b10, 0b10);
+        for _ in 0..62 {
+            apu.tick(SEQUENCER_PERIOD * 2);
+        }
+        assert_eq!(apu.read(NR52) & 0b10, 0b10);
+        apu.tick(SEQUENCER_PERIOD * 2);
+        assert_eq!(apu.read(NR52) & 0b10, 0);
+    }
+
+    #[test]
+    fn test_mix() {
+        let mut apu = Apu::default();
+        apu.write(NR50, 0x70); // left at full volume, right at 1/8
+        apu.write(NR51, 0x22); // channel 2 on both
+        apu.write(NR21, 0x80); // 50% duty, so the first step is high
+        apu.write(0xFF17, 0xF0);
+        apu.write(NR24, 0x87);
+        let sample = apu.mix();
+        // 15 -> 1.0, divided between 4 channels
+        assert_eq!(sample.left, 0.25);
+        assert_eq!(sample.right, 0.25 / 8.0);
+    }
+
+    /// The loudest sample from a tone far above what the output rate can carry
+    fn loudest_ultrasonic_sample(resampling: Resampling) -> f32 {
+        let mut apu = Apu::default();
+        apu.set_resampling(resampling);
+        apu.write(NR50, 0x77);
+        apu.write(NR51, 0x22);
+        apu.write(NR21, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF18, 0xFF);
+        apu.write(NR24, 0x87); // 131 kHz
+        apu.tick(CLOCK_HZ / 10);
+        apu.samples()
+            .drain()
+            .map(|sample| sample.left.abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_resampling() {
+        // Picking samples aliases the tone into something audible,
+        // averaging smooths it out
+        assert!(loudest_ultrasonic_sample(Resampling::Nearest) > 0.2);
+        assert!(loudest_ultrasonic_sample(Resampling::Average) < 0.05);
+    }
+
+    #[test]
+    fn test_mute_solo() {
+        let mut apu = Apu::default();
+        apu.write(NR51, 0x11); // channel 1 on the right
+        apu.write(0xFF12, 0xF0);
+        apu.write(NR14, 0x80);
+        let playing = apu.mix().right;
+        assert!(playing != 0.0);
+
+        apu.set_muted(Channel::Square1, true);
+        assert_eq!(apu.mix().right, 0.0);
+        apu.set_muted(Channel::Square1, false);
+
+        // Soloing another channel leaves this one out
+        apu.set_soloed(Channel::Noise, true);
+        assert_eq!(apu.mix().right, 0.0);
+        apu.set_soloed(Channel::Square1, true);
+        assert_eq!(apu.mix().right, playing);
+    }
+
+    #[test]
+    fn test_parse_channels() {
+        assert_eq!(
+            parse_channels("1, 4"),
+            Ok(vec![Channel::Square1, Channel::Noise])
+        );
+        assert!(parse_channels("5").is_err());
+        assert!(parse_channels("").is_err());
+    }
+
+    #[test]
+    fn test_wave_channel() {
+        let mut apu = Apu::default();
+        apu.write(NR30, 0x80);
+        apu.write(NR34, 0x80);
+        assert_eq!(apu.read(NR52), 0xF4);
+        // NR30 turns the DAC off, which stops the channel
+        apu.write(NR30, 0x00);
+        assert_eq!(apu.read(NR52), 0xF0);
+    }
+
+    #[test]
+    fn test_noise_channel() {
+        let mut apu = Apu::default();
+        apu.write(0xFF21, 0xF0); // NR42: DAC on
+        apu.write(NR44, 0x80);
+        assert_eq!(apu.read(NR52), 0xF8);
+    }
+
+    #[test]
+    fn test_wave_ram() {
+        let mut apu = Apu::default();
+        apu.write(WAVE_RAM_START, 0x12);
+        apu.write(WAVE_RAM_END, 0xEF);
+        assert_eq!(apu.read(WAVE_RAM_START), 0x12);
+        assert_eq!(apu.read(WAVE_RAM_END), 0xEF);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut apu = Apu::new(32_768);
+        // 128 T-cycles per sample at this rate
+        apu.tick(127);
+        assert_eq!(apu.samples().drain().count(), 0);
+        apu.tick(1);
+        assert_eq!(apu.samples().drain().count(), 1);
+
+        // A second's worth of cycles gives a second's worth of samples
+        let mut apu = Apu::new(DEFAULT_SAMPLE_RATE);
+        let mut samples = 0;
+        for _ in 0..64 {
+            apu.tick(CLOCK_HZ / 64);
+            samples += apu.samples().drain().count();
+        }
+        assert_eq!(samples, DEFAULT_SAMPLE_RATE as usize);
+    }
+}
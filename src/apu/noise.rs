@@ -0,0 +1,234 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length::LengthCounter;
+use crate::savestate::save_state_fields;
+
+/// NR43 bits 0-2 pick the base divisor, in T-cycles
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+/// NR43 bit 3 shortens the LFSR to 7 bits, for a more metallic sound
+const WIDTH_7_BITS: u8 = 1 << 3;
+
+/// Channel 4: pseudo-random noise from a linear feedback shift register
+#[derive(Debug)]
+pub struct NoiseChannel {
+    /// 15 bits
+    lfsr: u16,
+    short_mode: bool,
+    divisor_code: u8,
+    shift: u8,
+    /// T-cycles until the LFSR next shifts
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+    enabled: bool,
+    dac_enabled: bool,
+}
+
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        NoiseChannel {
+            lfsr: 0x7FFF,
+            short_mode: false,
+            divisor_code: 0,
+            shift: 0,
+            timer: DIVISORS[0],
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            enabled: false,
+            dac_enabled: false,
+        }
+    }
+}
+
+impl NoiseChannel {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    /// T-cycles per LFSR shift
+    fn period(&self) -> u32 {
+        DIVISORS[self.divisor_code as usize] << self.shift
+    }
+
+    /// Reset everything but the length counter, as when the APU is turned off
+    pub fn power_off(&mut self, keep_length: bool) {
+        *self = NoiseChannel {
+            length: self.length.powered_off(keep_length),
+            ..NoiseChannel::default()
+        };
+    }
+
+    /// Load the length from NR41, as the DMG allows while the APU is off
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    /// Write NR41-NR43, given as 1-3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.write(value);
+                self.dac_enabled = Envelope::dac_enabled(value);
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            3 => {
+                self.shift = value >> 4;
+                self.short_mode = value & WIDTH_7_BITS != 0;
+                self.divisor_code = value & 0b111;
+            }
+            _ => unreachable!("NR4{} is not a noise channel register", register),
+        }
+    }
+
+    /// Write NR44: length enable and trigger.
+    /// `extra_length_clock` comes from the frame sequencer.
+    pub fn write_control(&mut self, value: u8, extra_length_clock: bool) {
+        let ran_out = self
+            .length
+            .set_enabled(value & 0x40 != 0, extra_length_clock);
+        if value & 0x80 != 0 {
+            self.trigger(extra_length_clock);
+        } else if ran_out {
+            self.enabled = false;
+        }
+    }
+
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(extra_length_clock);
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    /// XOR the bottom two bits into the top, and into bit 6 in 7-bit mode
+    fn shift_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (bit << 6);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        // Shifts of 14 and 15 stop the LFSR
+        if self.shift >= 14 {
+            return;
+        }
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.shift_lfsr();
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// The current output level, 0-15: the volume while bit 0 of the LFSR is clear
+    pub fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume()
+        } else {
+            0
+        }
+    }
+}
+
+save_state_fields!(NoiseChannel {
+    lfsr,
+    short_mode,
+    divisor_code,
+    shift,
+    timer,
+    length,
+    envelope,
+    enabled,
+    dac_enabled,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(nr43: u8) -> NoiseChannel {
+        let mut channel = NoiseChannel::default();
+        channel.write(2, 0xF0);
+        channel.write(3, nr43);
+        channel.write_control(0x80, false);
+        channel
+    }
+
+    /// How many shifts until the bits under `mask` repeat. The first shifts
+    /// are skipped so the 7-bit mode has settled into its cycle.
+    fn lfsr_period(channel: &mut NoiseChannel, mask: u16) -> usize {
+        for _ in 0..32 {
+            channel.shift_lfsr();
+        }
+        let start = channel.lfsr & mask;
+        (1..=1 << 15)
+            .find(|_| {
+                channel.shift_lfsr();
+                channel.lfsr & mask == start
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lfsr() {
+        let mut channel = playing(0x00);
+        // All ones: the first shift feeds in a 0 at the top
+        channel.tick(8);
+        assert_eq!(channel.lfsr, 0x3FFF);
+        assert_eq!(channel.output(), 0);
+        // The first 0 reaches bit 0 after 15 shifts
+        channel.tick(8 * 14);
+        assert_eq!(channel.lfsr & 1, 0);
+        assert_eq!(channel.output(), 15);
+    }
+
+    #[test]
+    fn test_lfsr_periods() {
+        assert_eq!(lfsr_period(&mut playing(0x00), 0x7FFF), 32767);
+        assert_eq!(lfsr_period(&mut playing(WIDTH_7_BITS), 0x7F), 127);
+    }
+
+    #[test]
+    fn test_period() {
+        // Divisor code 3 (48) shifted left 2
+        let mut channel = playing(0x23);
+        channel.tick(48 * 4 - 1);
+        assert_eq!(channel.lfsr, 0x7FFF);
+        channel.tick(1);
+        assert_eq!(channel.lfsr, 0x3FFF);
+
+        // Shift 14 stops the clock
+        let mut channel = playing(0xE0);
+        channel.tick(1 << 20);
+        assert_eq!(channel.lfsr, 0x7FFF);
+    }
+
+    #[test]
+    fn test_length() {
+        let mut channel = playing(0x00);
+        channel.write(1, 0x3F);
+        channel.write_control(0x40, false);
+        channel.clock_length();
+        assert!(!channel.enabled());
+    }
+}
@@ -0,0 +1,114 @@
+// The channels' output changes at most once per M-cycle, about 1 MHz, but the
+// audio output only takes 44.1 or 48 kHz. Picking the output at each sample
+// time is cheap but aliases the high notes into noise; averaging everything
+// since the last sample filters them out.
+
+use crate::apu::buffer::StereoSample;
+use crate::prelude::*;
+use crate::savestate::save_state_fields;
+
+/// How the mixed output becomes samples at the output rate
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Resampling {
+    /// The output at the time of each sample
+    Nearest,
+    /// The average output since the last sample
+    #[default]
+    Average,
+}
+
+/// Parse a resampling method as given on the command line
+pub fn parse_resampling(value: &str) -> Result<Resampling, String> {
+    match value.trim() {
+        "nearest" => Ok(Resampling::Nearest),
+        "average" => Ok(Resampling::Average),
+        other => Err(format!(
+            "Unknown resampler {:?}, expected nearest or average",
+            other
+        )),
+    }
+}
+
+/// Sums the output over the time since the last sample
+#[derive(Debug, Default)]
+pub struct Resampler {
+    pub resampling: Resampling,
+    left: f32,
+    right: f32,
+    /// T-cycles summed so far
+    cycles: u32,
+}
+
+impl Resampler {
+    /// Whether the output needs to be added for every stretch of time
+    pub fn averages(&self) -> bool {
+        self.resampling == Resampling::Average
+    }
+
+    /// Add the output held for a number of T-cycles
+    pub fn add(&mut self, output: StereoSample, cycles: u32) {
+        self.left += output.left * cycles as f32;
+        self.right += output.right * cycles as f32;
+        self.cycles += cycles;
+    }
+
+    /// The next sample, given the output right now, starting a new sum
+    pub fn take(&mut self, output: StereoSample) -> StereoSample {
+        if !self.averages() || self.cycles == 0 {
+            return output;
+        }
+        let sample = StereoSample {
+            left: self.left / self.cycles as f32,
+            right: self.right / self.cycles as f32,
+        };
+        self.left = 0.0;
+        self.right = 0.0;
+        self.cycles = 0;
+        sample
+    }
+}
+
+save_state_fields!(Resampler {
+    left,
+    right,
+    cycles
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(value: f32) -> StereoSample {
+        StereoSample {
+            left: value,
+            right: value,
+        }
+    }
+
+    #[test]
+    fn test_average() {
+        let mut resampler = Resampler::default();
+        resampler.add(level(1.0), 3);
+        resampler.add(level(-1.0), 1);
+        assert_eq!(resampler.take(level(-1.0)), level(0.5));
+        // Starts again from nothing
+        resampler.add(level(0.25), 2);
+        assert_eq!(resampler.take(level(0.25)), level(0.25));
+    }
+
+    #[test]
+    fn test_nearest() {
+        let mut resampler = Resampler {
+            resampling: Resampling::Nearest,
+            ..Default::default()
+        };
+        assert!(!resampler.averages());
+        assert_eq!(resampler.take(level(-1.0)), level(-1.0));
+    }
+
+    #[test]
+    fn test_parse_resampling() {
+        assert_eq!(parse_resampling("nearest"), Ok(Resampling::Nearest));
+        assert!(parse_resampling("sinc").is_err());
+    }
+}
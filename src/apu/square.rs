@@ -0,0 +1,235 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length::LengthCounter;
+use crate::apu::sweep::Sweep;
+use crate::savestate::save_state_fields;
+
+/// The waveforms NRx1 bits 6-7 select from, one bit per step:
+/// 12.5%, 25%, 50% and 75% high
+const DUTY_CYCLES: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// A pulse wave channel: channel 1 (with a frequency sweep) or channel 2
+#[derive(Debug)]
+pub struct SquareChannel {
+    /// Only channel 1 has one
+    sweep: Option<Sweep>,
+    duty: u8,
+    /// Which of the 8 steps of the waveform is playing
+    duty_step: u8,
+    /// 11 bits, from NRx3 and the bottom of NRx4
+    frequency: u16,
+    /// T-cycles until the next step of the waveform
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+    enabled: bool,
+    dac_enabled: bool,
+}
+
+impl SquareChannel {
+    pub fn new(has_sweep: bool) -> SquareChannel {
+        SquareChannel {
+            sweep: if has_sweep {
+                Some(Sweep::default())
+            } else {
+                None
+            },
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            enabled: false,
+            dac_enabled: false,
+        }
+    }
+
+    /// Whether the channel is playing, as reported in NR52
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    /// T-cycles per step of the waveform
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    /// Reset everything but the length counter, as when the APU is turned off
+    pub fn power_off(&mut self, keep_length: bool) {
+        *self = SquareChannel {
+            length: self.length.powered_off(keep_length),
+            ..SquareChannel::new(self.sweep.is_some())
+        };
+    }
+
+    /// Load the length from NRx1 without touching the duty,
+    /// as the DMG allows while the APU is off
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    /// Write NRx0-NRx3, given as 0-3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    sweep.write(value);
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.write(value);
+                self.dac_enabled = Envelope::dac_enabled(value);
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            _ => unreachable!("NRx{} is not a square channel register", register),
+        }
+    }
+
+    /// Write NRx4: the frequency's top bits, length enable and trigger.
+    /// `extra_length_clock` comes from the frame sequencer.
+    pub fn write_control(&mut self, value: u8, extra_length_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | (((value & 0b111) as u16) << 8);
+        let ran_out = self
+            .length
+            .set_enabled(value & 0x40 != 0, extra_length_clock);
+        if value & 0x80 != 0 {
+            self.trigger(extra_length_clock);
+        } else if ran_out {
+            self.enabled = false;
+        }
+    }
+
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(extra_length_clock);
+        self.timer = self.period();
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            if !sweep.trigger(self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if let Some(sweep) = &mut self.sweep {
+            if !sweep.clock(&mut self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// The current output level, 0-15
+    pub fn output(&self) -> u8 {
+        let high = DUTY_CYCLES[self.duty as usize] & (0x80 >> self.duty_step) != 0;
+        if self.enabled && high {
+            self.envelope.volume()
+        } else {
+            0
+        }
+    }
+}
+
+save_state_fields!(SquareChannel {
+    sweep,
+    duty,
+    duty_step,
+    frequency,
+    timer,
+    length,
+    envelope,
+    enabled,
+    dac_enabled,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Channel 2 at full volume with a 50% duty cycle
+    fn playing(frequency: u16) -> SquareChannel {
+        let mut channel = SquareChannel::new(false);
+        channel.write(1, 0x80);
+        channel.write(2, 0xF0);
+        channel.write(3, frequency as u8);
+        channel.write_control(0x80 | (frequency >> 8) as u8, false);
+        channel
+    }
+
+    #[test]
+    fn test_waveform() {
+        // 2048 - 0x7FE = 2, so 8 T-cycles per step
+        let mut channel = playing(0x7FE);
+        let mut wave = Vec::new();
+        for _ in 0..8 {
+            channel.tick(8);
+            wave.push(channel.output());
+        }
+        // 50% duty is 0b1000_0111, starting from step 1
+        assert_eq!(wave, vec![0, 0, 0, 0, 15, 15, 15, 15]);
+    }
+
+    #[test]
+    fn test_dac_off() {
+        let mut channel = playing(0x700);
+        assert!(channel.enabled());
+        channel.write(2, 0x00);
+        assert!(!channel.enabled());
+        // Triggering doesn't turn it back on while the DAC is off
+        channel.write_control(0x80, false);
+        assert!(!channel.enabled());
+    }
+
+    #[test]
+    fn test_length() {
+        let mut channel = playing(0x700);
+        channel.write(1, 0x3E); // 2 clocks
+        channel.write_control(0xC7, false);
+        channel.clock_length();
+        assert!(channel.enabled());
+        channel.clock_length();
+        assert!(!channel.enabled());
+    }
+
+    #[test]
+    fn test_sweep_overflow() {
+        let mut channel = SquareChannel::new(true);
+        channel.write(0, 0x11);
+        channel.write(2, 0xF0);
+        channel.write(3, 0x00);
+        channel.write_control(0x87, false); // frequency 0x700
+                                            // Overflows on the trigger's check
+        assert!(!channel.enabled());
+    }
+}
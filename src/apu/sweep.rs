@@ -0,0 +1,130 @@
+use crate::savestate::save_state_fields;
+
+/// Channel 1's frequency sweep, set through NR10. Every `period` sweep
+/// clocks the frequency moves up or down by itself shifted right `shift` times.
+#[derive(Debug, Default)]
+pub struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    /// A copy of the frequency the sweep works from
+    shadow: u16,
+}
+
+/// The largest frequency that fits in 11 bits
+const MAX_FREQUENCY: u16 = 2047;
+
+impl Sweep {
+    pub fn write(&mut self, nr10: u8) {
+        self.period = (nr10 >> 4) & 0b111;
+        self.negate = nr10 & 0b1000 != 0;
+        self.shift = nr10 & 0b111;
+    }
+
+    /// A period of 0 is treated as 8 by the timer
+    fn reload_timer(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    /// The next frequency, which may be out of range
+    fn next_frequency(&self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    /// Returns false if the channel should be turned off straight away
+    /// because the first calculation overflows
+    pub fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow = frequency;
+        self.reload_timer();
+        self.enabled = self.period != 0 || self.shift != 0;
+        self.shift == 0 || self.next_frequency() <= MAX_FREQUENCY
+    }
+
+    /// Called at 128 Hz by the frame sequencer. Updates the frequency and
+    /// returns false if it overflowed, which turns the channel off.
+    pub fn clock(&mut self, frequency: &mut u16) -> bool {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return true;
+        }
+        self.reload_timer();
+        if !self.enabled || self.period == 0 {
+            return true;
+        }
+
+        let next = self.next_frequency();
+        if next > MAX_FREQUENCY {
+            return false;
+        }
+        if self.shift != 0 {
+            self.shadow = next;
+            *frequency = next;
+        }
+        // The new frequency is checked for overflow again, but not used
+        self.next_frequency() <= MAX_FREQUENCY
+    }
+}
+
+save_state_fields!(Sweep {
+    period,
+    negate,
+    shift,
+    timer,
+    enabled,
+    shadow,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_up() {
+        let mut sweep = Sweep::default();
+        sweep.write(0x11); // period 1, up, shift 1
+        let mut frequency = 0x100;
+        assert!(sweep.trigger(frequency));
+        assert!(sweep.clock(&mut frequency));
+        assert_eq!(frequency, 0x180);
+        assert!(sweep.clock(&mut frequency));
+        assert_eq!(frequency, 0x240);
+    }
+
+    #[test]
+    fn test_sweep_down() {
+        let mut sweep = Sweep::default();
+        sweep.write(0x2A); // period 2, down, shift 2
+        let mut frequency = 0x400;
+        assert!(sweep.trigger(frequency));
+        assert!(sweep.clock(&mut frequency));
+        assert_eq!(frequency, 0x400);
+        assert!(sweep.clock(&mut frequency));
+        assert_eq!(frequency, 0x300);
+    }
+
+    #[test]
+    fn test_overflow_on_trigger() {
+        let mut sweep = Sweep::default();
+        sweep.write(0x01);
+        // 0x700 + 0x380 doesn't fit in 11 bits
+        assert!(!sweep.trigger(0x700));
+    }
+
+    #[test]
+    fn test_overflow_after_update() {
+        let mut sweep = Sweep::default();
+        sweep.write(0x11);
+        let mut frequency = 0x500;
+        assert!(sweep.trigger(frequency));
+        // 0x500 -> 0x780 is in range, but the check after it (0xB40) isn't
+        assert!(!sweep.clock(&mut frequency));
+        assert_eq!(frequency, 0x780);
+    }
+}
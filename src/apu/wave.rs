@@ -0,0 +1,239 @@
+use crate::apu::length::LengthCounter;
+use crate::savestate::save_state_fields;
+
+/// 16 bytes holding 32 4-bit samples, the upper nibble first
+pub const WAVE_RAM_SIZE: usize = 16;
+const SAMPLES: u8 = 32;
+
+/// NR30 bit 7 turns the DAC on
+const DAC_ENABLE: u8 = 1 << 7;
+
+/// Channel 3: plays the 32 samples in wave RAM in a loop
+#[derive(Debug)]
+pub struct WaveChannel {
+    wave_ram: [u8; WAVE_RAM_SIZE],
+    /// Which of the 32 samples is playing
+    position: u8,
+    /// The sample read at the last step, 0-15
+    sample: u8,
+    /// NR32 bits 5-6: 0 mutes, 1-3 shift the samples right by 0-2
+    volume_code: u8,
+    /// 11 bits, from NR33 and the bottom of NR34
+    frequency: u16,
+    /// T-cycles until the next sample
+    timer: u32,
+    length: LengthCounter,
+    enabled: bool,
+    dac_enabled: bool,
+}
+
+impl Default for WaveChannel {
+    fn default() -> Self {
+        WaveChannel {
+            wave_ram: [0; WAVE_RAM_SIZE],
+            position: 0,
+            sample: 0,
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(256),
+            enabled: false,
+            dac_enabled: false,
+        }
+    }
+}
+
+impl WaveChannel {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    /// T-cycles per sample
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    /// The wave RAM byte the CPU sees at an offset. While the channel plays,
+    /// every offset reaches the byte being played instead, as on the CGB.
+    fn wave_ram_index(&self, offset: usize) -> usize {
+        if self.enabled {
+            (self.position / 2) as usize
+        } else {
+            offset
+        }
+    }
+
+    pub fn read_wave_ram(&self, offset: usize) -> u8 {
+        self.wave_ram[self.wave_ram_index(offset)]
+    }
+
+    pub fn write_wave_ram(&mut self, offset: usize, value: u8) {
+        let index = self.wave_ram_index(offset);
+        self.wave_ram[index] = value;
+    }
+
+    /// Reset everything but the length counter and wave RAM,
+    /// as when the APU is turned off
+    pub fn power_off(&mut self, keep_length: bool) {
+        *self = WaveChannel {
+            wave_ram: self.wave_ram,
+            length: self.length.powered_off(keep_length),
+            ..WaveChannel::default()
+        };
+    }
+
+    /// Load the length from NR31, as the DMG allows while the APU is off
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value);
+    }
+
+    /// Write NR30-NR33, given as 0-3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.dac_enabled = value & DAC_ENABLE != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.volume_code = (value >> 5) & 0b11,
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            _ => unreachable!("NR3{} is not a wave channel register", register),
+        }
+    }
+
+    /// Write NR34: the frequency's top bits, length enable and trigger.
+    /// `extra_length_clock` comes from the frame sequencer.
+    pub fn write_control(&mut self, value: u8, extra_length_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | (((value & 0b111) as u16) << 8);
+        let ran_out = self
+            .length
+            .set_enabled(value & 0x40 != 0, extra_length_clock);
+        if value & 0x80 != 0 {
+            self.trigger(extra_length_clock);
+        } else if ran_out {
+            self.enabled = false;
+        }
+    }
+
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(extra_length_clock);
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % SAMPLES;
+            let byte = self.wave_ram[(self.position / 2) as usize];
+            self.sample = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// The current output level, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.volume_code == 0 {
+            return 0;
+        }
+        self.sample >> (self.volume_code - 1)
+    }
+}
+
+save_state_fields!(WaveChannel {
+    wave_ram,
+    position,
+    sample,
+    volume_code,
+    frequency,
+    timer,
+    length,
+    enabled,
+    dac_enabled,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    /// Wave RAM counting up 0, 1, 2, ... 15, 0, 1, ...
+    fn playing(volume_code: u8) -> WaveChannel {
+        let mut channel = WaveChannel::default();
+        for offset in 0..WAVE_RAM_SIZE {
+            let first = (offset as u8 * 2) & 0x0F;
+            channel.write_wave_ram(offset, (first << 4) | (first + 1));
+        }
+        channel.write(0, DAC_ENABLE);
+        channel.write(2, volume_code << 5);
+        channel.write(3, 0xFF); // 2 T-cycles per sample
+        channel.write_control(0x87, false);
+        channel
+    }
+
+    #[test_case(1, [1, 2, 3, 4]; "full volume")]
+    #[test_case(2, [0, 1, 1, 2]; "half volume")]
+    #[test_case(3, [0, 0, 0, 1]; "quarter volume")]
+    #[test_case(0, [0, 0, 0, 0]; "muted")]
+    fn test_samples(volume_code: u8, expected: [u8; 4]) {
+        let mut channel = playing(volume_code);
+        let mut samples = [0; 4];
+        for sample in samples.iter_mut() {
+            channel.tick(2);
+            *sample = channel.output();
+        }
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn test_dac_off() {
+        let mut channel = playing(1);
+        assert!(channel.enabled());
+        channel.write(0, 0x00);
+        assert!(!channel.enabled());
+        channel.write_control(0x80, false);
+        assert!(!channel.enabled());
+    }
+
+    #[test]
+    fn test_wave_ram_while_playing() {
+        let mut channel = playing(1);
+        channel.tick(2 * 5); // sample 5, in byte 2
+        assert_eq!(channel.read_wave_ram(0), 0x45);
+        channel.write_wave_ram(15, 0xAB);
+        assert_eq!(channel.read_wave_ram(7), 0xAB);
+
+        // Once it stops, each offset is its own byte again
+        channel.write(0, 0x00);
+        assert_eq!(channel.read_wave_ram(0), 0x01);
+        assert_eq!(channel.read_wave_ram(2), 0xAB);
+    }
+
+    #[test]
+    fn test_length() {
+        let mut channel = playing(1);
+        channel.write(1, 0xFF); // 1 clock
+        channel.write_control(0x47, false);
+        channel.clock_length();
+        assert!(!channel.enabled());
+    }
+}
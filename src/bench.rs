@@ -0,0 +1,103 @@
+// Runs a ROM as fast as it goes for a number of frames and reports how fast
+// that was, to catch changes that slow the emulator down.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::gameboy::{GameBoy, CLOCK_HZ};
+use crate::ppu::DOTS_PER_FRAME;
+
+/// How many frames to run if not told, ten seconds of the game
+pub const DEFAULT_FRAMES: u64 = 600;
+
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub frames: u64,
+    pub instructions: u64,
+    /// T-cycles
+    pub cycles: u64,
+    /// Wall-clock time taken
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// Seconds of the game run per second of wall-clock time
+    pub fn speed(&self) -> f64 {
+        self.cycles as f64 / CLOCK_HZ as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn per_second(&self, count: u64) -> f64 {
+        count as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames ({:.2} s emulated) in {:.3} s",
+            self.frames,
+            self.cycles as f64 / CLOCK_HZ as f64,
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(f, "Speed         {:.2}x real time", self.speed())?;
+        writeln!(
+            f,
+            "Instructions  {} ({:.2} M/s)",
+            self.instructions,
+            self.per_second(self.instructions) / 1e6
+        )?;
+        writeln!(
+            f,
+            "T-cycles      {} ({:.2} M/s)",
+            self.cycles,
+            self.per_second(self.cycles) / 1e6
+        )
+    }
+}
+
+/// Run `frames` frames with nothing holding the emulator back
+pub fn run(gameboy: &mut GameBoy, frames: u64) -> Report {
+    let instructions = gameboy.instructions_run();
+    let start = Instant::now();
+    for _ in 0..frames {
+        gameboy.step_frame();
+    }
+    Report {
+        frames,
+        instructions: gameboy.instructions_run() - instructions,
+        cycles: frames * DOTS_PER_FRAME as u64,
+        elapsed: start.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        // NOPs, 4 T-cycles each
+        let mut gameboy = GameBoy::new(vec![0; 0x8000]);
+        let report = run(&mut gameboy, 1);
+        assert_eq!(report.instructions, DOTS_PER_FRAME as u64 / 4);
+        assert_eq!(report.cycles, DOTS_PER_FRAME as u64);
+    }
+
+    #[test]
+    fn test_report() {
+        let report = Report {
+            frames: 60,
+            instructions: 1_000_000,
+            cycles: CLOCK_HZ as u64 * 2,
+            elapsed: Duration::from_millis(500),
+        };
+        assert_eq!(report.speed(), 4.0);
+        let text = report.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "60 frames (2.00 s emulated) in 0.500 s");
+        assert_eq!(lines[1], "Speed         4.00x real time");
+        assert_eq!(lines[2], "Instructions  1000000 (2.00 M/s)");
+        assert_eq!(lines[3], "T-cycles      8388608 (16.78 M/s)");
+    }
+}
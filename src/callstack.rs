@@ -0,0 +1,234 @@
+// A shadow of the call stack, kept beside the real one on the GameBoy's
+// stack: CALL and RST push a frame, an interrupt being taken pushes one
+// too, and RET pops back to the frame it returns to. It's what debugger
+// backtraces and per-subroutine profiles are made from. Games that play
+// tricks with the stack, like returning somewhere other than where they
+// were called from, only confuse it until they return for real.
+
+use crate::cpu_core::cpu::Registers;
+use crate::prelude::*;
+
+/// Where the CPU jumps to for each interrupt
+const INTERRUPT_VECTORS: [u16; 5] = [0x0040, 0x0048, 0x0050, 0x0058, 0x0060];
+
+/// Frames past this are dropped from the bottom, so a game that calls
+/// without ever returning doesn't use more and more memory
+const MAX_FRAMES: usize = 1024;
+
+/// How an instruction moves between subroutines
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    /// CALL, conditional or not, and RST. Holds the instruction's size.
+    Call(u16),
+    /// RET, conditional or not, and RETI
+    Return,
+    Other,
+}
+
+pub fn flow(opcode: u8) -> Flow {
+    match opcode {
+        0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => Flow::Call(3),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Flow::Call(1),
+        0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8 => Flow::Return,
+        _ => Flow::Other,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    /// The start of the subroutine or interrupt handler
+    pub entry: u16,
+    /// Where it was called from, or the instruction that was interrupted
+    pub caller: u16,
+    /// Where it goes back to
+    pub return_address: u16,
+    pub interrupt: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    /// Outermost first
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    /// Outermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The subroutine the CPU is in, or None at the top level
+    pub fn current(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+
+    /// Follow an instruction starting with `opcode`, which took the CPU
+    /// from `before` to `after`
+    pub fn update(&mut self, before: &Registers, opcode: u8, after: &Registers) {
+        let next = |size: u16| before.pc.wrapping_add(size);
+        match flow(opcode) {
+            Flow::Call(size) if after.pc != next(size) => self.push(Frame {
+                entry: after.pc,
+                caller: before.pc,
+                return_address: next(size),
+                interrupt: false,
+            }),
+            Flow::Return if after.pc != next(1) => self.pop(after.pc),
+            // An interrupt was taken: the CPU pushed PC and jumped to a vector
+            _ if INTERRUPT_VECTORS.contains(&after.pc) && after.sp == before.sp.wrapping_sub(2) => {
+                self.push(Frame {
+                    entry: after.pc,
+                    caller: before.pc,
+                    return_address: before.pc,
+                    interrupt: true,
+                })
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, frame: Frame) {
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    /// Return to `address`, dropping every frame down to the one that
+    /// returns there. If none do, only the innermost one is dropped.
+    fn pop(&mut self, address: u16) {
+        match self
+            .frames
+            .iter()
+            .rposition(|frame| frame.return_address == address)
+        {
+            Some(index) => self.frames.truncate(index),
+            None => {
+                self.frames.pop();
+            }
+        }
+    }
+
+    /// One line per frame, innermost first, starting from `pc`
+    pub fn backtrace(&self, pc: u16) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut address = pc;
+        for (number, frame) in self.frames.iter().rev().enumerate() {
+            lines.push(format!(
+                "#{} {:04X} in {:04X}{}",
+                number,
+                address,
+                frame.entry,
+                if frame.interrupt { " (interrupt)" } else { "" }
+            ));
+            address = frame.caller;
+        }
+        lines.push(format!(
+            "#{} {:04X} at the top level",
+            self.frames.len(),
+            address
+        ));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn at(pc: u16, sp: u16) -> Registers {
+        Registers {
+            pc,
+            sp,
+            ..Default::default()
+        }
+    }
+
+    #[test_case(0xCD, Flow::Call(3); "call")]
+    #[test_case(0xDC, Flow::Call(3); "call c")]
+    #[test_case(0xEF, Flow::Call(1); "rst 28h")]
+    #[test_case(0xC9, Flow::Return; "ret")]
+    #[test_case(0xD9, Flow::Return; "reti")]
+    #[test_case(0xC0, Flow::Return; "ret nz")]
+    #[test_case(0xC3, Flow::Other; "jp")]
+    #[test_case(0x00, Flow::Other; "nop")]
+    fn test_flow(opcode: u8, expected: Flow) {
+        assert_eq!(flow(opcode), expected);
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        let mut stack = CallStack::default();
+        // CALL 0x4000 at 0x0150
+        stack.update(&at(0x0150, 0xFFFE), 0xCD, &at(0x4000, 0xFFFC));
+        // RST 0x28 at 0x4010
+        stack.update(&at(0x4010, 0xFFFC), 0xEF, &at(0x0028, 0xFFFA));
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(
+            stack.backtrace(0x002A),
+            vec![
+                "#0 002A in 0028",
+                "#1 4010 in 4000",
+                "#2 0150 at the top level"
+            ]
+        );
+
+        // A conditional return that isn't taken
+        stack.update(&at(0x002A, 0xFFFA), 0xC0, &at(0x002B, 0xFFFA));
+        assert_eq!(stack.depth(), 2);
+        stack.update(&at(0x002B, 0xFFFA), 0xC9, &at(0x4011, 0xFFFC));
+        assert_eq!(stack.current().unwrap().entry, 0x4000);
+        stack.update(&at(0x4011, 0xFFFC), 0xC9, &at(0x0153, 0xFFFE));
+        assert_eq!(stack.depth(), 0);
+    }
+
+    #[test]
+    fn test_call_not_taken() {
+        let mut stack = CallStack::default();
+        stack.update(&at(0x0150, 0xFFFE), 0xC4, &at(0x0153, 0xFFFE));
+        assert_eq!(stack.depth(), 0);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut stack = CallStack::default();
+        stack.update(&at(0x0150, 0xFFFE), 0x00, &at(0x0040, 0xFFFC));
+        assert_eq!(
+            stack.current(),
+            Some(&Frame {
+                entry: 0x0040,
+                caller: 0x0150,
+                return_address: 0x0150,
+                interrupt: true,
+            })
+        );
+        assert_eq!(stack.backtrace(0x0041)[0], "#0 0041 in 0040 (interrupt)");
+        // RETI
+        stack.update(&at(0x0045, 0xFFFC), 0xD9, &at(0x0150, 0xFFFE));
+        assert_eq!(stack.depth(), 0);
+    }
+
+    /// Returning past a frame, eg after the game drops a return address
+    #[test]
+    fn test_return_past_frames() {
+        let mut stack = CallStack::default();
+        stack.update(&at(0x0150, 0xFFFE), 0xCD, &at(0x4000, 0xFFFC));
+        stack.update(&at(0x4000, 0xFFFC), 0xCD, &at(0x5000, 0xFFFA));
+        stack.update(&at(0x5000, 0xFFFC), 0xC9, &at(0x0153, 0xFFFE));
+        assert_eq!(stack.depth(), 0);
+    }
+
+    #[test]
+    fn test_max_frames() {
+        let mut stack = CallStack::default();
+        for _ in 0..MAX_FRAMES + 1 {
+            stack.update(&at(0x4000, 0xFFFE), 0xCD, &at(0x4000, 0xFFFC));
+        }
+        assert_eq!(stack.depth(), MAX_FRAMES);
+    }
+}
@@ -0,0 +1,189 @@
+use core::fmt;
+
+use crate::prelude::*;
+
+/// Cartridge header locations
+pub const LOGO_ADDRESS: usize = 0x0104;
+pub const TITLE_ADDRESS: usize = 0x0134;
+pub const CGB_FLAG_ADDRESS: usize = 0x0143;
+pub const HEADER_CHECKSUM_ADDRESS: usize = 0x014D;
+pub const GLOBAL_CHECKSUM_ADDRESS: usize = 0x014E;
+/// The header spans 0x0100-0x014F, so the ROM must be at least this large
+pub const HEADER_END: usize = 0x0150;
+
+/// The Nintendo logo, which the boot ROM compares against the cartridge
+/// before handing over control
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// A problem found while verifying a ROM's header
+#[derive(Debug, PartialEq)]
+pub enum HeaderError {
+    /// The ROM is too small to contain a header
+    TooSmall(usize),
+    /// Index of the first byte of the logo that differs
+    LogoMismatch(usize),
+    HeaderChecksum {
+        expected: u8,
+        actual: u8,
+    },
+    GlobalChecksum {
+        expected: u16,
+        actual: u16,
+    },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderError::TooSmall(size) => write!(
+                f,
+                "ROM is {} bytes, too small to contain a header ({} bytes)",
+                size, HEADER_END
+            ),
+            HeaderError::LogoMismatch(index) => {
+                write!(f, "Nintendo logo differs at {:#06x}", LOGO_ADDRESS + index)
+            }
+            HeaderError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "Header checksum mismatch: header says {:#04x}, computed {:#04x}",
+                expected, actual
+            ),
+            HeaderError::GlobalChecksum { expected, actual } => write!(
+                f,
+                "Global checksum mismatch: header says {:#06x}, computed {:#06x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+/// Compute the header checksum over 0x0134-0x014C, the same way the boot ROM does
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_ADDRESS..HEADER_CHECKSUM_ADDRESS]
+        .iter()
+        .fold(0u8, |checksum, byte| {
+            checksum.wrapping_sub(*byte).wrapping_sub(1)
+        })
+}
+
+/// Compute the global checksum: the sum of every byte in the ROM
+/// except the two checksum bytes themselves
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(index, _)| {
+            *index != GLOBAL_CHECKSUM_ADDRESS && *index != GLOBAL_CHECKSUM_ADDRESS + 1
+        })
+        .fold(0u16, |checksum, (_, byte)| {
+            checksum.wrapping_add(*byte as u16)
+        })
+}
+
+/// Check the Nintendo logo, header checksum and global checksum of a ROM.
+/// Returns every mismatch found; an empty list means the ROM looks intact.
+pub fn verify(rom: &[u8]) -> Vec<HeaderError> {
+    if rom.len() < HEADER_END {
+        return vec![HeaderError::TooSmall(rom.len())];
+    }
+
+    let mut errors = Vec::new();
+
+    let logo = &rom[LOGO_ADDRESS..LOGO_ADDRESS + NINTENDO_LOGO.len()];
+    if let Some(index) = logo
+        .iter()
+        .zip(NINTENDO_LOGO.iter())
+        .position(|(actual, expected)| actual != expected)
+    {
+        errors.push(HeaderError::LogoMismatch(index));
+    }
+
+    let expected = rom[HEADER_CHECKSUM_ADDRESS];
+    let actual = header_checksum(rom);
+    if expected != actual {
+        errors.push(HeaderError::HeaderChecksum { expected, actual });
+    }
+
+    // Stored big-endian, unlike everything else on the GameBoy
+    let expected =
+        ((rom[GLOBAL_CHECKSUM_ADDRESS] as u16) << 8) | rom[GLOBAL_CHECKSUM_ADDRESS + 1] as u16;
+    let actual = global_checksum(rom);
+    if expected != actual {
+        errors.push(HeaderError::GlobalChecksum { expected, actual });
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a ROM with a valid logo and checksums
+    fn valid_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[LOGO_ADDRESS..LOGO_ADDRESS + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE_ADDRESS..TITLE_ADDRESS + 6].copy_from_slice(b"RUSTY!");
+        rom[0x0200] = 0xAB; // some program data
+
+        rom[HEADER_CHECKSUM_ADDRESS] = header_checksum(&rom);
+        let global = global_checksum(&rom);
+        rom[GLOBAL_CHECKSUM_ADDRESS] = (global >> 8) as u8;
+        rom[GLOBAL_CHECKSUM_ADDRESS + 1] = global as u8;
+        rom
+    }
+
+    #[test]
+    fn test_header_checksum() {
+        // An all-zero header: 0x19 bytes, each subtracting 1
+        let rom = vec![0; HEADER_END];
+        assert_eq!(header_checksum(&rom), 0xE7);
+    }
+
+    #[test]
+    fn test_boot_rom_logo() {
+        // The boot ROM carries its own copy of the logo to compare against
+        let boot_rom = include_bytes!("../../roms/dmg_boot.bin");
+        assert_eq!(
+            &boot_rom[0xA8..0xA8 + NINTENDO_LOGO.len()],
+            &NINTENDO_LOGO[..]
+        );
+    }
+
+    #[test]
+    fn test_verify_valid() {
+        assert!(verify(&valid_rom()).is_empty());
+    }
+
+    #[test]
+    fn test_verify_corrupt() {
+        let mut rom = valid_rom();
+        rom[LOGO_ADDRESS + 3] ^= 0xFF;
+        rom[TITLE_ADDRESS] = b'X';
+        rom[0x0300] = 0x01;
+
+        let errors = verify(&rom);
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0], HeaderError::LogoMismatch(3));
+        assert!(matches!(errors[1], HeaderError::HeaderChecksum { .. }));
+        assert!(matches!(errors[2], HeaderError::GlobalChecksum { .. }));
+    }
+
+    /// Only the global checksum changes when program data is corrupted
+    #[test]
+    fn test_verify_corrupt_data() {
+        let mut rom = valid_rom();
+        rom[0x4000] = 0x77;
+        let errors = verify(&rom);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], HeaderError::GlobalChecksum { .. }));
+    }
+
+    #[test]
+    fn test_verify_too_small() {
+        assert_eq!(verify(&[0; 16]), vec![HeaderError::TooSmall(16)]);
+    }
+}
@@ -0,0 +1,300 @@
+// What the header says about a cartridge, for the info subcommand: its
+// title, who made it, what hardware it needs, and whether the checksums
+// hold.
+
+use crate::cartridge::header::{verify, HeaderError, CGB_FLAG_ADDRESS, HEADER_END, TITLE_ADDRESS};
+use crate::cartridge::{ram_size, CARTRIDGE_TYPE_ADDRESS, RAM_SIZE_ADDRESS};
+use crate::disassembler::json::string;
+use crate::prelude::*;
+
+const MANUFACTURER_ADDRESS: usize = 0x013F;
+const NEW_LICENSEE_ADDRESS: usize = 0x0144;
+const SGB_FLAG_ADDRESS: usize = 0x0146;
+const ROM_SIZE_ADDRESS: usize = 0x0148;
+const DESTINATION_ADDRESS: usize = 0x014A;
+const OLD_LICENSEE_ADDRESS: usize = 0x014B;
+const VERSION_ADDRESS: usize = 0x014C;
+
+/// The old licensee code that means the new one is used instead
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+/// What the hardware in a cartridge of each type is called
+fn cartridge_type_name(code: u8) -> &'static str {
+    match code {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => "unknown",
+    }
+}
+
+/// Header bytes as text, up to the first NUL, leaving out anything that
+/// isn't printable
+fn text(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .filter(|byte| byte.is_ascii_graphic() || **byte == b' ')
+        .map(|&byte| byte as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// How much a cartridge uses the GameBoy Color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cgb {
+    No,
+    /// Also runs on the original GameBoy
+    Supported,
+    Required,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Info {
+    pub title: String,
+    /// A four letter code on later cartridges, or empty
+    pub manufacturer: String,
+    /// Who published it: two characters from the new licensee code, or the
+    /// old code as hex
+    pub licensee: String,
+    pub cgb: Cgb,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    /// In bytes
+    pub rom_size: usize,
+    /// In bytes, not counting RAM built into the MBC
+    pub ram_size: usize,
+    pub japanese: bool,
+    pub version: u8,
+    /// What's wrong with the logo and checksums
+    pub problems: Vec<HeaderError>,
+}
+
+impl Info {
+    pub fn parse(rom: &[u8]) -> Result<Info, String> {
+        if rom.len() < HEADER_END {
+            return Err(HeaderError::TooSmall(rom.len()).to_string());
+        }
+        let cgb = match rom[CGB_FLAG_ADDRESS] {
+            0x80 => Cgb::Supported,
+            0xC0 => Cgb::Required,
+            _ => Cgb::No,
+        };
+        // Color games have a shorter title, with the manufacturer and CGB flag after it
+        let (title_end, manufacturer) = match cgb {
+            Cgb::No => (CGB_FLAG_ADDRESS + 1, String::new()),
+            _ => {
+                let manufacturer = &rom[MANUFACTURER_ADDRESS..CGB_FLAG_ADDRESS];
+                match manufacturer.iter().all(u8::is_ascii_uppercase) {
+                    true => (MANUFACTURER_ADDRESS, text(manufacturer)),
+                    false => (CGB_FLAG_ADDRESS, String::new()),
+                }
+            }
+        };
+        let licensee = match rom[OLD_LICENSEE_ADDRESS] {
+            USE_NEW_LICENSEE => text(&rom[NEW_LICENSEE_ADDRESS..NEW_LICENSEE_ADDRESS + 2]),
+            code => format!("${:02X}", code),
+        };
+        Ok(Info {
+            title: text(&rom[TITLE_ADDRESS..title_end]),
+            manufacturer,
+            licensee,
+            cgb,
+            sgb: rom[SGB_FLAG_ADDRESS] == 0x03,
+            cartridge_type: rom[CARTRIDGE_TYPE_ADDRESS],
+            rom_size: (32 * 1024) << rom[ROM_SIZE_ADDRESS].min(8),
+            ram_size: ram_size(rom[RAM_SIZE_ADDRESS]),
+            japanese: rom[DESTINATION_ADDRESS] == 0x00,
+            version: rom[VERSION_ADDRESS],
+            problems: verify(rom),
+        })
+    }
+
+    /// The header's fields, a line each, eg `Title            TETRIS`
+    pub fn table(&self) -> String {
+        let size = |bytes: usize| match bytes {
+            0 => String::from("none"),
+            bytes if bytes >= 1024 * 1024 => format!("{} MiB", bytes / (1024 * 1024)),
+            bytes => format!("{} KiB", bytes / 1024),
+        };
+        let checks = match self.problems.len() {
+            0 => String::from("logo and checksums OK"),
+            _ => self
+                .problems
+                .iter()
+                .map(HeaderError::to_string)
+                .collect::<Vec<_>>()
+                .join("\n                 "),
+        };
+        let rows = [
+            ("Title", self.title.clone()),
+            ("Manufacturer", self.manufacturer.clone()),
+            ("Licensee", self.licensee.clone()),
+            ("CGB", format!("{:?}", self.cgb)),
+            ("SGB", String::from(if self.sgb { "Yes" } else { "No" })),
+            (
+                "Cartridge type",
+                format!(
+                    "${:02X} {}",
+                    self.cartridge_type,
+                    cartridge_type_name(self.cartridge_type)
+                ),
+            ),
+            (
+                "ROM size",
+                format!(
+                    "{} ({} banks)",
+                    size(self.rom_size),
+                    self.rom_size / (16 * 1024)
+                ),
+            ),
+            ("RAM size", size(self.ram_size)),
+            ("Region", String::from(self.region())),
+            ("Version", self.version.to_string()),
+            ("Checks", checks),
+        ];
+        rows.iter()
+            .map(|(name, value)| format!("{:<16} {}\n", name, value))
+            .collect()
+    }
+
+    /// The header's fields as a JSON object
+    pub fn json(&self) -> String {
+        let problems: Vec<String> = self
+            .problems
+            .iter()
+            .map(|problem| string(&problem.to_string()))
+            .collect();
+        format!(
+            "{{\"title\": {}, \"manufacturer\": {}, \"licensee\": {}, \"cgb\": {}, \
+             \"sgb\": {}, \"cartridge_type\": {}, \"cartridge_type_name\": {}, \
+             \"rom_size\": {}, \"ram_size\": {}, \"region\": {}, \"version\": {}, \
+             \"problems\": [{}]}}",
+            string(&self.title),
+            string(&self.manufacturer),
+            string(&self.licensee),
+            string(&format!("{:?}", self.cgb).to_lowercase()),
+            self.sgb,
+            self.cartridge_type,
+            string(cartridge_type_name(self.cartridge_type)),
+            self.rom_size,
+            self.ram_size,
+            string(self.region()),
+            self.version,
+            problems.join(", ")
+        )
+    }
+
+    fn region(&self) -> &'static str {
+        match self.japanese {
+            true => "Japan",
+            false => "Overseas",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::header::{
+        header_checksum, HEADER_CHECKSUM_ADDRESS, LOGO_ADDRESS, NINTENDO_LOGO,
+    };
+
+    /// A ROM with a valid logo and header checksum, but not global checksum
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x10000];
+        rom[LOGO_ADDRESS..LOGO_ADDRESS + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE_ADDRESS..TITLE_ADDRESS + 6].copy_from_slice(b"TETRIS");
+        rom[OLD_LICENSEE_ADDRESS] = USE_NEW_LICENSEE;
+        rom[NEW_LICENSEE_ADDRESS..NEW_LICENSEE_ADDRESS + 2].copy_from_slice(b"01");
+        rom[CARTRIDGE_TYPE_ADDRESS] = 0x13;
+        rom[ROM_SIZE_ADDRESS] = 0x01;
+        rom[RAM_SIZE_ADDRESS] = 0x03;
+        rom[DESTINATION_ADDRESS] = 0x01;
+        rom[VERSION_ADDRESS] = 0x02;
+        rom[HEADER_CHECKSUM_ADDRESS] = header_checksum(&rom);
+        rom
+    }
+
+    #[test]
+    fn test_parse() {
+        let info = Info::parse(&rom()).unwrap();
+        assert_eq!(info.title, "TETRIS");
+        assert_eq!(info.manufacturer, "");
+        assert_eq!(info.licensee, "01");
+        assert_eq!(info.cgb, Cgb::No);
+        assert!(!info.sgb);
+        assert_eq!(info.rom_size, 64 * 1024);
+        assert_eq!(info.ram_size, 32 * 1024);
+        assert!(!info.japanese);
+        assert_eq!(info.version, 2);
+        assert!(matches!(
+            info.problems[..],
+            [HeaderError::GlobalChecksum { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_cgb() {
+        let mut rom = rom();
+        rom[TITLE_ADDRESS..CGB_FLAG_ADDRESS].copy_from_slice(b"POKEMON GOLAAUE");
+        rom[CGB_FLAG_ADDRESS] = 0x80;
+        rom[OLD_LICENSEE_ADDRESS] = 0x01;
+        let info = Info::parse(&rom).unwrap();
+        assert_eq!(info.title, "POKEMON GOL");
+        assert_eq!(info.manufacturer, "AAUE");
+        assert_eq!(info.licensee, "$01");
+        assert_eq!(info.cgb, Cgb::Supported);
+    }
+
+    #[test]
+    fn test_table() {
+        let table = Info::parse(&rom()).unwrap().table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "Title            TETRIS");
+        assert_eq!(lines[5], "Cartridge type   $13 MBC3+RAM+BATTERY");
+        assert_eq!(lines[6], "ROM size         64 KiB (4 banks)");
+        assert_eq!(lines[7], "RAM size         32 KiB");
+        assert_eq!(lines[8], "Region           Overseas");
+        assert!(lines[10].starts_with("Checks           Global checksum mismatch"));
+    }
+
+    #[test]
+    fn test_json() {
+        let json = Info::parse(&rom()).unwrap().json();
+        assert!(json.starts_with("{\"title\": \"TETRIS\", \"manufacturer\": \"\""));
+        assert!(json.contains("\"cgb\": \"no\", \"sgb\": false, \"cartridge_type\": 19"));
+        assert!(json.contains("\"problems\": [\"Global checksum mismatch"));
+    }
+
+    #[test]
+    fn test_too_small() {
+        assert!(Info::parse(&[0; 0x100]).is_err());
+    }
+}
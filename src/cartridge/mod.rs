@@ -0,0 +1,385 @@
+pub mod header;
+pub mod info;
+pub mod rtc;
+
+#[cfg(feature = "std")]
+use log::info;
+use log::{debug, warn};
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+use crate::cartridge::header::{CGB_FLAG_ADDRESS, HEADER_END, TITLE_ADDRESS};
+use crate::cartridge::rtc::{Clock, Rtc, RTC_DAY_HIGH, RTC_SECONDS};
+use crate::prelude::*;
+use crate::savestate::save_state_fields;
+
+/// Cartridge header locations
+const CARTRIDGE_TYPE_ADDRESS: usize = 0x0147;
+const RAM_SIZE_ADDRESS: usize = 0x0149;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+/// The memory bank controller (MBC) wired into the cartridge
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum Mbc {
+    /// 32 KiB ROM only, no bank switching
+    #[default]
+    None,
+    Mbc3,
+}
+
+/// The GameBoy cartridge: ROM, external RAM and the bank controller
+/// that maps them into 0x0000-0x7FFF and 0xA000-0xBFFF
+#[derive(Default)]
+pub struct Cartridge {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: Mbc,
+    /// Only present on MBC3 cartridges with a timer
+    rtc: Option<Rtc>,
+    /// What the RTC keeps time by
+    clock: Clock,
+    ram_enabled: bool,
+    rom_bank: usize,
+    /// 0x00-0x03 selects a RAM bank, 0x08-0x0C selects an RTC register
+    ram_bank: u8,
+    /// Where the ROM was loaded from; save data is stored next to it
+    #[cfg(feature = "std")]
+    rom_path: Option<PathBuf>,
+}
+
+/// Size of the external RAM in bytes, from the header byte at 0x0149
+fn ram_size(code: u8) -> usize {
+    match code {
+        0x02 => 8 * 1024,
+        0x03 => 32 * 1024,
+        0x04 => 128 * 1024,
+        0x05 => 64 * 1024,
+        _ => 0,
+    }
+}
+
+/// The sidecar file the RTC is saved to, eg `pokemon.gbc` -> `pokemon.rtc`
+#[cfg(feature = "std")]
+pub fn rtc_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("rtc")
+}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Cartridge {
+        let cartridge_type = rom.get(CARTRIDGE_TYPE_ADDRESS).copied().unwrap_or(0);
+        let (mbc, has_rtc) = match cartridge_type {
+            0x0F | 0x10 => (Mbc::Mbc3, true), // MBC3+TIMER(+RAM)+BATTERY
+            0x11..=0x13 => (Mbc::Mbc3, false),
+            _ => (Mbc::None, false),
+        };
+        let ram_size = ram_size(rom.get(RAM_SIZE_ADDRESS).copied().unwrap_or(0));
+        debug!(
+            "Cartridge type {:#04x}: {:?}, RTC={}, {} bytes of RAM",
+            cartridge_type, mbc, has_rtc, ram_size
+        );
+
+        Cartridge {
+            rom,
+            ram: vec![0; ram_size],
+            mbc,
+            rtc: if has_rtc {
+                Some(Rtc::new(Clock::default().now()))
+            } else {
+                None
+            },
+            rom_bank: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Remember where the ROM was loaded from and restore
+    /// the save data stored next to it (currently the RTC)
+    #[cfg(feature = "std")]
+    pub fn load_save_data(&mut self, rom_path: PathBuf) {
+        if self.rtc.is_some() {
+            self.load_rtc(&rtc_path(&rom_path));
+        }
+        self.rom_path = Some(rom_path);
+    }
+
+    /// Keep time for the RTC by `clock`. Call before load_save_data(), so
+    /// a saved RTC is restored by the same clock.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+        if let Some(rtc) = &mut self.rtc {
+            rtc.update(clock.now());
+        }
+    }
+
+    /// Advance emulated time, if that's what the RTC keeps time by
+    pub fn tick(&mut self, cycles: u32) {
+        self.clock.tick(cycles);
+    }
+
+    /// Whether the ROM supports (0x80) or requires (0xC0) the GameBoy Color
+    pub fn supports_cgb(&self) -> bool {
+        matches!(self.rom.get(CGB_FLAG_ADDRESS), Some(0x80) | Some(0xC0))
+    }
+
+    /// The title and checksums from the header, which tell ROMs apart
+    pub fn header(&self) -> &[u8] {
+        self.rom.get(TITLE_ADDRESS..HEADER_END).unwrap_or(&[])
+    }
+
+    pub fn rom_size(&self) -> usize {
+        self.rom.len()
+    }
+
+    fn rom_byte(&self, index: usize) -> u8 {
+        match self.rom.get(index) {
+            Some(byte) => *byte,
+            None => 0xFF,
+        }
+    }
+
+    /// Where in the ROM an address in 0x0000-0x7FFF reads from,
+    /// with the bank that's mapped now
+    pub fn rom_offset(&self, address: u16) -> Option<usize> {
+        let address = address as usize;
+        match address {
+            0x0000..=0x3FFF => Some(address),
+            0x4000..=0x7FFF => {
+                let bank = match self.mbc {
+                    Mbc::None => 1,
+                    Mbc::Mbc3 => self.rom_bank.max(1),
+                };
+                Some(bank * ROM_BANK_SIZE + (address - 0x4000))
+            }
+            _ => None,
+        }
+    }
+
+    /// Read from the cartridge ROM (0x0000-0x7FFF) or RAM (0xA000-0xBFFF)
+    pub fn read(&self, address: u16) -> u8 {
+        if let Some(offset) = self.rom_offset(address) {
+            return self.rom_byte(offset);
+        }
+        let address = address as usize;
+        match address {
+            0xA000..=0xBFFF => self.read_ram(address - 0xA000),
+            _ => {
+                warn!("Address {:#06x} is not on the cartridge", address);
+                0xFF
+            }
+        }
+    }
+
+    fn read_ram(&self, offset: usize) -> u8 {
+        if self.mbc == Mbc::None {
+            return self.ram.get(offset).copied().unwrap_or(0xFF);
+        }
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.ram_bank, &self.rtc) {
+            (RTC_SECONDS..=RTC_DAY_HIGH, Some(rtc)) => rtc.read(self.ram_bank),
+            (0x00..=0x03, _) => {
+                let index = self.ram_bank as usize * RAM_BANK_SIZE + offset;
+                self.ram.get(index).copied().unwrap_or(0xFF)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    /// Write to the bank controller registers (0x0000-0x7FFF) or RAM (0xA000-0xBFFF)
+    pub fn write(&mut self, address: u16, value: u8) {
+        let address = address as usize;
+        if let 0xA000..=0xBFFF = address {
+            self.write_ram(address - 0xA000, value);
+            return;
+        }
+
+        match (self.mbc, address) {
+            (Mbc::None, _) => debug!("Ignoring write to ROM at {:#06x}", address),
+            (Mbc::Mbc3, 0x0000..=0x1FFF) => self.ram_enabled = (value & 0x0F) == 0x0A,
+            (Mbc::Mbc3, 0x2000..=0x3FFF) => self.rom_bank = (value & 0x7F) as usize,
+            (Mbc::Mbc3, 0x4000..=0x5FFF) => self.ram_bank = value,
+            (Mbc::Mbc3, 0x6000..=0x7FFF) => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value, self.clock.now());
+                }
+            }
+            _ => warn!("Address {:#06x} is not on the cartridge", address),
+        }
+    }
+
+    fn write_ram(&mut self, offset: usize, value: u8) {
+        if self.mbc == Mbc::None {
+            if let Some(byte) = self.ram.get_mut(offset) {
+                *byte = value;
+            }
+            return;
+        }
+        if !self.ram_enabled {
+            return;
+        }
+        match (self.ram_bank, &mut self.rtc) {
+            (RTC_SECONDS..=RTC_DAY_HIGH, Some(rtc)) => {
+                rtc.write(self.ram_bank, value, self.clock.now())
+            }
+            (0x00..=0x03, _) => {
+                let index = self.ram_bank as usize * RAM_BANK_SIZE + offset;
+                if let Some(byte) = self.ram.get_mut(index) {
+                    *byte = value;
+                }
+            }
+            _ => debug!("Ignoring write to unmapped RAM bank {:#04x}", self.ram_bank),
+        }
+    }
+
+    /// Write straight to a bank of the cartridge RAM, whichever is mapped in,
+    /// for a cheat. `address` is in 0xA000-0xBFFF.
+    pub fn write_ram_bank(&mut self, bank: u8, address: u16, value: u8) {
+        let index = bank as usize * RAM_BANK_SIZE + (address - 0xA000) as usize;
+        if let Some(byte) = self.ram.get_mut(index) {
+            *byte = value;
+        }
+    }
+
+    /// Restore the RTC from a sidecar file, catching up on the time
+    /// that passed since it was saved
+    #[cfg(feature = "std")]
+    fn load_rtc(&mut self, path: &Path) {
+        if !path.exists() {
+            return;
+        }
+        match fs::read(path)
+            .ok()
+            .and_then(|bytes| Rtc::from_bytes(&bytes))
+        {
+            Some(mut rtc) => {
+                // Emulated time picks up where the save left off
+                if let Clock::Emulated { seconds, .. } = &mut self.clock {
+                    *seconds = rtc.last_update();
+                }
+                rtc.update(self.clock.now());
+                info!("Loaded RTC from {:?}", path);
+                self.rtc = Some(rtc);
+            }
+            None => warn!("Could not load RTC from {:?}, starting from zero.", path),
+        }
+    }
+
+    /// Write the RTC to the sidecar file next to the ROM, if there is one
+    #[cfg(feature = "std")]
+    pub fn save_rtc(&mut self) {
+        let (rtc, rom_path) = match (&mut self.rtc, &self.rom_path) {
+            (Some(rtc), Some(rom_path)) => (rtc, rom_path),
+            _ => return,
+        };
+        rtc.update(self.clock.now());
+        let path = rtc_path(rom_path);
+        match fs::write(&path, rtc.to_bytes()) {
+            Ok(()) => info!("Saved RTC to {:?}", path),
+            Err(e) => warn!("Could not save RTC to {:?}: {}", path, e),
+        }
+    }
+}
+
+// The ROM and the MBC type come from the cartridge itself
+save_state_fields!(Cartridge {
+    ram_enabled,
+    rom_bank,
+    ram_bank,
+    rtc,
+    clock,
+} fixed {
+    ram
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mbc3_rom(cartridge_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; 4 * ROM_BANK_SIZE];
+        rom[CARTRIDGE_TYPE_ADDRESS] = cartridge_type;
+        rom[RAM_SIZE_ADDRESS] = 0x03; // 32 KiB
+                                      // Tag the first byte of each bank with its bank number
+        for bank in 0..4 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_rom_only() {
+        let cartridge = Cartridge::new(vec![0x00, 0x18, 0x05]);
+        assert_eq!(cartridge.mbc, Mbc::None);
+        assert_eq!(cartridge.read(0x0001), 0x18);
+        // Reads past the end of a small ROM
+        assert_eq!(cartridge.read(0x0150), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc3_rom_banking() {
+        let mut cartridge = Cartridge::new(mbc3_rom(0x13));
+        assert_eq!(cartridge.mbc, Mbc::Mbc3);
+        assert_eq!(cartridge.read(0x4000), 1);
+
+        cartridge.write(0x2000, 3);
+        assert_eq!(cartridge.read(0x4000), 3);
+        assert_eq!(cartridge.rom_offset(0x4001), Some(3 * ROM_BANK_SIZE + 1));
+        assert_eq!(cartridge.rom_offset(0x0150), Some(0x0150));
+        assert_eq!(cartridge.rom_offset(0xA000), None);
+        // Bank 0 is mapped as bank 1
+        cartridge.write(0x2000, 0);
+        assert_eq!(cartridge.read(0x4000), 1);
+        assert_eq!(cartridge.read(0x0000), 0);
+    }
+
+    #[test]
+    fn test_mbc3_ram_banking() {
+        let mut cartridge = Cartridge::new(mbc3_rom(0x13));
+        // RAM is disabled on power up
+        cartridge.write(0xA000, 0x42);
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x4000, 2);
+        cartridge.write(0xA000, 0x42);
+        assert_eq!(cartridge.read(0xA000), 0x42);
+        cartridge.write(0x4000, 1);
+        assert_eq!(cartridge.read(0xA000), 0x00);
+    }
+
+    #[test]
+    fn test_mbc3_rtc_registers() {
+        let mut cartridge = Cartridge::new(mbc3_rom(0x10));
+        assert!(cartridge.rtc.is_some());
+        cartridge.write(0x0000, 0x0A);
+
+        // Write the minutes register, then latch so it becomes readable
+        cartridge.write(0x4000, 0x09);
+        cartridge.write(0xA000, 42);
+        cartridge.write(0x6000, 0x00);
+        cartridge.write(0x6000, 0x01);
+        assert_eq!(cartridge.read(0xA000), 42);
+    }
+
+    #[test]
+    fn test_supports_cgb() {
+        let mut rom = mbc3_rom(0x13);
+        assert!(!Cartridge::new(rom.clone()).supports_cgb());
+        rom[CGB_FLAG_ADDRESS] = 0x80;
+        assert!(Cartridge::new(rom.clone()).supports_cgb());
+        rom[CGB_FLAG_ADDRESS] = 0xC0;
+        assert!(Cartridge::new(rom).supports_cgb());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_rtc_path() {
+        let path = rtc_path(Path::new("roms/pokemon.gbc"));
+        assert_eq!(path, PathBuf::from("roms/pokemon.rtc"));
+    }
+}
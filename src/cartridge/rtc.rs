@@ -0,0 +1,418 @@
+use log::{debug, warn};
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::gameboy::CLOCK_HZ;
+use crate::prelude::*;
+use crate::savestate::{save_state_fields, SaveState};
+
+/// RTC register numbers, as selected by writing to the MBC3 RAM bank register
+pub const RTC_SECONDS: u8 = 0x08;
+pub const RTC_MINUTES: u8 = 0x09;
+pub const RTC_HOURS: u8 = 0x0A;
+pub const RTC_DAY_LOW: u8 = 0x0B;
+pub const RTC_DAY_HIGH: u8 = 0x0C;
+
+/// Bits of the day-high register
+const DAY_HIGH_MSB: u8 = 0b0000_0001; // bit 8 of the day counter
+const DAY_HIGH_HALT: u8 = 0b0100_0000;
+const DAY_HIGH_CARRY: u8 = 0b1000_0000;
+
+/// The day counter is 9 bits wide
+const MAX_DAYS: u64 = 512;
+
+/// Size of the RTC save data: 10 little-endian u32 registers followed by a
+/// 64-bit UNIX timestamp, the block BGB and VBA-M append to the .sav file.
+/// Some older emulators write a 32-bit timestamp instead (44 bytes).
+pub const RTC_SAVE_SIZE: usize = 48;
+const RTC_SAVE_SIZE_SHORT: usize = 44;
+
+/// Seconds since the UNIX epoch on the host
+#[cfg(feature = "std")]
+pub fn host_time() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => {
+            warn!("Host clock is set before the UNIX epoch!");
+            0
+        }
+    }
+}
+
+/// What the RTC keeps time by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clock {
+    /// The host's clock, so the RTC runs on while the emulator is closed
+    #[cfg(feature = "std")]
+    Host,
+    /// Emulated time, the same on every run: `seconds` since the UNIX epoch
+    /// and `cycles` T-cycles into the next second
+    Emulated { seconds: u64, cycles: u32 },
+}
+
+/// The host's clock, or emulated time from the epoch without one
+impl Default for Clock {
+    #[cfg(feature = "std")]
+    fn default() -> Clock {
+        Clock::Host
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn default() -> Clock {
+        Clock::Emulated {
+            seconds: 0,
+            cycles: 0,
+        }
+    }
+}
+
+impl Clock {
+    /// Seconds since the UNIX epoch
+    pub fn now(&self) -> u64 {
+        match self {
+            #[cfg(feature = "std")]
+            Clock::Host => host_time(),
+            Clock::Emulated { seconds, .. } => *seconds,
+        }
+    }
+
+    /// Advance emulated time by a number of T-cycles
+    pub fn tick(&mut self, cycles: u32) {
+        match self {
+            #[cfg(feature = "std")]
+            Clock::Host => {}
+            Clock::Emulated {
+                seconds,
+                cycles: elapsed,
+            } => {
+                *elapsed += cycles;
+                *seconds += (*elapsed / CLOCK_HZ) as u64;
+                *elapsed %= CLOCK_HZ;
+            }
+        }
+    }
+}
+
+/// The real-time clock inside MBC3 cartridges (eg, Pokémon Gold/Silver).
+///
+/// Rather than ticking every cycle, the clock is advanced by the elapsed
+/// host time whenever it is observed. This also lets the clock catch up
+/// on the time that passed while the emulator was closed.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day_low: u8,
+    day_high: u8,
+    /// Copy of the registers above, taken on latch; this is what the game reads
+    latched: [u8; 5],
+    /// The previous value written to the latch register; 0x00 then 0x01 latches
+    latch_primed: bool,
+    /// Host timestamp the clock was last advanced to
+    last_update: u64,
+}
+
+impl Rtc {
+    pub fn new(now: u64) -> Rtc {
+        Rtc {
+            last_update: now,
+            ..Default::default()
+        }
+    }
+
+    /// Host timestamp the clock was last advanced to
+    pub fn last_update(&self) -> u64 {
+        self.last_update
+    }
+
+    fn is_halted(&self) -> bool {
+        self.day_high & DAY_HIGH_HALT != 0
+    }
+
+    fn days(&self) -> u64 {
+        (((self.day_high & DAY_HIGH_MSB) as u64) << 8) | self.day_low as u64
+    }
+
+    /// Advance the clock to the host timestamp `now`
+    pub fn update(&mut self, now: u64) {
+        if !self.is_halted() && now > self.last_update {
+            self.advance(now - self.last_update);
+        }
+        self.last_update = now;
+    }
+
+    /// Advance the clock by a number of seconds
+    pub fn advance(&mut self, seconds: u64) {
+        let mut total: u64 = self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days() * 86400
+            + seconds;
+
+        self.seconds = (total % 60) as u8;
+        total /= 60;
+        self.minutes = (total % 60) as u8;
+        total /= 60;
+        self.hours = (total % 24) as u8;
+        total /= 24;
+
+        // The carry bit stays set until the game clears it
+        if total >= MAX_DAYS {
+            debug!("RTC day counter overflowed");
+            self.day_high |= DAY_HIGH_CARRY;
+        }
+        let days = total % MAX_DAYS;
+        self.day_low = (days & 0xFF) as u8;
+        self.day_high = (self.day_high & !DAY_HIGH_MSB) | ((days >> 8) as u8 & DAY_HIGH_MSB);
+    }
+
+    /// Read a latched RTC register
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            RTC_SECONDS..=RTC_DAY_HIGH => self.latched[(register - RTC_SECONDS) as usize],
+            _ => {
+                warn!("Invalid RTC register {:#04x}", register);
+                0xFF
+            }
+        }
+    }
+
+    /// Write to a live RTC register
+    pub fn write(&mut self, register: u8, value: u8, now: u64) {
+        // Bring the clock up to date so the elapsed time isn't lost
+        self.update(now);
+        match register {
+            RTC_SECONDS => self.seconds = value & 0x3F,
+            RTC_MINUTES => self.minutes = value & 0x3F,
+            RTC_HOURS => self.hours = value & 0x1F,
+            RTC_DAY_LOW => self.day_low = value,
+            RTC_DAY_HIGH => self.day_high = value & (DAY_HIGH_MSB | DAY_HIGH_HALT | DAY_HIGH_CARRY),
+            _ => warn!("Invalid RTC register {:#04x}", register),
+        }
+    }
+
+    /// Handle a write to the latch clock data register (0x6000-0x7FFF)
+    pub fn write_latch(&mut self, value: u8, now: u64) {
+        if self.latch_primed && value == 0x01 {
+            self.update(now);
+            self.latched = [
+                self.seconds,
+                self.minutes,
+                self.hours,
+                self.day_low,
+                self.day_high,
+            ];
+            debug!("Latched RTC: {:?}", self.latched);
+        }
+        self.latch_primed = value == 0x00;
+    }
+
+    /// Serialize the clock into the 48-byte RTC save format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RTC_SAVE_SIZE);
+        let live = [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.day_low,
+            self.day_high,
+        ];
+        for register in live.iter().chain(self.latched.iter()) {
+            bytes.extend_from_slice(&(*register as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.last_update.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize a clock saved with to_bytes().
+    /// Returns None if the data is not in a known RTC save format.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Rtc> {
+        if bytes.len() != RTC_SAVE_SIZE && bytes.len() != RTC_SAVE_SIZE_SHORT {
+            warn!("Unexpected RTC save size: {} bytes", bytes.len());
+            return None;
+        }
+
+        let word = |index: usize| -> u8 {
+            let mut raw = [0; 4];
+            raw.copy_from_slice(&bytes[index * 4..index * 4 + 4]);
+            u32::from_le_bytes(raw) as u8
+        };
+
+        let last_update = if bytes.len() == RTC_SAVE_SIZE {
+            let mut raw = [0; 8];
+            raw.copy_from_slice(&bytes[40..48]);
+            u64::from_le_bytes(raw)
+        } else {
+            let mut raw = [0; 4];
+            raw.copy_from_slice(&bytes[40..44]);
+            u32::from_le_bytes(raw) as u64
+        };
+
+        Some(Rtc {
+            seconds: word(0),
+            minutes: word(1),
+            hours: word(2),
+            day_low: word(3),
+            day_high: word(4),
+            latched: [word(5), word(6), word(7), word(8), word(9)],
+            latch_primed: false,
+            last_update,
+        })
+    }
+}
+
+save_state_fields!(Rtc {
+    seconds,
+    minutes,
+    hours,
+    day_low,
+    day_high,
+    latched,
+    latch_primed,
+    last_update,
+});
+
+impl SaveState for Clock {
+    fn save(&self, out: &mut Vec<u8>) {
+        match *self {
+            #[cfg(feature = "std")]
+            Clock::Host => false.save(out),
+            Clock::Emulated { seconds, cycles } => {
+                true.save(out);
+                seconds.save(out);
+                cycles.save(out);
+            }
+        }
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+        let mut emulated = false;
+        emulated.load(input)?;
+        *self = Clock::default();
+        if emulated {
+            let (mut seconds, mut cycles) = (0u64, 0u32);
+            seconds.load(input)?;
+            cycles.load(input)?;
+            *self = Clock::Emulated { seconds, cycles };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn latch(rtc: &mut Rtc, now: u64) {
+        rtc.write_latch(0x00, now);
+        rtc.write_latch(0x01, now);
+    }
+
+    #[test_case(0, 0, 0, 0, 0; "no time passed")]
+    #[test_case(1, 1, 0, 0, 0; "one second")]
+    #[test_case(60, 0, 1, 0, 0; "one minute")]
+    #[test_case(3600, 0, 0, 1, 0; "one hour")]
+    #[test_case(90061, 1, 1, 1, 1; "one of each")]
+    #[test_case(300 * 86400, 0, 0, 0, 300; "more than 255 days")]
+    fn test_advance(seconds: u64, s: u8, m: u8, h: u8, days: u64) {
+        let mut rtc = Rtc::new(0);
+        rtc.advance(seconds);
+
+        assert_eq!(rtc.seconds, s);
+        assert_eq!(rtc.minutes, m);
+        assert_eq!(rtc.hours, h);
+        assert_eq!(rtc.days(), days);
+        assert_eq!(rtc.day_high & DAY_HIGH_CARRY, 0);
+    }
+
+    #[test]
+    fn test_day_carry() {
+        let mut rtc = Rtc::new(0);
+        rtc.advance(513 * 86400);
+        assert_eq!(rtc.days(), 1);
+        assert_eq!(rtc.day_high & DAY_HIGH_CARRY, DAY_HIGH_CARRY);
+    }
+
+    #[test]
+    fn test_latch() {
+        let mut rtc = Rtc::new(1000);
+        // Nothing is visible until the clock is latched
+        assert_eq!(rtc.read(RTC_MINUTES), 0);
+
+        latch(&mut rtc, 1000 + 125);
+        assert_eq!(rtc.read(RTC_SECONDS), 5);
+        assert_eq!(rtc.read(RTC_MINUTES), 2);
+
+        // Writing 0x01 without a preceding 0x00 does not latch
+        rtc.write_latch(0x01, 1000 + 200);
+        assert_eq!(rtc.read(RTC_SECONDS), 5);
+    }
+
+    #[test]
+    fn test_halt() {
+        let mut rtc = Rtc::new(0);
+        rtc.write(RTC_DAY_HIGH, DAY_HIGH_HALT, 0);
+        latch(&mut rtc, 5000);
+        assert_eq!(rtc.read(RTC_SECONDS), 0);
+        assert_eq!(rtc.read(RTC_DAY_HIGH), DAY_HIGH_HALT);
+
+        // Resuming the clock does not count the time spent halted
+        rtc.write(RTC_DAY_HIGH, 0, 5000);
+        latch(&mut rtc, 5010);
+        assert_eq!(rtc.read(RTC_SECONDS), 10);
+    }
+
+    #[test]
+    fn test_save_round_trip() {
+        let mut rtc = Rtc::new(1_600_000_000);
+        rtc.advance(3 * 86400 + 4 * 3600 + 5 * 60 + 6);
+        latch(&mut rtc, 1_600_000_000);
+
+        let bytes = rtc.to_bytes();
+        assert_eq!(bytes.len(), RTC_SAVE_SIZE);
+        let loaded = Rtc::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded, rtc);
+    }
+
+    /// The clock keeps running while the emulator is closed
+    #[test]
+    fn test_catch_up_after_load() {
+        let rtc = Rtc::new(1_600_000_000);
+        let mut loaded = Rtc::from_bytes(&rtc.to_bytes()).unwrap();
+        latch(&mut loaded, 1_600_000_000 + 2 * 86400 + 30);
+        assert_eq!(loaded.read(RTC_SECONDS), 30);
+        assert_eq!(loaded.read(RTC_DAY_LOW), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_emulated_clock() {
+        let mut clock = Clock::Emulated {
+            seconds: 100,
+            cycles: 0,
+        };
+        clock.tick(CLOCK_HZ - 1);
+        assert_eq!(clock.now(), 100);
+        clock.tick(CLOCK_HZ + 1);
+        assert_eq!(clock.now(), 102);
+
+        // The host's clock isn't ticked
+        let mut clock = Clock::Host;
+        clock.tick(CLOCK_HZ);
+        assert_eq!(clock, Clock::Host);
+    }
+
+    #[test]
+    fn test_from_bytes_short_timestamp() {
+        let mut bytes = vec![0; RTC_SAVE_SIZE_SHORT];
+        bytes[0] = 42; // seconds
+        bytes[40..44].copy_from_slice(&1234u32.to_le_bytes());
+        let rtc = Rtc::from_bytes(&bytes).unwrap();
+        assert_eq!(rtc.seconds, 42);
+        assert_eq!(rtc.last_update, 1234);
+
+        assert!(Rtc::from_bytes(&[0; 10]).is_none());
+    }
+}
@@ -0,0 +1,104 @@
+// Game Genie codes patch a byte of ROM as the CPU reads it. A code is
+// ABC-DEF-GHI in hex: AB is the new byte and FCDE the address, with F
+// inverted. GI is the byte that has to be there for the patch to apply,
+// XORed with 0xBA and rotated left by two, which keeps the patch to the
+// right bank of a banked ROM. H isn't used. The short form, ABC-DEF,
+// patches the address whatever is there.
+
+use crate::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameGenie {
+    pub address: u16,
+    pub value: u8,
+    /// Only patch the address while it holds this
+    pub compare: Option<u8>,
+}
+
+impl GameGenie {
+    pub fn parse(code: &str) -> Result<GameGenie, String> {
+        let digits: Vec<u8> = code
+            .chars()
+            .filter(|c| *c != '-')
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("{:?} isn't a Game Genie code", code))?;
+        if digits.len() != 6 && digits.len() != 9 {
+            return Err(format!(
+                "A Game Genie code has 6 or 9 digits, like 00A-17B-C49, got {:?}",
+                code
+            ));
+        }
+
+        let value = digits[0] << 4 | digits[1];
+        let address = ((digits[5] ^ 0xF) as u16) << 12
+            | (digits[2] as u16) << 8
+            | (digits[3] as u16) << 4
+            | digits[4] as u16;
+        if address > 0x7FFF {
+            return Err(format!(
+                "{:?} patches {:#06x}, which isn't ROM",
+                code, address
+            ));
+        }
+        let compare = if digits.len() == 9 {
+            Some((digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA)
+        } else {
+            None
+        };
+        Ok(GameGenie {
+            address,
+            value,
+            compare,
+        })
+    }
+
+    /// The byte the CPU reads at `address`, where the ROM holds `byte`
+    pub fn patch(&self, address: u16, byte: u8) -> u8 {
+        if address == self.address && self.compare.is_none_or(|compare| compare == byte) {
+            self.value
+        } else {
+            byte
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("3E1-50F-E6A", 0x0150, 0x3E, Some(0x00); "with compare")]
+    #[test_case("3e150fe6a", 0x0150, 0x3E, Some(0x00); "without dashes")]
+    #[test_case("C9A-B7E", 0x1AB7, 0xC9, None; "short")]
+    #[test_case("001-23B-BAA", 0x4123, 0x00, Some(0x14); "banked")]
+    fn test_parse(code: &str, address: u16, value: u8, compare: Option<u8>) {
+        assert_eq!(
+            GameGenie::parse(code),
+            Ok(GameGenie {
+                address,
+                value,
+                compare
+            })
+        );
+    }
+
+    #[test_case("3E1-50F-E6"; "too short")]
+    #[test_case("3E1-50G-E6A"; "not hex")]
+    #[test_case("3E1-506-E6A"; "not rom")]
+    fn test_parse_invalid(code: &str) {
+        assert!(GameGenie::parse(code).is_err());
+    }
+
+    #[test]
+    fn test_patch() {
+        let code = GameGenie::parse("3E1-50F-E6A").unwrap();
+        assert_eq!(code.patch(0x0150, 0x00), 0x3E);
+        // Another bank is mapped in
+        assert_eq!(code.patch(0x0150, 0x01), 0x01);
+        assert_eq!(code.patch(0x0151, 0x00), 0x00);
+
+        let code = GameGenie::parse("3E1-50F").unwrap();
+        assert_eq!(code.patch(0x0150, 0x01), 0x3E);
+    }
+}
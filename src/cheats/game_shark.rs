@@ -0,0 +1,83 @@
+// GameShark codes write a byte to RAM at every VBlank, so the game keeps
+// seeing it however often it changes it. A code is TTVVLLHH in hex: VV is
+// the byte, HHLL the address and TT the bank. 01 writes to whatever bank is
+// mapped in; 8X or 9X writes to bank X of the cartridge RAM at 0xA000-0xBFFF,
+// or of the CGB work RAM at 0xD000-0xDFFF.
+
+use crate::prelude::*;
+
+/// The RAM a code can write to: the cartridge RAM and the work RAM
+const RAM_START: u16 = 0xA000;
+const RAM_END: u16 = 0xDFFF;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameShark {
+    pub address: u16,
+    pub value: u8,
+    /// The bank to write to, or None for the one mapped in
+    pub bank: Option<u8>,
+}
+
+impl GameShark {
+    pub fn parse(code: &str) -> Result<GameShark, String> {
+        if code.len() != 8 {
+            return Err(format!(
+                "A GameShark code has 8 digits, like 010FE1C6, got {:?}",
+                code
+            ));
+        }
+        if !code.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("{:?} isn't a GameShark code", code));
+        }
+        let digits = u32::from_str_radix(code, 16).unwrap();
+        let [kind, value, low, high] = digits.to_be_bytes();
+
+        let address = u16::from_le_bytes([low, high]);
+        if !(RAM_START..=RAM_END).contains(&address) {
+            return Err(format!(
+                "{:?} writes to {:#06x}, which isn't RAM",
+                code, address
+            ));
+        }
+        let bank = match kind {
+            0x01 => None,
+            0x80..=0x87 | 0x90..=0x97 => Some(kind & 0x07),
+            _ => return Err(format!("{:?} has an unknown type {:#04x}", code, kind)),
+        };
+        Ok(GameShark {
+            address,
+            value,
+            bank,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("010FE1C6", 0xC6E1, 0x0F, None; "mapped bank")]
+    #[test_case("019900A0", 0xA000, 0x99, None; "cartridge ram")]
+    #[test_case("826314D0", 0xD014, 0x63, Some(2); "wram bank")]
+    #[test_case("91010BA0", 0xA00B, 0x01, Some(1); "cartridge ram bank")]
+    fn test_parse(code: &str, address: u16, value: u8, bank: Option<u8>) {
+        assert_eq!(
+            GameShark::parse(code),
+            Ok(GameShark {
+                address,
+                value,
+                bank
+            })
+        );
+    }
+
+    #[test_case("010FE1C"; "too short")]
+    #[test_case("010FE1CG"; "not hex")]
+    #[test_case("+10FE1C6"; "sign")]
+    #[test_case("010F0080"; "not ram")]
+    #[test_case("020FE1C6"; "unknown type")]
+    fn test_parse_invalid(code: &str) {
+        assert!(GameShark::parse(code).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+// Cheat codes, kept in a list that can be changed while the game runs.
+// Each code can be turned off and on again without losing its place.
+// Game Genie codes patch the ROM and GameShark codes write to RAM. Frozen
+// addresses are like GameShark codes without the code, for quick cheats.
+
+pub mod game_genie;
+pub mod game_shark;
+
+use crate::cheats::game_genie::GameGenie;
+use crate::cheats::game_shark::GameShark;
+use crate::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Patch {
+    GameGenie(GameGenie),
+    GameShark(GameShark),
+}
+
+impl Patch {
+    /// GameShark codes are 8 digits, Game Genie codes 6 or 9 with dashes
+    fn parse(code: &str) -> Result<Patch, String> {
+        if code.len() == 8 && !code.contains('-') {
+            GameShark::parse(code).map(Patch::GameShark)
+        } else {
+            GameGenie::parse(code).map(Patch::GameGenie)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    /// The code as it was entered, in upper case
+    pub code: String,
+    pub enabled: bool,
+    pub patch: Patch,
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    /// Addresses written to at every VBlank, and the byte written
+    frozen: Vec<(u16, u8)>,
+}
+
+impl Cheats {
+    /// Add a code, turned on. Adding a code that's already there turns it on.
+    pub fn add(&mut self, code: &str) -> Result<(), String> {
+        let code = code.trim().to_uppercase();
+        if self.set_enabled(&code, true) {
+            return Ok(());
+        }
+        let patch = Patch::parse(&code)?;
+        self.cheats.push(Cheat {
+            code,
+            enabled: true,
+            patch,
+        });
+        Ok(())
+    }
+
+    /// Turn a code on or off. Returns whether there was such a code.
+    pub fn set_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        match self.find(code) {
+            Some(index) => {
+                self.cheats[index].enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Turn a code off if it's on, or on if it's off.
+    /// Returns whether it's now on, or None if there was no such code.
+    pub fn toggle(&mut self, code: &str) -> Option<bool> {
+        let index = self.find(code)?;
+        let cheat = &mut self.cheats[index];
+        cheat.enabled = !cheat.enabled;
+        Some(cheat.enabled)
+    }
+
+    /// Returns whether there was such a code
+    pub fn remove(&mut self, code: &str) -> bool {
+        match self.find(code) {
+            Some(index) => {
+                self.cheats.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    fn find(&self, code: &str) -> Option<usize> {
+        let code = code.trim().to_uppercase();
+        self.cheats.iter().position(|cheat| cheat.code == code)
+    }
+
+    /// Write `value` to `address` at every VBlank,
+    /// in place of any value it was frozen at before
+    pub fn freeze(&mut self, address: u16, value: u8) {
+        self.unfreeze(address);
+        self.frozen.push((address, value));
+    }
+
+    /// Returns whether the address was frozen
+    pub fn unfreeze(&mut self, address: u16) -> bool {
+        let frozen = self.frozen.len();
+        self.frozen.retain(|(frozen, _)| *frozen != address);
+        self.frozen.len() != frozen
+    }
+
+    pub fn frozen(&self) -> &[(u16, u8)] {
+        &self.frozen
+    }
+
+    /// The byte the CPU reads at ROM `address`, where the ROM holds `byte`
+    pub fn patch_rom(&self, address: u16, byte: u8) -> u8 {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .fold(byte, |patched, cheat| match &cheat.patch {
+                Patch::GameGenie(code) => code.patch(address, patched),
+                Patch::GameShark(_) => patched,
+            })
+    }
+
+    /// The RAM writes to make at VBlank
+    pub fn ram_writes(&self) -> impl Iterator<Item = &GameShark> {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match &cheat.patch {
+                Patch::GameShark(code) => Some(code),
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cheats() {
+        let mut cheats = Cheats::default();
+        cheats.add("3e1-50f-e6a").unwrap();
+        assert!(cheats.add("nonsense").is_err());
+        assert_eq!(cheats.list().len(), 1);
+        assert_eq!(cheats.patch_rom(0x0150, 0x00), 0x3E);
+
+        assert!(cheats.set_enabled("3E1-50F-E6A", false));
+        assert_eq!(cheats.patch_rom(0x0150, 0x00), 0x00);
+        // Adding it again turns it back on
+        cheats.add("3E1-50F-E6A").unwrap();
+        assert_eq!(cheats.list().len(), 1);
+        assert_eq!(cheats.patch_rom(0x0150, 0x00), 0x3E);
+
+        assert_eq!(cheats.toggle("3E1-50F-E6A"), Some(false));
+        assert_eq!(cheats.toggle("3E1-50F-E6A"), Some(true));
+        assert_eq!(cheats.toggle("3E1-50F"), None);
+
+        assert!(cheats.remove("3E1-50F-E6A"));
+        assert!(!cheats.remove("3E1-50F-E6A"));
+        assert_eq!(cheats.patch_rom(0x0150, 0x00), 0x00);
+    }
+
+    #[test]
+    fn test_ram_writes() {
+        let mut cheats = Cheats::default();
+        cheats.add("3E1-50F").unwrap();
+        cheats.add("010fe1c6").unwrap();
+        cheats.add("826314D0").unwrap();
+        assert_eq!(cheats.list()[1].code, "010FE1C6");
+        // Only the ROM is patched by Game Genie codes
+        assert_eq!(cheats.patch_rom(0xC6E1, 0x00), 0x00);
+        assert_eq!(cheats.ram_writes().count(), 2);
+
+        cheats.set_enabled("826314D0", false);
+        let writes: Vec<_> = cheats.ram_writes().collect();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].address, 0xC6E1);
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut cheats = Cheats::default();
+        cheats.freeze(0xC000, 0x01);
+        cheats.freeze(0xC001, 0x02);
+        cheats.freeze(0xC000, 0x03);
+        assert_eq!(cheats.frozen(), &[(0xC001, 0x02), (0xC000, 0x03)]);
+        assert!(cheats.unfreeze(0xC001));
+        assert!(!cheats.unfreeze(0xC001));
+        assert_eq!(cheats.frozen(), &[(0xC000, 0x03)]);
+    }
+}
@@ -1,34 +1,576 @@
-use clap::{load_yaml, App};
-use std::path::PathBuf;
+// The command line, declared with clap's derive in Cli and turned into
+// CommandLineArgs with the config file's settings filled in.
+
+use clap::error::ErrorKind;
+use clap::{value_parser, CommandFactory, Parser};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::apu::resampler::{parse_resampling, Resampling};
+use crate::apu::{parse_channels, Channel};
+use crate::config::Config;
+use crate::disassembler::{parse_format, Format};
+use crate::frontend::headless::{FrameHashes, Headless};
+use crate::frontend::keymap::{parse_binding, parse_turbo_period, KeyMap};
+use crate::frontend::pacer::{parse_max_speed, Pacer};
+use crate::frontend::scaler::{parse_filter, parse_scale, Filter, Scaler};
+use crate::frontend::FrontendKind;
+use crate::joypad::Button;
+use crate::mmu::trace::{parse_address, parse_ranges, AddressRange};
+use crate::ppu::palette::{parse_shades, Shades};
+use crate::savestate::state_path;
+use crate::seed::parse_seed;
+use crate::serial::{parse_link, Link};
+use crate::test_rom::{parse_suite, Suite};
 
 #[derive(Debug)]
 pub enum Subcommand {
     Run,
+    Debug,
     Disassemble,
+    Verify,
+    Info,
+    Bench,
+    /// Run the test ROMs in a directory
+    TestRoms(PathBuf),
 }
 
 #[derive(Debug)]
 pub struct CommandLineArgs {
     pub subcommand: Subcommand,
     pub rom_path: PathBuf,
+    /// Where to write a trace of every instruction; `-` is stdout
+    pub trace_instructions: Option<PathBuf>,
+    /// Where the instruction trace starts and stops
+    pub trace_start: Option<u16>,
+    pub trace_stop: Option<u16>,
+    /// A Gameboy Doctor log to check the run against
+    pub compare_trace: Option<PathBuf>,
+    /// Where to write a report of where the CPU spent its time; `-` is stdout
+    pub profile: Option<PathBuf>,
+    /// Where to write which ROM bytes ran as code; `-` is stdout
+    pub coverage: Option<PathBuf>,
+    /// Debugger commands to run on frames, breakpoints and memory writes
+    pub script: Option<PathBuf>,
+    /// Address ranges to trace bus accesses for, if tracing is enabled
+    pub trace_memory: Option<Vec<AddressRange>>,
+    /// Render with the pixel FIFO instead of whole scanlines
+    pub pixel_fifo: bool,
+    /// The frontend to run the game in, if one was picked
+    pub frontend: Option<FrontendKind>,
+    /// What to do when running headless
+    pub headless: Headless,
+    /// Save a screenshot of this frame
+    pub screenshot_frame: Option<u64>,
+    /// Where to copy the bytes sent over the serial port; `-` is stdout
+    pub serial_output: Option<PathBuf>,
+    /// What the serial port is plugged into
+    pub link: Option<Link>,
+    /// Record the input to this movie file
+    pub record_movie: Option<PathBuf>,
+    /// Play back the input from this movie file
+    pub play_movie: Option<PathBuf>,
+    /// Record the audio to this WAV file
+    pub record_audio: Option<PathBuf>,
+    /// How the sound is brought down to the output's sample rate
+    pub resampling: Resampling,
+    /// How the disassemble and info subcommands print what they find
+    pub format: Format,
+    /// Which test ROMs the test-roms subcommand runs
+    pub suite: Suite,
+    /// Labels for the disassembler and debugger, in place of the ROM's .sym file
+    pub symbols: Option<PathBuf>,
+    /// Where the disassembly starts and ends, as locations in the ROM
+    pub start: Option<String>,
+    pub end: Option<String>,
+    /// More places for the disassembler to follow the code from
+    pub entries: Vec<String>,
+    /// Sound channels left out of the mix
+    pub mute: Vec<Channel>,
+    /// If any, the only sound channels mixed in
+    pub solo: Vec<Channel>,
+    /// The colors of the four DMG shades, if not the default grayscale
+    pub palette: Option<Shades>,
+    /// How a window frontend draws the screen
+    pub scaler: Scaler,
+    /// Which keys press which buttons in a window frontend
+    pub keymap: KeyMap,
+    /// How fast a window frontend runs the game
+    pub pacer: Pacer,
+    /// Debug in a terminal UI, rather than with typed commands
+    pub tui: bool,
+    /// Cheat codes to start with
+    pub cheats: Vec<String>,
+    /// A save state to start from, from a slot or a file
+    pub load_state: Option<PathBuf>,
+    /// Run deterministically, with everything left to chance taken from this
+    pub seed: Option<u64>,
+    /// The boot ROM to run first; without one the cartridge starts at 0x0100
+    pub boot_rom: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "rusty-gameboy",
+    subcommand_required = true,
+    arg_required_else_help = true
+)]
+struct Cli {
+    /// The path to the GameBoy ROM
+    #[arg(long, value_parser = parse_rom)]
+    rom: Option<PathBuf>,
+    /// A file of 'key = value' settings, such as 'palette = green'. Flags override it
+    #[arg(long, value_name = "path")]
+    config: Option<PathBuf>,
+    /// The four shades DMG games are shown in: grayscale, green, or four hex
+    /// colors lightest first (eg '#E0F8D0,#88C070,#346856,#081820')
+    #[arg(long, value_parser = parse_shades)]
+    palette: Option<Shades>,
+    /// How many times larger than 160x144 the window is, from 1 to 6. Defaults to 3
+    #[arg(long, value_parser = parse_scale)]
+    scale: Option<u32>,
+    /// An effect drawn over the screen in a window, at a scale of 2 or more:
+    /// none, scanlines or lcd-grid
+    #[arg(long, value_parser = parse_filter)]
+    filter: Option<Filter>,
+    /// Press buttons with other keys, as comma-separated button=key pairs
+    /// (eg a=j,b=k,start=space). Also 'key.a = j' in the config file
+    #[arg(long, value_name = "bindings", value_delimiter = ',', value_parser = parse_binding)]
+    bind: Vec<(Button, String)>,
+    /// Keys that press and release a button over and over while held, as
+    /// comma-separated button=key pairs (eg a=s,b=a). Also 'turbo.a = s' in
+    /// the config file
+    #[arg(long, value_name = "bindings", value_delimiter = ',', value_parser = parse_binding)]
+    bind_turbo: Vec<(Button, String)>,
+    /// How many frames a turbo button stays pressed, then released. Defaults
+    /// to 4. Also 'turbo-period' in the config file
+    #[arg(long, value_name = "frames", value_parser = parse_turbo_period)]
+    turbo_period: Option<u32>,
+    /// Start in fast forward, running as fast as possible without sound. Tab
+    /// turns it on and off in a window
+    #[arg(long)]
+    turbo: bool,
+    /// Cap fast forward at this many times normal speed
+    #[arg(long, value_name = "speed", value_parser = parse_max_speed)]
+    max_speed: Option<u32>,
+    /// Debug in a terminal UI with panes for the registers, code, memory and
+    /// serial output, rather than with typed commands
+    #[arg(long)]
+    tui: bool,
+    /// Apply a Game Genie code, like 00A-17B-C49, or a GameShark code, like
+    /// 010FE1C6. Can be given more than once
+    #[arg(long = "cheat", value_name = "code")]
+    cheats: Vec<String>,
+    /// Start from the save state in this slot (0-9) or file. In a window, the
+    /// number keys pick a slot, F5 saves to it and F9 loads it
+    #[arg(long, value_name = "slot or path")]
+    load_state: Option<String>,
+    /// Run a DMG (256 byte) or CGB (2304 byte) boot ROM before the cartridge,
+    /// scrolling the logo in. Without one, the cartridge starts at 0x0100
+    /// with the registers the boot ROM leaves. Also 'boot-rom' in the config
+    /// file
+    #[arg(long, value_name = "path")]
+    boot_rom: Option<PathBuf>,
+    /// Run deterministically: power-on RAM and timer phase come from this
+    /// seed (decimal or 0x hex) and the cartridge clock follows emulated time
+    #[arg(long, value_parser = parse_seed)]
+    seed: Option<u64>,
+    /// Write the registers before every instruction to a file, or stdout if
+    /// it's '-', in Gameboy Doctor's format. LY reads 0x90 while tracing, as
+    /// it does for Gameboy Doctor. Also --trace-out
+    #[arg(long, value_name = "path", alias = "trace-out")]
+    trace_instructions: Option<PathBuf>,
+    /// Start the instruction trace when the CPU first reaches this address, in hex
+    #[arg(long, value_name = "address", value_parser = parse_address, requires = "trace_instructions")]
+    trace_start: Option<u16>,
+    /// End the instruction trace after the instruction at this address, in hex
+    #[arg(long, value_name = "address", value_parser = parse_address, requires = "trace_instructions")]
+    trace_stop: Option<u16>,
+    /// Check the state before every instruction against a Gameboy Doctor
+    /// log, stopping at the first line that differs
+    #[arg(long, value_name = "path")]
+    compare_trace: Option<PathBuf>,
+    /// Count the T-cycles spent at each address and in each subroutine, and
+    /// write the hottest to a file, or stdout if it's '-', when the emulator
+    /// closes
+    #[arg(long, value_name = "path")]
+    profile: Option<PathBuf>,
+    /// Track which ROM bytes run as code, and write the ranges in each bank
+    /// to a file, or stdout if it's '-', when the emulator closes
+    #[arg(long, value_name = "path")]
+    coverage: Option<PathBuf>,
+    /// How the disassemble and info subcommands print what they find: text,
+    /// or json for a record per line of the listing (with the address, bank,
+    /// bytes, mnemonic, operands, size and cycles) or an object of the
+    /// header's fields. Defaults to text
+    #[arg(long, value_parser = parse_format)]
+    format: Option<Format>,
+    /// Which test ROMs the test-roms subcommand runs: blargg for cpu_instrs,
+    /// instr_timing and mem_timing from gb-test-roms, or mooneye for the
+    /// DMG's acceptance tests in mooneye-test-suite. Defaults to blargg
+    #[arg(long, value_parser = parse_suite)]
+    suite: Option<Suite>,
+    /// Where the disassemble subcommand starts: an address, a bank and
+    /// address like 03:4000, or a label. Defaults to the start of the ROM
+    #[arg(long, value_name = "location")]
+    start: Option<String>,
+    /// Where the disassemble subcommand ends, including the instruction
+    /// there. Defaults to the end of the ROM
+    #[arg(long, value_name = "location")]
+    end: Option<String>,
+    /// More places for the disassembler to follow the code from, as
+    /// comma-separated locations like --start, for code it can't find
+    /// itself, like jump tables
+    #[arg(long = "entry", value_name = "locations", value_delimiter = ',')]
+    entries: Vec<String>,
+    /// Labels for the disassembler and debugger, from a symbol file like
+    /// RGBDS writes, with lines like '00:0150 Main'. Defaults to the .sym
+    /// file next to the ROM, if there is one
+    #[arg(long, value_name = "path")]
+    symbols: Option<PathBuf>,
+    /// Run debugger commands when the game reaches a frame, an address or
+    /// writes to memory, with hooks like 'on frame 60: press start', one per
+    /// line, or a rhai script if the file ends in .rhai
+    #[arg(long, value_name = "path")]
+    script: Option<PathBuf>,
+    /// Log every bus read/write to stderr, filtered by comma-separated
+    /// address ranges (eg 8000-9FFF,FF40) or 'all'
+    #[arg(long, value_name = "ranges")]
+    trace_memory: Option<String>,
+    /// How to show the game: sdl or winit for a window (if built with that
+    /// feature), or headless. Defaults to the first window frontend built in
+    #[arg(long, value_parser = ["sdl", "winit", "headless"])]
+    frontend: Option<String>,
+    /// Run without a window. Same as --frontend headless
+    #[arg(long, conflicts_with = "frontend")]
+    headless: bool,
+    /// Stop after this many frames (headless only)
+    #[arg(long, value_name = "count", value_parser = value_parser!(u64).range(1..))]
+    frames: Option<u64>,
+    /// Stop after this many T-cycles, 4194304 a second (headless only). With
+    /// --frames, whichever comes first
+    #[arg(long, value_name = "count", value_parser = value_parser!(u64).range(1..))]
+    max_cycles: Option<u64>,
+    /// Print a hash of every completed frame (headless only)
+    #[arg(long, conflicts_with = "hash_frame")]
+    hash_frames: bool,
+    /// Print a hash of frame N, counting from 1, then stop (headless only)
+    #[arg(long, value_name = "N", value_parser = value_parser!(u64).range(1..))]
+    hash_frame: Option<u64>,
+    /// Save a PNG of frame N, counting from 1, next to the ROM. Running
+    /// headless, stop after it
+    #[arg(long, value_name = "N", value_parser = value_parser!(u64).range(1..))]
+    screenshot_at_frame: Option<u64>,
+    /// Copy every byte sent over the serial port to a file, or to stdout with
+    /// '-'. Test ROMs like Blargg's print their results this way
+    #[arg(long, value_name = "path")]
+    serial_output: Option<PathBuf>,
+    /// Plug something into the serial port: listen:<port> waits for another
+    /// emulator to connect over TCP, connect:<host>:<port> connects to one
+    /// that's listening, loopback sends every byte back, and script:<path>
+    /// answers with the hex bytes in a file
+    #[arg(long, value_parser = parse_link)]
+    link: Option<Link>,
+    /// Write the buttons held in every frame to a movie file, to play back
+    /// later with --play-movie
+    #[arg(long, value_name = "path", conflicts_with = "play_movie")]
+    record_movie: Option<PathBuf>,
+    /// Play back the buttons recorded in a movie file from power on. The
+    /// keyboard takes over when it ends
+    #[arg(long, value_name = "path")]
+    play_movie: Option<PathBuf>,
+    /// Write the sound to a 16-bit stereo WAV file for the whole run
+    #[arg(long, value_name = "path")]
+    record_audio: Option<PathBuf>,
+    /// How the sound is brought down to the output's sample rate: average
+    /// (smoother) or nearest (faster). Defaults to average
+    #[arg(long, value_parser = parse_resampling)]
+    resampler: Option<Resampling>,
+    /// Leave these sound channels out, as comma-separated numbers 1-4 (eg
+    /// 1,3). In a window, F1-F4 toggle them
+    #[arg(long, value_name = "channels")]
+    mute: Option<String>,
+    /// Only play these sound channels, as comma-separated numbers 1-4
+    #[arg(long, value_name = "channels")]
+    solo: Option<String>,
+    /// Render with a dot-by-dot pixel FIFO instead of whole scanlines.
+    /// Slower, but accurate for mid-scanline effects
+    #[arg(long)]
+    pixel_fifo: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Run the GameBoy ROM
+    Run,
+    /// Run the GameBoy ROM an instruction at a time, with commands read from stdin
+    Debug,
+    /// Print every instruction in the ROM with its bank, address and bytes,
+    /// instead of running it
+    Disassemble,
+    /// Run the GameBoy ROM as fast as it goes for --frames frames (600 if not
+    /// given), then print how fast that was
+    Bench,
+    /// Run a suite of test ROMs headless (see --suite) and print whether each passed
+    TestRoms {
+        /// Where the suite is, a checkout of gb-test-roms or a build of
+        /// mooneye-test-suite
+        dir: PathBuf,
+    },
+    /// Check the Nintendo logo, header checksum and global checksum of the GameBoy ROM
+    Verify {
+        /// The path to the GameBoy ROM
+        #[arg(value_parser = parse_rom)]
+        rom: Option<PathBuf>,
+    },
+    /// Print what the GameBoy ROM's header says about the cartridge, like its
+    /// title, MBC and ROM and RAM sizes
+    Info {
+        /// The path to the GameBoy ROM
+        #[arg(value_parser = parse_rom)]
+        rom: Option<PathBuf>,
+    },
+}
+
+/// The value, or exit with a usage error saying why it's invalid
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| Cli::command().error(ErrorKind::InvalidValue, e).exit())
+}
+
+/// Check the ROM is a file that's there, so a typo is a usage error rather
+/// than a crash once the emulator starts
+fn check_rom(path: &Path) -> Result<(), String> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => Ok(()),
+        Ok(_) => Err(format!("The ROM {:?} isn't a file", path)),
+        Err(e) => Err(format!("Could not open the ROM {:?}: {}", path, e)),
+    }
+}
+
+fn parse_rom(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    check_rom(&path)?;
+    Ok(path)
 }
 
 impl CommandLineArgs {
-    pub fn new() -> CommandLineArgs {
-        let cli_yml_file = load_yaml!("cli.yml");
-        let matches = App::from_yaml(cli_yml_file).get_matches();
-
-        let subcommand = match matches.subcommand_name() {
-            Some("run") => Subcommand::Run,
-            Some("disassemble") => Subcommand::Disassemble,
-            _ => unreachable!(),
+    /// The arguments the program was run with, exiting with the usage if
+    /// they are wrong
+    pub fn parse() -> CommandLineArgs {
+        CommandLineArgs::from_cli(Cli::parse())
+    }
+
+    fn from_cli(cli: Cli) -> CommandLineArgs {
+        // `verify` and `info` take the ROM as a positional argument, the others use --rom
+        let (subcommand, rom) = match cli.command {
+            Command::Run => (Subcommand::Run, cli.rom),
+            Command::Debug => (Subcommand::Debug, cli.rom),
+            Command::Disassemble => (Subcommand::Disassemble, cli.rom),
+            Command::Bench => (Subcommand::Bench, cli.rom),
+            // `test-roms` runs the ROMs it finds itself
+            Command::TestRoms { dir } => (Subcommand::TestRoms(dir), Some(PathBuf::new())),
+            Command::Verify { rom } => (Subcommand::Verify, rom.or(cli.rom)),
+            Command::Info { rom } => (Subcommand::Info, rom.or(cli.rom)),
+        };
+        let rom_path = rom.unwrap_or_else(|| {
+            Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "The path to the GameBoy ROM is required (--rom <rom>)",
+                )
+                .exit()
+        });
+
+        let config = cli
+            .config
+            .map(|path| or_exit(Config::load(&path)))
+            .unwrap_or_default();
+
+        let mut scaler = Scaler::default();
+        scaler.scale = cli.scale.or(config.scale).unwrap_or(scaler.scale);
+        scaler.filter = cli.filter.or(config.filter).unwrap_or_default();
+
+        let mut keymap = config.keymap;
+        for (button, key) in cli.bind {
+            keymap.bind(button, &key);
+        }
+        for (button, key) in cli.bind_turbo {
+            keymap.bind_turbo(button, &key);
+        }
+        if let Some(period) = cli.turbo_period {
+            keymap.turbo_period = period;
+        }
+
+        let mut pacer = Pacer::default();
+        pacer.max_speed = cli.max_speed;
+        pacer.set_fast_forward(cli.turbo);
+
+        let mut headless = Headless {
+            frames: cli.frames,
+            max_cycles: cli.max_cycles,
+            hashes: match cli.hash_frame {
+                Some(frame) => FrameHashes::Frame(frame),
+                None if cli.hash_frames => FrameHashes::Every,
+                None => FrameHashes::None,
+            },
         };
+        let headless_options = headless.frames.is_some()
+            || headless.max_cycles.is_some()
+            || headless.hashes != FrameHashes::None;
 
-        let rom_path = PathBuf::from(matches.value_of("rom").unwrap());
+        let frontend = match cli.frontend.as_deref() {
+            Some("sdl") => Some(FrontendKind::Sdl),
+            Some("winit") => Some(FrontendKind::Winit),
+            Some(_) => Some(FrontendKind::Headless),
+            // Asking for frames, cycles or hashes only makes sense without a window
+            None if cli.headless || headless_options => Some(FrontendKind::Headless),
+            None => None,
+        };
+        if headless_options && frontend != Some(FrontendKind::Headless) {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--frames, --max-cycles, --hash-frames and --hash-frame only apply to the headless frontend",
+                )
+                .exit();
+        }
+        if let Some(kind) = frontend {
+            if let (Some(feature), false) = (kind.feature(), kind.is_compiled_in()) {
+                or_exit::<()>(Err(format!(
+                    "rusty-gameboy was built without the {} frontend (cargo build --features {})",
+                    feature, feature
+                )));
+            }
+        }
+        // Without a window there's no way to quit, so stop once the screenshot is taken
+        if frontend == Some(FrontendKind::Headless) && headless.frames.is_none() {
+            headless.frames = cli.screenshot_at_frame;
+        }
 
         CommandLineArgs {
             subcommand,
+            load_state: cli.load_state.map(|value| state_path(&rom_path, &value)),
             rom_path,
+            trace_memory: cli
+                .trace_memory
+                .map(|ranges| or_exit(parse_ranges(&ranges))),
+            profile: cli.profile,
+            coverage: cli.coverage,
+            script: cli.script,
+            symbols: cli.symbols,
+            start: cli.start,
+            end: cli.end,
+            entries: cli.entries,
+            compare_trace: cli.compare_trace,
+            trace_instructions: cli.trace_instructions,
+            trace_start: cli.trace_start,
+            trace_stop: cli.trace_stop,
+            pixel_fifo: cli.pixel_fifo,
+            frontend,
+            headless,
+            screenshot_frame: cli.screenshot_at_frame,
+            serial_output: cli.serial_output,
+            link: cli.link,
+            record_movie: cli.record_movie,
+            play_movie: cli.play_movie,
+            record_audio: cli.record_audio,
+            resampling: cli.resampler.or(config.resampler).unwrap_or_default(),
+            format: cli.format.unwrap_or_default(),
+            suite: cli.suite.unwrap_or(Suite::Blargg),
+            mute: cli
+                .mute
+                .map(|channels| or_exit(parse_channels(&channels)))
+                .unwrap_or_default(),
+            solo: cli
+                .solo
+                .map(|channels| or_exit(parse_channels(&channels)))
+                .unwrap_or_default(),
+            palette: cli.palette.or(config.palette),
+            scaler,
+            keymap,
+            pacer,
+            seed: cli.seed,
+            boot_rom: cli.boot_rom.or(config.boot_rom),
+            tui: cli.tui,
+            cheats: cli.cheats,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    /// Parse `args`, after the program's name
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("rusty-gameboy").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse() {
+        let cli = parse(&[
+            "--rom",
+            "Cargo.toml",
+            "--frames",
+            "10",
+            "--bind",
+            "a=j,b=k",
+            "--cheat",
+            "00A-17B-C49",
+            "--cheat",
+            "010FE1C6",
+            "run",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Command::Run));
+        assert_eq!(cli.rom, Some(PathBuf::from("Cargo.toml")));
+        assert_eq!(cli.frames, Some(10));
+        assert_eq!(
+            cli.bind,
+            vec![
+                (Button::A, String::from("j")),
+                (Button::B, String::from("k"))
+            ]
+        );
+        assert_eq!(cli.cheats, vec!["00A-17B-C49", "010FE1C6"]);
+    }
+
+    #[test]
+    fn test_positional_rom() {
+        let cli = parse(&["verify", "Cargo.toml"]).unwrap();
+        assert!(
+            matches!(cli.command, Command::Verify { rom: Some(rom) } if rom == Path::new("Cargo.toml"))
+        );
+    }
+
+    #[test_case(&["--rom", "Cargo.toml"], ErrorKind::MissingSubcommand; "no subcommand")]
+    #[test_case(&["--rom", "missing.gb", "run"], ErrorKind::ValueValidation; "missing rom")]
+    #[test_case(&["info", "src"], ErrorKind::ValueValidation; "rom is a directory")]
+    #[test_case(&["--frames", "0", "run"], ErrorKind::ValueValidation; "no frames")]
+    #[test_case(&["--scale", "9", "run"], ErrorKind::ValueValidation; "scale too big")]
+    #[test_case(&["--frontend", "x11", "run"], ErrorKind::InvalidValue; "unknown frontend")]
+    #[test_case(&["--trace-start", "0150", "run"], ErrorKind::MissingRequiredArgument; "trace start alone")]
+    #[test_case(&["--headless", "--frontend", "winit", "run"], ErrorKind::ArgumentConflict; "headless and a frontend")]
+    #[test_case(&["--rom", "Cargo.toml", "play"], ErrorKind::InvalidSubcommand; "unknown subcommand")]
+    fn test_parse_invalid(args: &[&str], kind: ErrorKind) {
+        assert_eq!(parse(args).unwrap_err().kind(), kind);
+    }
+
+    #[test]
+    fn test_check_rom() {
+        assert_eq!(check_rom(Path::new("Cargo.toml")), Ok(()));
+        assert_eq!(
+            check_rom(Path::new("src")),
+            Err(String::from("The ROM \"src\" isn't a file"))
+        );
+        assert!(check_rom(Path::new("missing.gb")).is_err());
+    }
+}
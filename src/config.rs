@@ -0,0 +1,114 @@
+// Settings read from a config file, one `key = value` per line.
+// Blank lines and lines starting with '#' are skipped.
+// Command line flags override anything set here.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::apu::resampler::{parse_resampling, Resampling};
+use crate::frontend::keymap::{parse_button, parse_turbo_period, KeyMap};
+use crate::frontend::scaler::{parse_filter, parse_scale, Filter};
+use crate::ppu::palette::{parse_shades, Shades};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    /// The colors of the four DMG shades
+    pub palette: Option<Shades>,
+    /// How many times larger than 160x144 the window is
+    pub scale: Option<u32>,
+    pub filter: Option<Filter>,
+    pub resampler: Option<Resampling>,
+    /// A DMG or CGB boot ROM to run before the cartridge
+    pub boot_rom: Option<PathBuf>,
+    /// The default layout with any `key.<button> = <key>` and
+    /// `turbo.<button> = <key>` bindings applied
+    pub keymap: KeyMap,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("Line {}: expected `key = value`", number + 1)),
+            };
+            match key {
+                "palette" => config.palette = Some(parse_shades(value)?),
+                "scale" => config.scale = Some(parse_scale(value)?),
+                "filter" => config.filter = Some(parse_filter(value)?),
+                "resampler" => config.resampler = Some(parse_resampling(value)?),
+                "boot-rom" => config.boot_rom = Some(PathBuf::from(value)),
+                _ if key.starts_with("key.") => {
+                    let button = parse_button(&key["key.".len()..])?;
+                    config.keymap.bind(button, value);
+                }
+                _ if key.starts_with("turbo.") => {
+                    let button = parse_button(&key["turbo.".len()..])?;
+                    config.keymap.bind_turbo(button, value);
+                }
+                "turbo-period" => config.keymap.turbo_period = parse_turbo_period(value)?,
+                _ => return Err(format!("Line {}: unknown setting {:?}", number + 1, key)),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        Config::parse(&text).map_err(|e| format!("{:?}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::Button;
+    use crate::ppu::palette::CLASSIC_GREEN;
+    use test_case::test_case;
+
+    #[test]
+    fn test_parse() {
+        let text = "# rusty-gameboy\n\n  palette = green  \nscale=4\nfilter = scanlines\nresampler = nearest\nboot-rom = roms/dmg_boot.bin\n";
+        let config = Config::parse(text).unwrap();
+        assert_eq!(config.palette, Some(CLASSIC_GREEN));
+        assert_eq!(config.scale, Some(4));
+        assert_eq!(config.filter, Some(Filter::Scanlines));
+        assert_eq!(config.resampler, Some(Resampling::Nearest));
+        assert_eq!(config.boot_rom, Some(PathBuf::from("roms/dmg_boot.bin")));
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let config = Config::parse("key.a = j\nkey.start = space\n").unwrap();
+        assert_eq!(config.keymap.button("j"), Some(Button::A));
+        assert_eq!(config.keymap.button("space"), Some(Button::Start));
+        // The rest keep their default keys
+        assert_eq!(config.keymap.button("z"), Some(Button::B));
+        assert!(Config::parse("key.turbo = t").is_err());
+    }
+
+    #[test]
+    fn test_parse_turbo() {
+        let config = Config::parse(
+            "turbo.a = s
+turbo-period = 2
+",
+        )
+        .unwrap();
+        assert_eq!(config.keymap.turbo_button("s"), Some(Button::A));
+        assert_eq!(config.keymap.turbo_period, 2);
+        assert!(Config::parse("turbo-period = 0").is_err());
+    }
+
+    #[test_case("palette", "Line 1: expected `key = value`"; "no value")]
+    #[test_case("volume = 11", "Line 1: unknown setting \"volume\""; "unknown key")]
+    fn test_parse_error(text: &str, error: &str) {
+        assert_eq!(Config::parse(text), Err(String::from(error)));
+    }
+}
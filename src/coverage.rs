@@ -0,0 +1,98 @@
+// Which ROM bytes the CPU has run as code. Every instruction fetched from
+// the cartridge marks its bytes in the ROM, so a bank switched in at
+// 0x4000-0x7FFF is told apart from the others. The map is written per bank
+// as the ranges that ran, which tells the code from the data.
+
+use core::fmt::Write;
+
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::prelude::*;
+
+pub struct Coverage {
+    /// One per ROM byte
+    executed: Vec<bool>,
+}
+
+impl Coverage {
+    pub fn new(rom_size: usize) -> Coverage {
+        Coverage {
+            executed: vec![false; rom_size],
+        }
+    }
+
+    /// An instruction of `size` bytes ran from `offset` in the ROM
+    pub fn record(&mut self, offset: usize, size: u16) {
+        let end = (offset + size as usize).min(self.executed.len());
+        if let Some(bytes) = self.executed.get_mut(offset..end) {
+            bytes.iter_mut().for_each(|byte| *byte = true);
+        }
+    }
+
+    /// How many bytes ran in each bank, and the ranges of them
+    /// at the addresses the bank is mapped to
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let total = self.executed.iter().filter(|executed| **executed).count();
+        writeln!(
+            report,
+            "{} of {} ROM bytes executed",
+            total,
+            self.executed.len()
+        )
+        .unwrap();
+
+        for (bank, executed) in self.executed.chunks(ROM_BANK_SIZE).enumerate() {
+            let count = executed.iter().filter(|executed| **executed).count();
+            writeln!(
+                report,
+                "\nBank {:02X}: {} of {} bytes ({:.2}%)",
+                bank,
+                count,
+                executed.len(),
+                100.0 * count as f64 / executed.len() as f64
+            )
+            .unwrap();
+            let base = if bank == 0 { 0 } else { ROM_BANK_SIZE };
+            let mut start = None;
+            for (offset, executed) in executed.iter().chain([&false]).enumerate() {
+                match (start, *executed) {
+                    (None, true) => start = Some(offset),
+                    (Some(first), false) => {
+                        writeln!(report, "  {:04X}-{:04X}", base + first, base + offset - 1)
+                            .unwrap();
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut coverage = Coverage::new(2 * ROM_BANK_SIZE);
+        coverage.record(0x0100, 1);
+        coverage.record(0x0101, 3);
+        coverage.record(0x0150, 1);
+        // The end of bank 1, mapped at 0x4000-0x7FFF
+        coverage.record(2 * ROM_BANK_SIZE - 2, 3);
+
+        assert_eq!(
+            coverage.report(),
+            "7 of 32768 ROM bytes executed\n\
+             \n\
+             Bank 00: 5 of 16384 bytes (0.03%)\n  \
+             0100-0103\n  \
+             0150-0150\n\
+             \n\
+             Bank 01: 2 of 16384 bytes (0.01%)\n  \
+             7FFE-7FFF\n"
+        );
+    }
+}
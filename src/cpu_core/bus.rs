@@ -0,0 +1,51 @@
+// What the CPU reads and writes through. On a GameBoy that's the whole
+// memory map in mmu::Mmu; FlatRam is nothing but RAM, to run the CPU on its
+// own, like the single instruction tests do.
+
+use crate::prelude::*;
+
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Where the instruction about to run starts, for a bus that reports it
+    fn set_pc(&mut self, _pc: u16) {}
+}
+
+/// All 64 KiB as plain RAM
+pub struct FlatRam {
+    ram: Vec<u8>,
+}
+
+impl Default for FlatRam {
+    fn default() -> FlatRam {
+        FlatRam {
+            ram: vec![0; 0x10000],
+        }
+    }
+}
+
+impl Bus for FlatRam {
+    fn read(&mut self, address: u16) -> u8 {
+        self.ram[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.ram[address as usize] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_ram() {
+        let mut ram = FlatRam::default();
+        for address in [0x0000, 0x4000, 0x8000, 0xFF00, 0xFF44, 0xFFFF] {
+            ram.write(address, 0x5A);
+            assert_eq!(ram.read(address), 0x5A);
+        }
+    }
+}
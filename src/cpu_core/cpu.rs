@@ -1,16 +1,17 @@
-use log::{debug, error, info, warn};
-use std::fmt;
-use std::format;
-use std::fs;
-use std::ops::{Index, IndexMut};
-use std::path::PathBuf;
-
-use crate::cli::Subcommand;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Index, IndexMut};
+use log::warn;
+
+use crate::cpu_core::bus::{Bus, FlatRam};
 use crate::cpu_core::flag_register::{FlagEffect, FlagRegister};
 use crate::cpu_core::insn::Insn;
+use crate::cpu_core::opcode::{Opcode, PREFIX_CB};
 use crate::cpu_core::register::{Register, RegisterOperation};
+use crate::prelude::*;
+use crate::savestate::save_state_fields;
 
-// Indices into Cpu::registers vector
+// Names for the registers in Cpu::regs
 #[derive(PartialEq, Clone, Copy, Debug)]
 #[repr(u8)]
 enum RegIndex {
@@ -24,44 +25,218 @@ enum RegIndex {
     SP,
     /// Program counter
     PC,
-    NumRegs,
     /// Custom for debugging
     Invalid,
 }
 
-impl Index<RegIndex> for Vec<Register> {
+/// The 16-bit registers, held in the Cpu itself rather than on the heap
+#[derive(Default)]
+struct RegisterFile {
+    af: Register,
+    bc: Register,
+    de: Register,
+    hl: Register,
+    sp: Register,
+    pc: Register,
+}
+
+impl Index<RegIndex> for RegisterFile {
     type Output = Register;
+
+    #[inline]
     fn index(&self, register_index: RegIndex) -> &Self::Output {
-        &self[register_index as usize]
+        match register_index {
+            RegIndex::AF => &self.af,
+            RegIndex::BC => &self.bc,
+            RegIndex::DE => &self.de,
+            RegIndex::HL => &self.hl,
+            RegIndex::SP => &self.sp,
+            RegIndex::PC => &self.pc,
+            RegIndex::Invalid => {
+                panic!("{:?} isn't a register", register_index)
+            }
+        }
     }
 }
 
-impl IndexMut<RegIndex> for Vec<Register> {
+impl IndexMut<RegIndex> for RegisterFile {
+    #[inline]
     fn index_mut(&mut self, register_index: RegIndex) -> &mut Self::Output {
-        &mut self[register_index as usize]
+        match register_index {
+            RegIndex::AF => &mut self.af,
+            RegIndex::BC => &mut self.bc,
+            RegIndex::DE => &mut self.de,
+            RegIndex::HL => &mut self.hl,
+            RegIndex::SP => &mut self.sp,
+            RegIndex::PC => &mut self.pc,
+            RegIndex::Invalid => {
+                panic!("{:?} isn't a register", register_index)
+            }
+        }
+    }
+}
+
+save_state_fields!(RegisterFile {
+    af,
+    bc,
+    de,
+    hl,
+    sp,
+    pc
+});
+
+/// Runs an instruction, given its opcode split into fields
+type Handler<B> = fn(&mut Cpu, &mut B, Opcode) -> Insn;
+
+/// The handlers for a bus
+struct Handlers<B>(PhantomData<B>);
+
+impl<B: Bus> Handlers<B> {
+    /// The handler for each opcode, or None if the CPU doesn't run it yet
+    const OPCODES: [Option<Handler<B>>; 256] = table(false);
+    /// The handler for each opcode after the CB prefix
+    const CB_OPCODES: [Option<Handler<B>>; 256] = table(true);
+}
+
+const fn table<B: Bus>(cb: bool) -> [Option<Handler<B>>; 256] {
+    let mut table: [Option<Handler<B>>; 256] = [None; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        table[byte] = match cb {
+            false => handler(byte as u8),
+            true => cb_handler(byte as u8),
+        };
+        byte += 1;
+    }
+    table
+}
+
+const fn handler<B: Bus>(byte: u8) -> Option<Handler<B>> {
+    if byte == PREFIX_CB {
+        // Looked up in CB_OPCODES with the next byte instead
+        return None;
+    }
+    let opcode = Opcode::new(byte);
+    let handler: Handler<B> = match (opcode.x, opcode.z, opcode.q) {
+        (0, 0, _) => match opcode.y {
+            0 => |_, _, _| Insn::nop(),
+            1 => |cpu, bus, _| cpu.ld_a16_sp(bus),
+            // STOP
+            2 => return None,
+            3 => |cpu, bus, _| cpu.jr_d8(bus),
+            _ => |cpu, bus, opcode| cpu.jr_d8_cond(bus, opcode.y),
+        },
+        (0, 1, 0) => |cpu, bus, opcode| cpu.ld_d16_rp(bus, opcode.p),
+        (0, 1, _) => |cpu, _, opcode| cpu.add_hl_rp(opcode.p),
+        (0, 2, 0) => |cpu, bus, opcode| cpu.store_a(bus, opcode.p),
+        (0, 2, _) => |cpu, bus, opcode| cpu.load_a(bus, opcode.p),
+        _ => return None,
+    };
+    Some(handler)
+}
+
+const fn cb_handler<B: Bus>(_byte: u8) -> Option<Handler<B>> {
+    // None of the rotates, shifts and bit operations yet
+    None
+}
+
+/// The opcodes the SM83 doesn't have, which lock it up
+const ILLEGAL_OPCODES: [u8; 11] = [
+    0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+];
+
+/// Why the CPU couldn't run the instruction at `pc`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuError {
+    /// An opcode the CPU doesn't run yet
+    Unimplemented { pc: u16, opcode: u8 },
+    /// An opcode after the CB prefix the CPU doesn't run yet
+    UnimplementedCb { pc: u16, opcode: u8 },
+    /// An opcode the SM83 doesn't have
+    Illegal { pc: u16, opcode: u8 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::Unimplemented { pc, opcode } => {
+                write!(
+                    f,
+                    "Opcode {:#04x} at {:#06x} is not implemented",
+                    opcode, pc
+                )
+            }
+            CpuError::UnimplementedCb { pc, opcode } => {
+                write!(
+                    f,
+                    "CB opcode {:#04x} at {:#06x} is not implemented",
+                    opcode, pc
+                )
+            }
+            CpuError::Illegal { pc, opcode } => {
+                write!(f, "Opcode {:#04x} at {:#06x} is illegal", opcode, pc)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CpuError {}
+
+/// Whether the CPU runs `opcode` yet, rather than returning an error. For the CB
+/// prefix, it depends on the next byte, see cb_is_implemented().
+pub fn is_implemented(opcode: u8) -> bool {
+    Handlers::<FlatRam>::OPCODES[opcode as usize].is_some()
+}
+
+/// Whether the CPU runs `opcode` after the CB prefix yet
+pub fn cb_is_implemented(opcode: u8) -> bool {
+    Handlers::<FlatRam>::CB_OPCODES[opcode as usize].is_some()
+}
+
+/// How many opcodes the CPU runs, without and with the CB prefix
+pub fn implemented_count() -> (usize, usize) {
+    let count = |table: &[Option<Handler<FlatRam>>]| table.iter().filter(|h| h.is_some()).count();
+    (count(&Handlers::OPCODES), count(&Handlers::CB_OPCODES))
+}
+
+/// A copy of the registers, for looking at from outside the CPU.
+/// Shown in Gameboy Doctor's format.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X}",
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.pc
+        )
     }
 }
 
 #[derive(Default)] // needed so Register initalizes to zero automatically
 pub struct Cpu {
-    regs: Vec<Register>,
-    memory: Vec<u8>, // 0x0000-0xFFFF; follow the GameBoy's memory map
+    regs: RegisterFile,
     cycle: u16,
-    // Loaded ROM
-    rom: Vec<u8>,
 }
 
 impl fmt::Display for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        debug!(
-            "{} registers, AF={}",
-            self.regs.len(),
-            RegIndex::AF as usize
-        );
         let registers = format!(
             "
             == Cycle {} ==
-            ROM: {} bytes
             Registers
             AF: {} {}
             BC: {} {}
@@ -71,7 +246,6 @@ impl fmt::Display for Cpu {
             program_counter: {}
             ",
             self.cycle,
-            self.rom.len(),
             self.regs[RegIndex::AF].read_upper(),
             self.regs[RegIndex::AF].read_lower(),
             self.regs[RegIndex::BC].read_upper(),
@@ -89,64 +263,71 @@ impl fmt::Display for Cpu {
 
 impl Cpu {
     pub fn new() -> Cpu {
-        let mut cpu: Cpu = Cpu {
-            regs: Vec::with_capacity(RegIndex::NumRegs as usize), // only sets upper bound
-            memory: vec![0; 0xFFFF],
-            ..Default::default()
+        Default::default()
+    }
+
+    /// Set the registers to what the boot ROM leaves in them when it hands
+    /// over to the cartridge at 0x0100, on a DMG or a CGB
+    pub fn skip_boot(&mut self, cgb: bool) {
+        let (af, bc, de, hl) = match cgb {
+            false => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            true => (0x1180, 0x0000, 0xFF56, 0x000D),
         };
+        self.regs[RegIndex::AF].write(af);
+        self.regs[RegIndex::BC].write(bc);
+        self.regs[RegIndex::DE].write(de);
+        self.regs[RegIndex::HL].write(hl);
+        self.regs[RegIndex::SP].write(0xFFFE);
+        self.regs[RegIndex::PC].write(0x0100);
+    }
 
-        // Initialize registers
-        for _ in 0..RegIndex::NumRegs as usize {
-            cpu.regs.push(Default::default());
+    pub fn registers(&self) -> Registers {
+        let pair = |index: RegIndex| {
+            let register = &self.regs[index];
+            (register.read_upper(), register.read_lower())
+        };
+        let (a, f) = pair(RegIndex::AF);
+        let (b, c) = pair(RegIndex::BC);
+        let (d, e) = pair(RegIndex::DE);
+        let (h, l) = pair(RegIndex::HL);
+        Registers {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: self.regs[RegIndex::SP].read(),
+            pc: self.regs[RegIndex::PC].read(),
         }
-        debug!("Initialized {} registers", cpu.regs.len());
-
-        cpu
     }
-    /// Create a Cpu from a Rom as a vector of bytes
-    pub fn new_from_vec(rom: Vec<u8>) -> Cpu {
-        let mut cpu = Cpu::new();
-        cpu.rom = rom;
-        cpu
-    }
-
-    /// Create a Cpu from a Rom path
-    pub fn new_from_path(rom_path: PathBuf) -> Cpu {
-        // Load ROM
-        if rom_path.exists() {
-            let cpu = Cpu::new_from_vec(fs::read(rom_path).unwrap());
-            debug!(
-                "Loaded ROM (byte preview): {:#02x} {:#02x} {:#02x}",
-                cpu.rom[0], cpu.rom[1], cpu.rom[2]
-            );
-            cpu
-        } else {
-            warn!("ROM file does not exist! Nothing was loaded.");
-            Cpu::new() // return default
-        }
+
+    /// Load every register at once, eg to start a test from a known state
+    pub fn set_registers(&mut self, registers: &Registers) {
+        let pair = |upper: u8, lower: u8| u16::from_be_bytes([upper, lower]);
+        self.regs[RegIndex::AF].write(pair(registers.a, registers.f));
+        self.regs[RegIndex::BC].write(pair(registers.b, registers.c));
+        self.regs[RegIndex::DE].write(pair(registers.d, registers.e));
+        self.regs[RegIndex::HL].write(pair(registers.h, registers.l));
+        self.regs[RegIndex::SP].write(registers.sp);
+        self.regs[RegIndex::PC].write(registers.pc);
     }
 
     /*
         Register helper methods
     */
 
-    // Update register
-    fn increment_reg(&mut self, reg_index: RegIndex, delta: u16) {
-        // Set the carry flag since the operation overflowed
-        if self.regs[reg_index].increment(delta).carry {
-            unimplemented!("Setting carry flag on overflow is not implemented!");
-        }
-    }
-
     fn read_pc(&self) -> u16 {
         self.regs[RegIndex::PC].read()
     }
 
     fn read_zero_flag(&self) -> u8 {
         let flag_reg_val: u8 = self.regs[RegIndex::AF].read_lower();
-        debug!("Read flag register: {:#010b}", flag_reg_val);
+        cpu_debug!("Read flag register: {:#010b}", flag_reg_val);
         let zero_flag_val: u8 = (flag_reg_val & 0b1000_0000) >> 7;
-        debug!("Retrieved zero flag: {}", zero_flag_val);
+        cpu_debug!("Retrieved zero flag: {}", zero_flag_val);
         zero_flag_val
     }
 
@@ -166,14 +347,9 @@ impl Cpu {
             https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
     */
 
-    fn invalid_opcode(&self, opcode: u8) -> Insn {
-        error!("Invalid opcode! {:#02x}", opcode);
-        Insn::invalid()
-    }
-
     // cc[index]
     fn cc(&self, index: u8) -> bool {
-        debug!("Condition table index={}", index);
+        cpu_debug!("Condition table index={}", index);
         let condition: bool = match index {
             0 => self.read_zero_flag() == 0,  // NZ
             1 => self.read_zero_flag() == 1,  // Z
@@ -206,70 +382,88 @@ impl Cpu {
     */
 
     // Loads a 16-bit value into a register
-    fn ld_d16_rp(&mut self, index: u8) -> Insn {
+    fn ld_d16_rp<B: Bus>(&mut self, bus: &mut B, index: u8) -> Insn {
         let insn = Insn {
             size: 3,
             cycles: 12,
             ..Default::default()
         };
 
-        let pc = self.read_pc() as usize; // points to the opcode
-        let mut imm16: u16 = self.rom[pc + 1] as u16;
-        imm16 <<= 8;
-        imm16 |= self.rom[pc + 2] as u16;
+        let imm16 = self.read_d16(bus);
 
         let reg_index: RegIndex = self.rp(index);
         self.regs[reg_index].write(imm16);
 
-        debug!("LD {:?}, {:#02x}", reg_index, imm16);
-        self.cycle += insn.cycles;
+        cpu_debug!("LD {:?}, {:#02x}", reg_index, imm16);
+        self.cycle = self.cycle.wrapping_add(insn.cycles);
         insn
     }
 
-    /// Load a 16-bit value into the stack pointer
-    fn ld_d16_sp(&mut self) -> Insn {
-        // 3 is the index into rp that corresponds to the SP register
-        self.ld_d16_rp(3)
+    /// The 16-bit value after the opcode, low byte first
+    fn read_d16<B: Bus>(&self, bus: &mut B) -> u16 {
+        let pc = self.read_pc(); // points to the opcode
+        u16::from_le_bytes([bus.read(pc.wrapping_add(1)), bus.read(pc.wrapping_add(2))])
+    }
+
+    /// Store the stack pointer at a 16-bit address, low byte first
+    fn ld_a16_sp<B: Bus>(&mut self, bus: &mut B) -> Insn {
+        let insn = Insn {
+            size: 3,
+            cycles: 20,
+            ..Default::default()
+        };
+
+        let address = self.read_d16(bus);
+        let [low, high] = self.regs[RegIndex::SP].read().to_le_bytes();
+        bus.write(address, low);
+        bus.write(address.wrapping_add(1), high);
+
+        cpu_debug!("LD ({:#06x}), SP", address);
+        self.cycle = self.cycle.wrapping_add(insn.cycles);
+        insn
     }
 
     /// Jump using an 8-bit offset
-    fn jr_d8(&mut self) -> Insn {
+    fn jr_d8<B: Bus>(&mut self, bus: &mut B) -> Insn {
         let insn = Insn {
             size: 2,
             cycles: 12,
+            jump: true,
             ..Default::default()
         };
 
-        let pc = self.read_pc() as usize; // points to the opcode
-        debug!(
+        let pc = self.read_pc(); // points to the opcode
+        let displacement_byte = bus.read(pc.wrapping_add(1));
+        cpu_debug!(
             "displacement as u8: {:#02x} = {}",
-            self.rom[pc + 1],
-            self.rom[pc + 1]
+            displacement_byte,
+            displacement_byte
         );
-        let displacement: i8 = self.rom[pc + 1] as i8;
-        debug!(
+        let displacement: i8 = displacement_byte as i8;
+        cpu_debug!(
             "displacement as i8: {:#02x} = {}",
-            displacement, displacement
+            displacement,
+            displacement
         );
 
-        let mut new_pc = pc as u16;
-        debug!("pc={}, new_pc={}", pc, new_pc);
-        if displacement < 0 {
-            new_pc -= displacement.abs() as u16;
-        } else {
-            new_pc += displacement.abs() as u16;
-        }
+        // From the end of the instruction, wrapping around the address space
+        // like the hardware
+        let new_pc = pc
+            .wrapping_add(insn.size)
+            .wrapping_add(displacement as i16 as u16);
+        cpu_debug!("pc={}, new_pc={}", pc, new_pc);
         self.regs[RegIndex::PC].write(new_pc);
 
-        self.cycle += insn.cycles;
+        self.cycle = self.cycle.wrapping_add(insn.cycles);
         insn
     }
 
-    /// Conditional jump using an 8-bit offset
-    fn jr_d8_cond(&mut self, y: u8) -> Insn {
+    /// Conditional jump using an 8-bit offset. Not taken, it's 4 cycles
+    /// shorter and goes on to the next instruction.
+    fn jr_d8_cond<B: Bus>(&mut self, bus: &mut B, y: u8) -> Insn {
         let insn = Insn {
             size: 2,
-            cycles: 12,
+            cycles: 8,
             ..Default::default()
         };
 
@@ -279,9 +473,9 @@ impl Cpu {
         }
 
         if self.cc(y - 4) {
-            return self.jr_d8();
+            return self.jr_d8(bus);
         }
-        info!("Jump condition not satisfied.");
+        cpu_debug!("Jump condition not satisfied.");
 
         insn
     }
@@ -297,21 +491,25 @@ impl Cpu {
                 FlagEffect::Result,
                 FlagEffect::Result,
             ],
+            ..Default::default()
         };
 
         let reg_val = self.regs[self.rp(p)].read();
         let carry_state = self.regs[RegIndex::HL].increment(reg_val);
 
-        // Set the condition flags
+        // Set the condition flags, clearing the ones the addition didn't set
         let af_reg = &mut self.regs[RegIndex::AF];
         af_reg.clear_bit_lower(FlagRegister::Subtract as u8);
-        if carry_state.half_carry {
-            debug!("Setting the half-carry flag.");
-            af_reg.set_bit_lower(FlagRegister::HalfCarry as u8);
-        }
-        if carry_state.carry {
-            debug!("Setting the carry flag.");
-            af_reg.set_bit_lower(FlagRegister::Carry as u8);
+        for (flag, set) in [
+            (FlagRegister::HalfCarry, carry_state.half_carry),
+            (FlagRegister::Carry, carry_state.carry),
+        ] {
+            cpu_debug!("{:?} flag: {}", flag, set);
+            if set {
+                af_reg.set_bit_lower(flag as u8);
+            } else {
+                af_reg.clear_bit_lower(flag as u8);
+            }
         }
 
         insn
@@ -319,7 +517,7 @@ impl Cpu {
 
     // Perform a load or store using register A
     // If is_store is true, perform a store operation. Otherwise, perform a load
-    fn a_mem_op(&mut self, p: u8, is_store: bool) -> Insn {
+    fn a_mem_op<B: Bus>(&mut self, bus: &mut B, p: u8, is_store: bool) -> Insn {
         let address_reg: RegIndex = match p {
             0 => RegIndex::BC,
             1 => RegIndex::DE,
@@ -331,22 +529,22 @@ impl Cpu {
             }
         };
 
-        let address: usize = self.regs[address_reg].read() as usize;
+        let address: u16 = self.regs[address_reg].read();
         if is_store {
             let a_val: u8 = self.regs[RegIndex::AF].read_upper();
-            self.memory[address] = a_val;
+            bus.write(address, a_val);
         } else {
             // is a load instruction
-            let val: u8 = self.memory[address];
+            let val: u8 = bus.read(address);
             self.regs[RegIndex::AF].write_upper(val);
         }
 
         // HL has special post-operation
         if p == 2 {
-            debug!("a_mem_op, is_store={}, post-increment HL", is_store);
+            cpu_debug!("a_mem_op, is_store={}, post-increment HL", is_store);
             self.regs[RegIndex::HL].increment(1);
         } else if p == 3 {
-            debug!("a_mem_op, is_store={}, post-decrement HL", is_store);
+            cpu_debug!("a_mem_op, is_store={}, post-decrement HL", is_store);
             self.regs[RegIndex::HL].decrement(1);
         }
 
@@ -358,90 +556,113 @@ impl Cpu {
     }
 
     // Store the value in register A into the address
-    fn store_a(&mut self, p: u8) -> Insn {
-        self.a_mem_op(p, true)
+    fn store_a<B: Bus>(&mut self, bus: &mut B, p: u8) -> Insn {
+        self.a_mem_op(bus, p, true)
     }
 
     // Load the value at address held in register into register A
-    fn load_a(&mut self, p: u8) -> Insn {
-        self.a_mem_op(p, false)
-    }
-
-    /// Decodes then executes the instruction pointed to by the program_counter
-    // Fields in the GameBoy manual label fields as single characters
-    #[allow(clippy::many_single_char_names)]
-    fn execute(&mut self) {
-        // Decode the opcode byte by reading the subfields according to:
-        // https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
-        let opcode_byte: u8 = self.rom[self.regs[RegIndex::PC].read() as usize];
-        debug!("program_counter: {}", self.regs[RegIndex::PC].read());
-        debug!("Opcode {:b}", opcode_byte);
-
-        let x: u8 = (opcode_byte & 0b1100_0000) >> 6;
-        let y: u8 = (opcode_byte & 0b0011_1000) >> 3;
-        let z: u8 = opcode_byte & 0b0000_0011;
-        let p: u8 = (y & 0b110) >> 1;
-        let q: u8 = y & 0b001;
-
-        // Unprefixed opcodes
-        let mut is_jump = false;
-        let insn: Insn = match x {
-            0 => {
-                match z {
-                    0 => match y {
-                        0 => Insn::nop(),      // NOP
-                        1 => self.ld_d16_sp(), // Load immediate into SP
-                        2 => {
-                            // STOP
-                            unimplemented!("STOP not implemented!");
-                        }
-                        3 => {
-                            // Jump
-                            is_jump = true;
-                            self.jr_d8()
-                        }
-                        4..=7 => {
-                            is_jump = true;
-                            self.jr_d8_cond(y)
-                        } // Conditional jump
-                        _ => self.invalid_opcode(opcode_byte),
-                    },
-                    1 => match q {
-                        0 => self.ld_d16_rp(p),
-                        1 => self.add_hl_rp(p),
-                        _ => self.invalid_opcode(opcode_byte),
-                    },
-                    2 => match q {
-                        0 => self.store_a(p),
-                        1 => self.load_a(p),
-                        _ => self.invalid_opcode(opcode_byte),
-                    },
-                    _ => unimplemented!("Not implemented this case of z!"),
-                }
+    fn load_a<B: Bus>(&mut self, bus: &mut B, p: u8) -> Insn {
+        self.a_mem_op(bus, p, false)
+    }
+
+    /// Reads the opcode at `pc` with `read`, and the one after it for the
+    /// CB prefix, and finds its handler
+    fn decode<B: Bus>(
+        pc: u16,
+        mut read: impl FnMut(u16) -> u8,
+    ) -> Result<(Handler<B>, Opcode), CpuError> {
+        let opcode: u8 = read(pc);
+        cpu_debug!("program_counter: {}", pc);
+        cpu_debug!("Opcode {:b}", opcode);
+
+        match opcode {
+            PREFIX_CB => {
+                let opcode = read(pc.wrapping_add(1));
+                Handlers::CB_OPCODES[opcode as usize]
+                    .map(|handler| (handler, Opcode::new(opcode)))
+                    .ok_or(CpuError::UnimplementedCb { pc, opcode })
             }
-            _ => unimplemented!("Not implemented this case of x!"),
-        };
+            _ if ILLEGAL_OPCODES.contains(&opcode) => Err(CpuError::Illegal { pc, opcode }),
+            _ => Handlers::OPCODES[opcode as usize]
+                .map(|handler| (handler, Opcode::new(opcode)))
+                .ok_or(CpuError::Unimplemented { pc, opcode }),
+        }
+    }
+
+    /// Decodes then executes the instruction pointed to by the program_counter.
+    /// Returns how many T-cycles it took.
+    fn execute<B: Bus>(&mut self, bus: &mut B) -> Result<u32, CpuError> {
+        let pc = self.regs[RegIndex::PC].read();
+        bus.set_pc(pc);
+        let (handler, opcode) = Cpu::decode(pc, |address| bus.read(address))?;
+        let flags = self.regs[RegIndex::AF].read_lower();
+        let insn = handler(self, bus, opcode);
+        if cfg!(debug_assertions) {
+            self.check_flags(&insn, flags);
+        }
 
-        // Increment the program counter
-        if !is_jump {
-            self.increment_reg(RegIndex::PC, insn.size);
+        // Increment the program counter, wrapping around like the hardware
+        if !insn.jump {
+            self.regs[RegIndex::PC].write(pc.wrapping_add(insn.size));
         }
+
+        Ok(insn.cycles as u32)
     }
 
-    pub fn start(&mut self, subcommand: Subcommand) {
-        info!("Subcommand: {:?}", subcommand);
+    /// Check that the instruction left the flags, which were `before`, as
+    /// its Insn says it would
+    fn check_flags(&self, insn: &Insn, before: u8) {
+        let after = self.regs[RegIndex::AF].read_lower();
+        let flags = [
+            FlagRegister::Zero,
+            FlagRegister::Subtract,
+            FlagRegister::HalfCarry,
+            FlagRegister::Carry,
+        ];
+        for (effect, flag) in insn.flags.iter().zip(flags) {
+            let bit = |value: u8| (value >> flag as u8) & 1;
+            match effect {
+                FlagEffect::None => assert_eq!(bit(after), bit(before), "{:?} changed", flag),
+                FlagEffect::Reset => assert_eq!(bit(after), 0, "{:?} wasn't reset", flag),
+                FlagEffect::Result => {}
+            }
+        }
+    }
+
+    /// Why the next instruction can't run, if it can't. Its bytes are read
+    /// with `peek`, so that looking doesn't have the side effects of running.
+    pub fn check_next(&self, peek: impl FnMut(u16) -> u8) -> Result<(), CpuError> {
+        Cpu::decode::<FlatRam>(self.read_pc(), peek).map(|_| ())
+    }
+
+    /// Execute the next instruction, returning how many T-cycles it took, or
+    /// why it couldn't, with nothing changed
+    pub fn try_step<B: Bus>(&mut self, bus: &mut B) -> Result<u32, CpuError> {
+        self.execute(bus)
+    }
 
-        info!("Running execute()");
-        self.execute();
-        debug!("{}", self);
+    /// Execute the next instruction, returning how many T-cycles it took.
+    /// Panics at an instruction the CPU can't run; see try_step().
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> u32 {
+        self.try_step(bus).unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
+save_state_fields!(Cpu { cycle, regs });
+
 #[cfg(test)]
 mod tests {
     use super::*; // use the same imports as outer scope
+    use crate::cartridge::Cartridge;
+    use crate::mmu::Mmu;
+    use log::debug;
     use test_case::test_case; // parameterized tests
 
+    /// A Cpu and the memory it runs a ROM from
+    fn cpu_with_rom(rom: Vec<u8>) -> (Cpu, Mmu) {
+        (Cpu::new(), Mmu::new(Cartridge::new(rom)))
+    }
+
     // Used until cpu.read_sp() is actually used somewhere
     // outside the test environment...
     fn read_sp(cpu: &Cpu) -> u16 {
@@ -470,36 +691,138 @@ mod tests {
         test ROM reads at arbitrary PC values
     */
 
+    #[test]
+    fn test_skip_boot() {
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(false);
+        assert_eq!(
+            cpu.registers().to_string(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100"
+        );
+        cpu.skip_boot(true);
+        assert_eq!(cpu.registers().a, 0x11);
+    }
+
+    #[test_case(0x00, true; "nop")]
+    #[test_case(0x3A, true; "ld a hl-")]
+    #[test_case(0x10, false; "stop")]
+    #[test_case(0x3E, false; "ld a d8")]
+    #[test_case(0xCB, false; "prefix")]
+    fn test_is_implemented(opcode: u8, expected: bool) {
+        assert_eq!(is_implemented(opcode), expected);
+    }
+
+    #[test]
+    fn test_implemented_count() {
+        // The x = 0 block up to z = 2, but for STOP
+        assert_eq!(implemented_count(), (23, 0));
+        assert!(!cb_is_implemented(0x7C));
+    }
+
+    #[test_case(&[0x3E, 0x01], CpuError::Unimplemented { pc: 0, opcode: 0x3E }; "unimplemented")]
+    #[test_case(&[0xCB, 0x7C], CpuError::UnimplementedCb { pc: 0, opcode: 0x7C }; "unimplemented cb")]
+    #[test_case(&[0xD3], CpuError::Illegal { pc: 0, opcode: 0xD3 }; "illegal")]
+    fn test_try_step_error(rom: &[u8], expected: CpuError) {
+        let (mut cpu, mut mmu) = cpu_with_rom(rom.to_vec());
+        let before = cpu.registers();
+        assert_eq!(cpu.try_step(&mut mmu), Err(expected));
+        assert_eq!(cpu.registers(), before);
+    }
+
+    #[test]
+    fn test_check_next() {
+        let (cpu, mut mmu) = cpu_with_rom(vec![0x00, 0xD3]);
+        assert_eq!(cpu.check_next(|address| mmu.peek(address)), Ok(()));
+        let mut cpu = cpu;
+        cpu.regs[RegIndex::PC].write(1);
+        assert_eq!(
+            cpu.check_next(|address| mmu.peek(address)),
+            Err(CpuError::Illegal {
+                pc: 1,
+                opcode: 0xD3
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Carry wasn't reset")]
+    fn test_check_flags() {
+        let mut cpu = Cpu::new();
+        cpu.regs[RegIndex::AF].write_lower(0b0001_0000);
+        let insn = Insn {
+            flags: [
+                FlagEffect::None,
+                FlagEffect::None,
+                FlagEffect::None,
+                FlagEffect::Reset,
+            ],
+            ..Insn::nop()
+        };
+        cpu.check_flags(&insn, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Opcode 0x3e at 0x0000 is not implemented")]
+    fn test_step_panics() {
+        let (mut cpu, mut mmu) = cpu_with_rom(vec![0x3E, 0x01]);
+        cpu.step(&mut mmu);
+    }
+
+    #[test]
+    fn test_cycle_wraps() {
+        let (mut cpu, mut mmu) = cpu_with_rom(vec![0x18, 0x00]);
+        cpu.cycle = u16::MAX;
+        cpu.execute(&mut mmu).unwrap();
+        assert_eq!(cpu.cycle, 11);
+    }
+
+    #[test]
+    fn test_jr_wraps() {
+        let (mut cpu, mut mmu) = cpu_with_rom(vec![0x18, 0x80]);
+        cpu.execute(&mut mmu).unwrap();
+        assert_eq!(cpu.read_pc(), 0xFF82);
+    }
+
+    #[test]
+    fn test_pc_wraps() {
+        let (mut cpu, mut mmu) = cpu_with_rom(vec![0; 0x8000]);
+        mmu.write(0xFFFF, 0x00);
+        cpu.regs[RegIndex::PC].write(0xFFFF);
+        assert_eq!(cpu.try_step(&mut mmu), Ok(4));
+        assert_eq!(cpu.read_pc(), 0x0000);
+    }
+
     #[test]
     fn test_nop() {
         // 0x00 = Opcode
         let rom: Vec<u8> = vec![0x00, 0xFF, 0xFF, 0x00, 0xFF];
 
-        let mut cpu = Cpu::new_from_vec(rom);
+        let (mut cpu, mut mmu) = cpu_with_rom(rom);
         let start_pc = 3;
         cpu.regs[RegIndex::PC].write(start_pc);
-        cpu.execute();
+        cpu.execute(&mut mmu).unwrap();
 
         assert_eq!(cpu.read_pc(), start_pc + 1); // size of instruction
         check_scratch_regs_are_zero(&cpu);
     }
 
-    // This opcode specifically loads into SP always.
-    // test_ld_d16_rp on the other hand, can load into any register
     #[test]
-    fn test_ld_d16_sp() {
+    fn test_ld_a16_sp() {
         let rom: Vec<u8> = vec![
             0xFF, 0xFF, 0x08, // 0x08 = Opcode
-            0xFF, // First byte of 16-bit data
-            0xA7, // Second byte of 16-bit data
+            0x00, // Low byte of the address
+            0xC1, // High byte of the address
             0xFF, 0xFF,
         ];
-        let mut cpu = Cpu::new_from_vec(rom);
+        let (mut cpu, mut mmu) = cpu_with_rom(rom);
         let start_pc = 2;
         cpu.regs[RegIndex::PC].write(start_pc);
-        cpu.execute();
+        cpu.regs[RegIndex::SP].write(0xFFA7);
+        assert_eq!(cpu.execute(&mut mmu), Ok(20));
 
         assert_eq!(cpu.read_pc(), start_pc + 3); // size of instruction
+        assert_eq!(mmu.read(0xC100), 0xA7);
+        assert_eq!(mmu.read(0xC101), 0xFF);
         assert_eq!(read_sp(&cpu), 0xFFA7);
         check_scratch_regs_are_zero(&cpu);
     }
@@ -509,30 +832,31 @@ mod tests {
         // Opcode = 0x18
         let rom: Vec<u8> = vec![0xFF, 0x18, 0x05, 0xFF, 0xFF, 0xFF, 0xFF];
 
-        let mut cpu = Cpu::new_from_vec(rom);
+        let (mut cpu, mut mmu) = cpu_with_rom(rom);
         let start_pc = 1;
         cpu.regs[RegIndex::PC].write(start_pc);
-        cpu.execute();
+        cpu.execute(&mut mmu).unwrap();
 
-        assert_eq!(cpu.read_pc(), start_pc + 0x05);
+        // From the end of the 2 byte instruction
+        assert_eq!(cpu.read_pc(), start_pc + 2 + 0x05);
         check_scratch_regs_are_zero(&cpu);
     }
 
     // Test jump with a negative offset
-    #[test_env_log::test]
+    #[test_log::test]
     fn test_jr_d8_negative() {
         // Opcode = 0x18
         // 0xFC= -4
         // Signed integers, 2s complement
-        let rom: Vec<u8> = vec![0xFF, 0x18, 0x05, 0xFF, 0xFF, 0x18, 0xFC];
+        let rom: Vec<u8> = vec![0xFF, 0x18, 0x05, 0xFF, 0xFF, 0x18, 0xFC, 0xFF];
 
-        let mut cpu = Cpu::new_from_vec(rom);
+        let (mut cpu, mut mmu) = cpu_with_rom(rom);
         let start_pc = 5;
         cpu.regs[RegIndex::PC].write(start_pc);
         debug!("pc: {}", cpu.read_pc());
-        cpu.execute();
+        cpu.execute(&mut mmu).unwrap();
 
-        assert_eq!(cpu.read_pc(), start_pc - 0x04);
+        assert_eq!(cpu.read_pc(), start_pc + 2 - 0x04);
         check_scratch_regs_are_zero(&cpu);
         assert_eq!(read_flag_reg(&cpu), 0);
     }
@@ -545,27 +869,35 @@ mod tests {
         0x38: Jump if the carry flag is set
     */
 
-    #[test_case(0x020, 0b0111_1111, 5, 1; "nz jump")] // zero flag is bit 7
-    #[test_case(0x020, 0b1000_0000, 5, 5; "no nz jump")]
-    #[test_case(0x028, 0b1000_0000, 5, 1; "z jump")]
-    #[test_case(0x028, 0b0111_1111, 5, 5; "no z jump")]
-    #[test_case(0x030, 0b1110_1111, 5, 1; "nc jump")] // carry flag is bit 4
-    #[test_case(0x030, 0b0001_0000, 5, 5; "no nc jump")]
-    #[test_case(0x038, 0b0001_0000, 5, 1; "c jump")]
-    #[test_case(0x038, 0b1110_1111, 5, 5; "no c jump")]
-    fn test_jr_d8_cond(opcode: u8, flag_reg_val: u8, start_pc: u16, expected_pc: u16) {
+    // Taken, the jump is 4 back from the end of the instruction at 7, and
+    // not taken, it goes on to 7 in 8 cycles rather than 12
+    #[test_case(0x020, 0b0111_1111, 5, 3, 12; "nz jump")] // zero flag is bit 7
+    #[test_case(0x020, 0b1000_0000, 5, 7, 8; "no nz jump")]
+    #[test_case(0x028, 0b1000_0000, 5, 3, 12; "z jump")]
+    #[test_case(0x028, 0b0111_1111, 5, 7, 8; "no z jump")]
+    #[test_case(0x030, 0b1110_1111, 5, 3, 12; "nc jump")] // carry flag is bit 4
+    #[test_case(0x030, 0b0001_0000, 5, 7, 8; "no nc jump")]
+    #[test_case(0x038, 0b0001_0000, 5, 3, 12; "c jump")]
+    #[test_case(0x038, 0b1110_1111, 5, 7, 8; "no c jump")]
+    fn test_jr_d8_cond(
+        opcode: u8,
+        flag_reg_val: u8,
+        start_pc: u16,
+        expected_pc: u16,
+        expected_cycles: u32,
+    ) {
         // The flag and condition to expect is written in the opcode
         // 0xFC= -4 ; signed integers, 2s complement
         let mut rom: Vec<u8> = vec![0xFF, 0x18, 0x05, 0xFF, 0xFF, 0x00, 0xFC];
         rom[start_pc as usize] = opcode; // Cpu will read the instruction from here
-        let mut cpu = Cpu::new_from_vec(rom);
-        cpu.regs[RegIndex::PC].write(start_pc as u16);
+        let (mut cpu, mut mmu) = cpu_with_rom(rom);
+        cpu.regs[RegIndex::PC].write(start_pc);
         debug!("pc: {}", cpu.read_pc());
 
         // Set the condition flag values
         cpu.regs[RegIndex::AF].write_lower(flag_reg_val);
         debug!("flag reg: {:#010b}", cpu.regs[RegIndex::AF].read_lower());
-        cpu.execute();
+        assert_eq!(cpu.execute(&mut mmu), Ok(expected_cycles));
 
         // Check if the jump occurred or not, based on the condition
         assert_eq!(cpu.read_pc(), expected_pc);
@@ -579,19 +911,19 @@ mod tests {
     #[test_case(0x31, RegIndex::SP; "stack pointer")]
     fn test_ld_d16_rp(opcode: u8, reg: RegIndex) {
         let mut rom: Vec<u8> = vec![
-            0xFF, 0xFF, 0x00, 0x41, // First byte of 16-bit data
-            0x23, // Second byte of 16-bit data
+            0xFF, 0xFF, 0x00, 0x41, // Low byte of 16-bit data
+            0x23, // High byte of 16-bit data
             0xFF, 0xFF,
         ];
         rom[2] = opcode;
 
-        let mut cpu = Cpu::new_from_vec(rom);
+        let (mut cpu, mut mmu) = cpu_with_rom(rom);
         let start_pc = 2;
         cpu.regs[RegIndex::PC].write(start_pc);
-        cpu.execute();
+        cpu.execute(&mut mmu).unwrap();
 
         assert_eq!(cpu.read_pc(), start_pc + 3); // size of instruction
-        assert_eq!(cpu.regs[reg].read(), 0x4123);
+        assert_eq!(cpu.regs[reg].read(), 0x2341); // low byte first
 
         // Check that other registers were not modified
         let regs_to_check = [RegIndex::AF, RegIndex::BC, RegIndex::DE, RegIndex::HL];
@@ -627,22 +959,22 @@ mod tests {
                           // All the bytes except at start_pc are arbitrary and not used
         let mut rom: Vec<u8> = vec![0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00];
         rom[start_pc as usize] = opcode; // Cpu will read the instruction from here
-        let mut cpu = Cpu::new_from_vec(rom);
+        let (mut cpu, mut mmu) = cpu_with_rom(rom);
         // Set up register values
-        cpu.regs[RegIndex::PC].write(start_pc as u16);
+        cpu.regs[RegIndex::PC].write(start_pc);
         cpu.regs[RegIndex::HL].write(hl_val);
         cpu.regs[reg_op].write(reg_op_val);
         debug!("pc: {}", cpu.read_pc());
 
-        cpu.execute();
+        cpu.execute(&mut mmu).unwrap();
 
         let overflow_check = hl_val.checked_add(reg_op_val);
         if reg_op == RegIndex::HL {
-            if overflow_check != None {
+            if overflow_check.is_some() {
                 assert_eq!(cpu.regs[RegIndex::HL].read(), reg_op_val + reg_op_val);
             }
         } else {
-            if overflow_check != None {
+            if overflow_check.is_some() {
                 assert_eq!(cpu.regs[RegIndex::HL].read(), hl_val + reg_op_val);
             }
             assert_eq!(cpu.regs[reg_op].read(), reg_op_val); // check that it is unchanged
@@ -650,6 +982,22 @@ mod tests {
         assert_eq!(cpu.regs[RegIndex::AF].read_lower(), expected_flag_reg_val); // check that it is unchanged
     }
 
+    // Zero is left alone, and subtract, half carry and carry set by an
+    // earlier instruction are cleared unless this addition sets them
+    #[test_case(151, 75, 0b1000_0000; "no carry")]
+    #[test_case(4095, 10, 0b1010_0000; "half carry")]
+    #[test_case(0x8000, 0x8000, 0b1001_0000; "carry")]
+    fn test_add_hl_rp_clears_flags(hl_val: u16, bc_val: u16, expected_flag_reg_val: u8) {
+        let (mut cpu, mut mmu) = cpu_with_rom(vec![0x09]);
+        cpu.regs[RegIndex::AF].write(0x00F0);
+        cpu.regs[RegIndex::HL].write(hl_val);
+        cpu.regs[RegIndex::BC].write(bc_val);
+        cpu.execute(&mut mmu).unwrap();
+
+        assert_eq!(cpu.regs[RegIndex::HL].read(), hl_val.wrapping_add(bc_val));
+        assert_eq!(read_flag_reg(&cpu), expected_flag_reg_val);
+    }
+
     #[test_case(0x02, RegIndex::BC, 0xC000, 209; "store a at address bc")]
     #[test_case(0x12, RegIndex::DE, 0xC000, 209; "store a at address de")]
     #[test_case(0x22, RegIndex::HL, 0xC000, 209; "store a at address hl increment")]
@@ -658,7 +1006,7 @@ mod tests {
         let start_pc = 2;
         let mut rom: Vec<u8> = vec![0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00];
         rom[start_pc as usize] = opcode; // Cpu will read the instruction from here
-        let mut cpu = Cpu::new_from_vec(rom);
+        let (mut cpu, mut mmu) = cpu_with_rom(rom);
 
         // Setup the register that will hold the memory address
         cpu.regs[address_reg].write(address);
@@ -666,18 +1014,18 @@ mod tests {
         // Setup the value to be written to memory
         cpu.regs[RegIndex::AF].write_upper(a_val);
         // Set PC
-        cpu.regs[RegIndex::PC].write(start_pc as u16);
+        cpu.regs[RegIndex::PC].write(start_pc);
 
         // Perform the store operation
-        cpu.execute();
+        cpu.execute(&mut mmu).unwrap();
         assert_eq!(cpu.regs[RegIndex::PC].read(), start_pc + 1); // insn size
 
-        assert_eq!(cpu.memory[address as usize], a_val);
+        assert_eq!(mmu.read(address), a_val);
         // Check if post-operation occurred for HL register
         if address_reg == RegIndex::HL {
             if opcode == 0x22 {
                 assert_eq!(cpu.regs[RegIndex::HL].read(), prev_hl_val + 1);
-            } else if opcode == 032 {
+            } else if opcode == 0x32 {
                 assert_eq!(cpu.regs[RegIndex::HL].read(), prev_hl_val - 1);
             }
         }
@@ -691,19 +1039,19 @@ mod tests {
         let start_pc = 2;
         let mut rom: Vec<u8> = vec![0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00];
         rom[start_pc as usize] = opcode; // Cpu will read the instruction from here
-        let mut cpu = Cpu::new_from_vec(rom);
+        let (mut cpu, mut mmu) = cpu_with_rom(rom);
 
         // Setup the register that will hold the memory address
         cpu.regs[address_reg].write(address);
         let prev_hl_val: u16 = cpu.regs[RegIndex::HL].read();
         // Setup the value to be loaded from memory
-        cpu.memory[address as usize] = val;
+        mmu.write(address, val);
         // Set PC
-        cpu.regs[RegIndex::PC].write(start_pc as u16);
+        cpu.regs[RegIndex::PC].write(start_pc);
 
         // Perform the store operation
         assert_ne!(cpu.regs[RegIndex::AF].read_upper(), val); // Ensure clean state beforehand
-        cpu.execute();
+        cpu.execute(&mut mmu).unwrap();
         assert_eq!(cpu.regs[RegIndex::PC].read(), start_pc + 1); // insn size
         assert_eq!(cpu.regs[RegIndex::AF].read_upper(), val);
 
@@ -711,7 +1059,7 @@ mod tests {
         if address_reg == RegIndex::HL {
             if opcode == 0x22 {
                 assert_eq!(cpu.regs[RegIndex::HL].read(), prev_hl_val + 1);
-            } else if opcode == 032 {
+            } else if opcode == 0x32 {
                 assert_eq!(cpu.regs[RegIndex::HL].read(), prev_hl_val - 1);
             }
         }
@@ -5,15 +5,11 @@ pub struct Insn {
     pub size: u16,              // length in bytes
     pub cycles: u16,            // duration in cycles
     pub flags: [FlagEffect; 4], // bit string: Z N H C
+    /// Whether it set PC itself, rather than PC moving on by its size
+    pub jump: bool,
 }
 
 impl Insn {
-    pub fn invalid() -> Insn {
-        Insn {
-            ..Default::default()
-        }
-    }
-
     pub fn nop() -> Insn {
         Insn {
             size: 1,
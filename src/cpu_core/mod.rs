@@ -1,5 +1,17 @@
+/// Log at debug level what the CPU does in an instruction. It's formatting
+/// in the hottest loop, so it's only built with the `cpu-log` feature.
+macro_rules! cpu_debug {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "cpu-log") {
+            log::debug!($($arg)*);
+        }
+    };
+}
+
 mod flag_register;
 mod insn;
-mod register;
 
+pub mod bus;
 pub mod cpu;
+pub mod opcode;
+pub mod register;
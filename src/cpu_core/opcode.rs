@@ -0,0 +1,64 @@
+// The fields an opcode is split into to decode it, and the registers,
+// conditions and operations they pick, shared by the CPU and the
+// disassembler. The layout follows
+// https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
+//
+//   7 6 5 4 3 2 1 0
+//   x   y     z
+//       p   q
+
+/// The opcode that makes the next byte pick from the CB table
+pub const PREFIX_CB: u8 = 0xCB;
+
+/// Registers by their index in an opcode, with (HL) at 6
+pub const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+/// Register pairs, with SP for loads and arithmetic
+pub const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+/// Register pairs, with AF for the stack
+pub const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+/// Conditions
+pub const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+/// Arithmetic and logic on A
+pub const ALU: [&str; 8] = [
+    "ADD A,", "ADC A,", "SUB", "SBC A,", "AND", "XOR", "OR", "CP",
+];
+/// Rotates and shifts, the CB opcodes with x=0
+pub const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+/// The bit operations, the CB opcodes with x=1 to 3
+pub const BIT_OPS: [&str; 3] = ["BIT", "RES", "SET"];
+
+/// An opcode split into its fields
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Opcode {
+    pub x: u8,
+    pub y: u8,
+    pub z: u8,
+    pub p: u8,
+    pub q: u8,
+}
+
+impl Opcode {
+    pub const fn new(byte: u8) -> Opcode {
+        let y = (byte & 0b0011_1000) >> 3;
+        Opcode {
+            x: (byte & 0b1100_0000) >> 6,
+            y,
+            z: byte & 0b0000_0111,
+            p: (y & 0b110) >> 1,
+            q: y & 0b001,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(0x00, Opcode { x: 0, y: 0, z: 0, p: 0, q: 0 }; "nop")]
+    #[test_case(0x2A, Opcode { x: 0, y: 5, z: 2, p: 2, q: 1 }; "ld a hl+")]
+    #[test_case(0xFF, Opcode { x: 3, y: 7, z: 7, p: 3, q: 1 }; "rst 38")]
+    fn test_new(byte: u8, expected: Opcode) {
+        assert_eq!(Opcode::new(byte), expected);
+    }
+}
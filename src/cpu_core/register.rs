@@ -1,4 +1,6 @@
-use log::{debug, warn};
+use log::warn;
+
+use crate::savestate::save_state_fields;
 
 /// The return value of a arithmetic operatiomn
 /// which indicates whether a carry or a half-carry occurred
@@ -14,7 +16,7 @@ pub struct CarryState {
 
 fn _read_upper(value: u16) -> u8 {
     let masked = value & 0b1111_1111_0000_0000;
-    debug!("_read_upper: {}={:#b} -> {:#b}", value, value, masked);
+    cpu_debug!("_read_upper: {}={:#b} -> {:#b}", value, value, masked);
     (masked >> 8) as u8
 }
 
@@ -59,25 +61,30 @@ pub struct Register {
 }
 
 impl RegisterOperation for Register {
+    #[inline]
     fn read(&self) -> u16 {
         self.value
     }
 
     /// Return the most-significant byte in the register
+    #[inline]
     fn read_upper(&self) -> u8 {
-        debug!("read_upper {}", self.value);
+        cpu_debug!("read_upper {}", self.value);
         _read_upper(self.value)
     }
 
     /// Return the least-significant byte in the register
+    #[inline]
     fn read_lower(&self) -> u8 {
         _read_lower(self.value)
     }
 
+    #[inline]
     fn write(&mut self, value: u16) {
         self.value = value;
     }
 
+    #[inline]
     fn write_upper(&mut self, value: u8) {
         // Zero out the most-significant bits
         self.value &= 0b0000_0000_1111_1111;
@@ -85,6 +92,7 @@ impl RegisterOperation for Register {
         self.value |= (value as u16) << 8;
     }
 
+    #[inline]
     fn write_lower(&mut self, value: u8) {
         // Zero out the least-significant bits
         self.value &= 0b1111_1111_0000_0000;
@@ -97,10 +105,10 @@ impl RegisterOperation for Register {
             warn!("Bit index {} is out of range!", bit_index);
             return;
         }
-        debug!("Before set_bit: {:b}", self.value);
+        cpu_debug!("Before set_bit: {:b}", self.value);
         let mask = 1 << bit_index;
         self.value |= mask;
-        debug!("After set_bit: {:b}", self.value);
+        cpu_debug!("After set_bit: {:b}", self.value);
     }
 
     /// Set a bit in the upper register (of a 16-bit register)
@@ -129,10 +137,10 @@ impl RegisterOperation for Register {
             warn!("Bit index {} is out of range!", bit_index);
             return;
         }
-        debug!("Before clear_bit: {:b}", self.value);
+        cpu_debug!("Before clear_bit: {:b}", self.value);
         let mask = 1 << bit_index;
         self.value &= !mask;
-        debug!("After clear_bit: {:b}", self.value);
+        cpu_debug!("After clear_bit: {:b}", self.value);
     }
 
     /// Clear a bit in the upper register (of a 16-bit register)
@@ -160,25 +168,28 @@ impl RegisterOperation for Register {
         let mask: u16 = 0b0000_1111_1111_1111;
         let a_masked: u16 = self.value & mask;
         let b_masked: u16 = delta & mask;
-        debug!(
+        cpu_debug!(
             "a_masked={:#b}={}, b_masked={:#b}={}",
-            a_masked, a_masked, b_masked, b_masked
+            a_masked,
+            a_masked,
+            b_masked,
+            b_masked
         );
-        debug!("a_masked + b_masked = {:#b}", a_masked + b_masked);
+        cpu_debug!("a_masked + b_masked = {:#b}", a_masked + b_masked);
         let carry_mask: u16 = 0b0001_0000_0000_0000;
         ((a_masked + b_masked) & carry_mask) == (carry_mask)
     }
 
     // If overflow occurs, return None
     fn increment(&mut self, delta: u16) -> CarryState {
-        debug!("Calculating: {}+{}", self.value, delta);
-        debug!("Calculating: {:#b}+{:#b}", self.value, delta);
+        cpu_debug!("Calculating: {}+{}", self.value, delta);
+        cpu_debug!("Calculating: {:#b}+{:#b}", self.value, delta);
         let overflow_check = self.value.checked_add(delta);
         let is_half_carry = self.is_half_carry(delta);
         self.value = self.value.wrapping_add(delta);
         CarryState {
-            overflow: overflow_check == None,
-            carry: overflow_check == None,
+            overflow: overflow_check.is_none(),
+            carry: overflow_check.is_none(),
             half_carry: is_half_carry,
         }
     }
@@ -187,17 +198,19 @@ impl RegisterOperation for Register {
         let overflow_check = self.value.checked_sub(delta);
         self.value = self.value.wrapping_sub(delta);
         CarryState {
-            overflow: overflow_check == None,
+            overflow: overflow_check.is_none(),
             carry: false,      // might not apply to sub...
             half_carry: false, // carries can't occur on subtract?
         }
     }
 }
 
+save_state_fields!(Register { value });
+
 #[cfg(test)]
 mod tests {
     use super::*; // use the same imports as outer scope
-    use test_env_log::test;
+    use test_log::test;
 
     #[test]
     fn test_read_default() {
@@ -345,7 +358,7 @@ mod tests {
     }
 
     #[test]
-    fn clear_bit_lower(&mut self, bit_index: u8) {
+    fn clear_bit_lower() {
         let val = 0b1111_0010_0100_1111;
         let mut reg = Register { value: val };
         assert_eq!(reg.read(), val);
@@ -372,7 +385,7 @@ mod tests {
         assert!(reg.is_half_carry(delta));
 
         // Test when a half-carry does not occur (adding zero)
-        assert_eq!(reg.is_half_carry(0), false);
+        assert!(!reg.is_half_carry(0));
     }
 
     /// Test that carries that are propagated to the 12th bit
@@ -396,9 +409,9 @@ mod tests {
         let carry_state = reg.increment(delta);
 
         assert_eq!(reg.read(), val + delta);
-        assert_eq!(carry_state.overflow, false);
-        assert_eq!(carry_state.carry, false);
-        assert_eq!(carry_state.half_carry, false);
+        assert!(!carry_state.overflow);
+        assert!(!carry_state.carry);
+        assert!(!carry_state.half_carry);
     }
 
     #[test]
@@ -410,9 +423,9 @@ mod tests {
         let delta = 2000;
         let carry_state = reg.increment(delta);
 
-        assert_eq!(carry_state.overflow, true);
-        assert_eq!(carry_state.carry, true);
-        assert_eq!(carry_state.half_carry, true);
+        assert!(carry_state.overflow);
+        assert!(carry_state.carry);
+        assert!(carry_state.half_carry);
         // wrapping_add() is equivalent mod u16::max
         // Need to compute in u32 as the temporary result cannot fit in u16
         let expected: u16 = ((val as u32) + (delta as u32) % (u16::MAX as u32 + 1)) as u16;
@@ -428,9 +441,9 @@ mod tests {
         let delta = 432;
         let carry_state = reg.increment(delta);
 
-        assert_eq!(carry_state.overflow, true);
-        assert_eq!(carry_state.carry, true);
-        assert_eq!(carry_state.half_carry, true);
+        assert!(carry_state.overflow);
+        assert!(carry_state.carry);
+        assert!(carry_state.half_carry);
         let expected: u16 = ((val as u32) + (delta as u32) % (u16::MAX as u32 + 1)) as u16;
         assert_eq!(reg.read(), expected);
     }
@@ -445,9 +458,9 @@ mod tests {
         let carry_state = reg.decrement(delta);
 
         assert_eq!(reg.read(), val - delta);
-        assert_eq!(carry_state.overflow, false);
-        assert_eq!(carry_state.carry, false);
-        assert_eq!(carry_state.half_carry, false);
+        assert!(!carry_state.overflow);
+        assert!(!carry_state.carry);
+        assert!(!carry_state.half_carry);
     }
 
     #[test]
@@ -458,9 +471,9 @@ mod tests {
         let delta = 432;
         let carry_state = reg.decrement(delta);
 
-        assert_eq!(carry_state.overflow, true); // "underflow"
-        assert_eq!(carry_state.carry, false);
-        assert_eq!(carry_state.half_carry, false);
+        assert!(carry_state.overflow); // "underflow"
+        assert!(!carry_state.carry);
+        assert!(!carry_state.half_carry);
         assert_eq!(reg.read(), u16::MAX - delta + 1);
     }
 
@@ -474,9 +487,9 @@ mod tests {
         assert!(delta > val);
         let carry_state = reg.decrement(delta);
 
-        assert_eq!(carry_state.overflow, true);
-        assert_eq!(carry_state.carry, false);
-        assert_eq!(carry_state.half_carry, false);
+        assert!(carry_state.overflow);
+        assert!(!carry_state.carry);
+        assert!(!carry_state.half_carry);
         let expected = u16::MAX - (delta - val) + 1;
         assert_eq!(reg.read(), expected);
     }
@@ -0,0 +1,439 @@
+// Expressions for breakpoint conditions, like `a == 0x3C && [0xC000] != 0`,
+// and for `print`, like `[hl + 1] * 2`. Values are 16 bits and wrap.
+// Comparisons and the logical operators give 1 for true and 0 for false,
+// and anything other than 0 counts as true.
+//
+// Operands are numbers (hex with 0x, or decimal), the registers a, f, b, c,
+// d, e, h, l, af, bc, de, hl, sp and pc, the flags zf, nf, hf and cf, and
+// bytes of memory, written [address]. From loosest to tightest binding:
+//
+//   ||   &&   == != < <= > >=   |   ^   &   << >>   + -   * / %
+//
+// then the unary operators ! (not), - (negate) and ~ (complement).
+
+use crate::cpu_core::cpu::Registers;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+    ZeroFlag,
+    SubtractFlag,
+    HalfCarryFlag,
+    CarryFlag,
+}
+
+impl Operand {
+    fn from_name(name: &str) -> Option<Operand> {
+        Some(match name {
+            "a" => Operand::A,
+            "f" => Operand::F,
+            "b" => Operand::B,
+            "c" => Operand::C,
+            "d" => Operand::D,
+            "e" => Operand::E,
+            "h" => Operand::H,
+            "l" => Operand::L,
+            "af" => Operand::AF,
+            "bc" => Operand::BC,
+            "de" => Operand::DE,
+            "hl" => Operand::HL,
+            "sp" => Operand::SP,
+            "pc" => Operand::PC,
+            "zf" => Operand::ZeroFlag,
+            "nf" => Operand::SubtractFlag,
+            "hf" => Operand::HalfCarryFlag,
+            "cf" => Operand::CarryFlag,
+            _ => return None,
+        })
+    }
+
+    fn value(self, registers: &Registers) -> u16 {
+        let pair = |upper: u8, lower: u8| u16::from_be_bytes([upper, lower]);
+        let flag = |bit: u8| (registers.f >> bit & 1) as u16;
+        match self {
+            Operand::A => registers.a as u16,
+            Operand::F => registers.f as u16,
+            Operand::B => registers.b as u16,
+            Operand::C => registers.c as u16,
+            Operand::D => registers.d as u16,
+            Operand::E => registers.e as u16,
+            Operand::H => registers.h as u16,
+            Operand::L => registers.l as u16,
+            Operand::AF => pair(registers.a, registers.f),
+            Operand::BC => pair(registers.b, registers.c),
+            Operand::DE => pair(registers.d, registers.e),
+            Operand::HL => pair(registers.h, registers.l),
+            Operand::SP => registers.sp,
+            Operand::PC => registers.pc,
+            Operand::ZeroFlag => flag(7),
+            Operand::SubtractFlag => flag(6),
+            Operand::HalfCarryFlag => flag(5),
+            Operand::CarryFlag => flag(4),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl BinaryOp {
+    /// Higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::Less
+            | BinaryOp::LessOrEqual
+            | BinaryOp::Greater
+            | BinaryOp::GreaterOrEqual => 3,
+            BinaryOp::BitOr => 4,
+            BinaryOp::BitXor => 5,
+            BinaryOp::BitAnd => 6,
+            BinaryOp::ShiftLeft | BinaryOp::ShiftRight => 7,
+            BinaryOp::Add | BinaryOp::Subtract => 8,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Remainder => 9,
+        }
+    }
+
+    fn apply(self, left: u16, right: u16) -> Result<u16, String> {
+        let value = match self {
+            BinaryOp::Or => (left != 0 || right != 0) as u16,
+            BinaryOp::And => (left != 0 && right != 0) as u16,
+            BinaryOp::Equal => (left == right) as u16,
+            BinaryOp::NotEqual => (left != right) as u16,
+            BinaryOp::Less => (left < right) as u16,
+            BinaryOp::LessOrEqual => (left <= right) as u16,
+            BinaryOp::Greater => (left > right) as u16,
+            BinaryOp::GreaterOrEqual => (left >= right) as u16,
+            BinaryOp::BitOr => left | right,
+            BinaryOp::BitXor => left ^ right,
+            BinaryOp::BitAnd => left & right,
+            // Shifting all the bits out leaves 0
+            BinaryOp::ShiftLeft => left.checked_shl(right as u32).unwrap_or(0),
+            BinaryOp::ShiftRight => left.checked_shr(right as u32).unwrap_or(0),
+            BinaryOp::Add => left.wrapping_add(right),
+            BinaryOp::Subtract => left.wrapping_sub(right),
+            BinaryOp::Multiply => left.wrapping_mul(right),
+            BinaryOp::Divide | BinaryOp::Remainder if right == 0 => {
+                return Err(String::from("Division by zero"))
+            }
+            BinaryOp::Divide => left / right,
+            BinaryOp::Remainder => left % right,
+        };
+        Ok(value)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(u16),
+    Operand(Operand),
+    /// The byte at an address
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    /// Every bit flipped
+    Complement(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(u16),
+    Name(String),
+    Op(BinaryOp),
+    Not,
+    Complement,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c.is_ascii_alphanumeric() {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                word.push(c);
+                chars.next();
+            }
+            let word = word.to_lowercase();
+            let number = match word.strip_prefix("0x") {
+                Some(digits) => u16::from_str_radix(digits, 16).ok(),
+                None if c.is_ascii_digit() => word.parse().ok(),
+                None => {
+                    tokens.push(Token::Name(word));
+                    continue;
+                }
+            };
+            tokens.push(Token::Number(
+                number.ok_or_else(|| format!("{:?} isn't a 16-bit number", word))?,
+            ));
+            continue;
+        }
+
+        chars.next();
+        let mut followed_by = |next: char| chars.next_if_eq(&next).is_some();
+        let token = match c {
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            '+' => Token::Op(BinaryOp::Add),
+            '-' => Token::Op(BinaryOp::Subtract),
+            '*' => Token::Op(BinaryOp::Multiply),
+            '/' => Token::Op(BinaryOp::Divide),
+            '%' => Token::Op(BinaryOp::Remainder),
+            '^' => Token::Op(BinaryOp::BitXor),
+            '~' => Token::Complement,
+            '|' if followed_by('|') => Token::Op(BinaryOp::Or),
+            '|' => Token::Op(BinaryOp::BitOr),
+            '&' if followed_by('&') => Token::Op(BinaryOp::And),
+            '&' => Token::Op(BinaryOp::BitAnd),
+            '=' if followed_by('=') => Token::Op(BinaryOp::Equal),
+            '!' if followed_by('=') => Token::Op(BinaryOp::NotEqual),
+            '!' => Token::Not,
+            '<' if followed_by('<') => Token::Op(BinaryOp::ShiftLeft),
+            '<' if followed_by('=') => Token::Op(BinaryOp::LessOrEqual),
+            '<' => Token::Op(BinaryOp::Less),
+            '>' if followed_by('>') => Token::Op(BinaryOp::ShiftRight),
+            '>' if followed_by('=') => Token::Op(BinaryOp::GreaterOrEqual),
+            '>' => Token::Op(BinaryOp::Greater),
+            _ => return Err(format!("Unexpected {:?}", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_op(&self) -> Option<BinaryOp> {
+        match self.tokens.get(self.position) {
+            Some(Token::Op(op)) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(format!("Expected {}", what)),
+        }
+    }
+
+    /// Binary operators binding at least as tightly as `min_precedence`
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(op) = self
+            .peek_op()
+            .filter(|op| op.precedence() >= min_precedence)
+        {
+            self.position += 1;
+            let right = self.binary(op.precedence() + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Name(name)) => Operand::from_name(&name)
+                .map(Expr::Operand)
+                .ok_or_else(|| format!("Unknown register or flag {:?}", name)),
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Op(BinaryOp::Subtract)) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Complement) => Ok(Expr::Complement(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.binary(0)?;
+                self.expect(Token::Close, "a closing )")?;
+                Ok(expr)
+            }
+            Some(Token::OpenBracket) => {
+                let address = self.binary(0)?;
+                self.expect(Token::CloseBracket, "a closing ]")?;
+                Ok(Expr::Memory(Box::new(address)))
+            }
+            _ => Err(String::from(
+                "Expected a number, register, flag or [address]",
+            )),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let expr = parser.binary(0)?;
+        if parser.position < parser.tokens.len() {
+            return Err(format!("Unexpected {:?}", parser.tokens[parser.position]));
+        }
+        Ok(expr)
+    }
+
+    /// The value with these registers, reading memory with `read`.
+    /// Fails on division by zero.
+    pub fn evaluate(
+        &self,
+        registers: &Registers,
+        read: &mut dyn FnMut(u16) -> u8,
+    ) -> Result<u16, String> {
+        let value = match self {
+            Expr::Number(number) => *number,
+            Expr::Operand(operand) => operand.value(registers),
+            Expr::Memory(address) => {
+                let address = address.evaluate(registers, read)?;
+                read(address) as u16
+            }
+            Expr::Not(expr) => (expr.evaluate(registers, read)? == 0) as u16,
+            Expr::Negate(expr) => expr.evaluate(registers, read)?.wrapping_neg(),
+            Expr::Complement(expr) => !expr.evaluate(registers, read)?,
+            Expr::Binary(op, left, right) => {
+                let left = left.evaluate(registers, read)?;
+                op.apply(left, right.evaluate(registers, read)?)?
+            }
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn registers() -> Registers {
+        Registers {
+            a: 0x3C,
+            f: 0b1001_0000,
+            b: 0x01,
+            c: 0x02,
+            h: 0xC0,
+            l: 0x00,
+            pc: 0x4123,
+            ..Default::default()
+        }
+    }
+
+    /// Memory where each byte holds the low byte of its address
+    fn evaluate(s: &str) -> u16 {
+        let mut read = |address: u16| address as u8;
+        Expr::parse(s)
+            .unwrap()
+            .evaluate(&registers(), &mut read)
+            .unwrap()
+    }
+
+    #[test_case("0x3c", 0x3C; "hex")]
+    #[test_case("60", 60; "decimal")]
+    #[test_case("a", 0x3C; "register")]
+    #[test_case("BC", 0x0102; "pair")]
+    #[test_case("zf", 1; "zero flag")]
+    #[test_case("nf", 0; "subtract flag")]
+    #[test_case("cf", 1; "carry flag")]
+    #[test_case("[0xC012]", 0x12; "memory")]
+    #[test_case("[hl + 5]", 0x05; "memory at a register")]
+    #[test_case("a == 0x3C && [0xC000] != 0", 0; "example")]
+    #[test_case("a == 0x3C && [0xC001] != 0", 1; "example true")]
+    #[test_case("1 + 2 == 3", 1; "sum before comparison")]
+    #[test_case("f & 0x80 != 0", 1; "mask before comparison")]
+    #[test_case("0 || 1 && 0", 0; "and before or")]
+    #[test_case("(0 || 1) && 1", 1; "parentheses")]
+    #[test_case("!zf", 0; "not")]
+    #[test_case("0 - 1", 0xFFFF; "wraps")]
+    #[test_case("10 - 3 - 2", 5; "left to right")]
+    #[test_case("pc >= 0x4000 && pc < 0x8000", 1; "range")]
+    #[test_case("[hl+1] * 2", 0x02; "example print")]
+    #[test_case("1 + 2 * 3", 7; "product before sum")]
+    #[test_case("a / 7 % 4", 0; "quotient then remainder")]
+    #[test_case("0x8000 * 2", 0; "product wraps")]
+    #[test_case("1 << 4 + 1", 0x20; "sum before shift")]
+    #[test_case("pc >> 8", 0x41; "shift right")]
+    #[test_case("1 << 16", 0; "shift out")]
+    #[test_case("a ^ 0xFF & 0x0F", 0x33; "and before xor")]
+    #[test_case("-1", 0xFFFF; "negate")]
+    #[test_case("2 - -1", 3; "minus negative")]
+    #[test_case("~a", 0xFFC3; "complement")]
+    fn test_evaluate(s: &str, expected: u16) {
+        assert_eq!(evaluate(s), expected);
+    }
+
+    #[test_case("1 / 0"; "quotient")]
+    #[test_case("[0xC000 % 0]"; "remainder in an address")]
+    fn test_division_by_zero(s: &str) {
+        let mut read = |_| 0;
+        assert_eq!(
+            Expr::parse(s).unwrap().evaluate(&registers(), &mut read),
+            Err(String::from("Division by zero"))
+        );
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("a =="; "missing operand")]
+    #[test_case("x == 1"; "unknown register")]
+    #[test_case("[0xC000"; "unclosed bracket")]
+    #[test_case("(1"; "unclosed parenthesis")]
+    #[test_case("1 2"; "two numbers")]
+    #[test_case("a = 1"; "single equals")]
+    #[test_case("0x10000"; "too big")]
+    #[test_case("2 *"; "missing right operand")]
+    #[test_case("a <<< 1"; "triple shift")]
+    fn test_parse_invalid(s: &str) {
+        assert!(Expr::parse(s).is_err());
+    }
+}
@@ -0,0 +1,666 @@
+// A command line debugger. It reads commands from stdin and runs the
+// GameBoy an instruction at a time between them. Breakpoints stop a
+// `continue` when the CPU reaches their address, and can have a condition
+// on the registers, flags and memory, like
+//
+//   break 0x4123 if a == 0x3C && [0xC000] != 0
+//
+// To step over and out of subroutines and show backtraces, the debugger
+// has the GameBoy keep a shadow call stack. Memory is edited by writing to
+// the bus like the CPU does, so a write to an MBC or IO register has the
+// same effect as it would from the game. With a symbol file, addresses can
+// be given as labels, like `break Main`.
+
+pub mod expr;
+#[cfg(feature = "scripting")]
+pub mod rhai_script;
+pub mod script;
+#[cfg(feature = "tui")]
+pub mod tui;
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::debugger::expr::Expr;
+use crate::frontend::keymap::parse_button;
+use crate::gameboy::GameBoy;
+use crate::joypad::Button;
+use crate::mmu::trace::parse_address;
+use crate::ppu::sprites::{Sprite, SPRITE_TABLE_HEADER};
+use crate::symbols::Symbols;
+
+const HELP: &str = "\
+break <address> [if <condition>]  Stop when the CPU reaches the address (b)
+delete <address>                  Remove the breakpoint at the address (d)
+breakpoints                       List the breakpoints (bl)
+continue                          Run until a breakpoint (c)
+step [count]                      Run one instruction, or count of them (s)
+next                              Step over a call, running until it returns (n)
+finish                            Run until the current subroutine returns (f)
+backtrace                         Show the subroutines the CPU is in (bt)
+registers                         Show the registers (r)
+print <expression>                Show the value of an expression (p)
+dump <address> [length]           Show memory as hex, 64 bytes by default (x)
+edit <address> <byte>...          Write bytes to memory, eg edit c000 12 34 (e)
+sprites                           List the sprites in OAM (oam)
+palettes                          Show the palette registers and CGB palettes (pal)
+press <button>                    Hold a button down, eg press start
+release <button>                  Let go of a button
+help                              Show this (h)
+quit                              Leave the debugger (q)
+
+Expressions use the registers a-l, af, bc, de, hl, sp and pc, the flags
+zf, nf, hf and cf, memory as [address], numbers, and the operators
+|| && == != < <= > >= | ^ & << >> + - * / % ! ~, eg print [hl+1] * 2.
+Addresses can be labels from the ROM's symbol file, eg break Main.";
+
+/// How many bytes `dump` shows without a length
+const DUMP_LENGTH: u16 = 64;
+const DUMP_BYTES_PER_LINE: u16 = 16;
+
+/// `count` bytes of memory from `start` as a line of hex and text,
+/// eg `C000: 48 49 00 HI.`
+pub fn hex_line(gameboy: &mut GameBoy, start: u16, count: u16) -> String {
+    let bytes: Vec<u8> = (0..count)
+        .map(|offset| gameboy.peek(start.wrapping_add(offset)))
+        .collect();
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    let text: String = bytes
+        .iter()
+        .map(|&byte| match byte {
+            0x20..=0x7E => byte as char,
+            _ => '.',
+        })
+        .collect();
+    format!("{:04X}: {} {}", start, hex.join(" "), text)
+}
+
+/// `length` bytes of memory from `start`, a line of hex at a time
+fn hexdump(gameboy: &mut GameBoy, start: u16, length: u16) -> String {
+    (0..length)
+        .step_by(DUMP_BYTES_PER_LINE as usize)
+        .map(|offset| {
+            let count = DUMP_BYTES_PER_LINE.min(length - offset);
+            hex_line(gameboy, start.wrapping_add(offset), count)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A byte in hex, like an address, eg `3C` or `0x3C`
+fn parse_byte(text: &str) -> Result<u8, String> {
+    match parse_address(text) {
+        Ok(value) if value <= 0xFF => Ok(value as u8),
+        _ => Err(format!("Expected a byte in hex, got {:?}", text)),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub address: u16,
+    /// Only stop when this isn't zero
+    pub condition: Option<Expr>,
+    /// The condition as it was typed, for listing
+    condition_text: String,
+}
+
+impl Breakpoint {
+    /// Whether the CPU is at the address, with the condition holding
+    pub fn is_hit(&self, gameboy: &mut GameBoy) -> bool {
+        let registers = gameboy.registers();
+        self.address == registers.pc
+            && self
+                .condition
+                .as_ref()
+                // A condition that can't be worked out stops, so it's noticed
+                .is_none_or(|condition| {
+                    condition.evaluate(&registers, &mut |address| gameboy.peek(address)) != Ok(0)
+                })
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#06x}", self.address)?;
+        if self.condition.is_some() {
+            write!(f, " if {}", self.condition_text)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Break(Breakpoint),
+    Delete(u16),
+    Breakpoints,
+    Continue,
+    Step(u32),
+    /// Step over a call
+    Next,
+    /// Step out of the current subroutine
+    Finish,
+    Backtrace,
+    Registers,
+    Print(Expr),
+    /// Show `length` bytes of memory
+    Dump {
+        address: u16,
+        length: u16,
+    },
+    /// Write bytes to memory from the address on
+    Edit {
+        address: u16,
+        bytes: Vec<u8>,
+    },
+    Sprites,
+    Palettes,
+    Press(Button),
+    Release(Button),
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        Command::parse_with(line, &Symbols::default())
+    }
+
+    /// Parse a command whose addresses can be labels in `symbols`
+    pub fn parse_with(line: &str, symbols: &Symbols) -> Result<Command, String> {
+        let parse_address = |text: &str| symbols.resolve(text);
+        let line = line.trim();
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let command = match name {
+            "break" | "b" => {
+                let (address, condition) = match rest.split_once(" if ") {
+                    Some((address, condition)) => (address, Some(condition.trim())),
+                    None => (rest, None),
+                };
+                Command::Break(Breakpoint {
+                    address: parse_address(address)?,
+                    condition: condition.map(Expr::parse).transpose()?,
+                    condition_text: condition.unwrap_or_default().to_string(),
+                })
+            }
+            "delete" | "d" => Command::Delete(parse_address(rest)?),
+            "breakpoints" | "bl" => Command::Breakpoints,
+            "continue" | "c" => Command::Continue,
+            "step" | "s" if rest.is_empty() => Command::Step(1),
+            "step" | "s" => Command::Step(
+                rest.parse()
+                    .map_err(|_| format!("Expected a number of instructions, got {:?}", rest))?,
+            ),
+            "next" | "n" => Command::Next,
+            "finish" | "f" => Command::Finish,
+            "backtrace" | "bt" => Command::Backtrace,
+            "registers" | "r" => Command::Registers,
+            "print" | "p" => Command::Print(Expr::parse(rest)?),
+            "dump" | "x" => {
+                let (address, length) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let length = match length.trim() {
+                    "" => DUMP_LENGTH,
+                    length => length
+                        .parse()
+                        .ok()
+                        .filter(|length| *length > 0)
+                        .ok_or_else(|| format!("Expected a number of bytes, got {:?}", length))?,
+                };
+                Command::Dump {
+                    address: parse_address(address)?,
+                    length,
+                }
+            }
+            "edit" | "e" => {
+                let mut words = rest.split_whitespace();
+                let address = parse_address(words.next().unwrap_or_default())?;
+                let bytes = words.map(parse_byte).collect::<Result<Vec<u8>, String>>()?;
+                if bytes.is_empty() {
+                    return Err(String::from("Expected the bytes to write"));
+                }
+                Command::Edit { address, bytes }
+            }
+            "sprites" | "oam" => Command::Sprites,
+            "palettes" | "pal" => Command::Palettes,
+            "press" => Command::Press(parse_button(rest)?),
+            "release" => Command::Release(parse_button(rest)?),
+            "help" | "h" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            _ => return Err(format!("Unknown command {:?}, try help", name)),
+        };
+        Ok(command)
+    }
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    symbols: Symbols,
+}
+
+impl Debugger {
+    /// A debugger that takes labels in `symbols` for addresses
+    pub fn with_symbols(symbols: Symbols) -> Debugger {
+        Debugger {
+            symbols,
+            ..Default::default()
+        }
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn parse(&self, line: &str) -> Result<Command, String> {
+        Command::parse_with(line, &self.symbols)
+    }
+
+    /// Add a breakpoint, in place of any at the same address
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        match self
+            .breakpoints
+            .iter_mut()
+            .find(|existing| existing.address == breakpoint.address)
+        {
+            Some(existing) => *existing = breakpoint,
+            None => self.breakpoints.push(breakpoint),
+        }
+    }
+
+    /// Returns whether there was a breakpoint at the address
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        let breakpoints = self.breakpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.address != address);
+        self.breakpoints.len() != breakpoints
+    }
+
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.address == address)
+    }
+
+    /// Add a breakpoint at the address, or remove the one there.
+    /// Returns whether there's one now.
+    pub fn toggle_breakpoint(&mut self, address: u16) -> bool {
+        if self.remove_breakpoint(address) {
+            return false;
+        }
+        self.add_breakpoint(Breakpoint {
+            address,
+            condition: None,
+            condition_text: String::new(),
+        });
+        true
+    }
+
+    /// Run for `cycles` T-cycles, or until a breakpoint is hit.
+    /// Returns the breakpoint, if one was.
+    pub fn run_for(&self, gameboy: &mut GameBoy, cycles: u32) -> Option<&Breakpoint> {
+        let mut elapsed = 0;
+        while elapsed < cycles {
+            elapsed += gameboy.step_instruction();
+            if let Some(breakpoint) = self.hit(gameboy) {
+                return Some(breakpoint);
+            }
+        }
+        None
+    }
+
+    /// The breakpoint the CPU has stopped at, if any: one at the program
+    /// counter whose condition holds
+    pub fn hit(&self, gameboy: &mut GameBoy) -> Option<&Breakpoint> {
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.is_hit(gameboy))
+    }
+
+    /// Run a command, returning what to show for it
+    pub fn execute(&mut self, gameboy: &mut GameBoy, command: Command) -> String {
+        match command {
+            Command::Break(breakpoint) => {
+                let added = format!("Breakpoint at {}", breakpoint);
+                self.add_breakpoint(breakpoint);
+                added
+            }
+            Command::Delete(address) => match self.remove_breakpoint(address) {
+                true => format!("Deleted the breakpoint at {:#06x}", address),
+                false => format!("There's no breakpoint at {:#06x}", address),
+            },
+            Command::Breakpoints if self.breakpoints.is_empty() => String::from("No breakpoints"),
+            Command::Breakpoints => self
+                .breakpoints
+                .iter()
+                .map(Breakpoint::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Command::Continue => self.run_until(gameboy, |_| false),
+            Command::Next => self.run_until(gameboy, |depth| depth <= 0),
+            Command::Finish => self.run_until(gameboy, |depth| depth < 0),
+            Command::Backtrace => {
+                let pc = gameboy.registers().pc;
+                gameboy.track_calls().backtrace(pc).join("\n")
+            }
+            Command::Step(count) => {
+                for _ in 0..count {
+                    gameboy.step_instruction();
+                }
+                gameboy.registers().to_string()
+            }
+            Command::Registers => gameboy.registers().to_string(),
+            Command::Print(expr) => {
+                let registers = gameboy.registers();
+                match expr.evaluate(&registers, &mut |address| gameboy.peek(address)) {
+                    Ok(value) => format!("{:#06x} ({})", value, value),
+                    Err(e) => e,
+                }
+            }
+            Command::Dump { address, length } => hexdump(gameboy, address, length),
+            Command::Edit { address, bytes } => {
+                for (offset, byte) in bytes.iter().enumerate() {
+                    gameboy.poke(address.wrapping_add(offset as u16), *byte);
+                }
+                // Read back, as a write to ROM or a register may not stick
+                format!(
+                    "Wrote {} bytes at {:#06x}\n{}",
+                    bytes.len(),
+                    address,
+                    hexdump(gameboy, address, bytes.len() as u16)
+                )
+            }
+            Command::Sprites => {
+                let mut lines = vec![String::from(SPRITE_TABLE_HEADER)];
+                lines.extend(gameboy.sprites().iter().map(Sprite::to_string));
+                lines.join("\n")
+            }
+            Command::Palettes => gameboy.palette_table(),
+            Command::Press(button) => {
+                gameboy.set_button(button, true);
+                format!("Pressed {:?}", button)
+            }
+            Command::Release(button) => {
+                gameboy.set_button(button, false);
+                format!("Released {:?}", button)
+            }
+            Command::Help => String::from(HELP),
+            Command::Quit => String::new(),
+        }
+    }
+
+    /// Run until `done` says to stop, or a breakpoint is hit. `done` is
+    /// given how much deeper the calls nest than where it started.
+    /// Returns what to show.
+    fn run_until(&self, gameboy: &mut GameBoy, done: impl Fn(isize) -> bool) -> String {
+        let start = gameboy.track_calls().depth() as isize;
+        loop {
+            gameboy.step_instruction();
+            let depth = gameboy.track_calls().depth() as isize - start;
+            if let Some(breakpoint) = self.hit(gameboy) {
+                return format!(
+                    "Stopped at breakpoint {}\n{}",
+                    breakpoint,
+                    gameboy.registers()
+                );
+            }
+            if done(depth) {
+                return gameboy.registers().to_string();
+            }
+        }
+    }
+
+    /// Read and run commands from stdin until `quit` or the end of the input
+    pub fn run(&mut self, gameboy: &mut GameBoy) {
+        gameboy.track_calls();
+        println!("{}", gameboy.registers());
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("(gb) ");
+            io::stdout().flush().ok();
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.parse(&line) {
+                Ok(Command::Quit) => break,
+                Ok(command) => println!("{}", self.execute(gameboy, command)),
+                Err(e) => println!("{}", e),
+            }
+        }
+        gameboy.save_data();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    /// A GameBoy running a ROM of NOPs
+    fn gameboy() -> GameBoy {
+        GameBoy::new(vec![0; 0x8000])
+    }
+
+    fn breakpoint(line: &str) -> Breakpoint {
+        match Command::parse(line) {
+            Ok(Command::Break(breakpoint)) => breakpoint,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test_case("step", Command::Step(1); "step")]
+    #[test_case("s 10", Command::Step(10); "step count")]
+    #[test_case("d 0x150", Command::Delete(0x0150); "delete")]
+    #[test_case("  c  ", Command::Continue; "spaces")]
+    #[test_case("n", Command::Next; "next")]
+    #[test_case("finish", Command::Finish; "finish")]
+    #[test_case("bt", Command::Backtrace; "backtrace")]
+    #[test_case("pal", Command::Palettes; "palettes")]
+    #[test_case("press Start", Command::Press(Button::Start); "press")]
+    #[test_case("release a", Command::Release(Button::A); "release")]
+    #[test_case("print a + 1", Command::Print(Expr::parse("a + 1").unwrap()); "print")]
+    #[test_case("x c000", Command::Dump { address: 0xC000, length: 64 }; "dump")]
+    #[test_case("dump 0xff80 16", Command::Dump { address: 0xFF80, length: 16 }; "dump length")]
+    #[test_case("e c000 12 0x3c", Command::Edit { address: 0xC000, bytes: vec![0x12, 0x3C] }; "edit")]
+    fn test_parse(line: &str, expected: Command) {
+        assert_eq!(Command::parse(line), Ok(expected));
+    }
+
+    #[test_case("jump 0x150"; "unknown")]
+    #[test_case("step many"; "step count")]
+    #[test_case("break"; "no address")]
+    #[test_case("break 0x150 if a =="; "bad condition")]
+    #[test_case("dump c000 0"; "dump nothing")]
+    #[test_case("edit c000"; "edit nothing")]
+    #[test_case("edit c000 123"; "edit not a byte")]
+    fn test_parse_invalid(line: &str) {
+        assert!(Command::parse(line).is_err());
+    }
+
+    #[test]
+    fn test_parse_break() {
+        let conditional = breakpoint("break 0x4123 if a == 0x3C && [0xC000] != 0");
+        assert_eq!(conditional.address, 0x4123);
+        assert_eq!(
+            conditional.condition,
+            Some(Expr::parse("a == 0x3C && [0xC000] != 0").unwrap())
+        );
+        assert_eq!(
+            conditional.to_string(),
+            "0x4123 if a == 0x3C && [0xC000] != 0"
+        );
+        assert_eq!(breakpoint("b 150").to_string(), "0x0150");
+    }
+
+    #[test]
+    fn test_parse_symbols() {
+        let debugger = Debugger::with_symbols(Symbols::parse("00:0150 Main").unwrap());
+        assert_eq!(
+            debugger.parse("break Main"),
+            Ok(Command::Break(breakpoint("b 0x150")))
+        );
+        assert_eq!(debugger.parse("x Main 16"), Command::parse("x 0x150 16"));
+        assert!(debugger.parse("break Nowhere").is_err());
+    }
+
+    #[test]
+    fn test_continue() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        debugger.add_breakpoint(breakpoint("break 0x0010"));
+        let output = debugger.execute(&mut gameboy, Command::Continue);
+        assert!(
+            output.starts_with("Stopped at breakpoint 0x0010"),
+            "{}",
+            output
+        );
+        assert_eq!(gameboy.registers().pc, 0x0010);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        debugger.add_breakpoint(breakpoint("break 0x0010 if [0xC000] == 1"));
+        gameboy.step_cycles(0x10 * 4);
+        assert_eq!(gameboy.registers().pc, 0x0010);
+        assert!(debugger.hit(&mut gameboy).is_none());
+        gameboy.poke(0xC000, 1);
+        assert!(debugger.hit(&mut gameboy).is_some());
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        debugger.add_breakpoint(breakpoint("break 0x0010"));
+        // Replaces the one at the same address
+        debugger.add_breakpoint(breakpoint("break 0x0010 if a == 1"));
+        debugger.add_breakpoint(breakpoint("break 0x0020"));
+        assert_eq!(
+            debugger.execute(&mut gameboy, Command::Breakpoints),
+            "0x0010 if a == 1\n0x0020"
+        );
+        assert!(debugger.remove_breakpoint(0x0010));
+        assert!(!debugger.remove_breakpoint(0x0010));
+
+        assert!(!debugger.toggle_breakpoint(0x0020));
+        assert!(!debugger.has_breakpoint(0x0020));
+        assert!(debugger.toggle_breakpoint(0x0020));
+        assert!(debugger.has_breakpoint(0x0020));
+    }
+
+    #[test]
+    fn test_run_for() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        assert!(debugger.run_for(&mut gameboy, 40).is_none());
+        assert_eq!(gameboy.registers().pc, 10);
+        debugger.toggle_breakpoint(12);
+        assert_eq!(debugger.run_for(&mut gameboy, 40).unwrap().address, 12);
+        assert_eq!(gameboy.registers().pc, 12);
+    }
+
+    #[test]
+    fn test_next() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        // Not a call, so it's the same as step
+        let output = debugger.execute(&mut gameboy, Command::Next);
+        assert!(output.ends_with("PC:0001"), "{}", output);
+    }
+
+    #[test]
+    fn test_finish_stops_at_breakpoint() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        debugger.add_breakpoint(breakpoint("break 0x0008"));
+        let output = debugger.execute(&mut gameboy, Command::Finish);
+        assert!(
+            output.starts_with("Stopped at breakpoint 0x0008"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        debugger.execute(&mut gameboy, Command::Step(2));
+        assert_eq!(
+            debugger.execute(&mut gameboy, Command::Backtrace),
+            "#0 0002 at the top level"
+        );
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        gameboy.poke(0xC001, b'H');
+        gameboy.poke(0xC002, b'I');
+        let output = debugger.execute(
+            &mut gameboy,
+            Command::Dump {
+                address: 0xC000,
+                length: 20,
+            },
+        );
+        assert_eq!(
+            output,
+            "C000: 00 48 49 00 00 00 00 00 00 00 00 00 00 00 00 00 .HI.............\n\
+             C010: 00 00 00 00 ...."
+        );
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        let output = debugger.execute(&mut gameboy, Command::parse("edit c000 12 34").unwrap());
+        assert_eq!(output, "Wrote 2 bytes at 0xc000\nC000: 12 34 .4");
+        assert_eq!(gameboy.peek(0xC001), 0x34);
+
+        // Writes go through the bus, so writing to ROM doesn't change it
+        let output = debugger.execute(&mut gameboy, Command::parse("edit 0 ff").unwrap());
+        assert!(output.ends_with("0000: 00 ."), "{}", output);
+    }
+
+    #[test]
+    fn test_print() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        gameboy.poke(0xC001, 0x21);
+        let mut print =
+            |expression: &str| debugger.execute(&mut gameboy, Command::parse(expression).unwrap());
+        assert_eq!(print("print [hl + 0xC001] * 2"), "0x0042 (66)");
+        assert_eq!(print("p 1 / a"), "Division by zero");
+    }
+
+    #[test]
+    fn test_sprites() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        gameboy.poke(0xFE04, 16);
+        gameboy.poke(0xFE05, 8);
+        gameboy.poke(0xFE07, 0x20);
+        let output = debugger.execute(&mut gameboy, Command::parse("oam").unwrap());
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 41);
+        assert_eq!(lines[2], " 1  16   8   00   20  OBP0 x-flip");
+    }
+
+    #[test]
+    fn test_step() {
+        let mut gameboy = gameboy();
+        let mut debugger = Debugger::default();
+        let output = debugger.execute(&mut gameboy, Command::Step(3));
+        assert!(output.ends_with("PC:0003"), "{}", output);
+    }
+}
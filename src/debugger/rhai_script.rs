@@ -0,0 +1,339 @@
+// Scripts in rhai (https://rhai.rs), for what the command scripts can't do
+// without variables, conditions and loops. Running the script registers
+// functions to call when something happens in the game:
+//
+//   let presses = 0;
+//   on_frame(|frame| if frame % 30 == 0 { press("a"); presses += 1; });
+//   on_break(0x0150, || print(`A is ${registers().a}`));
+//   on_write(0xC000, |value| if value > 3 { write(0xC001, 0) });
+//
+// They can use read(address), write(address, value), registers() and
+// press(button) and release(button), like the command scripts' hooks, but
+// not run the CPU.
+
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Map, AST, INT};
+
+use crate::frontend::keymap::parse_button;
+use crate::gameboy::GameBoy;
+
+type RhaiResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The GameBoy the script's functions use, only set while the script runs
+#[derive(Clone, Default)]
+struct Current(Rc<Cell<Option<NonNull<GameBoy>>>>);
+
+impl Current {
+    /// Run `f` with the script's functions using `gameboy`
+    fn with<T>(&self, gameboy: &mut GameBoy, f: impl FnOnce() -> T) -> T {
+        /// Unsets the GameBoy, even if `f` panics
+        struct Unset<'a>(&'a Current);
+
+        impl Drop for Unset<'_> {
+            fn drop(&mut self) {
+                self.0 .0.set(None);
+            }
+        }
+
+        self.0.set(Some(NonNull::from(gameboy)));
+        let _unset = Unset(self);
+        f()
+    }
+
+    fn gameboy<T>(&self, f: impl FnOnce(&mut GameBoy) -> T) -> RhaiResult<T> {
+        match self.0.get() {
+            // SAFETY: it's only set inside with(), which holds the GameBoy's
+            // mutable borrow and doesn't use it until `f` returns, and the
+            // functions calling this don't call each other
+            Some(mut gameboy) => Ok(f(unsafe { gameboy.as_mut() })),
+            None => Err("The GameBoy can only be used while the script runs".into()),
+        }
+    }
+}
+
+/// The functions the script registered
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    breaks: Vec<(u16, FnPtr)>,
+    writes: Vec<(u16, FnPtr)>,
+}
+
+fn address(address: INT) -> RhaiResult<u16> {
+    u16::try_from(address).map_err(|_| format!("{:#x} isn't an address", address).into())
+}
+
+pub struct RhaiScript {
+    engine: Engine,
+    ast: AST,
+    hooks: Rc<RefCell<Hooks>>,
+    gameboy: Current,
+    /// Where print() and errors go
+    output: Rc<RefCell<Box<dyn Write>>>,
+}
+
+impl RhaiScript {
+    pub fn parse(text: &str) -> Result<RhaiScript, String> {
+        let mut engine = Engine::new();
+        let hooks = Rc::new(RefCell::new(Hooks::default()));
+        let gameboy = Current::default();
+        let output: Rc<RefCell<Box<dyn Write>>> = Rc::new(RefCell::new(Box::new(io::stdout())));
+
+        let registered = hooks.clone();
+        engine.register_fn("on_frame", move |callback: FnPtr| {
+            registered.borrow_mut().frame.push(callback)
+        });
+        let registered = hooks.clone();
+        engine.register_fn(
+            "on_break",
+            move |at: INT, callback: FnPtr| -> RhaiResult<()> {
+                registered
+                    .borrow_mut()
+                    .breaks
+                    .push((address(at)?, callback));
+                Ok(())
+            },
+        );
+        let registered = hooks.clone();
+        engine.register_fn(
+            "on_write",
+            move |at: INT, callback: FnPtr| -> RhaiResult<()> {
+                registered
+                    .borrow_mut()
+                    .writes
+                    .push((address(at)?, callback));
+                Ok(())
+            },
+        );
+
+        let current = gameboy.clone();
+        engine.register_fn("read", move |at: INT| -> RhaiResult<INT> {
+            let at = address(at)?;
+            current.gameboy(|gameboy| gameboy.peek(at) as INT)
+        });
+        let current = gameboy.clone();
+        engine.register_fn("write", move |at: INT, value: INT| -> RhaiResult<()> {
+            let at = address(at)?;
+            let value = u8::try_from(value).map_err(|_| format!("{} isn't a byte", value))?;
+            current.gameboy(|gameboy| gameboy.poke(at, value))
+        });
+        let current = gameboy.clone();
+        engine.register_fn("registers", move || {
+            current.gameboy(|gameboy| {
+                let registers = gameboy.registers();
+                let mut map = Map::new();
+                for (name, value) in [
+                    ("a", registers.a as INT),
+                    ("f", registers.f as INT),
+                    ("b", registers.b as INT),
+                    ("c", registers.c as INT),
+                    ("d", registers.d as INT),
+                    ("e", registers.e as INT),
+                    ("h", registers.h as INT),
+                    ("l", registers.l as INT),
+                    ("sp", registers.sp as INT),
+                    ("pc", registers.pc as INT),
+                ] {
+                    map.insert(name.into(), Dynamic::from(value));
+                }
+                map
+            })
+        });
+        for (name, pressed) in [("press", true), ("release", false)] {
+            let current = gameboy.clone();
+            engine.register_fn(name, move |name: &str| -> RhaiResult<()> {
+                let button = parse_button(name)?;
+                current.gameboy(|gameboy| gameboy.set_button(button, pressed))
+            });
+        }
+
+        let printed = output.clone();
+        engine.on_print(move |text| {
+            writeln!(printed.borrow_mut(), "{}", text).ok();
+        });
+
+        let ast = engine.compile(text).map_err(|e| e.to_string())?;
+        Ok(RhaiScript {
+            engine,
+            ast,
+            hooks,
+            gameboy,
+            output,
+        })
+    }
+
+    /// Send print() and errors somewhere other than stdout
+    #[cfg(test)]
+    pub(crate) fn set_output(&mut self, output: impl Write + 'static) {
+        *self.output.borrow_mut() = Box::new(output);
+    }
+
+    /// The addresses the script's on_write functions watch
+    pub fn watched_addresses(&self) -> Vec<u16> {
+        self.hooks
+            .borrow()
+            .writes
+            .iter()
+            .map(|&(address, _)| address)
+            .collect()
+    }
+
+    /// Run the script, which registers its functions
+    pub fn start(&mut self, gameboy: &mut GameBoy) {
+        let result = self
+            .gameboy
+            .with(gameboy, || self.engine.run_ast(&self.ast));
+        self.report("start", result);
+    }
+
+    /// Call the on_frame functions with `frame`
+    pub fn end_of_frame(&mut self, gameboy: &mut GameBoy, frame: u64) {
+        let callbacks = self.hooks.borrow().frame.clone();
+        for callback in callbacks {
+            self.call(
+                gameboy,
+                &format!("frame {}", frame),
+                &callback,
+                (frame as INT,),
+            );
+        }
+    }
+
+    /// Call the functions for the instruction that just ran, with the
+    /// writes it made
+    pub fn after_instruction(&mut self, gameboy: &mut GameBoy, writes: &[(u16, u8)]) {
+        for &(address, value) in writes {
+            let label = format!("write {:#06x} = {:#04x}", address, value);
+            let callbacks: Vec<FnPtr> = self
+                .hooks
+                .borrow()
+                .writes
+                .iter()
+                .filter(|(at, _)| *at == address)
+                .map(|(_, callback)| callback.clone())
+                .collect();
+            for callback in callbacks {
+                self.call(gameboy, &label, &callback, (value as INT,));
+            }
+        }
+        let pc = gameboy.registers().pc;
+        let callbacks: Vec<FnPtr> = self
+            .hooks
+            .borrow()
+            .breaks
+            .iter()
+            .filter(|(at, _)| *at == pc)
+            .map(|(_, callback)| callback.clone())
+            .collect();
+        for callback in callbacks {
+            self.call(gameboy, &format!("break {:#06x}", pc), &callback, ());
+        }
+    }
+
+    fn call(
+        &self,
+        gameboy: &mut GameBoy,
+        label: &str,
+        callback: &FnPtr,
+        args: impl rhai::FuncArgs,
+    ) {
+        let result = self.gameboy.with(gameboy, || {
+            callback.call::<Dynamic>(&self.engine, &self.ast, args)
+        });
+        self.report(label, result);
+    }
+
+    fn report<T>(&self, label: &str, result: RhaiResult<T>) {
+        if let Err(e) = result {
+            writeln!(self.output.borrow_mut(), "[{}] {}", label, e).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::script::tests::Shared;
+    use crate::debugger::script::Script;
+
+    /// Run `text` on a GameBoy with `rom` for `frames` frames
+    fn run(rom: Vec<u8>, text: &str, frames: u32) -> (GameBoy, String) {
+        let mut gameboy = GameBoy::new(rom);
+        let mut script = RhaiScript::parse(text).unwrap();
+        let output = Shared::default();
+        script.set_output(output.clone());
+        gameboy.set_script(Script::Rhai(Box::new(script)));
+        for _ in 0..frames {
+            gameboy.step_frame();
+        }
+        (gameboy, output.text())
+    }
+
+    #[test]
+    fn test_start() {
+        let (mut gameboy, output) = run(
+            vec![0; 0x8000],
+            "write(0xC000, 0x2A); print(read(0xC000)); print(registers().pc)",
+            0,
+        );
+        assert_eq!(gameboy.peek(0xC000), 0x2A);
+        assert_eq!(output, "42\n0\n");
+    }
+
+    #[test]
+    fn test_frame() {
+        // Start is bit 3 of P1 with the buttons selected, and 0 when pressed
+        let (_, output) = run(
+            vec![0; 0x8000],
+            "let frames = [];\n\
+             on_frame(|frame| { frames.push(frame); if frame == 2 { press(\"start\") } });\n\
+             on_frame(|frame| if frame == 2 { print(frames); write(0xFF00, 0x10); print(read(0xFF00) & 0x08) });",
+            2,
+        );
+        assert_eq!(output, "[1, 2]\n0\n");
+    }
+
+    #[test]
+    fn test_break() {
+        let (_, output) = run(
+            vec![0; 0x8000],
+            "on_break(0x0003, || print(registers().pc))",
+            1,
+        );
+        assert_eq!(output, "3\n");
+    }
+
+    #[test]
+    fn test_write() {
+        // LD BC,0xC0C0; LD (BC),A
+        let mut rom = vec![0; 0x8000];
+        rom[..4].copy_from_slice(&[0x01, 0xC0, 0xC0, 0x02]);
+        let (_, output) = run(
+            rom,
+            "on_write(0xC0C0, |value| print(`${value} at ${registers().pc}`));\n\
+             on_write(0xC000, |value| print(\"wrong address\"))",
+            1,
+        );
+        assert_eq!(output, "0 at 4\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(RhaiScript::parse("on_frame(|frame| ").is_err());
+        let (_, output) = run(
+            vec![0; 0x8000],
+            "on_frame(|frame| if frame == 1 { press(\"turbo\") }); read(0x10000)",
+            1,
+        );
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("[start]"));
+        assert!(lines[0].contains("0x10000 isn't an address"));
+        assert!(lines[1].starts_with("[frame 1]"));
+        assert!(lines[1].contains("Unknown button \"turbo\""));
+    }
+}
@@ -0,0 +1,376 @@
+// Scripts that run debugger commands when something happens in the game.
+// Each line is a hook: a trigger, then the commands to run, separated by
+// semicolons. Lines that aren't hooks run once, when the script is loaded.
+//
+//   on frame 60: press start
+//   on frame 65: release start
+//   on frame: print [0xC0A0]
+//   on break 0x0150 if a == 3: registers; edit c000 01
+//   on write 0xC000: print [0xC000]
+//
+// Hooks can look at and change memory and press buttons, but not run the
+// CPU, as they're called in the middle of running it.
+//
+// With the `scripting` feature, files ending in .rhai are rhai scripts
+// instead, in rhai_script.rs.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[cfg(feature = "scripting")]
+use crate::debugger::rhai_script::RhaiScript;
+use crate::debugger::{Breakpoint, Command, Debugger};
+use crate::gameboy::GameBoy;
+use crate::mmu::trace::parse_address;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trigger {
+    /// The end of a frame, counting from 1, or of every frame
+    Frame(Option<u64>),
+    /// The CPU reaching an address, if the condition holds
+    Break(Breakpoint),
+    /// A write to an address
+    Write(u16),
+}
+
+impl Trigger {
+    fn parse(text: &str) -> Result<Trigger, String> {
+        let (name, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let rest = rest.trim();
+        match name {
+            "frame" if rest.is_empty() => Ok(Trigger::Frame(None)),
+            "frame" => rest
+                .parse()
+                .map(|frame| Trigger::Frame(Some(frame)))
+                .map_err(|_| format!("Expected a frame number, got {:?}", rest)),
+            "break" => match Command::parse(text)? {
+                Command::Break(breakpoint) => Ok(Trigger::Break(breakpoint)),
+                _ => unreachable!(),
+            },
+            "write" => Ok(Trigger::Write(parse_address(rest)?)),
+            other => Err(format!(
+                "Unknown trigger {:?}, expected frame, break or write",
+                other
+            )),
+        }
+    }
+}
+
+/// A script loaded with --script
+pub enum Script {
+    Commands(CommandScript),
+    #[cfg(feature = "scripting")]
+    Rhai(Box<RhaiScript>),
+}
+
+impl Script {
+    /// Load a rhai script if the file ends in .rhai, otherwise a command script
+    pub fn load(path: &Path) -> Result<Script, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "rhai")
+        {
+            #[cfg(feature = "scripting")]
+            return RhaiScript::parse(&text).map(|script| Script::Rhai(Box::new(script)));
+            #[cfg(not(feature = "scripting"))]
+            return Err(String::from("rhai scripts need the scripting feature"));
+        }
+        CommandScript::parse(&text).map(Script::Commands)
+    }
+
+    /// The addresses whose writes the script wants to hear about, known
+    /// once it's started
+    pub fn watched_addresses(&self) -> Vec<u16> {
+        match self {
+            Script::Commands(script) => script.watched_addresses(),
+            #[cfg(feature = "scripting")]
+            Script::Rhai(script) => script.watched_addresses(),
+        }
+    }
+
+    pub fn start(&mut self, gameboy: &mut GameBoy) {
+        match self {
+            Script::Commands(script) => script.start(gameboy),
+            #[cfg(feature = "scripting")]
+            Script::Rhai(script) => script.start(gameboy),
+        }
+    }
+
+    pub fn end_of_frame(&mut self, gameboy: &mut GameBoy, frame: u64) {
+        match self {
+            Script::Commands(script) => script.end_of_frame(gameboy, frame),
+            #[cfg(feature = "scripting")]
+            Script::Rhai(script) => script.end_of_frame(gameboy, frame),
+        }
+    }
+
+    pub fn after_instruction(&mut self, gameboy: &mut GameBoy, writes: &[(u16, u8)]) {
+        match self {
+            Script::Commands(script) => script.after_instruction(gameboy, writes),
+            #[cfg(feature = "scripting")]
+            Script::Rhai(script) => script.after_instruction(gameboy, writes),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Hook {
+    trigger: Trigger,
+    commands: Vec<Command>,
+}
+
+/// Parse the commands of a hook, which mustn't run the CPU
+fn parse_commands(text: &str) -> Result<Vec<Command>, String> {
+    text.split(';')
+        .map(|command| match Command::parse(command)? {
+            command @ (Command::Backtrace
+            | Command::Registers
+            | Command::Print(_)
+            | Command::Dump { .. }
+            | Command::Edit { .. }
+            | Command::Sprites
+            | Command::Palettes
+            | Command::Press(_)
+            | Command::Release(_)) => Ok(command),
+            _ => Err(format!("{:?} can't be used in a script", command.trim())),
+        })
+        .collect()
+}
+
+pub struct CommandScript {
+    /// Commands to run when the script is loaded
+    setup: Vec<Command>,
+    hooks: Vec<Hook>,
+    debugger: Debugger,
+    /// Where the commands' output goes
+    output: Box<dyn Write>,
+}
+
+impl CommandScript {
+    pub fn parse(text: &str) -> Result<CommandScript, String> {
+        let mut script = CommandScript {
+            setup: Vec::new(),
+            hooks: Vec::new(),
+            debugger: Debugger::default(),
+            output: Box::new(io::stdout()),
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = match line.strip_prefix("on ") {
+                Some(hook) => hook
+                    .split_once(':')
+                    .ok_or_else(|| String::from("Expected a ':' after the trigger"))
+                    .and_then(|(trigger, commands)| {
+                        Ok(Hook {
+                            trigger: Trigger::parse(trigger.trim())?,
+                            commands: parse_commands(commands)?,
+                        })
+                    })
+                    .map(|hook| script.hooks.push(hook)),
+                None => parse_commands(line).map(|commands| script.setup.extend(commands)),
+            };
+            parsed.map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        }
+        Ok(script)
+    }
+
+    /// Send the commands' output somewhere other than stdout
+    #[cfg(test)]
+    fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// The addresses the write hooks watch
+    pub fn watched_addresses(&self) -> Vec<u16> {
+        self.hooks
+            .iter()
+            .filter_map(|hook| match hook.trigger {
+                Trigger::Write(address) => Some(address),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Run the commands outside hooks
+    pub fn start(&mut self, gameboy: &mut GameBoy) {
+        let setup = std::mem::take(&mut self.setup);
+        self.run(gameboy, "start", &setup);
+    }
+
+    /// Run the hooks for the end of `frame`
+    pub fn end_of_frame(&mut self, gameboy: &mut GameBoy, frame: u64) {
+        self.run_hooks(
+            gameboy,
+            &format!("frame {}", frame),
+            |trigger, _| matches!(trigger, Trigger::Frame(at) if at.is_none_or(|at| at == frame)),
+        );
+    }
+
+    /// Run the hooks for the instruction that just ran, with the writes it made
+    pub fn after_instruction(&mut self, gameboy: &mut GameBoy, writes: &[(u16, u8)]) {
+        for &(address, value) in writes {
+            let label = format!("write {:#06x} = {:#04x}", address, value);
+            self.run_hooks(gameboy, &label, |trigger, _| {
+                *trigger == Trigger::Write(address)
+            });
+        }
+        let label = format!("break {:#06x}", gameboy.registers().pc);
+        self.run_hooks(gameboy, &label, |trigger, gameboy| match trigger {
+            Trigger::Break(breakpoint) => breakpoint.is_hit(gameboy),
+            _ => false,
+        });
+    }
+
+    fn run_hooks(
+        &mut self,
+        gameboy: &mut GameBoy,
+        label: &str,
+        mut triggered: impl FnMut(&Trigger, &mut GameBoy) -> bool,
+    ) {
+        let hooks = std::mem::take(&mut self.hooks);
+        for hook in &hooks {
+            if triggered(&hook.trigger, gameboy) {
+                self.run(gameboy, label, &hook.commands);
+            }
+        }
+        self.hooks = hooks;
+    }
+
+    fn run(&mut self, gameboy: &mut GameBoy, label: &str, commands: &[Command]) {
+        for command in commands {
+            let output = self.debugger.execute(gameboy, command.clone());
+            if !output.is_empty() {
+                writeln!(self.output, "[{}] {}", label, output).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::joypad::Button;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use test_case::test_case;
+
+    /// Output that can be read after the script has it
+    #[derive(Clone, Default)]
+    pub(crate) struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        pub(crate) fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    /// Run `text` on a GameBoy with `rom` for `frames` frames
+    fn run(rom: Vec<u8>, text: &str, frames: u32) -> (GameBoy, String) {
+        let mut gameboy = GameBoy::new(rom);
+        let mut script = CommandScript::parse(text).unwrap();
+        let output = Shared::default();
+        script.set_output(output.clone());
+        gameboy.set_script(Script::Commands(script));
+        for _ in 0..frames {
+            gameboy.step_frame();
+        }
+        (gameboy, output.text())
+    }
+
+    #[test_case("frame", Trigger::Frame(None); "every frame")]
+    #[test_case("frame 60", Trigger::Frame(Some(60)); "frame")]
+    #[test_case("write 0xc000", Trigger::Write(0xC000); "write")]
+    fn test_parse_trigger(text: &str, expected: Trigger) {
+        assert_eq!(Trigger::parse(text), Ok(expected));
+    }
+
+    #[test]
+    fn test_parse() {
+        let script = CommandScript::parse(
+            "# Comments and blank lines are skipped\n\
+             \n\
+             edit c000 01\n\
+             on frame 60: press start; release a\n\
+             on break 0x150 if a == 3: r\n",
+        )
+        .unwrap();
+        assert_eq!(script.setup, vec![Command::parse("edit c000 01").unwrap()]);
+        assert_eq!(
+            script.hooks[0],
+            Hook {
+                trigger: Trigger::Frame(Some(60)),
+                commands: vec![Command::Press(Button::Start), Command::Release(Button::A)],
+            }
+        );
+        assert!(
+            matches!(&script.hooks[1].trigger, Trigger::Break(breakpoint)
+            if breakpoint.to_string() == "0x0150 if a == 3")
+        );
+    }
+
+    #[test_case("on frame 60 press start"; "no colon")]
+    #[test_case("on vblank: r"; "unknown trigger")]
+    #[test_case("on frame soon: r"; "bad frame")]
+    #[test_case("on frame: continue"; "runs the cpu")]
+    #[test_case("on frame: press turbo"; "unknown button")]
+    #[test_case("step"; "setup runs the cpu")]
+    fn test_parse_invalid(text: &str) {
+        assert!(CommandScript::parse(text).is_err());
+    }
+
+    #[test]
+    fn test_line_number() {
+        assert_eq!(
+            CommandScript::parse("r\non frame: quit").err(),
+            Some(String::from("Line 2: \"quit\" can't be used in a script"))
+        );
+    }
+
+    #[test]
+    fn test_frame_hooks() {
+        let (_, output) = run(
+            vec![0; 0x8000],
+            "edit c000 2a\non frame 2: print [0xc000]\non frame: press a",
+            2,
+        );
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "[start] Wrote 1 bytes at 0xc000");
+        assert_eq!(lines[2], "[frame 1] Pressed A");
+        assert_eq!(lines[3], "[frame 2] 0x002a (42)");
+        assert_eq!(lines[4], "[frame 2] Pressed A");
+    }
+
+    #[test]
+    fn test_break_hook() {
+        let (_, output) = run(
+            vec![0; 0x8000],
+            "on break 0x0003: print pc\non break 0x0004 if a == 0x99: print pc",
+            1,
+        );
+        assert_eq!(output, "[break 0x0003] 0x0003 (3)\n");
+    }
+
+    #[test]
+    fn test_write_hook() {
+        // LD BC,0xC0C0; LD (BC),A
+        let mut rom = vec![0; 0x8000];
+        rom[..4].copy_from_slice(&[0x01, 0xC0, 0xC0, 0x02]);
+        let (_, output) = run(rom, "on write 0xc0c0: print pc\non write 0xc000: r", 1);
+        assert_eq!(output, "[write 0xc0c0 = 0x00] 0x0004 (4)\n");
+    }
+}
@@ -0,0 +1,425 @@
+// A terminal UI for the debugger, drawn with ratatui: panes for the
+// registers, the code from the program counter on, a window onto memory,
+// which can be edited, and the serial output, redrawn after every key and
+// every frame while the game runs. The terminal goes back to how it was
+// when the UI closes, even if it panics.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::Frame;
+
+use crate::debugger::{hex_line, Command, Debugger};
+use crate::disassembler::decode;
+use crate::gameboy::GameBoy;
+use crate::ppu::DOTS_PER_FRAME;
+use crate::symbols::Symbols;
+
+/// The width of the left column of panes, which fits a line of memory
+const WIDTH: u16 = 40;
+const CODE_LINES: usize = 8;
+const MEMORY_LINES: usize = 8;
+const BYTES_PER_LINE: u16 = 8;
+const SERIAL_LINES: usize = 8;
+
+const KEYS: &str = "s step n next f finish c run/stop b break j/k J/K memory g go to e edit q quit";
+
+/// How long to wait for a key while stopped before drawing again
+const IDLE: Duration = Duration::from_millis(100);
+
+/// The bytes sent over the serial port, kept for the serial pane
+#[derive(Clone, Default)]
+struct SerialBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SerialBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Puts the terminal back as it was when dropped, including when
+/// returning early with an error. ratatui restores it on a panic.
+struct Restore;
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+/// Draw `lines` in a box with a title
+fn pane(frame: &mut Frame, area: Rect, title: &str, lines: Vec<String>) {
+    let lines: Vec<Line> = lines.into_iter().map(Line::from).collect();
+    let block = Block::bordered().title(format!(" {} ", title));
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// What's being typed under the panes
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Prompt {
+    /// An address to show in the memory pane
+    GoTo,
+    /// An address and the bytes to write there
+    Edit,
+}
+
+impl Prompt {
+    fn label(self) -> &'static str {
+        match self {
+            Prompt::GoTo => "Go to address",
+            Prompt::Edit => "Edit address and bytes",
+        }
+    }
+}
+
+pub struct Tui {
+    debugger: Debugger,
+    serial: SerialBuffer,
+    /// The first address in the memory pane
+    memory_address: u16,
+    /// Running the game, rather than stopped between keys
+    running: bool,
+    /// What the last key did, shown under the panes
+    message: String,
+    /// What's been typed so far at a prompt, while there is one
+    input: Option<(Prompt, String)>,
+}
+
+impl Tui {
+    pub fn new(gameboy: &mut GameBoy, symbols: Symbols) -> Tui {
+        let serial = SerialBuffer::default();
+        gameboy.set_serial_output(Box::new(serial.clone()));
+        gameboy.track_calls();
+        Tui {
+            debugger: Debugger::with_symbols(symbols),
+            serial,
+            memory_address: 0xC000,
+            running: false,
+            message: String::new(),
+            input: None,
+        }
+    }
+
+    /// Run until `q` is pressed
+    pub fn run(&mut self, gameboy: &mut GameBoy) -> io::Result<()> {
+        let mut terminal = ratatui::try_init()?;
+        let restore = Restore;
+        loop {
+            terminal.draw(|frame| self.draw(frame, gameboy))?;
+            if self.running {
+                if let Some(breakpoint) = self.debugger.run_for(gameboy, DOTS_PER_FRAME) {
+                    self.message = format!("Stopped at breakpoint {}", breakpoint);
+                    self.running = false;
+                }
+            }
+            let wait = if self.running { Duration::ZERO } else { IDLE };
+            if !event::poll(wait)? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.press(gameboy, key) {
+                    break;
+                }
+            }
+        }
+        drop(restore);
+        gameboy.save_data();
+        Ok(())
+    }
+
+    fn draw(&self, frame: &mut Frame, gameboy: &mut GameBoy) {
+        let [top, bottom, message, keys] = Layout::vertical([
+            Constraint::Length(CODE_LINES as u16 + 2),
+            Constraint::Length(MEMORY_LINES as u16 + 2),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let columns = Layout::horizontal([Constraint::Length(WIDTH), Constraint::Min(0)]);
+        let [registers, code] = columns.areas(top);
+        let [memory, serial] = columns.areas(bottom);
+        pane(frame, registers, "Registers", self.registers(gameboy));
+        pane(frame, code, "Code", self.code(gameboy));
+        pane(frame, memory, "Memory", self.memory(gameboy));
+        pane(frame, serial, "Serial", self.serial());
+        let status = match &self.input {
+            Some((prompt, text)) => format!("{}: {}", prompt.label(), text),
+            None => self.message.clone(),
+        };
+        frame.render_widget(Paragraph::new(status), message);
+        frame.render_widget(Paragraph::new(KEYS), keys);
+    }
+
+    fn registers(&self, gameboy: &GameBoy) -> Vec<String> {
+        let r = gameboy.registers();
+        let flag = |bit: u8| r.f >> bit & 1;
+        vec![
+            format!("A  {:02X}   F  {:02X}", r.a, r.f),
+            format!("B  {:02X}   C  {:02X}", r.b, r.c),
+            format!("D  {:02X}   E  {:02X}", r.d, r.e),
+            format!("H  {:02X}   L  {:02X}", r.h, r.l),
+            format!("SP {:04X} PC {:04X}", r.sp, r.pc),
+            format!("Z{} N{} H{} C{}", flag(7), flag(6), flag(5), flag(4)),
+            String::from(if self.running { "Running" } else { "Stopped" }),
+        ]
+    }
+
+    /// The instructions from the program counter on. `>` marks the next
+    /// one to run and `*` a breakpoint.
+    fn code(&self, gameboy: &mut GameBoy) -> Vec<String> {
+        let pc = gameboy.registers().pc;
+        let mut lines = Vec::new();
+        let mut address = pc;
+        for _ in 0..CODE_LINES {
+            let bytes: Vec<u8> = (0..3)
+                .map(|offset| gameboy.peek(address.wrapping_add(offset)))
+                .collect();
+            let instruction = decode(address, &bytes);
+            lines.push(format!(
+                "{}{}{:04X}  {}",
+                if address == pc { '>' } else { ' ' },
+                if self.debugger.has_breakpoint(address) {
+                    '*'
+                } else {
+                    ' '
+                },
+                address,
+                instruction.mnemonic
+            ));
+            address = address.wrapping_add(instruction.size);
+        }
+        lines
+    }
+
+    fn memory(&self, gameboy: &mut GameBoy) -> Vec<String> {
+        let mut lines = Vec::new();
+        for line in 0..MEMORY_LINES as u16 {
+            let start = self.memory_address.wrapping_add(line * BYTES_PER_LINE);
+            lines.push(hex_line(gameboy, start, BYTES_PER_LINE));
+        }
+        lines
+    }
+
+    /// The last lines sent over the serial port
+    fn serial(&self) -> Vec<String> {
+        let bytes = self.serial.0.borrow();
+        let text: String = String::from_utf8_lossy(&bytes)
+            .chars()
+            .map(|c| match c {
+                '\n' => '\n',
+                c if c.is_control() => '.',
+                c => c,
+            })
+            .collect();
+        let serial_lines: Vec<&str> = text.lines().collect();
+        let skip = serial_lines.len().saturating_sub(SERIAL_LINES);
+        serial_lines[skip..]
+            .iter()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    /// Act on a key. Returns false to quit.
+    fn press(&mut self, gameboy: &mut GameBoy, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+        if let Some((prompt, text)) = &mut self.input {
+            match key.code {
+                KeyCode::Enter => {
+                    let (prompt, text) = (*prompt, text.clone());
+                    self.input = None;
+                    self.enter(gameboy, prompt, &text);
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Char(c) if c.is_ascii_hexdigit() || c == 'x' => text.push(c),
+                KeyCode::Char(' ') if *prompt == Prompt::Edit => text.push(' '),
+                _ => {}
+            }
+            return true;
+        }
+
+        self.message.clear();
+        let key = match key.code {
+            KeyCode::Char(key) => key,
+            _ => return true,
+        };
+        match key {
+            'q' => return false,
+            's' | 'n' | 'f' => {
+                self.running = false;
+                let command = match key {
+                    's' => Command::Step(1),
+                    'n' => Command::Next,
+                    _ => Command::Finish,
+                };
+                let output = self.debugger.execute(gameboy, command);
+                // Only say something if it stopped at a breakpoint
+                if output.starts_with("Stopped") {
+                    self.message = output.lines().next().unwrap_or_default().to_string();
+                }
+            }
+            'c' => self.running = !self.running,
+            'b' => {
+                let pc = gameboy.registers().pc;
+                self.message = match self.debugger.toggle_breakpoint(pc) {
+                    true => format!("Breakpoint at {:#06x}", pc),
+                    false => format!("Deleted the breakpoint at {:#06x}", pc),
+                };
+            }
+            'j' => self.scroll_memory(1),
+            'k' => self.scroll_memory(-1),
+            'J' => self.scroll_memory(MEMORY_LINES as i16),
+            'K' => self.scroll_memory(-(MEMORY_LINES as i16)),
+            'g' => self.input = Some((Prompt::GoTo, String::new())),
+            'e' => self.input = Some((Prompt::Edit, String::new())),
+            _ => {}
+        }
+        true
+    }
+
+    /// Act on what was typed at a prompt
+    fn enter(&mut self, gameboy: &mut GameBoy, prompt: Prompt, text: &str) {
+        match prompt {
+            Prompt::GoTo => match self.debugger.symbols().resolve(text) {
+                Ok(address) => self.memory_address = address,
+                Err(e) => self.message = e,
+            },
+            Prompt::Edit => match self.debugger.parse(&format!("edit {}", text)) {
+                Ok(command) => {
+                    let output = self.debugger.execute(gameboy, command);
+                    self.message = output.lines().next().unwrap_or_default().to_string();
+                }
+                Err(e) => self.message = e,
+            },
+        }
+    }
+
+    /// Move the memory pane by a number of lines
+    fn scroll_memory(&mut self, lines: i16) {
+        let offset = lines.wrapping_mul(BYTES_PER_LINE as i16);
+        self.memory_address = self.memory_address.wrapping_add(offset as u16);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    /// A GameBoy running a ROM of NOPs
+    fn gameboy() -> GameBoy {
+        GameBoy::new(vec![0; 0x8000])
+    }
+
+    /// Press keys typed as bytes, with \r for Enter, \x1b for Escape and
+    /// \x7f for Backspace
+    fn press_keys(tui: &mut Tui, gameboy: &mut GameBoy, keys: &[u8]) {
+        for &key in keys {
+            let code = match key {
+                b'\r' => KeyCode::Enter,
+                0x1B => KeyCode::Esc,
+                0x7F => KeyCode::Backspace,
+                key => KeyCode::Char(key as char),
+            };
+            assert!(tui.press(gameboy, KeyEvent::from(code)));
+        }
+    }
+
+    /// What the UI draws on an 80 by 24 terminal
+    fn screen(tui: &Tui, gameboy: &mut GameBoy) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal.draw(|frame| tui.draw(frame, gameboy)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(80)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_render() {
+        let mut gameboy = gameboy();
+        let mut tui = Tui::new(&mut gameboy, Symbols::default());
+        press_keys(&mut tui, &mut gameboy, b"sb");
+        gameboy.poke(0xC000, b'H');
+        let screen = screen(&tui, &mut gameboy);
+        assert!(screen.contains("SP 0000 PC 0001"), "{}", screen);
+        assert!(screen.contains(">*0001  NOP"), "{}", screen);
+        assert!(screen.contains("C000: 48 00"), "{}", screen);
+        assert!(screen.contains("Breakpoint at 0x0001"), "{}", screen);
+        assert!(screen.contains("┌ Serial "), "{}", screen);
+    }
+
+    #[test]
+    fn test_serial() {
+        let mut gameboy = gameboy();
+        let mut tui = Tui::new(&mut gameboy, Symbols::default());
+        for line in 0..10 {
+            writeln!(tui.serial, "line {}", line).unwrap();
+        }
+        let serial = tui.serial();
+        assert_eq!(serial.len(), SERIAL_LINES);
+        assert_eq!(serial.last().unwrap(), "line 9");
+    }
+
+    #[test]
+    fn test_memory_keys() {
+        let mut gameboy = gameboy();
+        let mut tui = Tui::new(&mut gameboy, Symbols::default());
+        press_keys(&mut tui, &mut gameboy, b"jjk");
+        assert_eq!(tui.memory_address, 0xC008);
+        press_keys(&mut tui, &mut gameboy, b"K");
+        assert_eq!(tui.memory_address, 0xBFC8);
+
+        press_keys(&mut tui, &mut gameboy, b"gFF81\x7f0\r");
+        assert_eq!(tui.memory_address, 0xFF80);
+        assert!(tui.input.is_none());
+        // Escape leaves the address as it was
+        press_keys(&mut tui, &mut gameboy, b"g12\x1b");
+        assert_eq!(tui.memory_address, 0xFF80);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut gameboy = gameboy();
+        let mut tui = Tui::new(&mut gameboy, Symbols::default());
+        press_keys(&mut tui, &mut gameboy, b"ec001 12 3");
+        assert!(screen(&tui, &mut gameboy).contains("Edit address and bytes: c001 12 3"));
+        press_keys(&mut tui, &mut gameboy, b"4\r");
+        assert_eq!(tui.message, "Wrote 2 bytes at 0xc001");
+        assert_eq!(
+            tui.memory(&mut gameboy)[0],
+            "C000: 00 12 34 00 00 00 00 00 ..4....."
+        );
+
+        press_keys(&mut tui, &mut gameboy, b"ec001\r");
+        assert_eq!(tui.message, "Expected the bytes to write");
+    }
+
+    #[test]
+    fn test_quit() {
+        let mut gameboy = gameboy();
+        let mut tui = Tui::new(&mut gameboy, Symbols::default());
+        assert!(!tui.press(&mut gameboy, KeyEvent::from(KeyCode::Char('q'))));
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(!tui.press(&mut gameboy, ctrl_c));
+    }
+}
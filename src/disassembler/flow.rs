@@ -0,0 +1,185 @@
+// Finds which ROM bytes are code by following the CPU's control flow from
+// the entry points: through jumps, calls and RSTs, and on past any that
+// might not be taken. Whatever isn't reached is data.
+//
+// Code in bank 0 can jump into any bank at 0x4000, so the bank switches
+// are followed too, when a bank number is loaded into A and written to the
+// MBC, like `LD A, $05; LD ($2000), A`. Without one, bank 1 is assumed.
+// Each instruction is only followed once, with the first bank seen.
+
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::disassembler::{decode, Location};
+use crate::prelude::*;
+
+/// Where the CPU starts running the cartridge, after the boot ROM
+pub const ENTRY_POINT: u16 = 0x0100;
+/// Where VBlank, LCD STAT, timer, serial and joypad interrupts jump to
+pub const INTERRUPT_VECTORS: [u16; 5] = [0x0040, 0x0048, 0x0050, 0x0058, 0x0060];
+
+/// Where an instruction the flow reaches is
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Reached {
+    address: u16,
+    /// The bank mapped at 0x4000
+    bank: usize,
+    /// What was last loaded into A, if the instruction before loaded it
+    a: Option<u8>,
+}
+
+/// The offset in the ROM of an address, with `bank` mapped at 0x4000,
+/// or None if it isn't in the ROM
+fn mapped_offset(rom: &[u8], address: u16, bank: usize) -> Option<usize> {
+    let offset = match address {
+        0x0000..=0x3FFF => address as usize,
+        0x4000..=0x7FFF => bank * ROM_BANK_SIZE + (address as usize - 0x4000),
+        _ => return None,
+    };
+    Some(offset).filter(|&offset| offset < rom.len())
+}
+
+/// Where control can go after the instruction at `address`, and whether
+/// it can go on to the next one
+fn successors(address: u16, bytes: &[u8]) -> (Option<u16>, bool) {
+    let byte = |index: usize| bytes.get(index).copied().unwrap_or(0);
+    let a16 = u16::from_le_bytes([byte(1), byte(2)]);
+    let relative = address.wrapping_add(2).wrapping_add(byte(1) as i8 as u16);
+    match byte(0) {
+        // JR, JP
+        0x18 => (Some(relative), false),
+        0xC3 => (Some(a16), false),
+        // JR cc
+        0x20 | 0x28 | 0x30 | 0x38 => (Some(relative), true),
+        // JP cc, CALL cc, CALL
+        0xC2 | 0xCA | 0xD2 | 0xDA | 0xC4 | 0xCC | 0xD4 | 0xDC | 0xCD => (Some(a16), true),
+        // RST
+        opcode if opcode & 0xC7 == 0xC7 => (Some((opcode & 0x38) as u16), true),
+        // RET, RETI, JP HL, and the opcodes that lock up the CPU
+        0xC9 | 0xD9 | 0xE9 | 0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4
+        | 0xFC | 0xFD => (None, false),
+        _ => (None, true),
+    }
+}
+
+/// Which ROM offsets start an instruction the CPU can reach from `entries`
+pub fn find_code(rom: &[u8], entries: &[Location]) -> Vec<bool> {
+    let mut code = vec![false; rom.len()];
+    let mut pending: Vec<Reached> = entries
+        .iter()
+        .map(|&(bank, address)| Reached {
+            address,
+            bank: bank.max(1),
+            a: None,
+        })
+        .collect();
+    while let Some(reached) = pending.pop() {
+        let offset = match mapped_offset(rom, reached.address, reached.bank) {
+            Some(offset) if !code[offset] => offset,
+            _ => continue,
+        };
+        code[offset] = true;
+        // Code in a switchable bank is only run with that bank mapped
+        let bank = match offset / ROM_BANK_SIZE {
+            0 => reached.bank,
+            bank => bank,
+        };
+        let bytes = &rom[offset..rom.len().min(offset + 3)];
+        let size = decode(reached.address, bytes).size;
+
+        let mut next = Reached {
+            address: reached.address.wrapping_add(size),
+            bank,
+            a: None,
+        };
+        match bytes {
+            [0x3E, value, ..] => next.a = Some(*value),
+            [0xEA, low, high] => {
+                if let (0x2000..=0x3FFF, Some(a)) = (u16::from_le_bytes([*low, *high]), reached.a) {
+                    next.bank = (a as usize).max(1);
+                }
+            }
+            _ => {}
+        }
+        let (target, falls_through) = successors(reached.address, bytes);
+        if let Some(address) = target {
+            pending.push(Reached {
+                address,
+                a: None,
+                ..next
+            });
+        }
+        if falls_through {
+            pending.push(next);
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The offsets of the instructions found from 0
+    fn code_at(rom: &[u8]) -> Vec<usize> {
+        let code = find_code(rom, &[(0, 0)]);
+        (0..rom.len()).filter(|&offset| code[offset]).collect()
+    }
+
+    #[test]
+    fn test_jumps() {
+        let mut rom = vec![0xFF; 0x20];
+        // JR NZ, $0005; JP $0010; RET, then CALL $0014; RET, and RET
+        rom[..2].copy_from_slice(&[0x20, 0x03]);
+        rom[2..5].copy_from_slice(&[0xC3, 0x10, 0x00]);
+        rom[0x05] = 0xC9;
+        rom[0x10..0x13].copy_from_slice(&[0xCD, 0x14, 0x00]);
+        rom[0x13] = 0xC9;
+        rom[0x14] = 0xC9;
+        assert_eq!(code_at(&rom), vec![0x00, 0x02, 0x05, 0x10, 0x13, 0x14]);
+    }
+
+    #[test]
+    fn test_rst() {
+        let mut rom = vec![0x00; 0x40];
+        // RST $08 from 0, and 0x08 returns
+        rom[0] = 0xCF;
+        rom[1] = 0xC9;
+        rom[0x08] = 0xC9;
+        assert_eq!(code_at(&rom), vec![0x00, 0x01, 0x08]);
+    }
+
+    #[test]
+    fn test_bank_switch() {
+        let mut rom = vec![0xC9; 4 * ROM_BANK_SIZE];
+        // LD A, $03; LD ($2000), A; JP $4000
+        rom[..2].copy_from_slice(&[0x3E, 0x03]);
+        rom[2..5].copy_from_slice(&[0xEA, 0x00, 0x20]);
+        rom[5..8].copy_from_slice(&[0xC3, 0x00, 0x40]);
+        assert_eq!(code_at(&rom), vec![0x00, 0x02, 0x05, 3 * ROM_BANK_SIZE]);
+    }
+
+    #[test]
+    fn test_banked_entry() {
+        let mut rom = vec![0xC9; 4 * ROM_BANK_SIZE];
+        // NOP; JP $4000, staying in bank 2
+        rom[2 * ROM_BANK_SIZE..2 * ROM_BANK_SIZE + 4].copy_from_slice(&[0x00, 0xC3, 0x00, 0x40]);
+        let code = find_code(&rom, &[(2, 0x4000)]);
+        let code: Vec<usize> = (0..rom.len()).filter(|&offset| code[offset]).collect();
+        let start = 2 * ROM_BANK_SIZE;
+        assert_eq!(code, vec![start, start + 1]);
+    }
+
+    #[test]
+    fn test_default_bank() {
+        let mut rom = vec![0xC9; 4 * ROM_BANK_SIZE];
+        // JP $4000
+        rom[..3].copy_from_slice(&[0xC3, 0x00, 0x40]);
+        assert_eq!(code_at(&rom), vec![0x00, ROM_BANK_SIZE]);
+    }
+
+    #[test]
+    fn test_outside_the_rom() {
+        // JP $FF80, to a routine copied to HRAM
+        let rom = vec![0xC3, 0x80, 0xFF];
+        assert_eq!(code_at(&rom), vec![0x00]);
+    }
+}
@@ -0,0 +1,138 @@
+// The listing as JSON, for tools and editors. It's an array with a record
+// on each line, like
+//
+//   {"address": 257, "bank": 0, "label": null, "bytes": [195, 80, 1],
+//    "mnemonic": "JP", "operands": ["Main"], "size": 3, "cycles": 16,
+//    "cycles_taken": null, "comment": null}
+//
+// Data has the mnemonic DB, its bytes as the operands, and no cycles. The
+// header's fields have their names as the comment.
+
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+use crate::disassembler::{cycles, Line};
+use crate::prelude::*;
+
+/// Quote a string, escaping what JSON needs escaped
+pub fn string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn array(values: impl Iterator<Item = String>) -> String {
+    format!("[{}]", values.collect::<Vec<_>>().join(", "))
+}
+
+fn optional_string(text: Option<&str>) -> String {
+    text.map_or_else(|| String::from("null"), string)
+}
+
+fn number(value: Option<u8>) -> String {
+    value.map_or_else(|| String::from("null"), |value| value.to_string())
+}
+
+/// One line of the listing as a JSON object
+pub fn record(line: &Line) -> String {
+    let (mnemonic, operands) = line
+        .instruction
+        .mnemonic
+        .split_once(' ')
+        .unwrap_or((&line.instruction.mnemonic, ""));
+    let operands = operands
+        .split(", ")
+        .filter(|operand| !operand.is_empty())
+        .map(string);
+    let (cycles, cycles_taken) = match line.code {
+        true => {
+            let (cycles, taken) = cycles(&line.bytes);
+            (Some(cycles), taken)
+        }
+        false => (None, None),
+    };
+    format!(
+        "{{\"address\": {}, \"bank\": {}, \"label\": {}, \"bytes\": {}, \"mnemonic\": {}, \
+         \"operands\": {}, \"size\": {}, \"cycles\": {}, \"cycles_taken\": {}, \"comment\": {}}}",
+        line.address,
+        line.bank,
+        optional_string(line.label.as_deref()),
+        array(line.bytes.iter().map(u8::to_string)),
+        string(mnemonic),
+        array(operands),
+        line.bytes.len(),
+        number(cycles),
+        number(cycles_taken),
+        optional_string(line.comment.as_deref())
+    )
+}
+
+/// Write the listing as a JSON array, a record a line
+#[cfg(feature = "std")]
+pub fn write(lines: &[Line], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (index, line) in lines.iter().enumerate() {
+        let separator = if index + 1 < lines.len() { "," } else { "" };
+        writeln!(out, "{}{}", record(line), separator)?;
+    }
+    writeln!(out, "]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::listing;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn test_record() {
+        let rom = [0xC2, 0x50, 0x01, 0xCE, 0xED];
+        let symbols = Symbols::parse("00:0000 Start").unwrap();
+        let lines = listing(&rom, &[true, false, false, false, false], 0..5, &symbols);
+        assert_eq!(
+            record(&lines[0]),
+            "{\"address\": 0, \"bank\": 0, \"label\": \"Start\", \"bytes\": [194, 80, 1], \
+             \"mnemonic\": \"JP\", \"operands\": [\"NZ\", \"$0150\"], \"size\": 3, \"cycles\": 12, \
+             \"cycles_taken\": 16, \"comment\": null}"
+        );
+        assert_eq!(
+            record(&lines[1]),
+            "{\"address\": 3, \"bank\": 0, \"label\": null, \"bytes\": [206, 237], \
+             \"mnemonic\": \"DB\", \"operands\": [\"$CE\", \"$ED\"], \"size\": 2, \"cycles\": null, \
+             \"cycles_taken\": null, \"comment\": null}"
+        );
+    }
+
+    #[test]
+    fn test_no_operands() {
+        let lines = listing(&[0x00], &[true], 0..1, &Symbols::default());
+        assert!(record(&lines[0]).contains("\"mnemonic\": \"NOP\", \"operands\": []"));
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_write() {
+        let lines = listing(&[0x00, 0x00], &[true, true], 0..2, &Symbols::default());
+        let mut out = Vec::new();
+        write(&lines, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let records: Vec<&str> = text.lines().collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!((records[0], records[3]), ("[", "]"));
+        assert!(records[1].ends_with("},"));
+        assert!(records[2].ends_with('}'));
+    }
+}
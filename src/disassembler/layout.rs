@@ -0,0 +1,209 @@
+// The parts of a ROM that are always in the same place: the RST and
+// interrupt vectors and the entry point, which get labels when the symbols
+// don't name them, and the header, which is shown as its fields rather
+// than as instructions or plain data.
+//
+//   Header:
+//   00:0104            DB $CE, $ED, $66, $66, $CC, $0D, $00, $0B  ; Nintendo logo
+//   ...
+//   00:0134            DB "TETRIS", $00, $00, $00, $00, $00, $00, $00, $00, $00  ; Title
+
+use core::ops::Range;
+
+use crate::cartridge::header::{
+    global_checksum, header_checksum, CGB_FLAG_ADDRESS, GLOBAL_CHECKSUM_ADDRESS,
+    HEADER_CHECKSUM_ADDRESS, HEADER_END, LOGO_ADDRESS, NINTENDO_LOGO, TITLE_ADDRESS,
+};
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::disassembler::flow::{ENTRY_POINT, INTERRUPT_VECTORS};
+use crate::disassembler::{Instruction, Line, DATA_BYTES_PER_LINE};
+use crate::prelude::*;
+
+/// The names of the interrupt vectors, in the same order
+const INTERRUPT_NAMES: [&str; 5] = ["VBlank", "LCDStat", "Timer", "Serial", "Joypad"];
+
+/// The header's fields after the entry point, and where they start
+const FIELDS: [(&str, usize); 13] = [
+    ("Nintendo logo", LOGO_ADDRESS),
+    ("Title", TITLE_ADDRESS),
+    ("CGB flag", CGB_FLAG_ADDRESS),
+    ("New licensee code", 0x0144),
+    ("SGB flag", 0x0146),
+    ("Cartridge type", 0x0147),
+    ("ROM size", 0x0148),
+    ("RAM size", 0x0149),
+    ("Destination", 0x014A),
+    ("Old licensee code", 0x014B),
+    ("Version", 0x014C),
+    ("Header checksum", HEADER_CHECKSUM_ADDRESS),
+    ("Global checksum", GLOBAL_CHECKSUM_ADDRESS),
+];
+
+/// The name of a fixed place in bank 0, like `RST_38` or `VBlank`
+pub fn label(offset: usize) -> Option<String> {
+    if offset >= ROM_BANK_SIZE {
+        return None;
+    }
+    let address = offset as u16;
+    match address {
+        _ if address == ENTRY_POINT => Some(String::from("EntryPoint")),
+        _ if address as usize == LOGO_ADDRESS => Some(String::from("Header")),
+        0x00..=0x38 if address.is_multiple_of(8) => Some(format!("RST_{:02X}", address)),
+        _ => INTERRUPT_VECTORS
+            .iter()
+            .position(|&vector| vector == address)
+            .map(|index| String::from(INTERRUPT_NAMES[index])),
+    }
+}
+
+/// Where the header field around `offset` starts and ends, and its name
+fn field(offset: usize) -> Option<(Range<usize>, &'static str)> {
+    let index = FIELDS.iter().rposition(|&(_, start)| start <= offset)?;
+    let (name, start) = FIELDS[index];
+    let end = FIELDS.get(index + 1).map_or(HEADER_END, |&(_, end)| end);
+    Some((start..end, name)).filter(|_| offset < end)
+}
+
+/// What's wrong with a field, if the boot ROM or a checksum says it's wrong
+fn problem(rom: &[u8], start: usize) -> Option<String> {
+    match start {
+        LOGO_ADDRESS if rom[LOGO_ADDRESS..TITLE_ADDRESS] != NINTENDO_LOGO => {
+            Some(String::from("differs from the boot ROM's"))
+        }
+        HEADER_CHECKSUM_ADDRESS if rom[start] != header_checksum(rom) => {
+            Some(format!("should be ${:02X}", header_checksum(rom)))
+        }
+        GLOBAL_CHECKSUM_ADDRESS
+            if u16::from_be_bytes([rom[start], rom[start + 1]]) != global_checksum(rom) =>
+        {
+            Some(format!("should be ${:04X}", global_checksum(rom)))
+        }
+        _ => None,
+    }
+}
+
+/// Bytes as DB operands, with runs of printable characters in quotes
+fn operands(bytes: &[u8], text: bool) -> String {
+    let mut operands = Vec::new();
+    let mut quoted = String::new();
+    for &byte in bytes {
+        if text && (0x20..0x7F).contains(&byte) && byte != b'"' {
+            quoted.push(byte as char);
+            continue;
+        }
+        if !quoted.is_empty() {
+            operands.push(format!("\"{}\"", core::mem::take(&mut quoted)));
+        }
+        operands.push(format!("${:02X}", byte));
+    }
+    if !quoted.is_empty() {
+        operands.push(format!("\"{}\"", quoted));
+    }
+    operands.join(", ")
+}
+
+/// The header from `offset` up to the end of its field, or of a line's
+/// worth of the logo, stopping before `end`. None outside the header, or
+/// if the ROM is too small to have one.
+pub fn header_line(rom: &[u8], offset: usize, end: usize) -> Option<Line> {
+    if rom.len() < HEADER_END {
+        return None;
+    }
+    let (field, name) = field(offset)?;
+    let mut line_end = field.end.min(end);
+    if field.start == LOGO_ADDRESS {
+        line_end = line_end.min(offset + DATA_BYTES_PER_LINE);
+    }
+    let bytes = rom[offset..line_end].to_vec();
+    let text = matches!(field.start, TITLE_ADDRESS | 0x0144);
+    let comment = match (offset == field.start, problem(rom, field.start)) {
+        (false, _) => None,
+        (true, None) => Some(String::from(name)),
+        (true, Some(problem)) => Some(format!("{}, {}", name, problem)),
+    };
+    Some(Line {
+        bank: 0,
+        address: offset as u16,
+        instruction: Instruction {
+            mnemonic: format!("DB {}", operands(&bytes, text)),
+            size: bytes.len() as u16,
+        },
+        bytes,
+        code: false,
+        label: None,
+        comment,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(0x0000, Some("RST_00"); "rst 00")]
+    #[test_case(0x0038, Some("RST_38"); "rst 38")]
+    #[test_case(0x0040, Some("VBlank"); "vblank")]
+    #[test_case(0x0060, Some("Joypad"); "joypad")]
+    #[test_case(0x0100, Some("EntryPoint"); "entry point")]
+    #[test_case(0x0104, Some("Header"); "header")]
+    #[test_case(0x0010 + 1, None; "between the vectors")]
+    #[test_case(0x4040, None; "another bank")]
+    fn test_label(offset: usize, expected: Option<&str>) {
+        assert_eq!(label(offset).as_deref(), expected);
+    }
+
+    #[test_case(&[0x54, 0x45, 0x54, 0x52, 0x49, 0x53, 0x00], true, "\"TETRIS\", $00"; "title")]
+    #[test_case(&[0x41, 0x22, 0x42], true, "\"A\", $22, \"B\""; "quote")]
+    #[test_case(&[0x54, 0x45], false, "$54, $45"; "not text")]
+    fn test_operands(bytes: &[u8], text: bool, expected: &str) {
+        assert_eq!(operands(bytes, text), expected);
+    }
+
+    #[test]
+    fn test_header_line() {
+        let mut rom = vec![0; 0x8000];
+        rom[LOGO_ADDRESS..TITLE_ADDRESS].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE_ADDRESS..TITLE_ADDRESS + 6].copy_from_slice(b"TETRIS");
+        rom[0x0147] = 0x01;
+        rom[HEADER_CHECKSUM_ADDRESS] = header_checksum(&rom);
+
+        let logo = header_line(&rom, LOGO_ADDRESS, rom.len()).unwrap();
+        assert_eq!(logo.bytes.len(), DATA_BYTES_PER_LINE);
+        assert_eq!(logo.comment.as_deref(), Some("Nintendo logo"));
+        let rest = header_line(&rom, LOGO_ADDRESS + 8, rom.len()).unwrap();
+        assert_eq!(rest.comment, None);
+
+        let title = header_line(&rom, TITLE_ADDRESS + 2, rom.len()).unwrap();
+        assert_eq!(
+            title.instruction.mnemonic,
+            "DB \"TRIS\", $00, $00, $00, $00, $00, $00, $00, $00, $00"
+        );
+        let cartridge_type = header_line(&rom, 0x0147, rom.len()).unwrap();
+        assert_eq!(cartridge_type.instruction.mnemonic, "DB $01");
+        assert_eq!(cartridge_type.comment.as_deref(), Some("Cartridge type"));
+        let checksum = header_line(&rom, HEADER_CHECKSUM_ADDRESS, rom.len()).unwrap();
+        assert_eq!(checksum.comment.as_deref(), Some("Header checksum"));
+        let global = header_line(&rom, GLOBAL_CHECKSUM_ADDRESS, rom.len()).unwrap();
+        assert_eq!(global.bytes.len(), 2);
+        assert_eq!(
+            global.comment,
+            Some(format!(
+                "Global checksum, should be ${:04X}",
+                global_checksum(&rom)
+            ))
+        );
+
+        assert_eq!(header_line(&rom, HEADER_END, rom.len()), None);
+        assert_eq!(header_line(&rom[..0x0140], LOGO_ADDRESS, 0x0140), None);
+    }
+
+    #[test]
+    fn test_wrong_logo() {
+        let rom = vec![0; 0x8000];
+        let logo = header_line(&rom, LOGO_ADDRESS, rom.len()).unwrap();
+        assert_eq!(
+            logo.comment.as_deref(),
+            Some("Nintendo logo, differs from the boot ROM's")
+        );
+    }
+}
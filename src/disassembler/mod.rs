@@ -0,0 +1,622 @@
+// Turns machine code back into assembly, one instruction at a time.
+// Opcodes are split into the same x, y, z, p and q fields the CPU decodes:
+// https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
+
+pub mod flow;
+pub mod json;
+pub mod layout;
+
+use core::fmt;
+use core::ops::Range;
+
+use crate::cartridge::header::HEADER_END;
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::cpu_core::opcode::{Opcode, ALU, BIT_OPS, CC, PREFIX_CB, R, ROT, RP, RP2};
+use crate::disassembler::flow::{find_code, ENTRY_POINT, INTERRUPT_VECTORS};
+use crate::mmu::trace::parse_address;
+use crate::prelude::*;
+use crate::symbols::Symbols;
+
+/// The one-byte instructions with x=0, z=7
+const ACCUMULATOR_OPS: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub mnemonic: String,
+    /// Length in bytes, including the opcode
+    pub size: u16,
+}
+
+/// How the disassemble subcommand prints the listing
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Text,
+    /// A JSON array with a record for each line
+    Json,
+}
+
+/// Parse a listing format as given on the command line
+pub fn parse_format(value: &str) -> Result<Format, String> {
+    match value.trim() {
+        "text" => Ok(Format::Text),
+        "json" => Ok(Format::Json),
+        other => Err(format!("Unknown format {:?}, expected text or json", other)),
+    }
+}
+
+/// A bank, and an address the CPU sees in it
+pub type Location = (usize, u16);
+
+/// How many bytes of data go on a line
+const DATA_BYTES_PER_LINE: usize = 8;
+
+/// An instruction or some data in a ROM listing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub bank: usize,
+    /// Where the CPU sees it, with banks past 0 at 0x4000
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub instruction: Instruction,
+    /// An instruction, rather than data
+    pub code: bool,
+    /// The name of the label here, if there's one
+    pub label: Option<String>,
+    /// What the bytes are, for the header's fields
+    pub comment: Option<String>,
+}
+
+impl fmt::Display for Line {
+    /// Eg `01:4000  C3 50 01  JP $0150`, after the label on a line of its
+    /// own if there is one. Data has its bytes in the DB, and any comment
+    /// goes at the end.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?;
+        }
+        let bytes: Vec<String> = match self.code {
+            true => self.bytes.iter().map(|b| format!("{:02X}", b)).collect(),
+            false => Vec::new(),
+        };
+        write!(
+            f,
+            "{:02X}:{:04X}  {:<8}  {}",
+            self.bank,
+            self.address,
+            bytes.join(" "),
+            self.instruction.mnemonic
+        )?;
+        match &self.comment {
+            Some(comment) => write!(f, "  ; {}", comment),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The code reached from the entry point, the interrupt vectors and any
+/// other `entries`, and the data in between, for the ROM offsets in `range`
+pub fn disassemble(
+    rom: &[u8],
+    entries: &[Location],
+    range: Range<usize>,
+    symbols: &Symbols,
+) -> Vec<Line> {
+    let mut all_entries = vec![(0, ENTRY_POINT)];
+    all_entries.extend(INTERRUPT_VECTORS.iter().map(|&address| (0, address)));
+    all_entries.extend_from_slice(entries);
+    listing(rom, &find_code(rom, &all_entries), range, symbols)
+}
+
+/// The bank of a ROM offset, and the address the CPU sees it at
+fn location(offset: usize) -> Location {
+    match offset / ROM_BANK_SIZE {
+        0 => (0, offset as u16),
+        bank => (bank, (0x4000 + offset % ROM_BANK_SIZE) as u16),
+    }
+}
+
+/// The ROM offset of an address in a bank
+pub fn rom_offset(bank: usize, address: u16) -> usize {
+    match bank {
+        0 => address as usize,
+        _ => bank * ROM_BANK_SIZE + (address as usize - 0x4000),
+    }
+}
+
+/// Parse a place in the ROM: a label, a bank and address like `03:4123`,
+/// or an address, which is in bank 1 from 0x4000 on
+pub fn parse_location(text: &str, symbols: &Symbols) -> Result<Location, String> {
+    let text = text.trim();
+    let (bank, address) = match (symbols.lookup(text), text.split_once(':')) {
+        (Some(location), _) => location,
+        (None, Some((bank, address))) => (
+            usize::from_str_radix(bank, 16).map_err(|_| format!("Invalid bank: {:?}", bank))?,
+            parse_address(address)?,
+        ),
+        (None, None) => {
+            let address = parse_address(text)?;
+            (if address < 0x4000 { 0 } else { 1 }, address)
+        }
+    };
+    match (bank, address) {
+        (0, 0x0000..=0x3FFF) | (1.., 0x4000..=0x7FFF) => Ok((bank, address)),
+        _ => Err(format!(
+            "{:?} isn't in the ROM: bank 0 is at 0000-3FFF and the others at 4000-7FFF",
+            text
+        )),
+    }
+}
+
+/// Put the names of labels in place of the addresses in a mnemonic,
+/// eg `CALL $0150` becomes `CALL Main`
+fn name_addresses(mnemonic: &str, bank: usize, symbols: &Symbols) -> String {
+    let mut named = String::new();
+    let mut rest = mnemonic;
+    while let Some(start) = rest.find('$') {
+        named.push_str(&rest[..start]);
+        let digits = &rest[start + 1..];
+        let length = digits
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(digits.len());
+        let name = match length {
+            4 => u16::from_str_radix(&digits[..4], 16)
+                .ok()
+                .and_then(|address| symbols.name_at(address, bank)),
+            _ => None,
+        };
+        match name {
+            Some(name) => named.push_str(name),
+            None => named.push_str(&rest[start..start + 1 + length]),
+        }
+        rest = &digits[length..];
+    }
+    named.push_str(rest);
+    named
+}
+
+/// List the ROM offsets in `range`, decoding instructions at the ones
+/// marked as `code` and showing the rest as data, with the labels in `symbols`.
+/// The vectors and entry point are labelled if `symbols` doesn't name them,
+/// and the header is shown as its fields.
+pub fn listing(rom: &[u8], code: &[bool], range: Range<usize>, symbols: &Symbols) -> Vec<Line> {
+    let label = |offset: usize| {
+        let (bank, address) = location(offset);
+        match symbols.name(bank, address) {
+            Some(name) => Some(String::from(name)),
+            None => layout::label(offset),
+        }
+    };
+    let mut lines = Vec::new();
+    let end = range.end.min(rom.len());
+    let mut offset = range.start;
+    while offset < end {
+        let (bank, address) = location(offset);
+        let bank_end = ((bank + 1) * ROM_BANK_SIZE).min(rom.len());
+        // The header's fields stop at any instruction, in case it's run as code
+        let field_end = (offset + 1..end.min(HEADER_END))
+            .find(|&offset| code[offset])
+            .unwrap_or(end);
+        let line = if code[offset] {
+            // An instruction can't run on into the next bank, which may not be the one mapped
+            let bytes = &rom[offset..bank_end.min(offset + 3)];
+            let mut instruction = decode(address, bytes);
+            instruction.mnemonic = name_addresses(&instruction.mnemonic, bank, symbols);
+            let size = (instruction.size as usize).min(bytes.len());
+            Line {
+                bank,
+                address,
+                bytes: bytes[..size].to_vec(),
+                instruction,
+                code: true,
+                label: label(offset),
+                comment: None,
+            }
+        } else if let Some(line) = layout::header_line(rom, offset, field_end) {
+            Line {
+                label: label(offset),
+                ..line
+            }
+        } else {
+            // Data stops at the next instruction or label
+            let line_end = bank_end.min(end).min(offset + DATA_BYTES_PER_LINE);
+            let end = (offset + 1..line_end)
+                .find(|&offset| code[offset] || label(offset).is_some())
+                .unwrap_or(line_end);
+            let bytes = rom[offset..end].to_vec();
+            let values: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+            Line {
+                bank,
+                address,
+                instruction: Instruction {
+                    mnemonic: format!("DB {}", values.join(", ")),
+                    size: bytes.len() as u16,
+                },
+                bytes,
+                code: false,
+                label: label(offset),
+                comment: None,
+            }
+        };
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+/// Decode the instruction at `address`, which starts with `bytes`.
+/// Fewer than 3 bytes are fine if the instruction is shorter.
+pub fn decode(address: u16, bytes: &[u8]) -> Instruction {
+    let byte = |index: usize| bytes.get(index).copied().unwrap_or(0);
+    let opcode = byte(0);
+    let d8 = format!("${:02X}", byte(1));
+    let a16 = format!("${:04X}", u16::from_le_bytes([byte(1), byte(2)]));
+    let e8 = byte(1) as i8;
+    // Where a relative jump lands, from the end of the instruction
+    let relative = format!("${:04X}", address.wrapping_add(2).wrapping_add(e8 as u16));
+
+    let Opcode { x, y, z, p, q } = Opcode::new(opcode);
+    let (y, z, p) = (y as usize, z as usize, p as usize);
+
+    let (mnemonic, size) = match (x, z) {
+        (0, 0) => match y {
+            0 => (String::from("NOP"), 1),
+            1 => (format!("LD ({}), SP", a16), 3),
+            2 => (String::from("STOP"), 2),
+            3 => (format!("JR {}", relative), 2),
+            _ => (format!("JR {}, {}", CC[y - 4], relative), 2),
+        },
+        (0, 1) if q == 0 => (format!("LD {}, {}", RP[p], a16), 3),
+        (0, 1) => (format!("ADD HL, {}", RP[p]), 1),
+        (0, 2) => {
+            let address = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            match q {
+                0 => (format!("LD {}, A", address), 1),
+                _ => (format!("LD A, {}", address), 1),
+            }
+        }
+        (0, 3) if q == 0 => (format!("INC {}", RP[p]), 1),
+        (0, 3) => (format!("DEC {}", RP[p]), 1),
+        (0, 4) => (format!("INC {}", R[y]), 1),
+        (0, 5) => (format!("DEC {}", R[y]), 1),
+        (0, 6) => (format!("LD {}, {}", R[y], d8), 2),
+        (0, _) => (String::from(ACCUMULATOR_OPS[y]), 1),
+        (1, 6) if y == 6 => (String::from("HALT"), 1),
+        (1, _) => (format!("LD {}, {}", R[y], R[z]), 1),
+        (2, _) => (format!("{} {}", ALU[y], R[z]), 1),
+        (_, 0) => match y {
+            0..=3 => (format!("RET {}", CC[y]), 1),
+            4 => (format!("LDH ({}), A", d8), 2),
+            5 => (format!("ADD SP, {}", e8), 2),
+            6 => (format!("LDH A, ({})", d8), 2),
+            _ => (format!("LD HL, SP{:+}", e8), 2),
+        },
+        (_, 1) if q == 0 => (format!("POP {}", RP2[p]), 1),
+        (_, 1) => match p {
+            0 => (String::from("RET"), 1),
+            1 => (String::from("RETI"), 1),
+            2 => (String::from("JP HL"), 1),
+            _ => (String::from("LD SP, HL"), 1),
+        },
+        (_, 2) => match y {
+            0..=3 => (format!("JP {}, {}", CC[y], a16), 3),
+            4 => (String::from("LD (C), A"), 1),
+            5 => (format!("LD ({}), A", a16), 3),
+            6 => (String::from("LD A, (C)"), 1),
+            _ => (format!("LD A, ({})", a16), 3),
+        },
+        (_, 3) => match y {
+            0 => (format!("JP {}", a16), 3),
+            1 => (prefixed(byte(1)), 2),
+            6 => (String::from("DI"), 1),
+            7 => (String::from("EI"), 1),
+            _ => invalid(opcode),
+        },
+        (_, 4) if y < 4 => (format!("CALL {}, {}", CC[y], a16), 3),
+        (_, 5) if q == 0 => (format!("PUSH {}", RP2[p]), 1),
+        (_, 5) if p == 0 => (format!("CALL {}", a16), 3),
+        (_, 6) => (format!("{} {}", ALU[y], d8), 2),
+        (_, 7) => (format!("RST ${:02X}", y * 8), 1),
+        _ => invalid(opcode),
+    };
+    Instruction { mnemonic, size }
+}
+
+/// The instruction picked by the byte after the CB prefix
+fn prefixed(opcode: u8) -> String {
+    let Opcode { x, y, z, .. } = Opcode::new(opcode);
+    let register = R[z as usize];
+    match x {
+        0 => format!("{} {}", ROT[y as usize], register),
+        _ => format!("{} {}, {}", BIT_OPS[x as usize - 1], y, register),
+    }
+}
+
+/// How many T-cycles the instruction starting with `bytes` takes, and for
+/// a conditional jump, call or return, how many when it's taken
+pub fn cycles(bytes: &[u8]) -> (u8, Option<u8>) {
+    let opcode = bytes.first().copied().unwrap_or(0);
+    let Opcode { x, y, z, .. } = Opcode::new(opcode);
+    // Going through (HL) takes an extra read or write
+    let hl = |register: u8| if register == 6 { 4 } else { 0 };
+    let cycles = match (x, z) {
+        (0, 0) => match y {
+            0 | 2 => 4,
+            1 => 20,
+            3 => 12,
+            _ => return (8, Some(12)),
+        },
+        (0, 1) if y & 1 == 0 => 12,
+        (0, 1) | (0, 2) | (0, 3) => 8,
+        (0, 4) | (0, 5) => 4 + 2 * hl(y),
+        (0, 6) => 8 + hl(y),
+        (0, _) => 4,
+        (1, 6) if y == 6 => 4,
+        (1, _) => 4 + hl(y) + hl(z),
+        (2, _) => 4 + hl(z),
+        (_, 0) => match y {
+            0..=3 => return (8, Some(20)),
+            5 => 16,
+            _ => 12,
+        },
+        (_, 1) => match y {
+            1 | 3 => 16,
+            5 => 4,
+            7 => 8,
+            _ => 12,
+        },
+        (_, 2) => match y {
+            0..=3 => return (12, Some(16)),
+            4 | 6 => 8,
+            _ => 16,
+        },
+        (_, 3) if y == 0 => 16,
+        (_, 3) if opcode == PREFIX_CB => {
+            // BIT only reads (HL), the others write it back too
+            let prefixed = Opcode::new(bytes.get(1).copied().unwrap_or(0));
+            match (prefixed.x, prefixed.z) {
+                (1, 6) => 12,
+                (_, 6) => 16,
+                _ => 8,
+            }
+        }
+        (_, 4) if y < 4 => return (12, Some(24)),
+        (_, 5) if y & 1 == 0 => 16,
+        (_, 5) if y == 1 => 24,
+        (_, 6) => 8,
+        (_, 7) => 16,
+        // DI, EI, and the opcodes the CPU doesn't have
+        _ => 4,
+    };
+    (cycles, None)
+}
+
+/// The opcodes the CPU doesn't have
+fn invalid(opcode: u8) -> (String, u16) {
+    (format!("DB ${:02X}", opcode), 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(&[0x00], "NOP", 1; "nop")]
+    #[test_case(&[0x01, 0x34, 0x12], "LD BC, $1234", 3; "ld rp d16")]
+    #[test_case(&[0x08, 0x00, 0xC0], "LD ($C000), SP", 3; "ld a16 sp")]
+    #[test_case(&[0x18, 0xFE], "JR $0150", 2; "jr to itself")]
+    #[test_case(&[0x20, 0x05], "JR NZ, $0157", 2; "jr nz")]
+    #[test_case(&[0x22], "LD (HL+), A", 1; "ld hl+ a")]
+    #[test_case(&[0x3A], "LD A, (HL-)", 1; "ld a hl-")]
+    #[test_case(&[0x36, 0x7F], "LD (HL), $7F", 2; "ld hl d8")]
+    #[test_case(&[0x2F], "CPL", 1; "cpl")]
+    #[test_case(&[0x76], "HALT", 1; "halt")]
+    #[test_case(&[0x78], "LD A, B", 1; "ld r r")]
+    #[test_case(&[0x86], "ADD A, (HL)", 1; "add hl")]
+    #[test_case(&[0xAF], "XOR A", 1; "xor a")]
+    #[test_case(&[0xC0], "RET NZ", 1; "ret nz")]
+    #[test_case(&[0xC3, 0x50, 0x01], "JP $0150", 3; "jp")]
+    #[test_case(&[0xCD, 0x00, 0x40], "CALL $4000", 3; "call")]
+    #[test_case(&[0xDC, 0x00, 0x40], "CALL C, $4000", 3; "call c")]
+    #[test_case(&[0xE0, 0x40], "LDH ($40), A", 2; "ldh")]
+    #[test_case(&[0xE8, 0xFE], "ADD SP, -2", 2; "add sp")]
+    #[test_case(&[0xF8, 0x02], "LD HL, SP+2", 2; "ld hl sp")]
+    #[test_case(&[0xF5], "PUSH AF", 1; "push af")]
+    #[test_case(&[0xFE, 0x90], "CP $90", 2; "cp d8")]
+    #[test_case(&[0xFF], "RST $38", 1; "rst")]
+    #[test_case(&[0xD3], "DB $D3", 1; "invalid")]
+    #[test_case(&[0xCB, 0x11], "RL C", 2; "rl")]
+    #[test_case(&[0xCB, 0x37], "SWAP A", 2; "swap")]
+    #[test_case(&[0xCB, 0x7E], "BIT 7, (HL)", 2; "bit")]
+    #[test_case(&[0xCB, 0x87], "RES 0, A", 2; "res")]
+    #[test_case(&[0xCB, 0xD8], "SET 3, B", 2; "set")]
+    fn test_decode(bytes: &[u8], mnemonic: &str, size: u16) {
+        assert_eq!(
+            decode(0x0150, bytes),
+            Instruction {
+                mnemonic: String::from(mnemonic),
+                size
+            }
+        );
+    }
+
+    #[test]
+    fn test_listing() {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[1..4].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        // LD A, d8 cut short by the end of the bank
+        rom[ROM_BANK_SIZE - 1] = 0x3E;
+        let mut code = vec![true; rom.len()];
+        code[4..0x10].iter_mut().for_each(|code| *code = false);
+        let lines = listing(&rom, &code, 0..rom.len(), &Symbols::default());
+        assert_eq!(lines[0].to_string(), "RST_00:\n00:0000  00        NOP");
+        assert_eq!(lines[1].to_string(), "00:0001  C3 50 01  JP $0150");
+        // Data stops at the next RST vector
+        assert_eq!(
+            lines[2].to_string(),
+            "00:0004            DB $00, $00, $00, $00"
+        );
+        assert_eq!(
+            lines[3].to_string(),
+            "RST_08:\n00:0008            DB $00, $00, $00, $00, $00, $00, $00, $00"
+        );
+        assert_eq!(lines[4].address, 0x0010);
+        let end = lines.iter().position(|line| line.bank == 1).unwrap();
+        assert_eq!(lines[end - 1].to_string(), "00:3FFF  3E        LD A, $00");
+        assert_eq!(lines[end].to_string(), "01:4000  00        NOP");
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let mut rom = vec![0xFF; 0x8000];
+        // The entry point jumps past the header, which is data
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x150] = 0x76;
+        rom[0x151..0x153].copy_from_slice(&[0x18, 0xFD]);
+        let lines: Vec<String> = disassemble(&rom, &[], 0..rom.len(), &Symbols::default())
+            .iter()
+            .map(Line::to_string)
+            .collect();
+        assert!(lines.contains(&String::from("VBlank:\n00:0040  FF        RST $38")));
+        assert!(lines.contains(&String::from("00:0101  C3 50 01  JP $0150")));
+        assert!(lines.contains(&String::from(
+            "Header:\n00:0104            DB $FF, $FF, $FF, $FF, $FF, $FF, $FF, $FF  \
+             ; Nintendo logo, differs from the boot ROM's"
+        )));
+        assert!(lines.contains(&String::from("00:0147            DB $FF  ; Cartridge type")));
+        assert!(lines.contains(&String::from("00:0151  18 FD     JR $0150")));
+        assert!(lines.contains(&String::from(
+            "00:0153            DB $FF, $FF, $FF, $FF, $FF, $FF, $FF, $FF"
+        )));
+    }
+
+    #[test]
+    fn test_labels() {
+        let symbols = Symbols::parse("00:0000 Start\n00:0005 Table\n01:4000 Far").unwrap();
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        // CALL $0005; JP $4000; data
+        rom[..3].copy_from_slice(&[0xCD, 0x05, 0x00]);
+        let mut code = vec![false; rom.len()];
+        code[0] = true;
+        code[ROM_BANK_SIZE] = true;
+        let lines = listing(&rom, &code, 0..rom.len(), &symbols);
+        assert_eq!(
+            lines[0].to_string(),
+            "Start:\n00:0000  CD 05 00  CALL Table"
+        );
+        assert_eq!(lines[1].to_string(), "00:0003            DB $00, $00");
+        assert_eq!(lines[2].label.as_deref(), Some("Table"));
+        let far = lines.iter().find(|line| line.bank == 1).unwrap();
+        assert_eq!(far.to_string(), "Far:\n01:4000  00        NOP");
+    }
+
+    #[test]
+    fn test_fixed_labels() {
+        let symbols = Symbols::parse("00:0040 OnVBlank").unwrap();
+        let rom = vec![0; 0x8000];
+        let mut code = vec![false; rom.len()];
+        // Code running into the title stops its field
+        code[0x0138] = true;
+        let lines = listing(&rom, &code, 0..HEADER_END, &symbols);
+        let label = |address: u16| {
+            let line = lines.iter().find(|line| line.address == address).unwrap();
+            line.label.clone()
+        };
+        assert_eq!(label(0x0040).as_deref(), Some("OnVBlank"));
+        assert_eq!(label(0x0048).as_deref(), Some("LCDStat"));
+        assert_eq!(label(0x0100).as_deref(), Some("EntryPoint"));
+        let title = lines.iter().find(|line| line.address == 0x0134).unwrap();
+        assert_eq!(title.bytes.len(), 4);
+        assert!(lines.iter().any(|line| line.address == 0x0138 && line.code));
+    }
+
+    #[test]
+    fn test_range() {
+        let rom = vec![0; 2 * ROM_BANK_SIZE];
+        let code = vec![false; rom.len()];
+        let range = rom_offset(0, 0x3FFC)..rom_offset(1, 0x4003) + 1;
+        let lines: Vec<String> = listing(&rom, &code, range, &Symbols::default())
+            .iter()
+            .map(Line::to_string)
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "00:3FFC            DB $00, $00, $00, $00",
+                "01:4000            DB $00, $00, $00, $00"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        // Only reached through JP HL, say
+        rom[0x4000] = 0xC9;
+        let start = rom_offset(1, 0x4000);
+        let lines = disassemble(&rom, &[(1, 0x4000)], start..start + 1, &Symbols::default());
+        assert_eq!(lines[0].to_string(), "01:4000  C9        RET");
+    }
+
+    #[test_case("0150", Ok((0, 0x0150)); "bank 0")]
+    #[test_case("0x4123", Ok((1, 0x4123)); "bank 1")]
+    #[test_case("03:4123", Ok((3, 0x4123)); "bank")]
+    #[test_case("Far", Ok((2, 0x4000)); "label")]
+    #[test_case("00:4000", Err(()); "bank 0 at 4000")]
+    #[test_case("02:0150", Err(()); "bank 2 at 0150")]
+    #[test_case("C000", Err(()); "ram")]
+    #[test_case("xx:4000", Err(()); "bad bank")]
+    fn test_parse_location(text: &str, expected: Result<(usize, u16), ()>) {
+        let symbols = Symbols::parse("02:4000 Far").unwrap();
+        assert_eq!(parse_location(text, &symbols).map_err(|_| ()), expected);
+    }
+
+    #[test_case("JP $4000", 1, "JP Far"; "banked")]
+    #[test_case("JP $4000", 0, "JP $4000"; "unknown bank")]
+    #[test_case("LD A, ($C000)", 0, "LD A, (wScore)"; "ram")]
+    #[test_case("LDH ($40), A", 0, "LDH ($40), A"; "short")]
+    #[test_case("LD ($C001), SP", 0, "LD ($C001), SP"; "no label")]
+    fn test_name_addresses(mnemonic: &str, bank: usize, expected: &str) {
+        let symbols = Symbols::parse("01:4000 Far\n00:C000 wScore").unwrap();
+        assert_eq!(name_addresses(mnemonic, bank, &symbols), expected);
+    }
+
+    #[test_case(0x00, (4, None); "nop")]
+    #[test_case(0x08, (20, None); "ld a16 sp")]
+    #[test_case(0x20, (8, Some(12)); "jr nz")]
+    #[test_case(0x34, (12, None); "inc hl")]
+    #[test_case(0x36, (12, None); "ld hl d8")]
+    #[test_case(0x46, (8, None); "ld b hl")]
+    #[test_case(0x76, (4, None); "halt")]
+    #[test_case(0x86, (8, None); "add hl")]
+    #[test_case(0xC0, (8, Some(20)); "ret nz")]
+    #[test_case(0xC1, (12, None); "pop")]
+    #[test_case(0xC9, (16, None); "ret")]
+    #[test_case(0xCD, (24, None); "call")]
+    #[test_case(0xD4, (12, Some(24)); "call nc")]
+    #[test_case(0xE8, (16, None); "add sp")]
+    #[test_case(0xE9, (4, None); "jp hl")]
+    #[test_case(0xF5, (16, None); "push")]
+    #[test_case(0xFA, (16, None); "ld a a16")]
+    #[test_case(0xFF, (16, None); "rst")]
+    fn test_cycles(opcode: u8, expected: (u8, Option<u8>)) {
+        assert_eq!(cycles(&[opcode]), expected);
+    }
+
+    #[test_case(0x11, 8; "rl")]
+    #[test_case(0x06, 16; "rlc hl")]
+    #[test_case(0x7E, 12; "bit hl")]
+    #[test_case(0xC6, 16; "set hl")]
+    fn test_prefixed_cycles(opcode: u8, expected: u8) {
+        assert_eq!(cycles(&[PREFIX_CB, opcode]), (expected, None));
+    }
+
+    /// Every opcode decodes to something, however few bytes follow
+    #[test]
+    fn test_decode_all() {
+        for opcode in 0..=0xFF {
+            let instruction = decode(0xFFFF, &[opcode]);
+            assert!((1..=3).contains(&instruction.size), "{:#04x}", opcode);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// Instruction traces in the format of Gameboy Doctor
+// (https://github.com/robert/gameboy-doctor), so they can be diffed against
+// its reference logs to find the first instruction that goes wrong. Each
+// line holds the registers and the four bytes from PC, before it runs:
+//
+// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//
+// The reference logs are made with LY always reading 0x90, so while tracing
+// it does here too.
+//
+// A run can also be checked against a reference log as it goes, stopping
+// at the first line that differs.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::BufRead;
+
+use crate::cpu_core::cpu::Registers;
+use crate::disassembler::decode;
+#[cfg(feature = "std")]
+use crate::gameboy::GameBoy;
+use crate::prelude::*;
+
+/// What LY reads while tracing
+pub const DOCTOR_LY: u8 = 0x90;
+
+pub fn trace_line(registers: &Registers, pcmem: [u8; 4]) -> String {
+    format!(
+        "{} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        registers, pcmem[0], pcmem[1], pcmem[2], pcmem[3]
+    )
+}
+
+/// The first line of a run that differs from the reference log
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    /// Counting from 1
+    pub line_number: usize,
+    pub expected: String,
+    pub actual: String,
+    /// The line before, which is the instruction that went wrong,
+    /// unless it's the first line that differs
+    pub previous: Option<String>,
+}
+
+/// The `KEY:VALUE` fields of a trace line
+fn fields(line: &str) -> Vec<(&str, &str)> {
+    line.split_whitespace()
+        .filter_map(|field| field.split_once(':'))
+        .collect()
+}
+
+/// The bytes of a PCMEM field
+fn pcmem_bytes(line: &str) -> Vec<u8> {
+    fields(line)
+        .into_iter()
+        .find(|(key, _)| *key == "PCMEM")
+        .map(|(_, bytes)| {
+            bytes
+                .split(',')
+                .filter_map(|byte| u8::from_str_radix(byte, 16).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Line {} differs from the reference log",
+            self.line_number
+        )?;
+        match &self.previous {
+            Some(previous) => {
+                let pc = fields(previous)
+                    .into_iter()
+                    .find(|(key, _)| *key == "PC")
+                    .and_then(|(_, pc)| u16::from_str_radix(pc, 16).ok())
+                    .unwrap_or(0);
+                let instruction = decode(pc, &pcmem_bytes(previous));
+                writeln!(f, "after running {:04X}: {}", pc, instruction.mnemonic)?;
+                writeln!(f, "  before:   {}", previous)?;
+            }
+            None => writeln!(f, "before running anything")?,
+        }
+        writeln!(f, "  expected: {}", self.expected)?;
+        writeln!(f, "  got:      {}", self.actual)?;
+
+        let actual = fields(&self.actual);
+        for (key, expected) in fields(&self.expected) {
+            match actual.iter().find(|(actual_key, _)| *actual_key == key) {
+                Some((_, actual)) if actual == &expected => {}
+                Some((_, actual)) => writeln!(f, "  {} is {}, expected {}", key, actual, expected)?,
+                None => writeln!(f, "  {} is missing", key)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run the GameBoy an instruction at a time, checking the state before
+/// each one against the next line of `reference`. Stops at the first line
+/// that differs. Returns how many lines matched.
+#[cfg(feature = "std")]
+pub fn compare(gameboy: &mut GameBoy, reference: impl BufRead) -> Result<usize, String> {
+    gameboy.set_stub_ly(true);
+    let mut previous = None;
+    let mut matched = 0;
+    for (index, expected) in reference.lines().enumerate() {
+        let expected = expected.map_err(|e| format!("Could not read the reference log: {}", e))?;
+        let expected = expected.trim();
+        if expected.is_empty() {
+            continue;
+        }
+        let actual = gameboy.trace_line();
+        if actual != expected {
+            return Err(Mismatch {
+                line_number: index + 1,
+                expected: expected.to_string(),
+                actual,
+                previous,
+            }
+            .to_string());
+        }
+        gameboy.step_instruction();
+        matched += 1;
+        previous = Some(actual);
+    }
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_line() {
+        let registers = Registers {
+            a: 0x01,
+            f: 0xB0,
+            c: 0x13,
+            e: 0xD8,
+            h: 0x01,
+            l: 0x4D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+            ..Default::default()
+        };
+        assert_eq!(
+            trace_line(&registers, [0x00, 0xC3, 0x13, 0x02]),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+        );
+    }
+
+    /// The log of a ROM of NOPs
+    #[cfg(feature = "std")]
+    fn nop_log(lines: u16) -> String {
+        (0..lines)
+            .map(|pc| {
+                format!(
+                    "A:00 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:{:04X} PCMEM:00,00,00,00\n",
+                    pc
+                )
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_compare() {
+        let mut gameboy = GameBoy::new(vec![0; 0x8000]);
+        assert_eq!(compare(&mut gameboy, nop_log(5).as_bytes()), Ok(5));
+        assert_eq!(gameboy.registers().pc, 5);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_compare_mismatch() {
+        let mut gameboy = GameBoy::new(vec![0; 0x8000]);
+        let log = nop_log(5).replace("PC:0003", "PC:0004");
+        let report = compare(&mut gameboy, log.as_bytes()).err().unwrap();
+        assert!(report.starts_with("Line 4 differs"), "{}", report);
+        assert!(report.contains("after running 0002: NOP"), "{}", report);
+        assert!(report.contains("  PC is 0003, expected 0004"), "{}", report);
+        // Stopped before running the instruction that differs
+        assert_eq!(gameboy.registers().pc, 3);
+    }
+
+    #[test]
+    fn test_mismatch_first_line() {
+        let mismatch = Mismatch {
+            line_number: 1,
+            expected: String::from("A:01 PC:0100"),
+            actual: String::from("A:00"),
+            previous: None,
+        };
+        assert_eq!(
+            mismatch.to_string(),
+            "Line 1 differs from the reference log\n\
+             before running anything\n  \
+             expected: A:01 PC:0100\n  \
+             got:      A:00\n  \
+             A is 00, expected 01\n  \
+             PC is missing\n"
+        );
+    }
+}
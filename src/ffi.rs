@@ -0,0 +1,177 @@
+// A C API for frontends that aren't written in Rust, built with the `ffi`
+// feature. An emulator is an opaque pointer from gb_create(), given back to
+// every other function and freed by gb_destroy(); include/rusty_gameboy.h
+// declares them. A panic in the core is caught and turned into an error,
+// after which the cartridge is gone until another is loaded.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::gameboy::GameBoy;
+use crate::joypad::BUTTONS;
+
+pub struct Emulator {
+    gameboy: Option<GameBoy>,
+}
+
+/// Run `f`, dropping the GameBoy if it panics
+fn guarded<T>(
+    gameboy: &mut Option<GameBoy>,
+    f: impl FnOnce(&mut Option<GameBoy>) -> T,
+) -> Option<T> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(gameboy)));
+    if result.is_err() {
+        *gameboy = None;
+    }
+    result.ok()
+}
+
+/// A new emulator with no cartridge in it
+#[no_mangle]
+pub extern "C" fn gb_create() -> *mut Emulator {
+    Box::into_raw(Box::new(Emulator { gameboy: None }))
+}
+
+/// Load `size` bytes of ROM and start it where the boot ROM would leave off.
+/// Returns 0, or -1 if the ROM couldn't be loaded.
+///
+/// # Safety
+/// `emulator` must come from gb_create() and `rom` point to `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gb_load_rom(emulator: *mut Emulator, rom: *const u8, size: usize) -> i32 {
+    let emulator = match emulator.as_mut() {
+        Some(emulator) if !rom.is_null() => emulator,
+        _ => return -1,
+    };
+    let rom = slice::from_raw_parts(rom, size).to_vec();
+    let loaded = guarded(&mut emulator.gameboy, |gameboy| {
+        let mut loaded = GameBoy::new(rom);
+        loaded.skip_boot();
+        *gameboy = Some(loaded);
+    });
+    match loaded {
+        Some(()) => 0,
+        None => -1,
+    }
+}
+
+/// Run until the next frame is drawn. Returns 0, or -1 if there's no
+/// cartridge or the emulator failed.
+///
+/// # Safety
+/// `emulator` must come from gb_create().
+#[no_mangle]
+pub unsafe extern "C" fn gb_step_frame(emulator: *mut Emulator) -> i32 {
+    let emulator = match emulator.as_mut() {
+        Some(emulator) if emulator.gameboy.is_some() => emulator,
+        _ => return -1,
+    };
+    let stepped = guarded(&mut emulator.gameboy, |gameboy| {
+        if let Some(gameboy) = gameboy {
+            gameboy.step_frame();
+        }
+    });
+    match stepped {
+        Some(()) => 0,
+        None => -1,
+    }
+}
+
+/// The screen, 160x144 0x00RRGGBB pixels row by row, or null without a
+/// cartridge. It's valid until the next call with this emulator.
+///
+/// # Safety
+/// `emulator` must come from gb_create().
+#[no_mangle]
+pub unsafe extern "C" fn gb_get_framebuffer(emulator: *const Emulator) -> *const u32 {
+    match emulator
+        .as_ref()
+        .and_then(|emulator| emulator.gameboy.as_ref())
+    {
+        Some(gameboy) => gameboy.framebuffer().as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Set which buttons are held, a bit each from bit 0: right, left, up,
+/// down, A, B, select and start
+///
+/// # Safety
+/// `emulator` must come from gb_create().
+#[no_mangle]
+pub unsafe extern "C" fn gb_set_input(emulator: *mut Emulator, buttons: u8) {
+    if let Some(gameboy) = emulator
+        .as_mut()
+        .and_then(|emulator| emulator.gameboy.as_mut())
+    {
+        for (bit, &button) in BUTTONS.iter().enumerate() {
+            gameboy.set_button(button, buttons & (1 << bit) != 0);
+        }
+    }
+}
+
+/// Free an emulator. Null is ignored.
+///
+/// # Safety
+/// `emulator` must come from gb_create() and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn gb_destroy(emulator: *mut Emulator) {
+    if !emulator.is_null() {
+        drop(Box::from_raw(emulator));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::P1;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn test_run() {
+        // NOPs
+        let rom = vec![0; 0x8000];
+        unsafe {
+            let emulator = gb_create();
+            assert!(gb_get_framebuffer(emulator).is_null());
+            assert_eq!(gb_step_frame(emulator), -1);
+            assert_eq!(gb_load_rom(emulator, rom.as_ptr(), rom.len()), 0);
+            assert_eq!(gb_step_frame(emulator), 0);
+            let pixels =
+                slice::from_raw_parts(gb_get_framebuffer(emulator), SCREEN_WIDTH * SCREEN_HEIGHT);
+            assert_eq!(
+                pixels,
+                &(*emulator).gameboy.as_ref().unwrap().framebuffer()[..]
+            );
+            gb_destroy(emulator);
+        }
+    }
+
+    #[test]
+    fn test_set_input() {
+        let rom = vec![0; 0x8000];
+        unsafe {
+            let emulator = gb_create();
+            gb_load_rom(emulator, rom.as_ptr(), rom.len());
+            // A and start
+            gb_set_input(emulator, 0b1001_0000);
+            let gameboy = (*emulator).gameboy.as_mut().unwrap();
+            // Select the action buttons, which read low when pressed
+            gameboy.poke(P1, 0x10);
+            assert_eq!(gameboy.peek(P1) & 0x0F, 0b0110);
+            gb_destroy(emulator);
+        }
+    }
+
+    #[test]
+    fn test_null() {
+        unsafe {
+            assert_eq!(gb_load_rom(ptr::null_mut(), ptr::null(), 0), -1);
+            assert_eq!(gb_step_frame(ptr::null_mut()), -1);
+            assert!(gb_get_framebuffer(ptr::null()).is_null());
+            gb_set_input(ptr::null_mut(), 0xFF);
+            gb_destroy(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,202 @@
+// Audio output for the window frontends, through cpal with the `audio`
+// feature. The emulator pushes samples into a small ring buffer after each
+// frame and the audio device's callback drains it. Video sets the pace, so
+// the emulator never produces exactly what the device plays: dynamic rate
+// control nudges the APU's sample rate up or down a little to keep the
+// buffer around half full.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "audio")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "audio")]
+use log::{info, warn};
+
+use crate::apu::buffer::StereoSample;
+#[cfg(feature = "audio")]
+use crate::gameboy::GameBoy;
+
+/// About 85 ms at 48 kHz: a few frames of slack without noticeable delay
+pub const RING_SAMPLES: usize = 4096;
+
+/// The most the sample rate is adjusted by, 0.5% either way. Small enough
+/// that the change in pitch can't be heard.
+const MAX_RATE_DELTA: f64 = 0.005;
+
+/// A queue of samples shared between the emulator and the audio device
+#[derive(Clone)]
+pub struct AudioRing {
+    samples: Arc<Mutex<VecDeque<StereoSample>>>,
+    capacity: usize,
+}
+
+impl AudioRing {
+    pub fn new(capacity: usize) -> AudioRing {
+        AudioRing {
+            samples: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Queue samples, dropping the oldest when the ring is full
+    pub fn push(&self, new_samples: impl Iterator<Item = StereoSample>) {
+        let mut samples = self.samples.lock().unwrap();
+        for sample in new_samples {
+            if samples.len() == self.capacity {
+                samples.pop_front();
+            }
+            samples.push_back(sample);
+        }
+    }
+
+    /// How full the ring is, from 0.0 to 1.0
+    pub fn fill(&self) -> f64 {
+        self.samples.lock().unwrap().len() as f64 / self.capacity as f64
+    }
+
+    /// Drop everything queued, eg so nothing plays on after pausing
+    pub fn clear(&self) {
+        self.samples.lock().unwrap().clear();
+    }
+
+    /// Fill an interleaved left/right buffer for the audio device.
+    /// Plays silence if the emulator has fallen behind.
+    pub fn pop_into(&self, out: &mut [f32]) {
+        let mut samples = self.samples.lock().unwrap();
+        for frame in out.chunks_exact_mut(2) {
+            let sample = samples.pop_front().unwrap_or_default();
+            frame[0] = sample.left;
+            frame[1] = sample.right;
+        }
+    }
+}
+
+/// The sample rate to generate at so the ring drifts back towards half full:
+/// a fuller ring asks for fewer samples, an emptier one for more
+pub fn adjusted_rate(device_rate: u32, fill: f64) -> u32 {
+    let delta = MAX_RATE_DELTA * (1.0 - 2.0 * fill.clamp(0.0, 1.0));
+    (device_rate as f64 * (1.0 + delta)).round() as u32
+}
+
+/// The host's default audio device, playing what the GameBoy makes
+#[cfg(feature = "audio")]
+pub struct Output {
+    /// Plays until dropped
+    stream: cpal::Stream,
+    ring: AudioRing,
+    /// The device's sample rate
+    rate: u32,
+}
+
+#[cfg(feature = "audio")]
+impl Output {
+    /// Open the default output device at its own sample rate, in stereo
+    pub fn open() -> Result<Output, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| String::from("There's no audio output device"))?;
+        let rate = device
+            .default_output_config()
+            .map_err(|e| e.to_string())?
+            .sample_rate();
+        let config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: rate,
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let ring = AudioRing::new(RING_SAMPLES);
+        let player = ring.clone();
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |out: &mut [f32], _| player.pop_into(out),
+                |e| warn!("Audio stream error: {}", e),
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        info!("Playing audio at {} Hz", rate.0);
+        Ok(Output {
+            stream,
+            ring,
+            rate: rate.0,
+        })
+    }
+
+    /// Open the default output device, or play without sound if it can't
+    pub fn try_open() -> Option<Output> {
+        Output::open()
+            .map_err(|e| {
+                warn!(
+                    "Could not open the audio device, playing without sound: {}",
+                    e
+                )
+            })
+            .ok()
+    }
+
+    /// Queue the samples made since the last frame, and adjust the rate the
+    /// next ones are made at
+    pub fn queue(&self, gameboy: &mut GameBoy) {
+        self.ring.push(gameboy.audio_samples());
+        gameboy.set_sample_rate(adjusted_rate(self.rate, self.ring.fill()));
+    }
+
+    /// Stop playing, dropping what's queued so it doesn't play on resume
+    pub fn pause(&self) {
+        if let Err(e) = self.stream.pause() {
+            warn!("Could not pause the audio: {}", e);
+        }
+        self.ring.clear();
+    }
+
+    pub fn resume(&self) {
+        if let Err(e) = self.stream.play() {
+            warn!("Could not resume the audio: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn sample(value: f32) -> StereoSample {
+        StereoSample {
+            left: value,
+            right: -value,
+        }
+    }
+
+    #[test]
+    fn test_ring() {
+        let ring = AudioRing::new(4);
+        ring.push(vec![sample(0.1), sample(0.2)].into_iter());
+        assert_eq!(ring.fill(), 0.5);
+
+        // Runs dry after two samples
+        let mut out = [1.0; 6];
+        ring.pop_into(&mut out);
+        assert_eq!(out, [0.1, -0.1, 0.2, -0.2, 0.0, 0.0]);
+        assert_eq!(ring.fill(), 0.0);
+    }
+
+    #[test]
+    fn test_ring_overflow() {
+        let ring = AudioRing::new(2);
+        ring.push(vec![sample(0.1), sample(0.2), sample(0.3)].into_iter());
+        let mut out = [0.0; 4];
+        ring.pop_into(&mut out);
+        assert_eq!(out, [0.2, -0.2, 0.3, -0.3]);
+    }
+
+    #[test_case(0.5, 48_000; "half full")]
+    #[test_case(0.0, 48_240; "empty")]
+    #[test_case(1.0, 47_760; "full")]
+    #[test_case(2.0, 47_760; "clamped")]
+    fn test_adjusted_rate(fill: f64, expected: u32) {
+        assert_eq!(adjusted_rate(48_000, fill), expected);
+    }
+}
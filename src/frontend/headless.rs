@@ -0,0 +1,153 @@
+use log::warn;
+
+use crate::frontend::Frontend;
+use crate::gameboy::{Framebuffer, GameBoy};
+use crate::ppu::DOTS_PER_FRAME;
+
+/// FNV-1a parameters for 64-bit hashes
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Which frames to print the hash of
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FrameHashes {
+    #[default]
+    None,
+    Every,
+    /// Only this frame, counting from 1, then stop
+    Frame(u64),
+}
+
+/// Runs the GameBoy without showing anything
+#[derive(Debug, Default)]
+pub struct Headless {
+    /// Stop after this many frames
+    pub frames: Option<u64>,
+    /// Stop after this many T-cycles, partway through a frame if need be
+    pub max_cycles: Option<u64>,
+    pub hashes: FrameHashes,
+}
+
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// A 64-bit FNV-1a hash of the frame's pixels, each as 4 little-endian bytes.
+/// Stable across platforms, so tests can compare against a known value.
+pub fn frame_hash(framebuffer: &Framebuffer) -> u64 {
+    fnv1a(framebuffer.iter().flat_map(|pixel| pixel.to_le_bytes()))
+}
+
+impl Headless {
+    /// The number of the last frame to run, if there is one
+    fn last_frame(&self) -> Option<u64> {
+        match (self.frames, self.hashes) {
+            (Some(frames), FrameHashes::Frame(frame)) => Some(frames.min(frame)),
+            (None, FrameHashes::Frame(frame)) => Some(frame),
+            (frames, _) => frames,
+        }
+    }
+}
+
+impl Frontend for Headless {
+    fn run(&mut self, gameboy: &mut GameBoy) {
+        if self.frames.is_none()
+            && self.max_cycles.is_none()
+            && self.hashes == FrameHashes::None
+            && !gameboy.has_frame_work()
+        {
+            gameboy.start();
+            return;
+        }
+
+        let last_frame = self.last_frame();
+        let mut frame = 0;
+        let mut cycles = 0;
+        while last_frame.is_none_or(|last_frame| frame < last_frame) {
+            // Finish with part of a frame if the cycles run out before its end
+            if let Some(left) = self.max_cycles.map(|max| max.saturating_sub(cycles)) {
+                if left < DOTS_PER_FRAME as u64 {
+                    if let Err(e) = gameboy.try_step_cycles(left as u32) {
+                        warn!("The emulator stopped: {}", e);
+                    }
+                    break;
+                }
+            }
+            if let Err(e) = gameboy.try_step_frame() {
+                warn!("The emulator stopped: {}", e);
+                break;
+            }
+            frame += 1;
+            cycles += DOTS_PER_FRAME as u64;
+            // A test ROM that's done just loops
+            if gameboy.test_verdict().is_some() {
+                break;
+            }
+            let print_hash = match self.hashes {
+                FrameHashes::None => false,
+                FrameHashes::Every => true,
+                FrameHashes::Frame(n) => n == frame,
+            };
+            if print_hash {
+                println!(
+                    "frame {}: {:016x}",
+                    frame,
+                    frame_hash(gameboy.framebuffer())
+                );
+            }
+        }
+        gameboy.save_data();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::palette::GRAYSCALE;
+    use test_case::test_case;
+
+    #[test_case(b"", 0xcbf29ce484222325; "empty")]
+    #[test_case(b"a", 0xaf63dc4c8601ec8c; "a")]
+    #[test_case(b"foobar", 0x85944171f73967e8; "foobar")]
+    fn test_fnv1a(bytes: &[u8], hash: u64) {
+        assert_eq!(fnv1a(bytes.iter().copied()), hash);
+    }
+
+    #[test]
+    fn test_frame_hash() {
+        let mut framebuffer = [GRAYSCALE[0]; 160 * 144];
+        let blank = frame_hash(&framebuffer);
+        framebuffer[160 * 144 - 1] = GRAYSCALE[3];
+        assert_ne!(frame_hash(&framebuffer), blank);
+    }
+
+    #[test_case(None, FrameHashes::Every, None)]
+    #[test_case(Some(10), FrameHashes::Every, Some(10))]
+    #[test_case(None, FrameHashes::Frame(3), Some(3))]
+    #[test_case(Some(2), FrameHashes::Frame(3), Some(2))]
+    fn test_last_frame(frames: Option<u64>, hashes: FrameHashes, last_frame: Option<u64>) {
+        let headless = Headless {
+            frames,
+            hashes,
+            ..Default::default()
+        };
+        assert_eq!(headless.last_frame(), last_frame);
+    }
+
+    #[test_case(None, 100, 25; "part of a frame")]
+    #[test_case(None, DOTS_PER_FRAME as u64 + 8, (DOTS_PER_FRAME / 4) as u16 + 2; "into the second frame")]
+    #[test_case(Some(1), 2 * DOTS_PER_FRAME as u64, (DOTS_PER_FRAME / 4) as u16; "frames first")]
+    fn test_max_cycles(frames: Option<u64>, max_cycles: u64, pc: u16) {
+        // NOPs, 4 T-cycles each
+        let mut gameboy = GameBoy::new(vec![0; 0x8000]);
+        let mut headless = Headless {
+            frames,
+            max_cycles: Some(max_cycles),
+            ..Default::default()
+        };
+        headless.run(&mut gameboy);
+        assert_eq!(gameboy.registers().pc, pc);
+    }
+}
@@ -0,0 +1,133 @@
+// The buttons held on whatever the user plays with. Keys are looked up in the
+// keymap, and any other device presses buttons with set_button(), so turbo
+// buttons behave the same everywhere: while held, a turbo button is pressed
+// for the keymap's turbo period, released for as long, and so on.
+
+use crate::frontend::keymap::KeyMap;
+use crate::gameboy::GameBoy;
+use crate::joypad::{Button, BUTTONS};
+
+pub struct Input {
+    keymap: KeyMap,
+    /// Buttons held down, one bit per button
+    held: u8,
+    /// How many frames each turbo button has been held for
+    turbo: [Option<u32>; 8],
+    /// The buttons last passed on to the GameBoy
+    applied: u8,
+}
+
+impl Input {
+    pub fn new(keymap: KeyMap) -> Input {
+        Input {
+            keymap,
+            held: 0,
+            turbo: [None; 8],
+            applied: 0,
+        }
+    }
+
+    /// Press or release the button bound to a key, given the frontend's
+    /// name for the key. Returns false if the key isn't bound to a button.
+    pub fn key(&mut self, key: &str, pressed: bool) -> bool {
+        if let Some(button) = self.keymap.button(key) {
+            self.set_button(button, false, pressed);
+        } else if let Some(button) = self.keymap.turbo_button(key) {
+            self.set_button(button, true, pressed);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Press or release a button, or its turbo version
+    pub fn set_button(&mut self, button: Button, turbo: bool, pressed: bool) {
+        let bit = 1 << button as u8;
+        match (turbo, pressed) {
+            (false, true) => self.held |= bit,
+            (false, false) => self.held &= !bit,
+            (true, true) => self.turbo[button as usize] = Some(0),
+            (true, false) => self.turbo[button as usize] = None,
+        }
+    }
+
+    /// The buttons pressed this frame
+    pub fn pressed(&self) -> u8 {
+        let period = self.keymap.turbo_period;
+        self.turbo
+            .iter()
+            .enumerate()
+            .filter(|(_, frames)| frames.is_some_and(|frames| (frames / period) & 1 == 0))
+            .fold(self.held, |pressed, (index, _)| pressed | 1 << index)
+    }
+
+    /// Move the turbo buttons on by a frame
+    pub fn next_frame(&mut self) {
+        for frames in self.turbo.iter_mut().flatten() {
+            *frames += 1;
+        }
+    }
+
+    /// Pass the buttons pressed this frame on to the GameBoy
+    pub fn apply(&mut self, gameboy: &mut GameBoy) {
+        let pressed = self.pressed();
+        for button in BUTTONS.iter() {
+            let bit = 1 << *button as u8;
+            if (pressed ^ self.applied) & bit != 0 {
+                gameboy.set_button(*button, pressed & bit != 0);
+            }
+        }
+        self.applied = pressed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        let mut input = Input::new(KeyMap::default());
+        assert!(input.key("X", true));
+        assert!(input.key("Return", true));
+        assert!(!input.key("J", true));
+        assert_eq!(
+            input.pressed(),
+            1 << Button::A as u8 | 1 << Button::Start as u8
+        );
+        input.key("X", false);
+        assert_eq!(input.pressed(), 1 << Button::Start as u8);
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut keymap = KeyMap::default();
+        keymap.bind_turbo(Button::B, "s");
+        keymap.turbo_period = 2;
+        let mut input = Input::new(keymap);
+        input.key("s", true);
+
+        let b = 1 << Button::B as u8;
+        let mut frames = Vec::new();
+        for _ in 0..6 {
+            frames.push(input.pressed());
+            input.next_frame();
+        }
+        assert_eq!(frames, [b, b, 0, 0, b, b]);
+
+        input.key("s", false);
+        assert_eq!(input.pressed(), 0);
+    }
+
+    /// Holding the normal key keeps the button down through the turbo's releases
+    #[test]
+    fn test_turbo_and_held() {
+        let mut input = Input::new(KeyMap::default());
+        input.set_button(Button::A, true, true);
+        input.set_button(Button::A, false, true);
+        for _ in 0..10 {
+            assert_eq!(input.pressed(), 1 << Button::A as u8);
+            input.next_frame();
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// Which keyboard key presses which button. Keys are named the same way in
+// every frontend, so one config works everywhere: letters and digits as
+// themselves ("x", "7"), and names like "enter", "space", "up" or "f5" for
+// the rest. The frontends turn their own key names into these with key_name().
+
+use std::collections::HashMap;
+
+use crate::joypad::Button;
+
+/// The layout used unless the user binds something else
+const DEFAULT_BINDINGS: [(Button, &str); 8] = [
+    (Button::Right, "right"),
+    (Button::Left, "left"),
+    (Button::Up, "up"),
+    (Button::Down, "down"),
+    (Button::A, "x"),
+    (Button::B, "z"),
+    (Button::Select, "backspace"),
+    (Button::Start, "enter"),
+];
+
+/// How many frames a turbo button stays pressed, then released
+pub const DEFAULT_TURBO_PERIOD: u32 = 4;
+
+/// A key name from a frontend in the form used for bindings,
+/// eg SDL's "Return" and winit's "Enter" both become "enter"
+pub fn key_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    // winit names keys KeyX, Digit1 and ArrowUp
+    let name = match (name.strip_prefix("key"), name.strip_prefix("digit")) {
+        (Some(rest), _) | (_, Some(rest)) if rest.len() == 1 => rest,
+        _ => name.strip_prefix("arrow").unwrap_or(&name),
+    };
+    match name {
+        "return" => "enter",
+        "leftshift" | "shiftleft" => "lshift",
+        "rightshift" | "shiftright" => "rshift",
+        "leftctrl" | "controlleft" => "lctrl",
+        "rightctrl" | "controlright" => "rctrl",
+        other => other,
+    }
+    .to_string()
+}
+
+/// Parse a button name as used in bindings
+pub fn parse_button(name: &str) -> Result<Button, String> {
+    match name.trim().to_lowercase().as_str() {
+        "right" => Ok(Button::Right),
+        "left" => Ok(Button::Left),
+        "up" => Ok(Button::Up),
+        "down" => Ok(Button::Down),
+        "a" => Ok(Button::A),
+        "b" => Ok(Button::B),
+        "select" => Ok(Button::Select),
+        "start" => Ok(Button::Start),
+        other => Err(format!(
+            "Unknown button {:?}, expected a, b, start, select, up, down, left or right",
+            other
+        )),
+    }
+}
+
+/// Parse how many frames a turbo button stays pressed, then released
+pub fn parse_turbo_period(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(period) if period > 0 => Ok(period),
+        _ => Err(format!(
+            "The turbo period must be a positive number of frames, got {:?}",
+            value.trim()
+        )),
+    }
+}
+
+/// Parse a `button=key` binding, eg `a=j`
+pub fn parse_binding(value: &str) -> Result<(Button, String), String> {
+    match value.split_once('=') {
+        Some((button, key)) if !key.trim().is_empty() => Ok((parse_button(button)?, key_name(key))),
+        _ => Err(format!("Expected a binding like a=j, got {:?}", value)),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyMap {
+    buttons: HashMap<String, Button>,
+    /// Keys that press and release a button over and over while held
+    turbo: HashMap<String, Button>,
+    /// Frames a turbo button spends pressed, and then released
+    pub turbo_period: u32,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let mut keymap = KeyMap {
+            buttons: HashMap::new(),
+            turbo: HashMap::new(),
+            turbo_period: DEFAULT_TURBO_PERIOD,
+        };
+        for (button, key) in DEFAULT_BINDINGS.iter() {
+            keymap.bind(*button, key);
+        }
+        keymap
+    }
+}
+
+impl KeyMap {
+    /// Press `button` with `key` instead of whatever key pressed it before
+    pub fn bind(&mut self, button: Button, key: &str) {
+        let key = key_name(key);
+        self.buttons.retain(|_, bound| *bound != button);
+        self.turbo.remove(&key);
+        self.buttons.insert(key, button);
+    }
+
+    /// Make `key` a turbo button for `button`, replacing its old turbo key
+    pub fn bind_turbo(&mut self, button: Button, key: &str) {
+        let key = key_name(key);
+        self.turbo.retain(|_, bound| *bound != button);
+        self.buttons.remove(&key);
+        self.turbo.insert(key, button);
+    }
+
+    /// The button a key presses, given the frontend's name for the key
+    pub fn button(&self, key: &str) -> Option<Button> {
+        self.buttons.get(&key_name(key)).copied()
+    }
+
+    /// The button a key presses as a turbo button
+    pub fn turbo_button(&self, key: &str) -> Option<Button> {
+        self.turbo.get(&key_name(key)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("Return", "enter"; "sdl enter")]
+    #[test_case("Enter", "enter"; "winit enter")]
+    #[test_case("X", "x"; "sdl letter")]
+    #[test_case("KeyX", "x"; "winit letter")]
+    #[test_case("Digit1", "1"; "winit digit")]
+    #[test_case("ArrowUp", "up"; "winit arrow")]
+    #[test_case("Left Shift", "lshift"; "sdl shift")]
+    #[test_case("ShiftLeft", "lshift"; "winit shift")]
+    #[test_case("Keypad 5", "keypad5"; "keypad is not a key prefix")]
+    fn test_key_name(name: &str, expected: &str) {
+        assert_eq!(key_name(name), expected);
+    }
+
+    #[test]
+    fn test_default() {
+        let keymap = KeyMap::default();
+        assert_eq!(keymap.button("Return"), Some(Button::Start));
+        assert_eq!(keymap.button("KeyX"), Some(Button::A));
+        assert_eq!(keymap.button("J"), None);
+    }
+
+    #[test]
+    fn test_bind() {
+        let mut keymap = KeyMap::default();
+        keymap.bind(Button::A, "j");
+        assert_eq!(keymap.button("KeyJ"), Some(Button::A));
+        // The old key is free again
+        assert_eq!(keymap.button("X"), None);
+        // A key presses one button
+        keymap.bind(Button::B, "j");
+        assert_eq!(keymap.button("j"), Some(Button::B));
+        assert_eq!(keymap.button("z"), None);
+    }
+
+    #[test]
+    fn test_bind_turbo() {
+        let mut keymap = KeyMap::default();
+        keymap.bind_turbo(Button::A, "s");
+        assert_eq!(keymap.turbo_button("KeyS"), Some(Button::A));
+        // The normal key still works
+        assert_eq!(keymap.button("x"), Some(Button::A));
+        // A key is either a turbo key or a normal one
+        keymap.bind_turbo(Button::B, "z");
+        assert_eq!(keymap.button("z"), None);
+        assert_eq!(keymap.turbo_button("z"), Some(Button::B));
+        keymap.bind(Button::Start, "s");
+        assert_eq!(keymap.turbo_button("s"), None);
+    }
+
+    #[test_case("4", Ok(4); "frames")]
+    #[test_case("0", Err(()); "zero")]
+    #[test_case("fast", Err(()); "not a number")]
+    fn test_parse_turbo_period(value: &str, expected: Result<u32, ()>) {
+        assert_eq!(parse_turbo_period(value).map_err(|_| ()), expected);
+    }
+
+    #[test]
+    fn test_parse_binding() {
+        assert_eq!(
+            parse_binding("start = Space"),
+            Ok((Button::Start, String::from("space")))
+        );
+        assert!(parse_binding("turbo=x").is_err());
+        assert!(parse_binding("a").is_err());
+        assert!(parse_binding("a=").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+pub mod audio;
+pub mod headless;
+pub mod input;
+pub mod keymap;
+pub mod pacer;
+pub mod scaler;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+#[cfg(feature = "winit")]
+pub mod winit;
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use log::info;
+use log::warn;
+
+use crate::frontend::headless::Headless;
+use crate::frontend::keymap::KeyMap;
+use crate::frontend::pacer::Pacer;
+use crate::frontend::scaler::Scaler;
+use crate::gameboy::GameBoy;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use crate::ppu::viewer::Image;
+
+/// Shows what the GameBoy outputs and feeds it the user's input
+pub trait Frontend {
+    /// Run the game until the user quits
+    fn run(&mut self, gameboy: &mut GameBoy);
+}
+
+/// Pause or resume the game from a hotkey
+#[cfg(any(feature = "sdl", feature = "winit"))]
+fn toggle_pause(gameboy: &mut GameBoy) {
+    if gameboy.is_paused() {
+        gameboy.resume();
+        info!("Resumed");
+    } else {
+        gameboy.pause();
+        info!("Paused");
+    }
+}
+
+/// What the debug window shows. F10 opens it and switches between them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugView {
+    /// The tile data and tile maps
+    Vram,
+    /// The sprites in OAM
+    Oam,
+    /// The colors of the palettes
+    Palettes,
+}
+
+/// How many times bigger than its image the debug window opens
+#[cfg(any(feature = "sdl", feature = "winit"))]
+const DEBUG_VIEW_SCALE: u32 = 2;
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+impl DebugView {
+    /// The view F10 switches to from `view`, or None to close the window
+    fn next(view: Option<DebugView>) -> Option<DebugView> {
+        match view {
+            None => Some(DebugView::Vram),
+            Some(DebugView::Vram) => Some(DebugView::Oam),
+            Some(DebugView::Oam) => Some(DebugView::Palettes),
+            Some(DebugView::Palettes) => None,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            DebugView::Vram => "VRAM",
+            DebugView::Oam => "OAM",
+            DebugView::Palettes => "Palettes",
+        }
+    }
+
+    fn render(self, gameboy: &GameBoy) -> Image {
+        match self {
+            DebugView::Vram => gameboy.vram_view(),
+            DebugView::Oam => gameboy.oam_view(),
+            DebugView::Palettes => gameboy.palette_view(),
+        }
+    }
+}
+
+/// The frontends that can be picked on the command line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrontendKind {
+    Sdl,
+    Winit,
+    Headless,
+}
+
+impl FrontendKind {
+    /// The cargo feature the frontend is built with, if any
+    pub fn feature(self) -> Option<&'static str> {
+        match self {
+            FrontendKind::Sdl => Some("sdl"),
+            FrontendKind::Winit => Some("winit"),
+            FrontendKind::Headless => None,
+        }
+    }
+
+    pub fn is_compiled_in(self) -> bool {
+        match self {
+            FrontendKind::Sdl => cfg!(feature = "sdl"),
+            FrontendKind::Winit => cfg!(feature = "winit"),
+            FrontendKind::Headless => true,
+        }
+    }
+}
+
+/// Open the given frontend, or the first window frontend that was compiled in
+/// if none was given. Falls back to running `headless` if no window can be opened.
+pub fn create_frontend(
+    kind: Option<FrontendKind>,
+    headless: Headless,
+    scaler: Scaler,
+    keymap: KeyMap,
+    pacer: Pacer,
+) -> Box<dyn Frontend> {
+    let candidates = match kind {
+        Some(kind) => vec![kind],
+        None => vec![FrontendKind::Sdl, FrontendKind::Winit],
+    };
+    for kind in candidates
+        .into_iter()
+        .filter(|kind| kind.is_compiled_in() && *kind != FrontendKind::Headless)
+    {
+        match open(kind, scaler, keymap.clone(), pacer) {
+            Ok(frontend) => return frontend,
+            Err(e) => warn!("Could not open the {:?} frontend: {}", kind, e),
+        }
+    }
+    if kind.is_none() {
+        warn!("No window frontend was compiled in, running headless");
+    }
+    Box::new(headless)
+}
+
+/// Open a window frontend that draws the screen with `scaler`,
+/// presses buttons with the keys in `keymap` and keeps time with `pacer`
+#[allow(unused_variables)] // unused without a window frontend
+fn open(
+    kind: FrontendKind,
+    scaler: Scaler,
+    keymap: KeyMap,
+    pacer: Pacer,
+) -> Result<Box<dyn Frontend>, String> {
+    match kind {
+        #[cfg(feature = "sdl")]
+        FrontendKind::Sdl => Ok(Box::new(sdl::SdlFrontend::new(scaler, keymap, pacer)?)),
+        #[cfg(feature = "winit")]
+        FrontendKind::Winit => Ok(Box::new(winit::WinitFrontend::new(scaler, keymap, pacer)?)),
+        #[allow(unreachable_patterns)]
+        _ => Err(String::from("not compiled in")),
+    }
+}
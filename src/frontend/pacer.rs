@@ -0,0 +1,211 @@
+// Keeps the game running at the GameBoy's own frame rate, about 59.7275 fps,
+// however fast the host is. Frames are scheduled against a fixed timeline
+// rather than measured one at a time, so small delays don't add up to drift.
+// After a slow frame the frontend runs the frames it missed to catch up,
+// unless it has fallen too far behind, in which case the timeline restarts.
+// Fast forward runs frames as fast as the host allows, or up to a cap.
+
+use log::info;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::gameboy::CLOCK_HZ;
+use crate::ppu::DOTS_PER_FRAME;
+
+/// How long a frame lasts on the real hardware:
+/// 70224 T-cycles at 4194304 Hz, or about 59.7275 frames per second
+pub const FRAME_DURATION: Duration =
+    Duration::from_nanos(DOTS_PER_FRAME as u64 * 1_000_000_000 / CLOCK_HZ as u64);
+
+/// The most frames run at once to catch up; any more are dropped
+const MAX_CATCH_UP: u32 = 4;
+
+/// Sleeping can overshoot by about this much, so the end of a wait spins instead
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+/// Parse how many times normal speed fast forward is capped at
+pub fn parse_max_speed(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(speed) if speed > 0 => Ok(speed),
+        _ => Err(format!(
+            "The speed must be a positive whole number, got {:?}",
+            value.trim()
+        )),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Pacer {
+    /// When the next frame is due
+    next_frame: Instant,
+    fast_forward: bool,
+    /// How many times normal speed fast forward is capped at, if it is
+    pub max_speed: Option<u32>,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Pacer {
+            next_frame: Instant::now(),
+            fast_forward: false,
+            max_speed: None,
+        }
+    }
+}
+
+impl Pacer {
+    pub fn is_fast_forward(&self) -> bool {
+        self.fast_forward
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+        // Start a new timeline, rather than catching up with the old one
+        self.next_frame = Instant::now();
+    }
+
+    /// Turn fast forward on or off, from a hotkey
+    pub fn toggle_fast_forward(&mut self) {
+        self.set_fast_forward(!self.fast_forward);
+        info!(
+            "Fast forward {}",
+            if self.fast_forward { "on" } else { "off" }
+        );
+    }
+
+    /// How long a frame lasts, or None if there's no limit
+    fn frame_duration(&self) -> Option<Duration> {
+        match (self.fast_forward, self.max_speed) {
+            (false, _) => Some(FRAME_DURATION),
+            (true, Some(speed)) => Some(FRAME_DURATION / speed),
+            (true, None) => None,
+        }
+    }
+
+    /// When the next frame is due
+    pub fn next_frame(&self) -> Instant {
+        self.next_frame
+    }
+
+    /// How many frames to run by `now`: none if the next one isn't due yet,
+    /// or more than one to catch up after a slow frame
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+        let frame_duration = match self.frame_duration() {
+            Some(frame_duration) => frame_duration,
+            None => {
+                self.next_frame = now;
+                return 1;
+            }
+        };
+        if now < self.next_frame {
+            return 0;
+        }
+        let behind = now - self.next_frame;
+        let frames = (behind.as_nanos() / frame_duration.as_nanos()) as u32 + 1;
+        if frames > MAX_CATCH_UP {
+            // Too far behind, eg after the window was dragged
+            self.next_frame = now + frame_duration;
+            return 1;
+        }
+        self.next_frame += frame_duration * frames;
+        frames
+    }
+
+    /// Block until the next frame is due
+    pub fn wait(&self) {
+        loop {
+            let now = Instant::now();
+            if now >= self.next_frame {
+                return;
+            }
+            match (self.next_frame - now).checked_sub(SPIN_MARGIN) {
+                Some(sleep) if !sleep.is_zero() => thread::sleep(sleep),
+                _ => thread::yield_now(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn test_frame_duration() {
+        let fps = 1.0 / FRAME_DURATION.as_secs_f64();
+        assert!((fps - 59.7275).abs() < 0.0001, "{}", fps);
+    }
+
+    #[test]
+    fn test_frames_due() {
+        let mut pacer = Pacer::default();
+        let start = pacer.next_frame();
+        assert_eq!(pacer.frames_due(start), 1);
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION / 2), 0);
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION), 1);
+        // The timeline stays fixed however late each frame is run
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION * 5 / 2), 1);
+        assert_eq!(pacer.next_frame(), start + FRAME_DURATION * 3);
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let mut pacer = Pacer::default();
+        let start = pacer.next_frame();
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION * 2), 3);
+        assert_eq!(pacer.next_frame(), start + FRAME_DURATION * 3);
+    }
+
+    #[test]
+    fn test_too_far_behind() {
+        let mut pacer = Pacer::default();
+        let now = pacer.next_frame() + FRAME_DURATION * 100;
+        assert_eq!(pacer.frames_due(now), 1);
+        assert_eq!(pacer.next_frame(), now + FRAME_DURATION);
+    }
+
+    #[test]
+    fn test_fast_forward() {
+        let mut pacer = Pacer::default();
+        pacer.set_fast_forward(true);
+        let now = pacer.next_frame();
+        assert_eq!(pacer.frames_due(now), 1);
+        assert_eq!(pacer.frames_due(now), 1);
+        assert_eq!(pacer.next_frame(), now);
+    }
+
+    #[test]
+    fn test_max_speed() {
+        let mut pacer = Pacer {
+            max_speed: Some(4),
+            ..Default::default()
+        };
+        pacer.set_fast_forward(true);
+        let start = pacer.next_frame();
+        assert_eq!(pacer.frames_due(start), 1);
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION / 8), 0);
+        // Four frames in the time of one, with the first one run already
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION * 3 / 4), 3);
+
+        pacer.set_fast_forward(false);
+        let start = pacer.next_frame();
+        assert_eq!(pacer.frames_due(start), 1);
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION * 3 / 4), 0);
+    }
+
+    #[test_case("3", Ok(3); "speed")]
+    #[test_case("0", Err(()); "zero")]
+    #[test_case("fast", Err(()); "not a number")]
+    fn test_parse_max_speed(value: &str, expected: Result<u32, ()>) {
+        assert_eq!(parse_max_speed(value).map_err(|_| ()), expected);
+    }
+
+    #[test]
+    fn test_wait() {
+        let mut pacer = Pacer::default();
+        pacer.frames_due(Instant::now());
+        pacer.wait();
+        assert!(Instant::now() >= pacer.next_frame());
+    }
+}
@@ -0,0 +1,181 @@
+// Scales the 160x144 frame up by a whole number, so every GameBoy pixel
+// becomes a square of the same size, and optionally draws an effect over it
+// to look more like the real screen.
+
+use crate::gameboy::Framebuffer;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const MIN_SCALE: u32 = 1;
+pub const MAX_SCALE: u32 = 6;
+pub const DEFAULT_SCALE: u32 = 3;
+
+/// An effect drawn over the scaled frame. Needs a scale of 2 or more to show.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Filter {
+    #[default]
+    None,
+    /// Darken the bottom row of every pixel, like a CRT
+    Scanlines,
+    /// Darken the bottom row and right column of every pixel, like the DMG's LCD
+    LcdGrid,
+}
+
+/// How bright the darkened rows and columns are, out of 256
+const SCANLINE_BRIGHTNESS: u32 = 128;
+const GRID_BRIGHTNESS: u32 = 192;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scaler {
+    pub scale: u32,
+    pub filter: Filter,
+}
+
+impl Default for Scaler {
+    fn default() -> Self {
+        Scaler {
+            scale: DEFAULT_SCALE,
+            filter: Filter::None,
+        }
+    }
+}
+
+/// Scale each channel of a 0x00RRGGBB color by `brightness`/256
+fn darken(rgb: u32, brightness: u32) -> u32 {
+    let channel = |shift: u32| ((((rgb >> shift) & 0xFF) * brightness) >> 8) << shift;
+    channel(16) | channel(8) | channel(0)
+}
+
+/// Parse a filter name as given on the command line
+pub fn parse_filter(value: &str) -> Result<Filter, String> {
+    match value.trim() {
+        "none" => Ok(Filter::None),
+        "scanlines" => Ok(Filter::Scanlines),
+        "lcd-grid" => Ok(Filter::LcdGrid),
+        other => Err(format!(
+            "Unknown filter {:?}, expected none, scanlines or lcd-grid",
+            other
+        )),
+    }
+}
+
+/// Parse a scale factor, which has to be a whole number from 1 to 6
+pub fn parse_scale(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(scale) if (MIN_SCALE..=MAX_SCALE).contains(&scale) => Ok(scale),
+        _ => Err(format!(
+            "The scale must be a whole number from {} to {}, got {:?}",
+            MIN_SCALE,
+            MAX_SCALE,
+            value.trim()
+        )),
+    }
+}
+
+impl Scaler {
+    pub fn width(&self) -> u32 {
+        SCREEN_WIDTH as u32 * self.scale
+    }
+
+    pub fn height(&self) -> u32 {
+        SCREEN_HEIGHT as u32 * self.scale
+    }
+
+    /// Draw the frame into `out`, which is width() by height() 0x00RRGGBB pixels
+    pub fn blit(&self, framebuffer: &Framebuffer, out: &mut [u32]) {
+        let scale = self.scale as usize;
+        let last = scale - 1;
+        let width = self.width() as usize;
+        for (y, row) in out.chunks_exact_mut(width).enumerate() {
+            let (source_y, sub_y) = (y / scale, y % scale);
+            let source = &framebuffer[source_y * SCREEN_WIDTH..(source_y + 1) * SCREEN_WIDTH];
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let (source_x, sub_x) = (x / scale, x % scale);
+                let rgb = source[source_x];
+                *pixel = match self.filter {
+                    _ if scale == 1 => rgb,
+                    Filter::None => rgb,
+                    Filter::Scanlines if sub_y == last => darken(rgb, SCANLINE_BRIGHTNESS),
+                    Filter::LcdGrid if sub_y == last || sub_x == last => {
+                        darken(rgb, GRID_BRIGHTNESS)
+                    }
+                    _ => rgb,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn blit(scaler: Scaler, framebuffer: &Framebuffer) -> Vec<u32> {
+        let mut out = vec![0; (scaler.width() * scaler.height()) as usize];
+        scaler.blit(framebuffer, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_blit_scale() {
+        let mut framebuffer = [0xFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT];
+        framebuffer[SCREEN_WIDTH + 1] = 0x123456;
+        let scaler = Scaler {
+            scale: 2,
+            filter: Filter::None,
+        };
+        let out = blit(scaler, &framebuffer);
+        let width = scaler.width() as usize;
+        // Pixel (1, 1) covers (2..4, 2..4)
+        for (x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)] {
+            assert_eq!(out[y * width + x], 0x123456);
+        }
+        assert_eq!(out[2 * width + 4], 0xFFFFFF);
+        assert_eq!(out[4 * width + 2], 0xFFFFFF);
+    }
+
+    #[test_case(Filter::Scanlines, [0xFFFFFF, 0xFFFFFF, 0xFFFFFF, 0x7F7F7F, 0x7F7F7F, 0x7F7F7F]; "scanlines")]
+    #[test_case(Filter::LcdGrid, [0xFFFFFF, 0xFFFFFF, 0xBFBFBF, 0xBFBFBF, 0xBFBFBF, 0xBFBFBF]; "lcd grid")]
+    fn test_blit_filter(filter: Filter, expected: [u32; 6]) {
+        let framebuffer = [0xFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let scaler = Scaler { scale: 3, filter };
+        let out = blit(scaler, &framebuffer);
+        let width = scaler.width() as usize;
+        // The top-left pixel: the first two rows of (x=0,1,2), then the last row
+        let first_pixel = [
+            out[0],
+            out[1],
+            out[2],
+            out[2 * width],
+            out[2 * width + 1],
+            out[2 * width + 2],
+        ];
+        assert_eq!(first_pixel, expected);
+    }
+
+    #[test]
+    fn test_blit_filter_scale_1() {
+        let framebuffer = [0xFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let scaler = Scaler {
+            scale: 1,
+            filter: Filter::LcdGrid,
+        };
+        assert!(blit(scaler, &framebuffer)
+            .iter()
+            .all(|pixel| *pixel == 0xFFFFFF));
+    }
+
+    #[test_case("1", Ok(1); "min")]
+    #[test_case("6", Ok(6); "max")]
+    #[test_case("0", Err(String::from("The scale must be a whole number from 1 to 6, got \"0\"")); "zero")]
+    #[test_case("2.5", Err(String::from("The scale must be a whole number from 1 to 6, got \"2.5\"")); "fraction")]
+    fn test_parse_scale(value: &str, expected: Result<u32, String>) {
+        assert_eq!(parse_scale(value), expected);
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(parse_filter("lcd-grid"), Ok(Filter::LcdGrid));
+        assert!(parse_filter("crt").is_err());
+    }
+}
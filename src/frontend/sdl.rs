@@ -0,0 +1,284 @@
+use log::{info, warn};
+use std::time::Instant;
+
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::Sdl;
+
+use crate::apu::Channel;
+#[cfg(feature = "audio")]
+use crate::frontend::audio::Output;
+use crate::frontend::input::Input;
+use crate::frontend::keymap::KeyMap;
+use crate::frontend::pacer::Pacer;
+use crate::frontend::scaler::Scaler;
+use crate::frontend::{toggle_pause, DebugView, Frontend, DEBUG_VIEW_SCALE};
+use crate::gameboy::GameBoy;
+
+/// A window drawn with SDL2, with input from the keyboard
+pub struct SdlFrontend {
+    context: Sdl,
+    canvas: Canvas<Window>,
+    scaler: Scaler,
+    input: Input,
+    pacer: Pacer,
+}
+
+impl SdlFrontend {
+    pub fn new(scaler: Scaler, keymap: KeyMap, pacer: Pacer) -> Result<SdlFrontend, String> {
+        let context = sdl2::init()?;
+        let window = context
+            .video()?
+            .window("rusty-gameboy", scaler.width(), scaler.height())
+            .position_centered()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window
+            .into_canvas()
+            .accelerated()
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(SdlFrontend {
+            context,
+            canvas,
+            scaler,
+            input: Input::new(keymap),
+            pacer,
+        })
+    }
+}
+
+/// A second window, showing one of the debug views
+struct DebugWindow {
+    view: DebugView,
+    canvas: Canvas<Window>,
+}
+
+impl DebugWindow {
+    fn open(context: &Sdl, view: DebugView, gameboy: &GameBoy) -> Result<DebugWindow, String> {
+        let image = view.render(gameboy);
+        let window = context
+            .video()?
+            .window(
+                view.title(),
+                image.width as u32 * DEBUG_VIEW_SCALE,
+                image.height as u32 * DEBUG_VIEW_SCALE,
+            )
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        Ok(DebugWindow { view, canvas })
+    }
+
+    fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    /// Draw the view as it is now
+    fn draw(&mut self, gameboy: &GameBoy) -> Result<(), String> {
+        let image = self.view.render(gameboy);
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB888,
+                image.width as u32,
+                image.height as u32,
+            )
+            .map_err(|e| e.to_string())?;
+        let pixels: Vec<u8> = image
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_ne_bytes())
+            .collect();
+        texture
+            .update(None, &pixels, image.width * 4)
+            .map_err(|e| e.to_string())?;
+        self.canvas.clear();
+        self.canvas.copy(&texture, None, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+/// The sound channel a key mutes
+fn channel(keycode: Keycode) -> Option<Channel> {
+    match keycode {
+        Keycode::F1 => Some(Channel::Square1),
+        Keycode::F2 => Some(Channel::Square2),
+        Keycode::F3 => Some(Channel::Wave),
+        Keycode::F4 => Some(Channel::Noise),
+        _ => None,
+    }
+}
+
+/// The save slot a number key picks
+fn slot_key(keycode: Keycode) -> Option<u8> {
+    keycode.name().parse().ok()
+}
+
+impl Frontend for SdlFrontend {
+    fn run(&mut self, gameboy: &mut GameBoy) {
+        let texture_creator = self.canvas.texture_creator();
+        // 0x00RRGGBB in native byte order, same as the framebuffer
+        let mut texture = match texture_creator.create_texture_streaming(
+            PixelFormatEnum::RGB888,
+            self.scaler.width(),
+            self.scaler.height(),
+        ) {
+            Ok(texture) => texture,
+            Err(e) => {
+                warn!("Could not create the screen texture: {}", e);
+                return;
+            }
+        };
+        let mut event_pump = match self.context.event_pump() {
+            Ok(event_pump) => event_pump,
+            Err(e) => {
+                warn!("Could not read SDL events: {}", e);
+                return;
+            }
+        };
+        let width = self.scaler.width() as usize;
+        let mut screen = vec![0; width * self.scaler.height() as usize];
+        let mut pixels = vec![0; screen.len() * 4];
+
+        #[cfg(feature = "audio")]
+        let audio = Output::try_open();
+
+        let mut slot = 0;
+        let mut debug_window: Option<DebugWindow> = None;
+        'running: loop {
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::Window {
+                        window_id,
+                        win_event: WindowEvent::Close,
+                        ..
+                    } => {
+                        if debug_window.as_ref().map(DebugWindow::id) == Some(window_id) {
+                            debug_window = None;
+                        } else {
+                            break 'running;
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F10),
+                        repeat: false,
+                        ..
+                    } => {
+                        let view = DebugView::next(debug_window.as_ref().map(|window| window.view));
+                        // Close the old view's window first, as the new one may be a different size
+                        drop(debug_window.take());
+                        debug_window = view.and_then(|view| {
+                            DebugWindow::open(&self.context, view, gameboy)
+                                .map_err(|e| warn!("Could not open the debug window: {}", e))
+                                .ok()
+                        });
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F12),
+                        repeat: false,
+                        ..
+                    } => gameboy.save_screenshot(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F5),
+                        repeat: false,
+                        ..
+                    } => gameboy.quick_save(slot),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F9),
+                        repeat: false,
+                        ..
+                    } => gameboy.quick_load(slot),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Tab),
+                        repeat: false,
+                        ..
+                    } => self.pacer.toggle_fast_forward(),
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        repeat: false,
+                        ..
+                    } => {
+                        if self.input.key(&keycode.name(), true) {
+                            continue;
+                        }
+                        if keycode == Keycode::P {
+                            toggle_pause(gameboy);
+                            #[cfg(feature = "audio")]
+                            if let Some(audio) = &audio {
+                                match gameboy.is_paused() {
+                                    true => audio.pause(),
+                                    false => audio.resume(),
+                                }
+                            }
+                        } else if let Some(number) = slot_key(keycode) {
+                            slot = number;
+                            info!("Save slot {}", slot);
+                        } else if let Some(channel) = channel(keycode) {
+                            gameboy.toggle_muted(channel);
+                        }
+                    }
+                    Event::KeyUp {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        self.input.key(&keycode.name(), false);
+                    }
+                    _ => {}
+                }
+            }
+
+            // Asked even while paused, so there are no missed frames to catch up on resume
+            let mut frames = self.pacer.frames_due(Instant::now());
+            if gameboy.is_paused() {
+                frames = 0;
+            }
+            for _ in 0..frames {
+                self.input.apply(gameboy);
+                if let Err(e) = gameboy.try_step_frame() {
+                    warn!("The emulator stopped: {}", e);
+                    break 'running;
+                }
+                self.input.next_frame();
+                // The sound is dropped while fast forwarding
+                if self.pacer.is_fast_forward() {
+                    continue;
+                }
+                #[cfg(feature = "audio")]
+                if let Some(audio) = &audio {
+                    audio.queue(gameboy);
+                }
+            }
+            self.scaler.blit(gameboy.framebuffer(), &mut screen);
+            for (bytes, pixel) in pixels.chunks_exact_mut(4).zip(screen.iter()) {
+                bytes.copy_from_slice(&pixel.to_ne_bytes());
+            }
+            if let Err(e) = texture.update(None, &pixels, width * 4) {
+                warn!("Could not draw the frame: {}", e);
+            }
+            self.canvas.clear();
+            if let Err(e) = self.canvas.copy(&texture, None, None) {
+                warn!("Could not draw the frame: {}", e);
+            }
+            self.canvas.present();
+            if let Some(Err(e)) = debug_window.as_mut().map(|window| window.draw(gameboy)) {
+                warn!("Could not draw the debug window: {}", e);
+                debug_window = None;
+            }
+            self.pacer.wait();
+        }
+
+        info!("Window closed");
+        gameboy.save_data();
+    }
+}
@@ -0,0 +1,294 @@
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Instant;
+
+use pixels::{Pixels, SurfaceTexture};
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::{Window, WindowBuilder};
+
+use crate::apu::Channel;
+#[cfg(feature = "audio")]
+use crate::frontend::audio::Output;
+use crate::frontend::input::Input;
+use crate::frontend::keymap::KeyMap;
+use crate::frontend::pacer::Pacer;
+use crate::frontend::scaler::Scaler;
+use crate::frontend::{toggle_pause, DebugView, Frontend, DEBUG_VIEW_SCALE};
+use crate::gameboy::GameBoy;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// A window drawn with winit and pixels, which needs no system libraries
+pub struct WinitFrontend {
+    /// Taken when the frontend runs; winit only lets it run once
+    event_loop: Option<EventLoop<()>>,
+    scaler: Scaler,
+    input: Input,
+    pacer: Pacer,
+}
+
+impl WinitFrontend {
+    pub fn new(scaler: Scaler, keymap: KeyMap, pacer: Pacer) -> Result<WinitFrontend, String> {
+        let event_loop = EventLoop::new().map_err(|e| e.to_string())?;
+        Ok(WinitFrontend {
+            event_loop: Some(event_loop),
+            scaler,
+            input: Input::new(keymap),
+            pacer,
+        })
+    }
+}
+
+/// The sound channel a key mutes, the same as in the SDL frontend
+fn channel(key: KeyCode) -> Option<Channel> {
+    match key {
+        KeyCode::F1 => Some(Channel::Square1),
+        KeyCode::F2 => Some(Channel::Square2),
+        KeyCode::F3 => Some(Channel::Wave),
+        KeyCode::F4 => Some(Channel::Noise),
+        _ => None,
+    }
+}
+
+/// The save slot a number key picks
+fn slot_key(key: KeyCode) -> Option<u8> {
+    match key {
+        KeyCode::Digit0 => Some(0),
+        KeyCode::Digit1 => Some(1),
+        KeyCode::Digit2 => Some(2),
+        KeyCode::Digit3 => Some(3),
+        KeyCode::Digit4 => Some(4),
+        KeyCode::Digit5 => Some(5),
+        KeyCode::Digit6 => Some(6),
+        KeyCode::Digit7 => Some(7),
+        KeyCode::Digit8 => Some(8),
+        KeyCode::Digit9 => Some(9),
+        _ => None,
+    }
+}
+
+/// Copy a frame into an RGBA buffer
+fn draw(frame: &mut [u8], screen: &[u32]) {
+    for (rgba, pixel) in frame.chunks_exact_mut(4).zip(screen.iter()) {
+        let [_, r, g, b] = pixel.to_be_bytes();
+        rgba.copy_from_slice(&[r, g, b, 0xFF]);
+    }
+}
+
+/// A second window, showing one of the debug views
+struct DebugWindow {
+    view: DebugView,
+    pixels: Pixels<'static>,
+    window: Arc<Window>,
+}
+
+impl DebugWindow {
+    fn open(
+        target: &EventLoopWindowTarget<()>,
+        view: DebugView,
+        gameboy: &GameBoy,
+    ) -> Result<DebugWindow, String> {
+        let image = view.render(gameboy);
+        let size = LogicalSize::new(
+            image.width as u32 * DEBUG_VIEW_SCALE,
+            image.height as u32 * DEBUG_VIEW_SCALE,
+        );
+        let window = WindowBuilder::new()
+            .with_title(view.title())
+            .with_inner_size(size)
+            .build(target)
+            .map_err(|e| e.to_string())?;
+        let window = Arc::new(window);
+        let window_size = window.inner_size();
+        let surface = SurfaceTexture::new(window_size.width, window_size.height, window.clone());
+        let pixels = Pixels::new(image.width as u32, image.height as u32, surface)
+            .map_err(|e| e.to_string())?;
+        Ok(DebugWindow {
+            view,
+            pixels,
+            window,
+        })
+    }
+
+    /// Draw the view as it is now
+    fn draw(&mut self, gameboy: &GameBoy) -> Result<(), String> {
+        let image = self.view.render(gameboy);
+        draw(self.pixels.frame_mut(), &image.pixels);
+        self.pixels.render().map_err(|e| e.to_string())
+    }
+}
+
+impl Frontend for WinitFrontend {
+    fn run(&mut self, gameboy: &mut GameBoy) {
+        let event_loop = match self.event_loop.take() {
+            Some(event_loop) => event_loop,
+            None => {
+                warn!("The winit frontend can only run once");
+                return;
+            }
+        };
+        let scaler = self.scaler;
+        let input = &mut self.input;
+        let size = LogicalSize::new(scaler.width() as f64, scaler.height() as f64);
+        let window = match WindowBuilder::new()
+            .with_title("rusty-gameboy")
+            .with_inner_size(size)
+            .with_min_inner_size(LogicalSize::new(SCREEN_WIDTH as f64, SCREEN_HEIGHT as f64))
+            .build(&event_loop)
+        {
+            Ok(window) => window,
+            Err(e) => {
+                warn!("Could not open a window: {}", e);
+                return;
+            }
+        };
+        let window_size = window.inner_size();
+        let surface = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        // The filters are drawn at the chosen scale, and pixels scales that to fit the window
+        let mut screen = vec![0; (scaler.width() * scaler.height()) as usize];
+        let mut pixels = match Pixels::new(scaler.width(), scaler.height(), surface) {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                warn!("Could not create the screen surface: {}", e);
+                return;
+            }
+        };
+
+        #[cfg(feature = "audio")]
+        let audio = Output::try_open();
+        let pacer = &mut self.pacer;
+        let mut slot = 0;
+        let mut debug_window: Option<DebugWindow> = None;
+        let result = event_loop.run(|event, target| match event {
+            Event::WindowEvent { window_id, event }
+                if debug_window.as_ref().map(|debug| debug.window.id()) == Some(window_id) =>
+            {
+                match event {
+                    WindowEvent::CloseRequested => debug_window = None,
+                    WindowEvent::Resized(size) => {
+                        if let Some(debug) = &mut debug_window {
+                            if let Err(e) = debug.pixels.resize_surface(size.width, size.height) {
+                                warn!("Could not resize the debug window: {}", e);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => target.exit(),
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(key),
+                            state,
+                            repeat: false,
+                            ..
+                        },
+                    ..
+                } => {
+                    let pressed = state == ElementState::Pressed;
+                    if key == KeyCode::Escape {
+                        target.exit();
+                    } else if key == KeyCode::Tab {
+                        if pressed {
+                            pacer.toggle_fast_forward();
+                        }
+                    } else if key == KeyCode::F12 {
+                        if pressed {
+                            gameboy.save_screenshot();
+                        }
+                    } else if key == KeyCode::F5 {
+                        if pressed {
+                            gameboy.quick_save(slot);
+                        }
+                    } else if key == KeyCode::F9 {
+                        if pressed {
+                            gameboy.quick_load(slot);
+                        }
+                    } else if key == KeyCode::F10 {
+                        if pressed {
+                            let view =
+                                DebugView::next(debug_window.as_ref().map(|debug| debug.view));
+                            // Close the old view's window first, as the new one may be a different size
+                            drop(debug_window.take());
+                            debug_window = view.and_then(|view| {
+                                DebugWindow::open(target, view, gameboy)
+                                    .map_err(|e| warn!("Could not open the debug window: {}", e))
+                                    .ok()
+                            });
+                        }
+                    } else if !input.key(&format!("{:?}", key), pressed) && pressed {
+                        if key == KeyCode::KeyP {
+                            toggle_pause(gameboy);
+                            #[cfg(feature = "audio")]
+                            if let Some(audio) = &audio {
+                                match gameboy.is_paused() {
+                                    true => audio.pause(),
+                                    false => audio.resume(),
+                                }
+                            }
+                        } else if let Some(number) = slot_key(key) {
+                            slot = number;
+                            info!("Save slot {}", slot);
+                        } else if let Some(channel) = channel(key) {
+                            gameboy.toggle_muted(channel);
+                        }
+                    }
+                }
+                WindowEvent::Resized(size) => {
+                    if let Err(e) = pixels.resize_surface(size.width, size.height) {
+                        warn!("Could not resize the screen surface: {}", e);
+                    }
+                }
+                WindowEvent::RedrawRequested => {
+                    scaler.blit(gameboy.framebuffer(), &mut screen);
+                    draw(pixels.frame_mut(), &screen);
+                    if let Err(e) = pixels.render() {
+                        warn!("Could not draw the frame: {}", e);
+                        target.exit();
+                    }
+                }
+                _ => {}
+            },
+            Event::AboutToWait => {
+                // Asked even while paused, so there are no missed frames to catch up on resume
+                let mut frames = pacer.frames_due(Instant::now());
+                if gameboy.is_paused() {
+                    frames = 0;
+                }
+                for _ in 0..frames {
+                    input.apply(gameboy);
+                    if let Err(e) = gameboy.try_step_frame() {
+                        warn!("The emulator stopped: {}", e);
+                        target.exit();
+                        return;
+                    }
+                    input.next_frame();
+                    // The sound is dropped while fast forwarding
+                    #[cfg(feature = "audio")]
+                    if let (Some(audio), false) = (&audio, pacer.is_fast_forward()) {
+                        audio.queue(gameboy);
+                    }
+                }
+                if frames > 0 {
+                    window.request_redraw();
+                    if let Some(Err(e)) = debug_window.as_mut().map(|debug| debug.draw(gameboy)) {
+                        warn!("Could not draw the debug window: {}", e);
+                        debug_window = None;
+                    }
+                }
+                target.set_control_flow(ControlFlow::WaitUntil(pacer.next_frame()));
+            }
+            _ => {}
+        });
+        if let Err(e) = result {
+            warn!("The window closed unexpectedly: {}", e);
+        }
+
+        info!("Window closed");
+        gameboy.save_data();
+    }
+}
@@ -0,0 +1,1314 @@
+use core::convert::TryInto;
+use core::fmt;
+#[cfg(feature = "std")]
+use log::warn;
+use log::{debug, info};
+#[cfg(feature = "std")]
+use std::fs::{self, File};
+#[cfg(feature = "std")]
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+use crate::apu::buffer::StereoSample;
+use crate::apu::resampler::Resampling;
+use crate::apu::Channel;
+use crate::callstack::CallStack;
+use crate::cartridge::rtc::Clock;
+use crate::cartridge::Cartridge;
+use crate::cheats::Cheats;
+use crate::coverage::Coverage;
+use crate::cpu_core::cpu::{Cpu, CpuError, Registers};
+#[cfg(feature = "debugger")]
+use crate::debugger::script::Script;
+use crate::disassembler;
+use crate::doctor;
+#[cfg(feature = "hooks")]
+use crate::hooks::Hooks;
+use crate::joypad::Button;
+#[cfg(feature = "std")]
+use crate::mmu::trace::AddressRange;
+use crate::mmu::Mmu;
+#[cfg(feature = "std")]
+use crate::mmu::{CGB_BOOT_ROM_SIZE, DMG_BOOT_ROM_SIZE};
+#[cfg(feature = "std")]
+use crate::movie::{Movie, MovieRecorder};
+use crate::ppu::palette::Shades;
+use crate::ppu::sprites::Sprite;
+use crate::ppu::viewer::Image;
+use crate::ppu::{Renderer, DOTS_PER_FRAME, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::prelude::*;
+use crate::profiler::Profiler;
+use crate::savestate::file::StateFile;
+use crate::savestate::save_state_fields;
+#[cfg(feature = "std")]
+use crate::savestate::slot_path;
+use crate::savestate::Snapshot;
+#[cfg(feature = "screenshot")]
+use crate::screenshot::{save_png, screenshot_path};
+use crate::seed::Rng;
+use crate::serial::SerialDevice;
+use crate::test_rom::{self, Verdict};
+#[cfg(feature = "std")]
+use crate::wav::WavWriter;
+
+/// T-cycles per second
+pub const CLOCK_HZ: u32 = 4_194_304;
+
+/// A frame of video output: one 0x00RRGGBB value per pixel, row by row
+pub type Framebuffer = [u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+/// Recording the input to a movie, or playing one back
+#[cfg(feature = "std")]
+enum MovieMode {
+    Recording(MovieRecorder),
+    Playing(Movie),
+}
+
+/// The whole console. This is what the command line and frontends talk to.
+/// It runs the CPU and keeps the rest of the hardware in step with it.
+pub struct GameBoy {
+    cpu: Cpu,
+    /// The memory bus, with the cartridge, PPU, APU, timer, serial port and
+    /// joypad mapped onto it
+    mmu: Mmu,
+    #[cfg(feature = "std")]
+    rom_path: PathBuf,
+    /// How many frames step_frame() has completed
+    frame: u64,
+    /// How many instructions have run
+    instructions: u64,
+    /// T-cycles the last instruction run by step_cycles() went past its end
+    overshoot: u32,
+    /// Save a screenshot when this frame completes
+    #[cfg(feature = "screenshot")]
+    screenshot_frame: Option<u64>,
+    /// The audio of the last frame run
+    audio: Vec<StereoSample>,
+    /// Where the audio is recorded to, if it is
+    #[cfg(feature = "std")]
+    audio_recording: Option<WavWriter<BufWriter<File>>>,
+    #[cfg(feature = "std")]
+    movie: Option<MovieMode>,
+    /// While paused, step_frame() runs nothing
+    paused: bool,
+    /// Where each instruction is traced to, if they are
+    #[cfg(feature = "std")]
+    instruction_trace: Option<Box<dyn Write>>,
+    /// The trace waits for the CPU to reach this address
+    #[cfg(feature = "std")]
+    trace_start: Option<u16>,
+    /// The trace ends after the instruction at this address
+    #[cfg(feature = "std")]
+    trace_stop: Option<u16>,
+    /// A shadow of the call stack, if calls are being tracked
+    call_stack: Option<CallStack>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    #[cfg(feature = "debugger")]
+    script: Option<Script>,
+    /// Whether to look for a test ROM's result
+    watch_test_result: bool,
+    test_verdict: Option<Verdict>,
+}
+
+// Everything else, like the movie or the audio recording, belongs to the run
+save_state_fields!(GameBoy { frame, overshoot });
+
+/// Write a report to a file, or stdout if the path is `-`
+#[cfg(feature = "std")]
+fn write_report(path: &Path, report: &str) -> io::Result<()> {
+    if path == Path::new("-") {
+        print!("{}", report);
+        Ok(())
+    } else {
+        fs::write(path, report)
+    }
+}
+
+impl fmt::Display for GameBoy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ROM: {} bytes", self.mmu.cartridge().rom_size())?;
+        write!(f, "{}", self.cpu)
+    }
+}
+
+impl GameBoy {
+    /// Power on with a cartridge holding `rom`
+    pub fn new(rom: Vec<u8>) -> GameBoy {
+        GameBoy {
+            cpu: Cpu::new(),
+            mmu: Mmu::new(Cartridge::new(rom)),
+            #[cfg(feature = "std")]
+            rom_path: PathBuf::new(),
+            frame: 0,
+            instructions: 0,
+            overshoot: 0,
+            #[cfg(feature = "screenshot")]
+            screenshot_frame: None,
+            audio: Vec::new(),
+            #[cfg(feature = "std")]
+            audio_recording: None,
+            #[cfg(feature = "std")]
+            movie: None,
+            paused: false,
+            #[cfg(feature = "std")]
+            instruction_trace: None,
+            #[cfg(feature = "std")]
+            trace_start: None,
+            #[cfg(feature = "std")]
+            trace_stop: None,
+            call_stack: None,
+            profiler: None,
+            coverage: None,
+            #[cfg(feature = "debugger")]
+            script: None,
+            watch_test_result: false,
+            test_verdict: None,
+        }
+    }
+
+    /// Power on with the ROM at `rom_path`, restoring the save data next to it,
+    /// or the error reading it. With a `seed`, the run is deterministic, see
+    /// seed().
+    #[cfg(feature = "std")]
+    pub fn new_from_path(rom_path: PathBuf, seed: Option<u64>) -> io::Result<GameBoy> {
+        let mut gameboy = GameBoy::new(fs::read(&rom_path)?);
+        if let Some(seed) = seed {
+            gameboy.seed(seed);
+        }
+        let cartridge = gameboy.mmu.cartridge_mut();
+        cartridge.load_save_data(rom_path.clone());
+        debug!(
+            "Loaded ROM (byte preview): {:#02x} {:#02x} {:#02x}",
+            cartridge.read(0),
+            cartridge.read(1),
+            cartridge.read(2)
+        );
+        gameboy.rom_path = rom_path;
+        Ok(gameboy)
+    }
+
+    /// Make the run deterministic: the RAM's contents and the divider's phase
+    /// at power on come from `seed`, and the cartridge's clock follows
+    /// emulated time rather than the host's
+    pub fn seed(&mut self, seed: u64) {
+        info!("Running deterministically with seed {}", seed);
+        self.mmu.randomize(&mut Rng::new(seed));
+        self.mmu.cartridge_mut().set_clock(Clock::Emulated {
+            seconds: 0,
+            cycles: 0,
+        });
+    }
+
+    /// Run one instruction, then let the rest of the hardware catch up.
+    /// Returns how many T-cycles it took. Panics at an instruction the CPU
+    /// can't run; see try_step_instruction().
+    pub fn step_instruction(&mut self) -> u32 {
+        self.try_step_instruction()
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Run one instruction, then let the rest of the hardware catch up.
+    /// Returns how many T-cycles it took, or why the CPU couldn't run it,
+    /// in which case nothing else runs either.
+    pub fn try_step_instruction(&mut self) -> Result<u32, CpuError> {
+        // Before the hooks, traces and coverage see the instruction
+        let mmu = &mut self.mmu;
+        self.cpu.check_next(|address| mmu.peek(address))?;
+        #[cfg(feature = "hooks")]
+        if let Some(hooks) = self.mmu.hooks_mut() {
+            hooks.on_instruction(&self.cpu.registers());
+        }
+        #[cfg(feature = "std")]
+        if self.instruction_trace.is_some() {
+            self.trace_instruction();
+        }
+        if self.coverage.is_some() {
+            self.record_coverage();
+        }
+        let cycles = if self.call_stack.is_some() {
+            self.step_tracking_calls()?
+        } else {
+            let cycles = self.cpu.try_step(&mut self.mmu)?;
+            self.mmu.tick(cycles);
+            cycles
+        };
+        #[cfg(feature = "debugger")]
+        if self.script.is_some() {
+            let writes = self.mmu.take_writes();
+            self.run_script(|script, gameboy| script.after_instruction(gameboy, &writes));
+        }
+        self.instructions += 1;
+        // Whether the ROM has said it passed where it's got to, as the next
+        // instruction may be Mooneye's LD B, B, which the CPU can't run yet
+        if self.watch_test_result && self.test_verdict.is_none() {
+            self.check_test_result();
+        }
+        Ok(cycles)
+    }
+
+    /// How many instructions have run since power on
+    pub fn instructions_run(&self) -> u64 {
+        self.instructions
+    }
+
+    fn step_tracking_calls(&mut self) -> Result<u32, CpuError> {
+        let before = self.cpu.registers();
+        let opcode = self.mmu.peek(before.pc);
+        let cycles = self.cpu.try_step(&mut self.mmu)?;
+        self.mmu.tick(cycles);
+        if let Some(call_stack) = &mut self.call_stack {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record(before.pc, cycles, call_stack);
+            }
+            call_stack.update(&before, opcode, &self.cpu.registers());
+        }
+        Ok(cycles)
+    }
+
+    /// Look for Blargg's and Mooneye's test ROMs saying whether they passed,
+    /// for test_verdict()
+    pub fn watch_test_result(&mut self) {
+        self.watch_test_result = true;
+        self.mmu.serial_mut().keep_sent(test_rom::SERIAL_TAIL);
+    }
+
+    /// Whether the test ROM passed, once it has said
+    pub fn test_verdict(&self) -> Option<Verdict> {
+        self.test_verdict
+    }
+
+    fn check_test_result(&mut self) {
+        let registers = self.cpu.registers();
+        let mmu = &mut self.mmu;
+        self.test_verdict = test_rom::from_registers(&registers, || mmu.peek(registers.pc))
+            .or_else(|| test_rom::from_serial(mmu.serial_mut().sent()));
+    }
+
+    /// Count where the CPU spends its time, for write_profile()
+    pub fn enable_profiling(&mut self) {
+        self.track_calls();
+        self.profiler = Some(Profiler::default());
+    }
+
+    /// Write the hottest addresses and subroutines with write_report()
+    #[cfg(feature = "std")]
+    pub fn write_profile(&self, path: &Path) -> io::Result<()> {
+        match &self.profiler {
+            Some(profiler) => write_report(path, &profiler.report()),
+            None => Ok(()),
+        }
+    }
+
+    /// Track which ROM bytes run as code, for write_coverage()
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new(self.mmu.cartridge().rom_size()));
+    }
+
+    fn record_coverage(&mut self) {
+        let pc = self.cpu.registers().pc;
+        let offset = match self.mmu.cartridge().rom_offset(pc) {
+            Some(offset) => offset,
+            None => return,
+        };
+        let bytes: Vec<u8> = (0..3).map(|i| self.mmu.peek(pc.wrapping_add(i))).collect();
+        let size = disassembler::decode(pc, &bytes).size;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(offset, size);
+        }
+    }
+
+    /// Write the ranges of each ROM bank that ran with write_report()
+    #[cfg(feature = "std")]
+    pub fn write_coverage(&self, path: &Path) -> io::Result<()> {
+        match &self.coverage {
+            Some(coverage) => write_report(path, &coverage.report()),
+            None => Ok(()),
+        }
+    }
+
+    /// Start a script, then run its hooks from now on
+    #[cfg(feature = "debugger")]
+    pub fn set_script(&mut self, script: Script) {
+        self.script = Some(script);
+        self.run_script(Script::start);
+        if let Some(script) = &self.script {
+            self.mmu.watch_writes(script.watched_addresses());
+        }
+    }
+
+    /// Run some of the script, which is taken out while it runs so it can
+    /// use the GameBoy
+    #[cfg(feature = "debugger")]
+    fn run_script(&mut self, run: impl FnOnce(&mut Script, &mut GameBoy)) {
+        if let Some(mut script) = self.script.take() {
+            run(&mut script, self);
+            // The script's own edits don't call its write hooks
+            self.mmu.take_writes();
+            self.script = Some(script);
+        }
+    }
+
+    /// Call `hooks` as the game runs, in place of any set before
+    #[cfg(feature = "hooks")]
+    pub fn set_hooks(&mut self, hooks: impl Hooks + 'static) {
+        self.mmu.set_hooks(Some(Box::new(hooks)));
+    }
+
+    #[cfg(feature = "hooks")]
+    pub fn remove_hooks(&mut self) {
+        self.mmu.set_hooks(None);
+    }
+
+    /// Start keeping a shadow of the call stack, if it isn't kept already.
+    /// It starts empty, as if the CPU were at the top level.
+    pub fn track_calls(&mut self) -> &CallStack {
+        self.call_stack.get_or_insert_with(CallStack::default)
+    }
+
+    /// Run whole instructions for `cycles` T-cycles. When the last one runs
+    /// past the end, the extra time comes off the next call, so calls add up
+    /// to exactly the time asked for.
+    pub fn step_cycles(&mut self, cycles: u32) {
+        self.try_step_cycles(cycles)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// step_cycles(), stopping at an instruction the CPU can't run
+    pub fn try_step_cycles(&mut self, cycles: u32) -> Result<(), CpuError> {
+        let mut elapsed = self.overshoot;
+        while elapsed < cycles {
+            elapsed += self.try_step_instruction()?;
+        }
+        self.overshoot = elapsed - cycles;
+        Ok(())
+    }
+
+    /// The cheat codes in use, which can be changed while the game runs
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        self.mmu.cheats_mut()
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    /// Read a byte from the bus, without the side effects of the CPU reading it
+    pub fn peek(&mut self, address: u16) -> u8 {
+        self.mmu.peek(address)
+    }
+
+    /// Write a byte to the bus, as the CPU would
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.mmu.write(address, value);
+    }
+
+    /// Write a byte to the bus now and at every VBlank from now on,
+    /// so the game can't change it for long
+    pub fn freeze(&mut self, address: u16, value: u8) {
+        self.poke(address, value);
+        self.mmu.cheats_mut().freeze(address, value);
+    }
+
+    /// Let the game change a frozen byte again.
+    /// Returns whether it was frozen.
+    pub fn unfreeze(&mut self, address: u16) -> bool {
+        self.mmu.cheats_mut().unfreeze(address)
+    }
+
+    /// Trace every instruction to a file, or stdout if the path is `-`,
+    /// in Gameboy Doctor's format
+    #[cfg(feature = "std")]
+    pub fn trace_instructions(&mut self, path: &Path) -> io::Result<()> {
+        let output: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(path)?)
+        };
+        self.instruction_trace = Some(Box::new(BufWriter::new(output)));
+        self.set_stub_ly(true);
+        Ok(())
+    }
+
+    /// Run the boot ROM at `path` first, which hands over to the cartridge
+    /// at 0x0100 once it has checked the header
+    #[cfg(feature = "std")]
+    pub fn load_boot_rom(&mut self, path: &Path) -> Result<(), String> {
+        let boot_rom = fs::read(path).map_err(|e| e.to_string())?;
+        if boot_rom.len() != DMG_BOOT_ROM_SIZE && boot_rom.len() != CGB_BOOT_ROM_SIZE {
+            return Err(format!(
+                "A boot ROM is {} bytes (DMG) or {} bytes (CGB), but this one is {}",
+                DMG_BOOT_ROM_SIZE,
+                CGB_BOOT_ROM_SIZE,
+                boot_rom.len()
+            ));
+        }
+        self.mmu.map_boot_rom(boot_rom);
+        Ok(())
+    }
+
+    /// Start the cartridge at 0x0100, as if the boot ROM had just run
+    pub fn skip_boot(&mut self) {
+        let cgb = self.mmu.cartridge().supports_cgb();
+        self.cpu.skip_boot(cgb);
+    }
+
+    /// Only trace from when the CPU first reaches `start`, and stop after
+    /// the instruction at `stop`
+    #[cfg(feature = "std")]
+    pub fn trace_between(&mut self, start: Option<u16>, stop: Option<u16>) {
+        self.trace_start = start;
+        self.trace_stop = stop;
+    }
+
+    /// Make LY always read 0x90, as it does for Gameboy Doctor's logs
+    pub fn set_stub_ly(&mut self, stub_ly: bool) {
+        self.mmu.set_stub_ly(stub_ly);
+    }
+
+    /// The state before the next instruction, as a line of Gameboy Doctor's log
+    pub fn trace_line(&mut self) -> String {
+        let registers = self.cpu.registers();
+        let mut pcmem = [0; 4];
+        for (offset, byte) in pcmem.iter_mut().enumerate() {
+            *byte = self.mmu.peek(registers.pc.wrapping_add(offset as u16));
+        }
+        doctor::trace_line(&registers, pcmem)
+    }
+
+    #[cfg(feature = "std")]
+    fn trace_instruction(&mut self) {
+        let pc = self.cpu.registers().pc;
+        match self.trace_start {
+            Some(start) if start != pc => return,
+            Some(_) => self.trace_start = None,
+            None => {}
+        }
+        let line = self.trace_line();
+        if let Some(trace) = &mut self.instruction_trace {
+            if let Err(e) = writeln!(trace, "{}", line) {
+                warn!("Stopped tracing instructions: {}", e);
+                self.instruction_trace = None;
+            }
+        }
+        if self.trace_stop == Some(pc) {
+            // Dropping the trace flushes it
+            self.instruction_trace = None;
+        }
+    }
+
+    /// Log every bus access to the given address ranges
+    #[cfg(feature = "std")]
+    pub fn enable_memory_trace(&mut self, ranges: Vec<AddressRange>) {
+        self.mmu.enable_trace(ranges);
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.mmu.set_renderer(renderer);
+    }
+
+    /// Choose the colors DMG games are shown in
+    pub fn set_shades(&mut self, shades: Shades) {
+        self.mmu.set_shades(shades);
+    }
+
+    /// The tile data and tile maps, for a debug view
+    pub fn vram_view(&self) -> Image {
+        self.mmu.ppu().vram_view(self.mmu.vram())
+    }
+
+    /// Every sprite in OAM drawn, for a debug view
+    pub fn oam_view(&self) -> Image {
+        self.mmu.ppu().oam_view(self.mmu.vram(), self.mmu.oam())
+    }
+
+    /// Swatches of every palette, for a debug view
+    pub fn palette_view(&self) -> Image {
+        self.mmu.ppu().palette_view()
+    }
+
+    /// The palette registers and CGB palettes, as text
+    pub fn palette_table(&self) -> String {
+        self.mmu.ppu().palette_table()
+    }
+
+    /// The 40 sprites in OAM
+    pub fn sprites(&self) -> Vec<Sprite> {
+        self.mmu.ppu().sprites(self.mmu.oam())
+    }
+
+    /// The last frame drawn. Only complete when take_frame_ready() says so;
+    /// otherwise the bottom of the screen may still be from the previous frame.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        self.mmu
+            .ppu()
+            .screen()
+            .try_into()
+            .expect("the screen is 160x144 pixels")
+    }
+
+    /// Whether the PPU has completed a frame since the last call
+    pub fn take_frame_ready(&mut self) -> bool {
+        self.mmu.take_frame_complete()
+    }
+
+    /// Stop running frames until resume(). The last frame's audio is dropped
+    /// so nothing is left to play.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.audio.clear();
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Run for one video frame, 70224 T-cycles, unless paused. Panics at an
+    /// instruction the CPU can't run; see try_step_frame().
+    pub fn step_frame(&mut self) {
+        self.try_step_frame().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Run for one video frame, 70224 T-cycles, unless paused. Stops at an
+    /// instruction the CPU can't run, leaving the frame unfinished.
+    pub fn try_step_frame(&mut self) -> Result<(), CpuError> {
+        if self.paused {
+            return Ok(());
+        }
+        #[cfg(feature = "std")]
+        self.update_movie();
+        self.try_step_cycles(DOTS_PER_FRAME)?;
+        self.frame += 1;
+        #[cfg(feature = "screenshot")]
+        if self.screenshot_frame == Some(self.frame) {
+            self.save_screenshot();
+        }
+        #[cfg(feature = "hooks")]
+        if let Some(mut hooks) = self.mmu.take_hooks() {
+            hooks.on_frame(self.frame, self.framebuffer());
+            self.mmu.set_hooks(Some(hooks));
+        }
+        #[cfg(feature = "debugger")]
+        {
+            let frame = self.frame;
+            self.run_script(|script, gameboy| script.end_of_frame(gameboy, frame));
+        }
+
+        self.audio.clear();
+        self.audio.extend(self.mmu.apu_mut().samples().drain());
+        #[cfg(feature = "std")]
+        if let Some(wav) = &mut self.audio_recording {
+            if let Err(e) = wav.write_samples(self.audio.iter()) {
+                warn!("Stopped recording audio: {}", e);
+                self.audio_recording = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record this frame's input, or set it from the movie being played
+    #[cfg(feature = "std")]
+    fn update_movie(&mut self) {
+        let frame = self.frame as usize;
+        let joypad = self.mmu.joypad_mut();
+        match &mut self.movie {
+            Some(MovieMode::Recording(recorder)) => {
+                if let Err(e) = recorder.record(joypad.pressed()) {
+                    warn!("Stopped recording the movie: {}", e);
+                    self.movie = None;
+                }
+            }
+            Some(MovieMode::Playing(movie)) => match movie.frames.get(frame) {
+                Some(pressed) => joypad.set_all_pressed(*pressed),
+                None => {
+                    info!("The movie has ended after {} frames", frame);
+                    joypad.set_all_pressed(0);
+                    self.movie = None;
+                }
+            },
+            None => {}
+        }
+    }
+
+    /// Save a screenshot when the given frame (counting from 1) completes
+    #[cfg(feature = "screenshot")]
+    pub fn set_screenshot_frame(&mut self, frame: u64) {
+        self.screenshot_frame = Some(frame);
+    }
+
+    /// Save the last frame to a PNG next to the ROM
+    #[cfg(feature = "screenshot")]
+    pub fn save_screenshot(&self) {
+        let path = screenshot_path(&self.rom_path, self.frame);
+        match save_png(&path, self.framebuffer()) {
+            Ok(()) => info!("Saved screenshot to {:?}", path),
+            Err(e) => warn!("Could not save screenshot to {:?}: {}", path, e),
+        }
+    }
+
+    /// The whole machine, a chunk for each part
+    fn state_file(&self) -> StateFile {
+        let mut file = StateFile::default();
+        file.set_chunk(b"ROM ", self.mmu.cartridge().header().to_vec());
+        file.add(b"GBOY", self);
+        file.add(b"CPU ", &self.cpu);
+        self.mmu.save_chunks(&mut file);
+        file
+    }
+
+    /// Restore the whole machine from `file`. If it can't be loaded,
+    /// the machine is left as it was.
+    fn restore_state_file(&mut self, file: &StateFile) -> Result<(), String> {
+        if file.chunk(b"ROM ").map(Vec::as_slice) != Some(self.mmu.cartridge().header()) {
+            return Err(String::from("The save state is for a different ROM"));
+        }
+        let backup = self.state_file();
+        let result = file
+            .load(b"GBOY", self)
+            .and_then(|()| file.load(b"CPU ", &mut self.cpu))
+            .and_then(|()| self.mmu.load_chunks(file));
+        if result.is_err() {
+            self.restore_state_file(&backup)
+                .expect("the state just saved can be loaded");
+        }
+        self.audio.clear();
+        if result.is_ok() {
+            // The state's stack holds calls this run never saw
+            if let Some(call_stack) = &mut self.call_stack {
+                *call_stack = CallStack::default();
+            }
+        }
+        result
+    }
+
+    /// Copy the whole machine in memory, to restore() later
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot(self.state_file())
+    }
+
+    /// Go back to a snapshot of this ROM
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+        self.restore_state_file(&snapshot.0)
+    }
+
+    /// Save the whole machine to a file
+    #[cfg(feature = "std")]
+    pub fn save_state(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.state_file().to_bytes())
+    }
+
+    /// Restore the whole machine from a file written by save_state(),
+    /// by this build or an older one
+    #[cfg(feature = "std")]
+    pub fn load_state(&mut self, path: &Path) -> Result<(), String> {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        self.restore_state_file(&StateFile::from_bytes(&bytes)?)
+    }
+
+    /// Save the whole machine to a numbered slot next to the ROM, from a hotkey
+    #[cfg(feature = "std")]
+    pub fn quick_save(&self, slot: u8) {
+        let path = slot_path(&self.rom_path, slot);
+        let result = match path.parent() {
+            Some(directory) => fs::create_dir_all(directory),
+            None => Ok(()),
+        }
+        .and_then(|()| self.save_state(&path));
+        match result {
+            Ok(()) => info!("Saved slot {} to {:?}", slot, path),
+            Err(e) => warn!("Could not save slot {} to {:?}: {}", slot, path, e),
+        }
+    }
+
+    /// Restore the state saved to a numbered slot, from a hotkey
+    #[cfg(feature = "std")]
+    pub fn quick_load(&mut self, slot: u8) {
+        let path = slot_path(&self.rom_path, slot);
+        match self.load_state(&path) {
+            Ok(()) => info!("Loaded slot {} from {:?}", slot, path),
+            Err(e) => warn!("Could not load slot {} from {:?}: {}", slot, path, e),
+        }
+    }
+
+    /// The audio of the last frame run, at `sample_rate()` samples per second
+    pub fn audio_samples(&mut self) -> impl Iterator<Item = StereoSample> + '_ {
+        self.audio.drain(..)
+    }
+
+    pub fn set_resampling(&mut self, resampling: Resampling) {
+        self.mmu.apu_mut().set_resampling(resampling);
+    }
+
+    /// Leave a sound channel out of the mix
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.mmu.apu_mut().set_muted(channel, muted);
+    }
+
+    /// Mute or unmute a sound channel, eg from a key press
+    pub fn toggle_muted(&mut self, channel: Channel) {
+        let apu = self.mmu.apu_mut();
+        let muted = !apu.is_muted(channel);
+        apu.set_muted(channel, muted);
+        info!(
+            "Channel {} {}",
+            channel.number(),
+            if muted { "muted" } else { "unmuted" }
+        );
+    }
+
+    /// Only mix in the soloed sound channels
+    pub fn set_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.mmu.apu_mut().set_soloed(channel, soloed);
+    }
+
+    /// Write the audio of every frame run from now on to a WAV file
+    #[cfg(feature = "std")]
+    pub fn record_audio(&mut self, path: &Path) -> io::Result<()> {
+        let sample_rate = self.sample_rate();
+        self.audio_recording = Some(WavWriter::create(path, sample_rate)?);
+        info!("Recording audio to {:?}", path);
+        Ok(())
+    }
+
+    /// Copy the bytes sent over the serial port to a file, or stdout if
+    /// the path is `-`
+    #[cfg(feature = "std")]
+    pub fn copy_serial_output(&mut self, path: &Path) -> io::Result<()> {
+        let output: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(path)?)
+        };
+        self.set_serial_output(output);
+        Ok(())
+    }
+
+    /// Copy the bytes sent over the serial port to `output`
+    #[cfg(feature = "std")]
+    pub fn set_serial_output(&mut self, output: Box<dyn Write>) {
+        self.mmu.serial_mut().set_output(output);
+    }
+
+    /// Plug something into the serial port, like a link cable
+    pub fn plug_in_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.mmu.serial_mut().plug_in(device);
+    }
+
+    /// Record the input of every frame from power on to a movie
+    #[cfg(feature = "std")]
+    pub fn record_movie(&mut self, path: &Path) -> io::Result<()> {
+        self.movie = Some(MovieMode::Recording(MovieRecorder::create(path)?));
+        info!("Recording a movie to {:?}", path);
+        Ok(())
+    }
+
+    /// Play back the input of a movie from power on
+    #[cfg(feature = "std")]
+    pub fn play_movie(&mut self, path: &Path) -> Result<(), String> {
+        let movie = Movie::load(path)?;
+        info!("Playing {} frames from {:?}", movie.frames.len(), path);
+        self.movie = Some(MovieMode::Playing(movie));
+        Ok(())
+    }
+
+    /// Whether something happens at the end of every frame, like recording,
+    /// so the game has to be run a frame at a time
+    pub fn has_frame_work(&self) -> bool {
+        self.is_recording() || self.has_script()
+    }
+
+    #[cfg(feature = "std")]
+    fn is_recording(&self) -> bool {
+        self.audio_recording.is_some() || self.movie.is_some()
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn is_recording(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "debugger")]
+    fn has_script(&self) -> bool {
+        self.script.is_some()
+    }
+
+    #[cfg(not(feature = "debugger"))]
+    fn has_script(&self) -> bool {
+        false
+    }
+
+    pub fn sample_rate(&mut self) -> u32 {
+        self.mmu.apu_mut().sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.mmu.apu_mut().set_sample_rate(sample_rate);
+    }
+
+    /// Press or release a button
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        // The movie has the controls
+        #[cfg(feature = "std")]
+        if let Some(MovieMode::Playing(_)) = self.movie {
+            return;
+        }
+        self.mmu.joypad_mut().set_pressed(button, pressed);
+    }
+
+    /// Write the cartridge's save data next to the ROM
+    #[cfg(feature = "std")]
+    pub fn save_data(&mut self) {
+        self.mmu.cartridge_mut().save_rtc();
+    }
+
+    /// Run one instruction, then save, for a run with nothing else to do
+    pub fn start(&mut self) {
+        info!("Running execute()");
+        self.step_instruction();
+        debug!("{}", self);
+
+        // Keep the in-game clock running while the emulator is closed
+        #[cfg(feature = "std")]
+        self.save_data();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::palette::GRAYSCALE;
+    #[cfg(feature = "std")]
+    use crate::ppu::LY;
+    use crate::ppu::{DOTS_PER_LINE, VISIBLE_LINES};
+    use crate::timer::DIV;
+    #[cfg(feature = "hooks")]
+    use alloc::rc::Rc;
+    #[cfg(feature = "hooks")]
+    use core::cell::RefCell;
+
+    /// A GameBoy running a ROM of NOPs
+    fn gameboy() -> GameBoy {
+        GameBoy::new(vec![0; 0x8000])
+    }
+
+    #[test]
+    fn test_framebuffer() {
+        let mut gameboy = gameboy();
+        assert!(!gameboy.take_frame_ready());
+        assert!(gameboy
+            .framebuffer()
+            .iter()
+            .all(|pixel| *pixel == GRAYSCALE[0]));
+
+        gameboy.mmu.tick(DOTS_PER_LINE * VISIBLE_LINES as u32);
+        assert!(gameboy.take_frame_ready());
+        assert!(!gameboy.take_frame_ready());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_new_from_path_missing() {
+        match GameBoy::new_from_path(PathBuf::from("missing.gb"), None) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            Ok(_) => panic!("Loaded a ROM that isn't there"),
+        }
+    }
+
+    /// The hardware runs for as long as each instruction takes
+    #[test]
+    fn test_step_instruction() {
+        let mut gameboy = gameboy();
+        // 64 NOPs of 4 T-cycles each
+        for _ in 0..64 {
+            assert_eq!(gameboy.step_instruction(), 4);
+        }
+        assert_eq!(gameboy.mmu.read(DIV), 1);
+    }
+
+    #[test]
+    fn test_try_step_instruction() {
+        let mut rom = vec![0; 0x8000];
+        rom[0] = 0xD3;
+        let mut gameboy = GameBoy::new(rom);
+        assert_eq!(
+            gameboy.try_step_instruction(),
+            Err(CpuError::Illegal {
+                pc: 0,
+                opcode: 0xD3
+            })
+        );
+        assert_eq!(gameboy.instructions_run(), 0);
+        assert_eq!(gameboy.mmu.read(DIV), 0);
+    }
+
+    #[test]
+    fn test_try_step_frame() {
+        // NOPs into an illegal opcode
+        let mut rom = vec![0; 0x8000];
+        rom[0x10] = 0xD3;
+        let mut gameboy = GameBoy::new(rom);
+        assert_eq!(
+            gameboy.try_step_frame(),
+            Err(CpuError::Illegal {
+                pc: 0x10,
+                opcode: 0xD3
+            })
+        );
+        assert_eq!(gameboy.frame, 0);
+        assert_eq!(gameboy.instructions_run(), 0x10);
+    }
+
+    #[test]
+    fn test_step_cycles() {
+        let mut gameboy = gameboy();
+        // A NOP is 4 T-cycles, so the second call starts 2 in
+        gameboy.step_cycles(6);
+        assert_eq!(gameboy.overshoot, 2);
+        gameboy.step_cycles(250);
+        assert_eq!(gameboy.overshoot, 0);
+        assert_eq!(gameboy.mmu.read(DIV), 1);
+    }
+
+    #[test]
+    fn test_step_frame() {
+        let mut gameboy = gameboy();
+        gameboy.step_frame();
+        // Exactly a frame's worth of DIV ticks, one every 256 T-cycles
+        assert_eq!(gameboy.mmu.read(DIV), (DOTS_PER_FRAME / 256) as u8);
+        assert!(gameboy.take_frame_ready());
+    }
+
+    /// Everything a seed decides: work RAM, high RAM and DIV
+    fn seeded_state(seed: u64) -> Vec<u8> {
+        let mut gameboy = gameboy();
+        gameboy.seed(seed);
+        gameboy.step_frame();
+        (0xC000..=0xDFFF)
+            .chain(0xFF80..=0xFFFE)
+            .chain(core::iter::once(DIV))
+            .map(|address| gameboy.mmu.read(address))
+            .collect()
+    }
+
+    #[test]
+    fn test_seed() {
+        assert_eq!(seeded_state(7), seeded_state(7));
+        assert_ne!(seeded_state(7), seeded_state(8));
+    }
+
+    /// Everything save states cover
+    fn state(gameboy: &GameBoy) -> Vec<u8> {
+        gameboy.state_file().to_bytes()
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_save_state() {
+        let path = std::env::temp_dir().join("rusty-gameboy-test-save-state.state");
+        let mut gameboy = gameboy();
+        gameboy.step_frame();
+        gameboy.save_state(&path).unwrap();
+        gameboy.step_frame();
+        let expected = state(&gameboy);
+
+        gameboy.load_state(&path).unwrap();
+        assert_eq!(gameboy.frame, 1);
+        gameboy.step_frame();
+        assert_eq!(state(&gameboy), expected);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut gameboy = gameboy();
+        gameboy.step_frame();
+        let snapshot = gameboy.snapshot();
+        gameboy.step_frame();
+        let expected = state(&gameboy);
+
+        // Restoring the same snapshot again gives the same result
+        for _ in 0..2 {
+            gameboy.restore(&snapshot).unwrap();
+            gameboy.step_frame();
+            assert_eq!(state(&gameboy), expected);
+        }
+    }
+
+    #[test]
+    fn test_poke_and_freeze() {
+        let mut gameboy = gameboy();
+        gameboy.poke(0xC000, 0x12);
+        gameboy.freeze(0xC001, 0x34);
+        assert_eq!(gameboy.mmu.read(0xC000), 0x12);
+        assert_eq!(gameboy.mmu.read(0xC001), 0x34);
+
+        gameboy.mmu.write(0xC000, 0x00);
+        gameboy.mmu.write(0xC001, 0x00);
+        gameboy.step_frame();
+        assert_eq!(gameboy.mmu.read(0xC000), 0x00);
+        assert_eq!(gameboy.mmu.read(0xC001), 0x34);
+
+        assert!(gameboy.unfreeze(0xC001));
+        gameboy.mmu.write(0xC001, 0x00);
+        gameboy.step_frame();
+        assert_eq!(gameboy.mmu.read(0xC001), 0x00);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_trace_instructions() {
+        let path = std::env::temp_dir().join("rusty-gameboy-test-trace.log");
+        let mut gameboy = gameboy();
+        gameboy.trace_instructions(&path).unwrap();
+        gameboy.step_instruction();
+        gameboy.step_instruction();
+        assert_eq!(gameboy.mmu.read(LY), doctor::DOCTOR_LY);
+        gameboy.instruction_trace = None;
+
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[1].ends_with("SP:0000 PC:0001 PCMEM:00,00,00,00"),
+            "{}",
+            lines[1]
+        );
+    }
+
+    #[test]
+    fn test_mooneye_verdict() {
+        // LD BC, DE and HL with 3, 5, 8, 13, 21, 34, then LD B, B
+        let mut rom = vec![0; 0x8000];
+        rom[..10].copy_from_slice(&[0x01, 5, 3, 0x11, 13, 8, 0x21, 34, 21, 0x40]);
+        let mut gameboy = GameBoy::new(rom);
+        gameboy.watch_test_result();
+        for _ in 0..2 {
+            gameboy.step_instruction();
+        }
+        assert_eq!(gameboy.test_verdict(), None);
+        // Once LD HL has run, the ROM has reached LD B, B
+        gameboy.step_instruction();
+        assert_eq!(gameboy.test_verdict(), Some(Verdict::Passed));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_trace_between() {
+        let path = std::env::temp_dir().join("rusty-gameboy-test-trace-between.log");
+        let mut gameboy = gameboy();
+        gameboy.trace_instructions(&path).unwrap();
+        gameboy.trace_between(Some(0x0002), Some(0x0004));
+        for _ in 0..8 {
+            gameboy.step_instruction();
+        }
+        assert!(gameboy.instruction_trace.is_none());
+
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let pcs: Vec<&str> = trace
+            .lines()
+            .map(|line| &line[line.find("PC:").unwrap()..][..7])
+            .collect();
+        assert_eq!(pcs, vec!["PC:0002", "PC:0003", "PC:0004"]);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let mut rom = vec![0; 0x8000];
+        // LD BC, $1234
+        rom[0..3].copy_from_slice(&[0x01, 0x34, 0x12]);
+        let mut gameboy = GameBoy::new(rom);
+        gameboy.enable_coverage();
+        gameboy.step_instruction();
+        gameboy.step_instruction();
+        let report = gameboy.coverage.as_ref().unwrap().report();
+        assert!(
+            report.starts_with("4 of 32768 ROM bytes executed\n"),
+            "{}",
+            report
+        );
+        assert!(report.contains("\n  0000-0003\n"), "{}", report);
+    }
+
+    /// Everything the hooks were called with
+    #[cfg(feature = "hooks")]
+    #[derive(Default)]
+    struct Calls {
+        instructions: Vec<u16>,
+        reads: Vec<(u16, u8)>,
+        writes: Vec<(u16, u8)>,
+        frames: Vec<u64>,
+    }
+
+    #[cfg(feature = "hooks")]
+    struct Recorder(Rc<RefCell<Calls>>);
+
+    #[cfg(feature = "hooks")]
+    impl Hooks for Recorder {
+        fn on_instruction(&mut self, registers: &Registers) {
+            self.0.borrow_mut().instructions.push(registers.pc);
+        }
+
+        fn on_mem_read(&mut self, address: u16, value: u8) {
+            self.0.borrow_mut().reads.push((address, value));
+        }
+
+        fn on_mem_write(&mut self, address: u16, value: u8) {
+            self.0.borrow_mut().writes.push((address, value));
+        }
+
+        fn on_frame(&mut self, frame: u64, _framebuffer: &Framebuffer) {
+            self.0.borrow_mut().frames.push(frame);
+        }
+    }
+
+    #[cfg(feature = "hooks")]
+    #[test]
+    fn test_hooks() {
+        let mut rom = vec![0; 0x8000];
+        // LD (BC),A with BC at 0
+        rom[0] = 0x02;
+        let mut gameboy = GameBoy::new(rom);
+        let calls = Rc::new(RefCell::new(Calls::default()));
+        gameboy.set_hooks(Recorder(calls.clone()));
+        gameboy.step_instruction();
+        gameboy.step_instruction();
+        // The debugger looking doesn't count
+        gameboy.peek(0xC000);
+        {
+            let calls = calls.borrow();
+            assert_eq!(calls.instructions, vec![0x0000, 0x0001]);
+            assert_eq!(calls.reads, vec![(0x0000, 0x02), (0x0001, 0x00)]);
+            assert_eq!(calls.writes, vec![(0x0000, 0x00)]);
+        }
+
+        gameboy.step_frame();
+        assert_eq!(calls.borrow().frames, vec![1]);
+        gameboy.remove_hooks();
+        gameboy.step_frame();
+        assert_eq!(calls.borrow().frames, vec![1]);
+    }
+
+    #[test]
+    fn test_try_step_instruction_unseen() {
+        let mut rom = vec![0; 0x8000];
+        rom[0] = 0xD3;
+        let mut gameboy = GameBoy::new(rom);
+        #[cfg(feature = "hooks")]
+        let calls = Rc::new(RefCell::new(Calls::default()));
+        #[cfg(feature = "hooks")]
+        gameboy.set_hooks(Recorder(calls.clone()));
+        gameboy.enable_coverage();
+        let coverage = gameboy.coverage.as_ref().unwrap().report();
+        assert!(gameboy.try_step_instruction().is_err());
+        // Neither the hooks nor the coverage saw the instruction that didn't run
+        #[cfg(feature = "hooks")]
+        {
+            assert!(calls.borrow().instructions.is_empty());
+            assert!(calls.borrow().reads.is_empty());
+        }
+        assert_eq!(gameboy.coverage.as_ref().unwrap().report(), coverage);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_load_bad_state() {
+        let path = std::env::temp_dir().join("rusty-gameboy-test-bad-state.state");
+        let mut gameboy = gameboy();
+        gameboy.step_frame();
+        let expected = state(&gameboy);
+
+        fs::write(&path, b"not a state").unwrap();
+        assert!(gameboy.load_state(&path).is_err());
+
+        // Cut short, after the header: nothing is changed
+        GameBoy::new(vec![0; 0x8000]).save_state(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(gameboy.load_state(&path).is_err());
+        assert_eq!(state(&gameboy), expected);
+
+        // Another ROM's state
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134] = b'X';
+        GameBoy::new(rom).save_state(&path).unwrap();
+        assert_eq!(
+            gameboy.load_state(&path),
+            Err(String::from("The save state is for a different ROM"))
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut gameboy = gameboy();
+        gameboy.step_frame();
+        gameboy.pause();
+        assert_eq!(gameboy.audio_samples().count(), 0);
+        gameboy.step_frame();
+        assert_eq!(gameboy.frame, 1);
+        assert_eq!(gameboy.mmu.read(DIV), (DOTS_PER_FRAME / 256) as u8);
+
+        gameboy.resume();
+        gameboy.step_frame();
+        assert_eq!(gameboy.frame, 2);
+        assert!(gameboy.audio_samples().count() > 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_record_audio() {
+        let path = std::env::temp_dir().join("rusty-gameboy-test-record-audio.wav");
+        let mut gameboy = gameboy();
+        gameboy.record_audio(&path).unwrap();
+        let mut samples = 0;
+        for _ in 0..2 {
+            gameboy.step_frame();
+            let frame_samples = gameboy.audio_samples().count();
+            // 48 kHz at 59.7 frames per second
+            assert!((803..=805).contains(&frame_samples), "{}", frame_samples);
+            samples += frame_samples;
+        }
+
+        let size = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(size, 44 + samples as u64 * 4);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_movie() {
+        let path = std::env::temp_dir().join("rusty-gameboy-test-movie.txt");
+        let mut gameboy = gameboy();
+        gameboy.record_movie(&path).unwrap();
+        gameboy.set_button(Button::A, true);
+        gameboy.step_frame();
+        gameboy.set_button(Button::A, false);
+        gameboy.set_button(Button::Down, true);
+        gameboy.step_frame();
+        drop(gameboy);
+
+        let mut gameboy = self::gameboy();
+        gameboy.play_movie(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut pressed = Vec::new();
+        for _ in 0..2 {
+            gameboy.step_frame();
+            // Ignored while the movie plays
+            gameboy.set_button(Button::Start, true);
+            pressed.push(gameboy.mmu.joypad_mut().pressed());
+        }
+        assert_eq!(pressed, [1 << Button::A as u8, 1 << Button::Down as u8]);
+
+        // The keyboard takes over when the movie ends
+        gameboy.update_movie();
+        gameboy.set_button(Button::Start, true);
+        assert_eq!(gameboy.mmu.joypad_mut().pressed(), 1 << Button::Start as u8);
+    }
+}
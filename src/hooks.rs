@@ -0,0 +1,23 @@
+// Callbacks for code using the emulator as a library, like fuzzers and bots,
+// to watch it run. Every method does nothing by default, so an
+// implementation only writes the ones it needs. They're only built with the
+// `hooks` feature, so that the emulator doesn't check for them on every
+// instruction and bus access when nothing uses them.
+
+use crate::cpu_core::cpu::Registers;
+use crate::gameboy::Framebuffer;
+
+pub trait Hooks {
+    /// Before each instruction runs
+    fn on_instruction(&mut self, _registers: &Registers) {}
+
+    /// After each read from the bus, including the CPU fetching instructions,
+    /// but not the debugger looking at memory
+    fn on_mem_read(&mut self, _address: u16, _value: u8) {}
+
+    /// Before each write to the bus
+    fn on_mem_write(&mut self, _address: u16, _value: u8) {}
+
+    /// At the end of each frame, counting from 1, with what's on the screen
+    fn on_frame(&mut self, _frame: u64, _framebuffer: &Framebuffer) {}
+}
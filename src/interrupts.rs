@@ -0,0 +1,34 @@
+/// Interrupt request flags; a bit is set when the source requests an interrupt
+pub const IF: u16 = 0xFF0F;
+/// Interrupt enable; a requested interrupt is only serviced if its bit is set here
+pub const IE: u16 = 0xFFFF;
+
+/// Only the lower 5 bits of IF are used, the rest read as 1
+const IF_UNUSED: u8 = 0b1110_0000;
+
+/// Interrupt sources, in priority order. The value is the bit in IF and IE.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Interrupt {
+    VBlank = 0,
+    Stat = 1,
+    Timer = 2,
+    Serial = 3,
+    Joypad = 4,
+}
+
+impl Interrupt {
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The value of IF as read by the CPU
+pub fn read_flags(flags: u8) -> u8 {
+    IF_UNUSED | flags
+}
+
+/// The value stored when the CPU writes to IF
+pub fn write_flags(value: u8) -> u8 {
+    value & !IF_UNUSED
+}
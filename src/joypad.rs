@@ -0,0 +1,178 @@
+use crate::savestate::save_state_fields;
+
+/// Joypad register. Bits 4 and 5 select the direction keys and the action
+/// buttons, active low; bits 0-3 read the selected buttons, 0 when pressed.
+pub const P1: u16 = 0xFF00;
+
+const SELECT_DIRECTIONS: u8 = 1 << 4;
+const SELECT_ACTIONS: u8 = 1 << 5;
+/// Bits 6-7 aren't connected and read as 1
+const P1_UNUSED: u8 = 0b1100_0000;
+
+/// The GameBoy's eight buttons. The first four are the direction keys and
+/// the last four the action buttons, in the order of their bits in P1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+/// Every button, in the order of their bits
+pub const BUTTONS: [Button; 8] = [
+    Button::Right,
+    Button::Left,
+    Button::Up,
+    Button::Down,
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+];
+
+/// The button matrix behind P1
+pub struct Joypad {
+    /// Which buttons are held down, one bit per button
+    pressed: u8,
+    /// P1 bits 4-5 as last written
+    select: u8,
+    /// Set when one of P1's input lines goes from high to low
+    interrupt: bool,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad {
+            pressed: 0,
+            select: SELECT_DIRECTIONS | SELECT_ACTIONS,
+            interrupt: false,
+        }
+    }
+}
+
+impl Joypad {
+    pub fn set_pressed(&mut self, button: Button, pressed: bool) {
+        let bit = 1 << button as u8;
+        self.update(|joypad| {
+            if pressed {
+                joypad.pressed |= bit;
+            } else {
+                joypad.pressed &= !bit;
+            }
+        });
+    }
+
+    /// Every button's state, one bit per button
+    pub fn pressed(&self) -> u8 {
+        self.pressed
+    }
+
+    /// Set every button at once, eg from a movie
+    pub fn set_all_pressed(&mut self, pressed: u8) {
+        self.update(|joypad| joypad.pressed = pressed);
+    }
+
+    /// The input lines P10-P13: low for a pressed button in a selected group
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            lines &= !(self.pressed & 0x0F);
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            lines &= !(self.pressed >> 4);
+        }
+        lines
+    }
+
+    /// Make a change, requesting the joypad interrupt if any line falls
+    fn update(&mut self, change: impl FnOnce(&mut Joypad)) {
+        let before = self.lines();
+        change(self);
+        if before & !self.lines() != 0 {
+            self.interrupt = true;
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        P1_UNUSED | self.select | self.lines()
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.update(|joypad| joypad.select = value & (SELECT_DIRECTIONS | SELECT_ACTIONS));
+    }
+
+    /// Whether the joypad interrupt was requested since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        core::mem::take(&mut self.interrupt)
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.pressed & (1 << button as u8) != 0
+    }
+}
+
+save_state_fields!(Joypad {
+    pressed,
+    select,
+    interrupt
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_pressed() {
+        let mut joypad = Joypad::default();
+        joypad.set_pressed(Button::A, true);
+        joypad.set_pressed(Button::Down, true);
+        assert!(joypad.is_pressed(Button::A));
+        assert!(joypad.is_pressed(Button::Down));
+        assert!(!joypad.is_pressed(Button::B));
+
+        joypad.set_pressed(Button::A, false);
+        assert!(!joypad.is_pressed(Button::A));
+        assert!(joypad.is_pressed(Button::Down));
+    }
+
+    #[test]
+    fn test_p1() {
+        let mut joypad = Joypad::default();
+        joypad.set_pressed(Button::Down, true);
+        joypad.set_pressed(Button::Start, true);
+        // Nothing selected
+        assert_eq!(joypad.read(), 0xFF);
+
+        joypad.write(0x20); // directions
+        assert_eq!(joypad.read(), 0xE7);
+        joypad.write(0x10); // actions
+        assert_eq!(joypad.read(), 0xD7);
+        // Both: either group pulls a line low
+        joypad.set_pressed(Button::Right, true);
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xC6);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut joypad = Joypad::default();
+        joypad.write(0x20);
+        joypad.set_pressed(Button::A, true);
+        // Actions aren't selected, so no line falls
+        assert!(!joypad.take_interrupt());
+        joypad.set_pressed(Button::Left, true);
+        assert!(joypad.take_interrupt());
+        assert!(!joypad.take_interrupt());
+
+        // Releasing doesn't request it, but selecting a held button does
+        joypad.set_pressed(Button::Left, false);
+        assert!(!joypad.take_interrupt());
+        joypad.write(0x10);
+        assert!(joypad.take_interrupt());
+    }
+}
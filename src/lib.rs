@@ -0,0 +1,78 @@
+// The emulator as a library, for frontends, tools and tests to embed.
+// `GameBoy` runs a cartridge; its CPU is in `cpu_core` and the bus it reads
+// and writes through is `mmu::Mmu`. The command line tool's parsing is in
+// `cli` and what it does in `app`, leaving main.rs to call them.
+//
+// Without the `std` feature the emulator is `no_std`, needing only an
+// allocator: the ROM comes in as bytes, frames, audio and save states come
+// out as values, and the RTC keeps emulated time. Everything that touches
+// files, the host's clock, threads or stdout is left out.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+// Where there is a std, link it even without the feature, for the cdylib's
+// panic handler and allocator, without letting the modules use it
+#[cfg(not(any(feature = "std", test, target_os = "none")))]
+extern crate std as _;
+
+#[cfg(feature = "cli")]
+pub mod app;
+pub mod apu;
+#[cfg(feature = "std")]
+pub mod bench;
+pub mod callstack;
+pub mod cartridge;
+pub mod cheats;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "cli")]
+mod config;
+pub mod coverage;
+pub mod cpu_core;
+#[cfg(feature = "debugger")]
+pub mod debugger;
+pub mod disassembler;
+pub mod doctor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod frontend;
+pub mod gameboy;
+#[cfg(feature = "hooks")]
+pub mod hooks;
+pub mod interrupts;
+pub mod joypad;
+pub mod mmu;
+#[cfg(feature = "std")]
+pub mod movie;
+pub mod ppu;
+pub mod profiler;
+pub mod savestate;
+#[cfg(feature = "screenshot")]
+pub mod screenshot;
+pub mod seed;
+pub mod serial;
+pub mod single_step;
+pub mod symbols;
+pub mod test_rom;
+pub mod timer;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod wav;
+
+/// What std's prelude has that core's doesn't, for the modules to import
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+}
+
+pub use crate::cpu_core::cpu::{Cpu, Registers};
+pub use crate::gameboy::{Framebuffer, GameBoy, CLOCK_HZ};
+#[cfg(feature = "hooks")]
+pub use crate::hooks::Hooks;
+pub use crate::joypad::Button;
+pub use crate::mmu::Mmu;
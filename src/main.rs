@@ -1,17 +1,15 @@
-mod cli;
-mod cpu_core;
+// The command line tool: parses the arguments and hands them to app::run,
+// which is in the library with the rest of the emulator.
 
-use crate::cpu_core::cpu::Cpu;
-use cli::CommandLineArgs;
 use log::{debug, info};
+use rusty_gameboy::app;
+use rusty_gameboy::cli::CommandLineArgs;
+use std::process;
 
 fn main() {
     env_logger::init();
     info!("Starting rusty-gameboy 🦀🎮");
-    let args = CommandLineArgs::new();
+    let args = CommandLineArgs::parse();
     debug!("Command line args: {:?}", args);
-
-    let mut cpu = Cpu::new_from_path(args.rom_path);
-    debug!("Created a CPU object {}", cpu);
-    cpu.start(args.subcommand);
+    process::exit(app::run(args));
 }
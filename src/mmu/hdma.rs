@@ -0,0 +1,162 @@
+// CGB VRAM DMA: copies blocks of 16 bytes from ROM, cartridge RAM or
+// work RAM into the selected VRAM bank, either all at once (general
+// purpose DMA) or one block at the start of every HBlank (HBlank DMA).
+// The CPU is stalled while a block is copied.
+
+use crate::savestate::save_state_fields;
+
+/// Source address, high and low byte. The low 4 bits are ignored.
+pub const HDMA1: u16 = 0xFF51;
+pub const HDMA2: u16 = 0xFF52;
+/// Destination address in VRAM, high and low byte. The low 4 bits are ignored.
+pub const HDMA3: u16 = 0xFF53;
+pub const HDMA4: u16 = 0xFF54;
+/// Length, mode and start
+pub const HDMA5: u16 = 0xFF55;
+
+pub const BLOCK_SIZE: u16 = 0x10;
+/// T-cycles the CPU is stalled for while a block is copied
+pub const BLOCK_CYCLES: u32 = 32;
+
+/// HDMA5 bit 7: when writing, start an HBlank DMA instead of a general
+/// purpose one; when reading, no HBlank DMA is running
+const HBLANK_MODE: u8 = 1 << 7;
+const LENGTH_MASK: u8 = 0x7F;
+
+#[derive(Debug, Default)]
+pub struct Hdma {
+    source: u16,
+    /// Offset into VRAM, 0x0000-0x1FF0
+    destination: u16,
+    /// Blocks left to copy
+    blocks: u16,
+    /// An HBlank DMA is running
+    hblank_active: bool,
+}
+
+impl Hdma {
+    pub fn hblank_active(&self) -> bool {
+        self.hblank_active
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            // The remaining length minus 1, so 0xFF once a transfer has completed
+            HDMA5 => {
+                let length = (self.blocks.wrapping_sub(1) as u8) & LENGTH_MASK;
+                if self.hblank_active {
+                    length
+                } else {
+                    HBLANK_MODE | length
+                }
+            }
+            // The address registers are write-only
+            _ => 0xFF,
+        }
+    }
+
+    /// Returns the number of blocks to copy straight away
+    /// when this starts a general purpose DMA
+    pub fn write(&mut self, address: u16, value: u8) -> Option<u16> {
+        match address {
+            HDMA1 => self.source = (self.source & 0x00FF) | ((value as u16) << 8),
+            HDMA2 => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            HDMA3 => {
+                self.destination = (self.destination & 0x00FF) | (((value & 0x1F) as u16) << 8)
+            }
+            HDMA4 => self.destination = (self.destination & 0x1F00) | (value & 0xF0) as u16,
+            HDMA5 => {
+                let blocks = (value & LENGTH_MASK) as u16 + 1;
+                if self.hblank_active && value & HBLANK_MODE == 0 {
+                    // Writing bit 7 clear stops a running HBlank DMA
+                    self.hblank_active = false;
+                } else if value & HBLANK_MODE != 0 {
+                    self.blocks = blocks;
+                    self.hblank_active = true;
+                } else {
+                    self.blocks = blocks;
+                    return Some(blocks);
+                }
+            }
+            _ => unreachable!("{:#06x} is not a VRAM DMA register", address),
+        }
+        None
+    }
+
+    /// Take the next block to copy: its source address and VRAM offset
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, self.destination);
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        // The destination wraps around within VRAM
+        self.destination = (self.destination + BLOCK_SIZE) & 0x1FF0;
+        self.blocks = self.blocks.saturating_sub(1);
+        if self.blocks == 0 {
+            self.hblank_active = false;
+        }
+        block
+    }
+}
+
+save_state_fields!(Hdma {
+    source,
+    destination,
+    blocks,
+    hblank_active,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addresses() {
+        let mut hdma = Hdma::default();
+        hdma.write(HDMA1, 0xD1);
+        hdma.write(HDMA2, 0x2F);
+        hdma.write(HDMA3, 0xFF);
+        hdma.write(HDMA4, 0xFF);
+        hdma.write(HDMA5, 0x81);
+        assert_eq!(hdma.next_block(), (0xD120, 0x1FF0));
+        // Both advance a block, the destination wrapping within VRAM
+        assert_eq!(hdma.next_block(), (0xD130, 0x0000));
+        // The address registers can't be read back
+        assert_eq!(hdma.read(HDMA1), 0xFF);
+    }
+
+    #[test]
+    fn test_general_purpose() {
+        let mut hdma = Hdma::default();
+        assert_eq!(hdma.write(HDMA5, 0x7F), Some(128));
+        assert!(!hdma.hblank_active());
+        for _ in 0..128 {
+            hdma.next_block();
+        }
+        assert_eq!(hdma.read(HDMA5), 0xFF);
+    }
+
+    #[test]
+    fn test_hblank() {
+        let mut hdma = Hdma::default();
+        assert_eq!(hdma.write(HDMA5, 0x82), None);
+        assert!(hdma.hblank_active());
+        assert_eq!(hdma.read(HDMA5), 0x02);
+        hdma.next_block();
+        assert_eq!(hdma.read(HDMA5), 0x01);
+        hdma.next_block();
+        hdma.next_block();
+        assert!(!hdma.hblank_active());
+        assert_eq!(hdma.read(HDMA5), 0xFF);
+    }
+
+    #[test]
+    fn test_hblank_stopped() {
+        let mut hdma = Hdma::default();
+        hdma.write(HDMA5, 0x85);
+        hdma.next_block();
+        // Stopping doesn't start a general purpose DMA
+        assert_eq!(hdma.write(HDMA5, 0x00), None);
+        assert!(!hdma.hblank_active());
+        // Bit 7 is set, and the length shows what was left
+        assert_eq!(hdma.read(HDMA5), 0x84);
+    }
+}
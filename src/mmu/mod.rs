@@ -0,0 +1,894 @@
+pub mod hdma;
+pub mod trace;
+
+use log::debug;
+
+use crate::apu::{Apu, NR10, NR52, WAVE_RAM_END, WAVE_RAM_START};
+use crate::cartridge::Cartridge;
+use crate::cheats::Cheats;
+use crate::cpu_core::bus::Bus;
+use crate::doctor::DOCTOR_LY;
+#[cfg(feature = "hooks")]
+use crate::hooks::Hooks;
+use crate::interrupts::{self, Interrupt, IE, IF};
+use crate::joypad::{Joypad, P1};
+use crate::mmu::hdma::{Hdma, BLOCK_CYCLES, BLOCK_SIZE, HDMA1, HDMA5};
+#[cfg(feature = "std")]
+use crate::mmu::trace::{Access, AddressRange, MemoryTracer};
+use crate::ppu::palette::{Shades, BCPS, BGP, OBP1, OCPD};
+use crate::ppu::{Mode, Ppu, Renderer, LCDC, LY, LYC, WX, WY};
+use crate::prelude::*;
+use crate::savestate::file::StateFile;
+use crate::savestate::save_state_fields;
+use crate::seed::Rng;
+use crate::serial::{Serial, SB, SC};
+use crate::timer::{Timer, DIV, TAC};
+
+/// Regions of the address space
+const VRAM_START: u16 = 0x8000;
+const OAM_START: u16 = 0xFE00;
+const IO_START: u16 = 0xFF00;
+const HRAM_START: u16 = 0xFF80;
+
+/// Size of a video RAM bank; the CGB has a second bank for tile attributes
+pub const VRAM_BANK_SIZE: usize = 0x2000;
+const CGB_VRAM_BANKS: usize = 2;
+
+/// Size of a work RAM bank; the DMG has 2 banks, the CGB has 8
+const WRAM_BANK_SIZE: usize = 0x1000;
+const CGB_WRAM_BANKS: usize = 8;
+
+/// CGB video RAM bank select
+pub const VBK: u16 = 0xFF4F;
+/// CGB work RAM bank select
+pub const SVBK: u16 = 0xFF70;
+
+/// Unmaps the boot ROM when written to
+pub const BOOT: u16 = 0xFF50;
+/// The sizes of the DMG's boot ROM and the CGB's, which skips the cartridge
+/// header at 0x0100-0x01FF
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// Value returned when reading an address nothing drives (open bus)
+const OPEN_BUS: u8 = 0xFF;
+
+/// The memory bus: maps the GameBoy's 16-bit address space
+/// onto the cartridge and the console's own memory
+pub struct Mmu {
+    cartridge: Cartridge,
+    ppu: Ppu,
+    apu: Apu,
+    timer: Timer,
+    serial: Serial,
+    joypad: Joypad,
+    /// 0x8000-0x9FFF, the bank selected by VBK
+    vram: Vec<u8>,
+    /// The bank mapped to 0x8000-0x9FFF, 0-1
+    vram_bank: u8,
+    /// 0xC000-0xDFFF, mirrored at 0xE000-0xFDFF (echo RAM).
+    /// Bank 0 is always at 0xC000; 0xD000 holds the bank selected by SVBK.
+    wram: Vec<u8>,
+    /// The bank mapped to 0xD000-0xDFFF, 1-7
+    wram_bank: u8,
+    /// 0xFE00-0xFE9F, sprite attributes
+    oam: Vec<u8>,
+    /// 0xFF00-0xFF7F, backing store for I/O registers
+    io: Vec<u8>,
+    /// 0xFF80-0xFFFE
+    hram: Vec<u8>,
+    /// 0xFF0F, interrupts requested by the hardware
+    interrupt_flag: u8,
+    /// 0xFFFF
+    interrupt_enable: u8,
+    /// CGB VRAM DMA
+    hdma: Hdma,
+    /// Running in GameBoy Color mode, which enables the banked memory
+    cgb_mode: bool,
+    /// Program counter of the instruction making the accesses, for tracing
+    pc: u16,
+    #[cfg(feature = "std")]
+    tracer: Option<MemoryTracer>,
+    /// Addresses whose writes are kept for take_writes()
+    watched: Vec<u16>,
+    writes: Vec<(u16, u8)>,
+    #[cfg(feature = "hooks")]
+    hooks: Option<Box<dyn Hooks>>,
+    cheats: Cheats,
+    /// LY always reads DOCTOR_LY, to match Gameboy Doctor's logs
+    stub_ly: bool,
+    /// Mapped over the cartridge from 0x0000 until it hands over to it
+    boot_rom: Option<Vec<u8>>,
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Mmu::new(Cartridge::default())
+    }
+}
+
+impl Mmu {
+    pub fn new(cartridge: Cartridge) -> Mmu {
+        let cgb_mode = cartridge.supports_cgb();
+        Mmu {
+            cartridge,
+            ppu: if cgb_mode { Ppu::new_cgb() } else { Ppu::new() },
+            apu: if cgb_mode {
+                Apu::new_cgb()
+            } else {
+                Apu::default()
+            },
+            timer: Timer::default(),
+            serial: if cgb_mode {
+                Serial::new_cgb()
+            } else {
+                Serial::default()
+            },
+            joypad: Joypad::default(),
+            vram: vec![0; CGB_VRAM_BANKS * VRAM_BANK_SIZE],
+            vram_bank: 0,
+            wram: vec![0; CGB_WRAM_BANKS * WRAM_BANK_SIZE],
+            wram_bank: 1,
+            oam: vec![0; 0xA0],
+            io: vec![0; 0x80],
+            hram: vec![0; 0x7F],
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            hdma: Hdma::default(),
+            cgb_mode,
+            pc: 0,
+            #[cfg(feature = "std")]
+            tracer: None,
+            watched: Vec::new(),
+            writes: Vec::new(),
+            #[cfg(feature = "hooks")]
+            hooks: None,
+            cheats: Cheats::default(),
+            stub_ly: false,
+            boot_rom: None,
+        }
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    /// Fill work RAM and high RAM with garbage and start the divider at
+    /// an arbitrary phase, as a real GameBoy powers on
+    pub fn randomize(&mut self, rng: &mut Rng) {
+        rng.fill(&mut self.wram);
+        rng.fill(&mut self.hram);
+        self.timer.set_counter(rng.next_u64() as u16);
+    }
+
+    /// Save the bus and the hardware on it, a chunk each
+    pub fn save_chunks(&self, file: &mut StateFile) {
+        file.add(b"BUS ", self);
+        file.add(b"CART", &self.cartridge);
+        file.add(b"PPU ", &self.ppu);
+        file.add(b"APU ", &self.apu);
+        file.add(b"TIMR", &self.timer);
+        file.add(b"SERL", &self.serial);
+        file.add(b"JOYP", &self.joypad);
+        file.add(b"HDMA", &self.hdma);
+    }
+
+    pub fn load_chunks(&mut self, file: &StateFile) -> Result<(), String> {
+        file.load(b"BUS ", self)?;
+        file.load(b"CART", &mut self.cartridge)?;
+        file.load(b"PPU ", &mut self.ppu)?;
+        file.load(b"APU ", &mut self.apu)?;
+        file.load(b"TIMR", &mut self.timer)?;
+        file.load(b"SERL", &mut self.serial)?;
+        file.load(b"JOYP", &mut self.joypad)?;
+        file.load(b"HDMA", &mut self.hdma)
+    }
+
+    /// Advance the hardware attached to the bus by a number of T-cycles
+    pub fn tick(&mut self, cycles: u32) {
+        self.cartridge.tick(cycles);
+        self.apu.tick(cycles);
+        self.interrupt_flag |= self.timer.tick(cycles);
+        self.interrupt_flag |= self.serial.tick(cycles);
+        if self.joypad.take_interrupt() {
+            self.interrupt_flag |= Interrupt::Joypad.mask();
+        }
+        if !self.hdma.hblank_active() {
+            self.tick_ppu(cycles);
+            return;
+        }
+
+        // Step dot by dot to catch the start of each HBlank
+        let mut cycles = cycles;
+        while cycles > 0 {
+            cycles -= 1;
+            let was_hblank = self.ppu.mode() == Mode::HBlank;
+            self.tick_ppu(1);
+            if !was_hblank && self.ppu.mode() == Mode::HBlank && self.hdma.hblank_active() {
+                self.copy_dma_block();
+                // The CPU is stalled while the rest of the hardware runs on
+                cycles += BLOCK_CYCLES;
+            }
+        }
+    }
+
+    fn tick_ppu(&mut self, cycles: u32) {
+        let interrupts = self.ppu.tick(cycles, &self.vram, &self.oam);
+        self.interrupt_flag |= interrupts;
+        if interrupts & Interrupt::VBlank.mask() != 0 {
+            self.apply_cheats();
+        }
+    }
+
+    /// Make the GameShark cheats' RAM writes, as the real one does at VBlank,
+    /// then put back the frozen addresses
+    fn apply_cheats(&mut self) {
+        for code in self.cheats.ram_writes() {
+            match (code.address, code.bank) {
+                (0xA000..=0xBFFF, Some(bank)) => {
+                    self.cartridge
+                        .write_ram_bank(bank, code.address, code.value)
+                }
+                (0xA000..=0xBFFF, None) => self.cartridge.write(code.address, code.value),
+                (_, bank) => {
+                    let index = match bank {
+                        Some(bank) if code.address >= 0xD000 => {
+                            bank.max(1) as usize * WRAM_BANK_SIZE + (code.address - 0xD000) as usize
+                        }
+                        _ => self.wram_index(code.address),
+                    };
+                    self.wram[index] = code.value;
+                }
+            }
+        }
+        for (address, value) in self.cheats.frozen().to_vec() {
+            self.write(address, value);
+        }
+    }
+
+    /// Read a byte for a VRAM DMA, which can only copy from
+    /// the cartridge and work RAM
+    fn dma_read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.read(address),
+            0xC000..=0xDFFF => self.wram[self.wram_index(address)],
+            _ => OPEN_BUS,
+        }
+    }
+
+    /// Copy the next block of a VRAM DMA into the selected VRAM bank
+    fn copy_dma_block(&mut self) {
+        let (source, destination) = self.hdma.next_block();
+        for offset in 0..BLOCK_SIZE {
+            let value = self.dma_read(source.wrapping_add(offset));
+            let index = self.vram_index(VRAM_START + destination + offset);
+            self.vram[index] = value;
+        }
+    }
+
+    fn write_hdma(&mut self, address: u16, value: u8) {
+        let started_hblank = !self.hdma.hblank_active();
+        match self.hdma.write(address, value) {
+            Some(blocks) => {
+                debug!("General purpose DMA of {} bytes", blocks * BLOCK_SIZE);
+                for _ in 0..blocks {
+                    self.copy_dma_block();
+                }
+                self.tick(blocks as u32 * BLOCK_CYCLES);
+            }
+            // Started during HBlank (or with the LCD off), the first block is copied straight away
+            None if started_hblank
+                && self.hdma.hblank_active()
+                && self.ppu.mode() == Mode::HBlank =>
+            {
+                self.copy_dma_block();
+                self.tick(BLOCK_CYCLES);
+            }
+            None => {}
+        }
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
+    pub fn joypad_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad
+    }
+
+    /// Whether the PPU has completed a frame since the last call
+    pub fn take_frame_complete(&mut self) -> bool {
+        self.ppu.take_frame_complete()
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.ppu.set_renderer(renderer);
+    }
+
+    /// Choose the colors of the four DMG shades
+    pub fn set_shades(&mut self, shades: Shades) {
+        self.ppu.set_shades(shades);
+    }
+
+    /// Log every access to the given address ranges
+    #[cfg(feature = "std")]
+    pub fn enable_trace(&mut self, ranges: Vec<AddressRange>) {
+        self.tracer = Some(MemoryTracer::new(ranges));
+    }
+
+    /// Keep the writes to the addresses, for take_writes()
+    pub fn watch_writes(&mut self, addresses: Vec<u16>) {
+        self.watched = addresses;
+    }
+
+    /// The writes to the watched addresses since the last call, with their values
+    pub fn take_writes(&mut self) -> Vec<(u16, u8)> {
+        core::mem::take(&mut self.writes)
+    }
+
+    /// Call the hooks on every bus access, or stop if there are none
+    #[cfg(feature = "hooks")]
+    pub fn set_hooks(&mut self, hooks: Option<Box<dyn Hooks>>) {
+        self.hooks = hooks;
+    }
+
+    #[cfg(feature = "hooks")]
+    pub fn take_hooks(&mut self) -> Option<Box<dyn Hooks>> {
+        self.hooks.take()
+    }
+
+    #[cfg(feature = "hooks")]
+    pub fn hooks_mut(&mut self) -> Option<&mut (dyn Hooks + 'static)> {
+        self.hooks.as_deref_mut()
+    }
+
+    pub fn set_stub_ly(&mut self, stub_ly: bool) {
+        self.stub_ly = stub_ly;
+    }
+
+    /// Map a boot ROM over the cartridge until it writes to BOOT
+    pub fn map_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = Some(boot_rom);
+    }
+
+    /// The byte of the boot ROM at an address, if it's mapped there
+    fn boot_rom_byte(&self, address: u16) -> Option<u8> {
+        match address {
+            0x0100..=0x01FF => None,
+            _ => self.boot_rom.as_ref()?.get(address as usize).copied(),
+        }
+    }
+
+    /// Read a byte without it showing up in the trace or the hooks, for the debugger
+    pub fn peek(&mut self, address: u16) -> u8 {
+        #[cfg(feature = "std")]
+        let tracer = self.tracer.take();
+        #[cfg(feature = "hooks")]
+        let hooks = self.hooks.take();
+        let value = self.read(address);
+        #[cfg(feature = "std")]
+        {
+            self.tracer = tracer;
+        }
+        #[cfg(feature = "hooks")]
+        {
+            self.hooks = hooks;
+        }
+        value
+    }
+
+    /// Set the program counter reported in trace lines
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// I/O addresses that are not connected to any register.
+    /// Reads return 0xFF and writes are ignored.
+    fn is_unmapped_io(&self, address: u16) -> bool {
+        match address {
+            VBK | SVBK | HDMA1..=HDMA5 | BCPS..=OCPD => !self.cgb_mode,
+            _ => matches!(
+                address,
+                0xFF03 | 0xFF08..=0xFF0E | 0xFF15 | 0xFF1F | 0xFF27..=0xFF2F | 0xFF4C..=0xFF7F
+            ),
+        }
+    }
+
+    /// The contents of a VRAM bank regardless of which one is selected,
+    /// for rendering and debug views. Bank 1 only exists on the CGB.
+    pub fn vram_bank(&self, bank: usize) -> &[u8] {
+        let start = bank * VRAM_BANK_SIZE;
+        &self.vram[start..start + VRAM_BANK_SIZE]
+    }
+
+    /// The sprite attributes at 0xFE00-0xFE9F
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    /// Both VRAM banks, as the PPU draws from them
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    /// Index into video RAM for an address in 0x8000-0x9FFF
+    fn vram_index(&self, address: u16) -> usize {
+        self.vram_bank as usize * VRAM_BANK_SIZE + (address - VRAM_START) as usize
+    }
+
+    /// Index into work RAM for an address in 0xC000-0xDFFF or its echo
+    fn wram_index(&self, address: u16) -> usize {
+        let offset = (address & 0x1FFF) as usize;
+        if offset < WRAM_BANK_SIZE {
+            offset
+        } else {
+            self.wram_bank as usize * WRAM_BANK_SIZE + (offset - WRAM_BANK_SIZE)
+        }
+    }
+
+    pub fn read(&mut self, address: u16) -> u8 {
+        let value = match address {
+            0x0000..=0x7FFF => match self.boot_rom_byte(address) {
+                Some(value) => value,
+                None => self.cheats.patch_rom(address, self.cartridge.read(address)),
+            },
+            0xA000..=0xBFFF => self.cartridge.read(address),
+            0x8000..=0x9FFF => self.vram[self.vram_index(address)],
+            0xC000..=0xFDFF => self.wram[self.wram_index(address)],
+            0xFE00..=0xFE9F => self.oam[(address - OAM_START) as usize],
+            // The unusable region reads 0x00 on the DMG while OAM is accessible
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F if self.is_unmapped_io(address) => OPEN_BUS,
+            P1 => self.joypad.read(),
+            SB..=SC => self.serial.read(address),
+            DIV..=TAC => self.timer.read(address),
+            IF => interrupts::read_flags(self.interrupt_flag),
+            NR10..=NR52 | WAVE_RAM_START..=WAVE_RAM_END => self.apu.read(address),
+            LY if self.stub_ly => DOCTOR_LY,
+            LCDC..=LYC | BGP..=OBP1 | WY | WX | BCPS..=OCPD => self.ppu.read(address),
+            // Only bit 0 picks the VRAM bank, the rest read as 1
+            VBK => 0b1111_1110 | self.vram_bank,
+            HDMA1..=HDMA5 => self.hdma.read(address),
+            // Only the lower 3 bits pick the WRAM bank, the rest read as 1
+            SVBK => 0b1111_1000 | self.wram_bank,
+            0xFF03..=0xFF7F => self.io[(address - IO_START) as usize],
+            0xFF80..=0xFFFE => self.hram[(address - HRAM_START) as usize],
+            IE => self.interrupt_enable,
+        };
+        #[cfg(feature = "std")]
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(Access::Read, self.pc, address, value);
+        }
+        #[cfg(feature = "hooks")]
+        if let Some(hooks) = &mut self.hooks {
+            hooks.on_mem_read(address, value);
+        }
+        value
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        #[cfg(feature = "std")]
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(Access::Write, self.pc, address, value);
+        }
+        #[cfg(feature = "hooks")]
+        if let Some(hooks) = &mut self.hooks {
+            hooks.on_mem_write(address, value);
+        }
+        if self.watched.contains(&address) {
+            self.writes.push((address, value));
+        }
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.write(address, value),
+            0x8000..=0x9FFF => {
+                let index = self.vram_index(address);
+                self.vram[index] = value;
+            }
+            0xC000..=0xFDFF => {
+                let index = self.wram_index(address);
+                self.wram[index] = value;
+            }
+            0xFE00..=0xFE9F => self.oam[(address - OAM_START) as usize] = value,
+            0xFEA0..=0xFEFF => debug!("Ignoring write to unusable address {:#06x}", address),
+            BOOT if self.boot_rom.is_some() && value != 0 => {
+                debug!("Unmapping the boot ROM");
+                self.boot_rom = None;
+            }
+            0xFF00..=0xFF7F if self.is_unmapped_io(address) => {
+                debug!("Ignoring write to unmapped I/O {:#06x}", address)
+            }
+            P1 => self.joypad.write(value),
+            SB..=SC => self.serial.write(address, value),
+            DIV..=TAC => self.timer.write(address, value),
+            IF => self.interrupt_flag = interrupts::write_flags(value),
+            NR10..=NR52 | WAVE_RAM_START..=WAVE_RAM_END => self.apu.write(address, value),
+            LCDC..=LYC | BGP..=OBP1 | WY | WX | BCPS..=OCPD => self.ppu.write(address, value),
+            VBK => self.vram_bank = value & 0b1,
+            HDMA1..=HDMA5 => self.write_hdma(address, value),
+            SVBK => {
+                // Selecting bank 0 selects bank 1
+                self.wram_bank = (value & 0b111).max(1);
+                debug!("Switched to WRAM bank {}", self.wram_bank);
+            }
+            0xFF03..=0xFF7F => self.io[(address - IO_START) as usize] = value,
+            0xFF80..=0xFFFE => self.hram[(address - HRAM_START) as usize] = value,
+            IE => self.interrupt_enable = value,
+        }
+    }
+}
+
+impl Bus for Mmu {
+    fn read(&mut self, address: u16) -> u8 {
+        Mmu::read(self, address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        Mmu::write(self, address, value)
+    }
+
+    fn set_pc(&mut self, pc: u16) {
+        Mmu::set_pc(self, pc)
+    }
+}
+
+// The bus's own memory and registers. The hardware on it is saved in its
+// own chunks, and the tracing and the cheats are left as they are.
+save_state_fields!(Mmu {
+    vram_bank,
+    wram_bank,
+    interrupt_flag,
+    interrupt_enable,
+    cgb_mode,
+} fixed {
+    vram,
+    wram,
+    oam,
+    io,
+    hram,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::header::CGB_FLAG_ADDRESS;
+    use crate::joypad::Button;
+    use crate::ppu::{DOTS_PER_FRAME, DOTS_PER_LINE, STAT, VISIBLE_LINES};
+    use crate::timer::TIMA;
+    use test_case::test_case;
+
+    #[test]
+    fn test_cartridge_mapping() {
+        let mut mmu = Mmu::new(Cartridge::new(vec![0x00, 0x31, 0xFE, 0xFF]));
+        assert_eq!(mmu.read(0x0001), 0x31);
+        // Writes to ROM don't modify it
+        mmu.write(0x0001, 0x42);
+        assert_eq!(mmu.read(0x0001), 0x31);
+    }
+
+    #[test]
+    fn test_boot_rom() {
+        let mut rom = vec![0x11; 0x8000];
+        rom[0x0150] = 0x22;
+        let mut mmu = Mmu::new(Cartridge::new(rom));
+        let mut boot_rom = vec![0xBB; CGB_BOOT_ROM_SIZE];
+        boot_rom[0x0200] = 0xCC;
+        mmu.map_boot_rom(boot_rom);
+        assert_eq!(mmu.read(0x0000), 0xBB);
+        // The CGB's boot ROM leaves the header showing
+        assert_eq!(mmu.read(0x0150), 0x22);
+        assert_eq!(mmu.read(0x0200), 0xCC);
+        assert_eq!(mmu.read(0x0900), 0x11);
+        mmu.write(BOOT, 0x00);
+        assert_eq!(mmu.read(0x0000), 0xBB);
+        mmu.write(BOOT, 0x01);
+        assert_eq!(mmu.read(0x0000), 0x11);
+        assert_eq!(mmu.read(0x0200), 0x11);
+    }
+
+    #[test]
+    fn test_game_genie() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0150] = 0x01;
+        let mut mmu = Mmu::new(Cartridge::new(rom));
+        mmu.cheats_mut().add("3E1-50F-E6A").unwrap();
+        // The ROM doesn't hold the compare value
+        assert_eq!(mmu.read(0x0150), 0x01);
+        mmu.cheats_mut().add("3E1-50F").unwrap();
+        assert_eq!(mmu.read(0x0150), 0x3E);
+    }
+
+    #[test]
+    fn test_game_shark() {
+        let mut mmu = Mmu::new(Cartridge::new(vec![0; 0x8000]));
+        mmu.cheats_mut().add("010FE1C6").unwrap();
+        mmu.cheats_mut().add("826314D0").unwrap();
+        mmu.tick(DOTS_PER_LINE * VISIBLE_LINES as u32 - 4);
+        assert_eq!(mmu.read(0xC6E1), 0x00);
+        mmu.tick(4);
+        assert_eq!(mmu.read(0xC6E1), 0x0F);
+        assert_eq!(mmu.wram[2 * WRAM_BANK_SIZE + 0x14], 0x63);
+        // Bank 1 is mapped in, so the write went past it
+        assert_eq!(mmu.read(0xD014), 0x00);
+
+        // The game changes the byte, and the cheat puts it back at the next VBlank
+        mmu.write(0xC6E1, 0x01);
+        mmu.tick(DOTS_PER_FRAME);
+        assert_eq!(mmu.read(0xC6E1), 0x0F);
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut mmu = Mmu::default();
+        mmu.write(0xC000, 0x42);
+        assert_eq!(mmu.read(0xC000), 0x42);
+        // The last byte of the address space is addressable
+        mmu.write(0xFFFF, 0x1F);
+        assert_eq!(mmu.read(0xFFFF), 0x1F);
+    }
+
+    #[test]
+    fn test_echo_ram() {
+        let mut mmu = Mmu::default();
+        mmu.write(0xC123, 0x42);
+        assert_eq!(mmu.read(0xE123), 0x42);
+        mmu.write(0xFDFF, 0x24);
+        assert_eq!(mmu.read(0xDDFF), 0x24);
+    }
+
+    fn cgb_mmu() -> Mmu {
+        let mut rom = vec![0; 0x8000];
+        rom[CGB_FLAG_ADDRESS] = 0xC0;
+        Mmu::new(Cartridge::new(rom))
+    }
+
+    #[test]
+    fn test_wram_banking() {
+        let mut mmu = cgb_mmu();
+        assert_eq!(mmu.read(SVBK), 0xF9);
+
+        // Write a different value to 0xD000 in each bank
+        for bank in 1..8 {
+            mmu.write(SVBK, bank);
+            mmu.write(0xD000, bank * 0x10);
+        }
+        for bank in 1..8 {
+            mmu.write(SVBK, bank);
+            assert_eq!(mmu.read(0xD000), bank * 0x10);
+            // Echo RAM mirrors the selected bank too
+            assert_eq!(mmu.read(0xF000), bank * 0x10);
+        }
+
+        // Bank 0 is always mapped to 0xC000
+        mmu.write(0xC000, 0xAB);
+        mmu.write(SVBK, 3);
+        assert_eq!(mmu.read(0xC000), 0xAB);
+    }
+
+    #[test]
+    fn test_wram_bank_zero_selects_one() {
+        let mut mmu = cgb_mmu();
+        mmu.write(SVBK, 1);
+        mmu.write(0xD000, 0x11);
+        mmu.write(SVBK, 0);
+        assert_eq!(mmu.read(SVBK), 0xF9);
+        assert_eq!(mmu.read(0xD000), 0x11);
+    }
+
+    /// SVBK doesn't exist on the DMG
+    #[test]
+    fn test_wram_banking_dmg() {
+        let mut mmu = Mmu::default();
+        mmu.write(0xD000, 0x11);
+        mmu.write(SVBK, 2);
+        assert_eq!(mmu.read(SVBK), 0xFF);
+        assert_eq!(mmu.read(0xD000), 0x11);
+    }
+
+    #[test]
+    fn test_vram_banking() {
+        let mut mmu = cgb_mmu();
+        assert_eq!(mmu.read(VBK), 0xFE);
+        mmu.write(0x9800, 0x11);
+
+        mmu.write(VBK, 1);
+        assert_eq!(mmu.read(VBK), 0xFF);
+        assert_eq!(mmu.read(0x9800), 0x00);
+        mmu.write(0x9800, 0x22);
+
+        // Both banks are visible to the PPU regardless of VBK
+        assert_eq!(mmu.vram_bank(0)[0x1800], 0x11);
+        assert_eq!(mmu.vram_bank(1)[0x1800], 0x22);
+
+        mmu.write(VBK, 0xFE); // only bit 0 is used
+        assert_eq!(mmu.read(0x9800), 0x11);
+    }
+
+    /// VBK doesn't exist on the DMG
+    #[test]
+    fn test_vram_banking_dmg() {
+        let mut mmu = Mmu::default();
+        mmu.write(0x8000, 0x11);
+        mmu.write(VBK, 1);
+        assert_eq!(mmu.read(VBK), 0xFF);
+        assert_eq!(mmu.read(0x8000), 0x11);
+    }
+
+    #[test_case(0xFEA0, 0x00; "start of unusable region")]
+    #[test_case(0xFEFF, 0x00; "end of unusable region")]
+    #[test_case(0xFF03, 0xFF; "unmapped io")]
+    #[test_case(0xFF4C, 0xFF; "unmapped io after lcd registers")]
+    #[test_case(0xFF7F, 0xFF; "end of io")]
+    #[test_case(0xA000, 0xFF; "no cartridge ram")]
+    fn test_unmapped_reads(address: u16, expected: u8) {
+        let mut mmu = Mmu::default();
+        // Writes to unmapped addresses are dropped
+        mmu.write(address, 0x42);
+        assert_eq!(mmu.read(address), expected);
+    }
+
+    #[test]
+    fn test_stat_interrupt_request() {
+        let mut mmu = Mmu::default();
+        assert_eq!(mmu.read(IF), 0xE0);
+        // LYC=0 matches straight away
+        mmu.write(STAT, 0b0100_0000);
+        mmu.tick(1);
+        assert_eq!(mmu.read(IF), 0xE0 | Interrupt::Stat.mask());
+
+        // The CPU acknowledges it by clearing the bit
+        mmu.write(IF, 0);
+        mmu.tick(1);
+        assert_eq!(mmu.read(IF), 0xE0);
+    }
+
+    #[test]
+    fn test_vblank_interrupt_request() {
+        let mut mmu = Mmu::default();
+        mmu.tick(DOTS_PER_LINE * VISIBLE_LINES as u32);
+        assert_eq!(mmu.read(IF), 0xE0 | Interrupt::VBlank.mask());
+        assert!(mmu.take_frame_complete());
+    }
+
+    /// Copy from work RAM to 0x8100 in VRAM bank 1
+    fn start_hdma(mmu: &mut Mmu, hdma5: u8) {
+        for (offset, byte) in (0..0x40).enumerate() {
+            mmu.write(0xC000 + offset as u16, byte);
+        }
+        mmu.write(VBK, 1);
+        for (address, value) in [
+            (HDMA1, 0xC0),
+            (HDMA1 + 1, 0x00),
+            (HDMA1 + 2, 0x01),
+            (HDMA1 + 3, 0x00),
+        ] {
+            mmu.write(address, value);
+        }
+        mmu.write(HDMA5, hdma5);
+    }
+
+    #[test]
+    fn test_general_purpose_dma() {
+        let mut mmu = cgb_mmu();
+        start_hdma(&mut mmu, 0x02); // 3 blocks
+        assert_eq!(mmu.read(HDMA5), 0xFF);
+        assert_eq!(mmu.read(0x8100), 0x00);
+        assert_eq!(mmu.read(0x812F), 0x2F);
+        assert_eq!(mmu.read(0x8130), 0x00);
+        assert_eq!(mmu.vram_bank(0)[0x0101], 0x00);
+        // The CPU was stalled while the PPU ran on
+        assert_eq!(mmu.ppu().mode(), Mode::Drawing);
+    }
+
+    #[test]
+    fn test_hblank_dma() {
+        let mut mmu = cgb_mmu();
+        start_hdma(&mut mmu, 0x81); // 2 blocks
+        assert_eq!(mmu.read(HDMA5), 0x01);
+        assert_eq!(mmu.read(0x8101), 0x00);
+
+        // One block at the start of each HBlank
+        mmu.tick(252);
+        assert_eq!(mmu.read(0x8101), 0x01);
+        assert_eq!(mmu.read(0x8111), 0x00);
+        assert_eq!(mmu.read(HDMA5), 0x00);
+        mmu.tick(DOTS_PER_LINE);
+        assert_eq!(mmu.read(0x8111), 0x11);
+        assert_eq!(mmu.read(HDMA5), 0xFF);
+    }
+
+    #[test]
+    fn test_hblank_dma_stopped() {
+        let mut mmu = cgb_mmu();
+        start_hdma(&mut mmu, 0x81);
+        mmu.write(HDMA5, 0x00);
+        assert_eq!(mmu.read(HDMA5), 0x81);
+        mmu.tick(DOTS_PER_LINE);
+        assert_eq!(mmu.read(0x8101), 0x00);
+    }
+
+    #[test]
+    fn test_timer_interrupt() {
+        let mut mmu = Mmu::default();
+        mmu.write(TAC, 0b101);
+        mmu.write(TIMA, 0xFF);
+        // Overflows after 16 T-cycles, then reloads one M-cycle later
+        mmu.tick(20);
+        assert_eq!(
+            mmu.read(IF) & Interrupt::Timer.mask(),
+            Interrupt::Timer.mask()
+        );
+    }
+
+    #[test]
+    fn test_joypad_interrupt() {
+        let mut mmu = Mmu::default();
+        mmu.write(P1, 0x10);
+        mmu.joypad_mut().set_pressed(Button::Start, true);
+        assert_eq!(mmu.read(P1), 0xD7);
+        mmu.tick(4);
+        assert_eq!(
+            mmu.read(IF) & Interrupt::Joypad.mask(),
+            Interrupt::Joypad.mask()
+        );
+    }
+
+    #[test]
+    fn test_serial_interrupt() {
+        let mut mmu = Mmu::default();
+        mmu.write(SB, 0x42);
+        mmu.write(SC, 0x81);
+        mmu.tick(4096);
+        assert_eq!(
+            mmu.read(IF) & Interrupt::Serial.mask(),
+            Interrupt::Serial.mask()
+        );
+        assert_eq!(mmu.read(SB), 0xFF);
+    }
+
+    #[test]
+    fn test_apu_registers() {
+        let mut mmu = Mmu::default();
+        mmu.write(NR52 - 1, 0xF3); // NR51
+        assert_eq!(mmu.read(NR52 - 1), 0xF3);
+        mmu.write(WAVE_RAM_START, 0xA5);
+        assert_eq!(mmu.read(WAVE_RAM_START), 0xA5);
+        // The gaps between the sound registers aren't connected
+        mmu.write(0xFF15, 0x00);
+        assert_eq!(mmu.read(0xFF15), 0xFF);
+        assert_eq!(mmu.read(0xFF27), 0xFF);
+
+        mmu.tick(crate::gameboy::CLOCK_HZ);
+        assert!(mmu.apu_mut().samples().drain().count() > 0);
+    }
+
+    #[test]
+    fn test_cgb_palette_registers() {
+        let mut mmu = cgb_mmu();
+        mmu.write(BCPS, 0x80);
+        mmu.write(BCPS + 1, 0x12);
+        mmu.write(BCPS + 1, 0x34);
+        mmu.write(BCPS, 0x01);
+        assert_eq!(mmu.read(BCPS + 1), 0x34);
+
+        // The DMG has no color palettes
+        let mut mmu = Mmu::default();
+        mmu.write(BCPS, 0x80);
+        assert_eq!(mmu.read(BCPS), 0xFF);
+    }
+}
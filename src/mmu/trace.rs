@@ -0,0 +1,159 @@
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+use crate::prelude::*;
+
+/// An inclusive range of addresses, eg `8000-9FFF` or a single address `FF40`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AddressRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl AddressRange {
+    pub fn contains(&self, address: u16) -> bool {
+        self.start <= address && address <= self.end
+    }
+}
+
+/// Parse a hex address, with or without a 0x prefix
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address: {:?}", s))
+}
+
+impl FromStr for AddressRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+            None => {
+                let address = parse_address(s)?;
+                (address, address)
+            }
+        };
+        if start > end {
+            return Err(format!("Range start is after its end: {:?}", s));
+        }
+        Ok(AddressRange { start, end })
+    }
+}
+
+/// Parse a comma-separated list of address ranges.
+/// `all` traces the entire address space.
+pub fn parse_ranges(s: &str) -> Result<Vec<AddressRange>, String> {
+    if s.trim() == "all" {
+        return Ok(vec![AddressRange {
+            start: 0x0000,
+            end: 0xFFFF,
+        }]);
+    }
+    s.split(',').map(AddressRange::from_str).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "R"),
+            Access::Write => write!(f, "W"),
+        }
+    }
+}
+
+/// Format a single bus access as a trace line
+pub fn format_access(access: Access, pc: u16, address: u16, value: u8) -> String {
+    format!(
+        "PC={:#06x} {} {:#06x} = {:#04x}",
+        pc, access, address, value
+    )
+}
+
+/// Writes every bus access that falls in one of its address ranges
+/// to stderr, so it can be separated from the emulator's normal output
+#[cfg(feature = "std")]
+pub struct MemoryTracer {
+    ranges: Vec<AddressRange>,
+    out: Box<dyn Write>,
+}
+
+#[cfg(feature = "std")]
+impl MemoryTracer {
+    pub fn new(ranges: Vec<AddressRange>) -> MemoryTracer {
+        MemoryTracer::with_writer(ranges, Box::new(io::stderr()))
+    }
+
+    pub fn with_writer(ranges: Vec<AddressRange>, out: Box<dyn Write>) -> MemoryTracer {
+        MemoryTracer { ranges, out }
+    }
+
+    pub fn is_traced(&self, address: u16) -> bool {
+        self.ranges.iter().any(|range| range.contains(address))
+    }
+
+    pub fn record(&mut self, access: Access, pc: u16, address: u16, value: u8) {
+        if self.is_traced(address) {
+            // Tracing is best-effort; a closed pipe should not stop the emulator
+            let _ = writeln!(self.out, "{}", format_access(access, pc, address, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("8000-9FFF", 0x8000, 0x9FFF; "range")]
+    #[test_case("0x8000-0x9fff", 0x8000, 0x9FFF; "prefixed range")]
+    #[test_case("FF40", 0xFF40, 0xFF40; "single address")]
+    fn test_parse_range(s: &str, start: u16, end: u16) {
+        assert_eq!(s.parse::<AddressRange>(), Ok(AddressRange { start, end }));
+    }
+
+    #[test_case("9FFF-8000"; "backwards")]
+    #[test_case("VRAM"; "not hex")]
+    #[test_case("10000"; "too large")]
+    fn test_parse_range_invalid(s: &str) {
+        assert!(s.parse::<AddressRange>().is_err());
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        let ranges = parse_ranges("8000-9FFF,FF40").unwrap();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(parse_ranges("all").unwrap()[0].end, 0xFFFF);
+        assert!(parse_ranges("8000-9FFF,oops").is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_is_traced() {
+        let tracer = MemoryTracer::new(parse_ranges("8000-9FFF,FF40").unwrap());
+        assert!(tracer.is_traced(0x8000));
+        assert!(tracer.is_traced(0x9FFF));
+        assert!(tracer.is_traced(0xFF40));
+        assert!(!tracer.is_traced(0xA000));
+        assert!(!tracer.is_traced(0xFF41));
+    }
+
+    #[test]
+    fn test_format_access() {
+        assert_eq!(
+            format_access(Access::Write, 0x0150, 0x8000, 0x3C),
+            "PC=0x0150 W 0x8000 = 0x3c"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+// A movie is the joypad input for every frame from power on, so a run can be
+// replayed exactly: for making tool-assisted runs, or attaching to a bug report.
+// The file is text with one line per frame, holding a letter for each button
+// down and a '.' for each button up, in the order Right, Left, Up, Down, A, B,
+// select and Start:
+//
+//     # rusty-gameboy movie
+//     ........
+//     ...D....
+//     ...DA...
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const HEADER: &str = "# rusty-gameboy movie";
+/// In the order of the Button bits
+const BUTTON_LETTERS: [char; 8] = ['R', 'L', 'U', 'D', 'A', 'B', 's', 'S'];
+
+/// One frame's buttons as a line of the movie
+pub fn format_frame(pressed: u8) -> String {
+    BUTTON_LETTERS
+        .iter()
+        .enumerate()
+        .map(|(bit, letter)| {
+            if pressed & (1 << bit) != 0 {
+                *letter
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// Parse a line of the movie into the buttons pressed, one bit per button
+pub fn parse_frame(line: &str) -> Result<u8, String> {
+    if line.chars().count() != BUTTON_LETTERS.len() {
+        return Err(format!("expected 8 buttons, got {:?}", line));
+    }
+    line.chars()
+        .zip(BUTTON_LETTERS.iter())
+        .enumerate()
+        .try_fold(0, |pressed, (bit, (c, letter))| match c {
+            '.' => Ok(pressed),
+            c if c == *letter => Ok(pressed | (1 << bit)),
+            _ => Err(format!("expected {:?} or '.' in {:?}", letter, line)),
+        })
+}
+
+/// The input for a run, frame by frame
+#[derive(Debug, Default, PartialEq)]
+pub struct Movie {
+    pub frames: Vec<u8>,
+}
+
+impl Movie {
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            _ => return Err(String::from("Not a rusty-gameboy movie")),
+        }
+        let frames = lines
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                parse_frame(line.trim()).map_err(|e| format!("Line {}: {}", number + 1, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Movie { frames })
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        Movie::parse(&text).map_err(|e| format!("{:?}: {}", path, e))
+    }
+}
+
+/// Writes a movie as it's played, a frame at a time, so nothing is lost
+/// if the emulator is killed
+pub struct MovieRecorder {
+    writer: BufWriter<File>,
+}
+
+impl MovieRecorder {
+    pub fn create(path: &Path) -> io::Result<MovieRecorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+        Ok(MovieRecorder { writer })
+    }
+
+    /// Add the buttons held for the next frame
+    pub fn record(&mut self, pressed: u8) -> io::Result<()> {
+        writeln!(self.writer, "{}", format_frame(pressed))?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::Button;
+    use test_case::test_case;
+
+    #[test_case(0x00, "........"; "nothing")]
+    #[test_case(1 << Button::Down as u8 | 1 << Button::A as u8, "...DA..."; "down and a")]
+    #[test_case(0xFF, "RLUDABsS"; "everything")]
+    fn test_frame(pressed: u8, line: &str) {
+        assert_eq!(format_frame(pressed), line);
+        assert_eq!(parse_frame(line), Ok(pressed));
+    }
+
+    #[test_case("..."; "too short")]
+    #[test_case("A......."; "wrong place")]
+    fn test_parse_frame_error(line: &str) {
+        assert!(parse_frame(line).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let movie = Movie::parse("# rusty-gameboy movie\n........\n\n...D....\n").unwrap();
+        assert_eq!(movie.frames, vec![0x00, 0x08]);
+        assert!(Movie::parse("........\n").is_err());
+        assert_eq!(
+            Movie::parse("# rusty-gameboy movie\n.x......\n"),
+            Err(String::from("Line 2: expected 'L' or '.' in \".x......\""))
+        );
+    }
+}
@@ -0,0 +1,381 @@
+// A dot-by-dot renderer modelled on the PPU's pixel FIFOs.
+//
+// Every dot during mode 3 the background fetcher works on the next 8 pixels
+// while one pixel is shifted out to the LCD. Registers are read as the fetch
+// happens, so mid-line writes take effect on the remaining pixels, and
+// mode 3 gets longer for fine scrolling, the window and sprites.
+
+use alloc::collections::VecDeque;
+
+use crate::ppu::sprites::{has_priority_over, select_sprites, sprite_over_bg, Sprite};
+use crate::ppu::tiles::{tile_address, TileAttributes, TILE_MAP_0, TILE_MAP_1, TILE_MAP_WIDTH};
+use crate::ppu::{
+    Pixel, Ppu, LCDC_BG_TILE_MAP, LCDC_OBJ_ENABLE, LCDC_TILE_DATA, LCDC_WINDOW_ENABLE,
+    LCDC_WINDOW_TILE_MAP, SCREEN_WIDTH, WX_MAX,
+};
+use crate::prelude::*;
+use crate::savestate::save_state_fields;
+
+/// Dots the fetcher takes to read the tile number and both bytes of tile data
+const FETCH_DOTS: u8 = 6;
+/// Dots the background fetcher is stalled for each sprite fetched
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+/// A sprite pixel waiting to be mixed with the background
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ObjPixel {
+    color: u8,
+    sprite: Sprite,
+}
+
+/// The state of mode 3 for one scanline
+#[derive(Default)]
+pub struct PixelFifo {
+    /// Background/window pixels
+    bg: VecDeque<Pixel>,
+    /// Sprite pixels, lined up with the front of the background FIFO
+    obj: VecDeque<Option<ObjPixel>>,
+    /// Dots into the current tile fetch
+    fetch_dot: u8,
+    /// Tile column the fetcher reads next, relative to the start of the line
+    /// (or of the window)
+    fetch_x: u8,
+    tile_number: u8,
+    attributes: TileAttributes,
+    data_low: u8,
+    data_high: u8,
+    /// Dots left before the fetcher and the output resume
+    stall: u8,
+    /// Pixels left to drop before the output starts (fine scrolling)
+    discard: u8,
+    /// Pixels output so far; the screen X of the next pixel
+    x: u8,
+    /// Whether the fetcher has switched to the window on this line
+    in_window: bool,
+    /// Sprites on this line that haven't been fetched yet
+    sprites: Vec<Sprite>,
+}
+
+impl Ppu {
+    /// Set up the FIFOs at the start of mode 3
+    pub(super) fn fifo_start_line(&mut self, oam: &[u8]) {
+        let mut sprites = if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            select_sprites(oam, self.ly, self.sprite_height(), self.cgb_mode)
+        } else {
+            Vec::new()
+        };
+        // Sprites are fetched left to right, and for equal X in OAM order
+        sprites.sort_by_key(|sprite| (sprite.x, sprite.index));
+
+        self.fifo = PixelFifo {
+            // The first tile fetched on every line is thrown away
+            stall: FETCH_DOTS,
+            discard: self.scx % 8,
+            sprites,
+            ..Default::default()
+        };
+    }
+
+    /// Run mode 3 for one dot. Returns true once the whole line has been output.
+    pub(super) fn fifo_step(&mut self, vram: &[u8]) -> bool {
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return false;
+        }
+
+        if self.fifo.discard == 0 {
+            if self.start_window() {
+                return false;
+            }
+            if self.fetch_sprite(vram) {
+                return false;
+            }
+        }
+
+        self.output_pixel();
+        self.fetcher_step(vram);
+        self.fifo.x as usize == SCREEN_WIDTH
+    }
+
+    /// Whether the fetcher should be showing the window at the current X
+    fn window_visible(&self) -> bool {
+        self.lcdc & LCDC_WINDOW_ENABLE != 0
+            && self.bg_enabled()
+            && self.window_triggered
+            && self.wx <= WX_MAX
+    }
+
+    /// Switch the fetcher over to the window once X reaches WX - 7.
+    /// Returns true if it did, which costs this dot.
+    fn start_window(&mut self) -> bool {
+        if self.fifo.in_window || (self.fifo.x as usize) + 7 < self.wx as usize {
+            return false;
+        }
+        if !self.window_visible() {
+            return false;
+        }
+        let fifo = &mut self.fifo;
+        fifo.in_window = true;
+        fifo.bg.clear();
+        fifo.fetch_dot = 0;
+        fifo.fetch_x = 0;
+        // When WX < 7 the window is shifted off the left edge
+        fifo.discard = 7u8.saturating_sub(self.wx);
+        true
+    }
+
+    /// Fetch the next sprite if it starts at the current X.
+    /// Returns true if one was fetched, which stalls the background.
+    fn fetch_sprite(&mut self, vram: &[u8]) -> bool {
+        let x = self.fifo.x;
+        let position = self
+            .fifo
+            .sprites
+            .iter()
+            .position(|sprite| sprite.x as usize <= x as usize + 8);
+        let sprite = match position {
+            Some(position) => self.fifo.sprites.remove(position),
+            None => return false,
+        };
+
+        let height = self.sprite_height();
+        let obj = &mut self.fifo.obj;
+        for column in 0..8 {
+            let color = match sprite.pixel(vram, x as usize + column, self.ly, height) {
+                Some(color) => color,
+                None => continue,
+            };
+            while obj.len() <= column {
+                obj.push_back(None);
+            }
+            // A sprite fetched earlier keeps its opaque pixels,
+            // unless this one is higher priority (CGB OAM order)
+            let replace = match &obj[column] {
+                Some(existing) => has_priority_over(&sprite, &existing.sprite),
+                None => true,
+            };
+            if replace {
+                obj[column] = Some(ObjPixel { color, sprite });
+            }
+        }
+        self.fifo.stall = SPRITE_FETCH_DOTS - 1;
+        true
+    }
+
+    /// Shift a pixel out of the FIFOs and onto the screen
+    fn output_pixel(&mut self) {
+        let bg = match self.fifo.bg.pop_front() {
+            Some(pixel) => pixel,
+            None => return,
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+
+        // With the background disabled it's drawn as color 0,
+        // but the fetcher keeps running
+        let bg = if self.bg_enabled() {
+            bg
+        } else {
+            Pixel::default()
+        };
+        let master_priority = self.master_priority();
+        let pixel = match self.fifo.obj.pop_front().flatten() {
+            Some(obj) if sprite_over_bg(bg, obj.sprite.has_bg_priority(), master_priority) => {
+                Pixel {
+                    color: obj.color,
+                    palette: obj.sprite.palette(),
+                    priority: false,
+                }
+            }
+            _ => bg,
+        };
+        let index = self.ly as usize * SCREEN_WIDTH + self.fifo.x as usize;
+        self.framebuffer[index] = pixel;
+        self.screen[index] = self.resolve(pixel);
+        self.fifo.x += 1;
+    }
+
+    /// Advance the background fetcher by a dot
+    fn fetcher_step(&mut self, vram: &[u8]) {
+        if self.fifo.fetch_dot < FETCH_DOTS {
+            self.fifo.fetch_dot += 1;
+        }
+
+        let unsigned_addressing = self.lcdc & LCDC_TILE_DATA != 0;
+        let (map, x, y) = if self.fifo.in_window {
+            let map = if self.lcdc & LCDC_WINDOW_TILE_MAP != 0 {
+                TILE_MAP_1
+            } else {
+                TILE_MAP_0
+            };
+            (map, self.fifo.fetch_x, self.window_line)
+        } else {
+            let map = if self.lcdc & LCDC_BG_TILE_MAP != 0 {
+                TILE_MAP_1
+            } else {
+                TILE_MAP_0
+            };
+            // SCX is re-read for every tile, SCY for every byte of tile data
+            let x = (self.scx / 8).wrapping_add(self.fifo.fetch_x) % TILE_MAP_WIDTH as u8;
+            (map, x, self.scy.wrapping_add(self.ly))
+        };
+        let attributes = self.fifo.attributes;
+        let tile = attributes.bank + tile_address(self.fifo.tile_number, unsigned_addressing);
+        let (_, row) = attributes.flip(0, y % 8);
+        let row = tile + row as usize * 2;
+
+        match self.fifo.fetch_dot {
+            2 => {
+                let map_address =
+                    map + (y as usize / 8) * TILE_MAP_WIDTH + x as usize % TILE_MAP_WIDTH;
+                self.fifo.tile_number = vram[map_address];
+                self.fifo.attributes = self.tile_attributes(vram, map_address);
+            }
+            4 => self.fifo.data_low = vram[row],
+            6 => self.fifo.data_high = vram[row + 1],
+            _ => {}
+        }
+
+        // The fetched tile waits until the FIFO has emptied
+        let palette = self.bg_palette(&attributes);
+        let fifo = &mut self.fifo;
+        if fifo.fetch_dot == FETCH_DOTS && fifo.bg.is_empty() {
+            for column in 0..8 {
+                let (column, _) = attributes.flip(column, 0);
+                let bit = 7 - column;
+                let low = (fifo.data_low >> bit) & 1;
+                let high = (fifo.data_high >> bit) & 1;
+                fifo.bg.push_back(Pixel {
+                    color: (high << 1) | low,
+                    palette,
+                    priority: attributes.priority,
+                });
+            }
+            fifo.fetch_dot = 0;
+            fifo.fetch_x = fifo.fetch_x.wrapping_add(1);
+        }
+    }
+
+    /// Wrap up the line at the end of mode 3
+    pub(super) fn fifo_end_line(&mut self) {
+        if self.fifo.in_window {
+            self.window_line += 1;
+        }
+    }
+}
+
+save_state_fields!(ObjPixel { color, sprite });
+
+save_state_fields!(PixelFifo {
+    bg,
+    obj,
+    fetch_dot,
+    fetch_x,
+    tile_number,
+    attributes,
+    data_low,
+    data_high,
+    stall,
+    discard,
+    x,
+    in_window,
+    sprites,
+});
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::tests::{oam, vram, write_tile};
+    use crate::ppu::tiles::TILE_SIZE;
+    use crate::ppu::*;
+    use test_case::test_case;
+
+    fn fifo_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(Renderer::Fifo);
+        ppu
+    }
+
+    /// Dots spent in mode 3 on the first line
+    fn drawing_dots(ppu: &mut Ppu, vram: &[u8], oam: &[u8]) -> u32 {
+        ppu.tick(OAM_SCAN_DOTS, vram, oam);
+        let mut dots = 0;
+        while ppu.mode == Mode::Drawing {
+            ppu.tick(1, vram, oam);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test_case(0, 172; "no scrolling")]
+    #[test_case(3, 175; "fine scroll")]
+    #[test_case(8, 172; "whole tile scroll")]
+    fn test_mode_3_length(scx: u8, dots: u32) {
+        let mut ppu = fifo_ppu();
+        ppu.write(SCX, scx);
+        assert_eq!(drawing_dots(&mut ppu, &vram(), &oam()), dots);
+    }
+
+    #[test]
+    fn test_mode_3_length_sprites() {
+        let mut oam = oam();
+        oam[0..4].copy_from_slice(&[16, 20, 0, 0]);
+        oam[4..8].copy_from_slice(&[16, 60, 0, 0]);
+        let mut ppu = fifo_ppu();
+        ppu.write(LCDC, 0x93);
+        assert_eq!(drawing_dots(&mut ppu, &vram(), &oam), 172 + 12);
+    }
+
+    /// Both renderers draw the same picture when nothing changes mid-line
+    #[test]
+    fn test_matches_scanline_renderer() {
+        let mut vram = vram();
+        write_tile(&mut vram, TILE_SIZE, 0xF0, 0x3C);
+        write_tile(&mut vram, 2 * TILE_SIZE, 0x81, 0xFF);
+        for (index, byte) in vram[TILE_MAP_0..TILE_MAP_0 + 0x400].iter_mut().enumerate() {
+            *byte = (index % 3) as u8;
+        }
+        vram[TILE_MAP_1..TILE_MAP_1 + 0x400].fill(2);
+        let mut oam = oam();
+        oam[0..4].copy_from_slice(&[20, 5, 1, 0b0010_0000]);
+        oam[4..8].copy_from_slice(&[24, 9, 2, 0b1001_0000]);
+        oam[8..12].copy_from_slice(&[90, 150, 1, 0]);
+
+        let mut scanline = Ppu::new();
+        let mut fifo = fifo_ppu();
+        for ppu in [&mut scanline, &mut fifo] {
+            // Background, window and sprites on, window from (30, 40)
+            ppu.write(LCDC, 0xF3);
+            ppu.write(SCX, 13);
+            ppu.write(SCY, 7);
+            ppu.write(WX, 37);
+            ppu.write(WY, 40);
+            ppu.tick(DOTS_PER_LINE * LINES_PER_FRAME as u32, &vram, &oam);
+        }
+        assert!(scanline.framebuffer() == fifo.framebuffer());
+    }
+
+    /// A write during mode 3 only affects the pixels not yet drawn
+    #[test]
+    fn test_mid_line_scroll() {
+        let mut vram = vram();
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        // Columns 0-15 of the map use tile 0, 16-31 use tile 1
+        for row in 0..32 {
+            let start = TILE_MAP_0 + row * 32 + 16;
+            vram[start..start + 16].fill(1);
+        }
+        let oam = oam();
+        let mut ppu = fifo_ppu();
+        ppu.write(LCDC, 0x91);
+        // Halfway through the line, scroll so the rest comes from map columns 16+
+        ppu.tick(OAM_SCAN_DOTS + 12 + 80, &vram, &oam);
+        ppu.write(SCX, 64);
+        ppu.tick(DOTS_PER_LINE, &vram, &oam);
+
+        let row = &ppu.framebuffer()[..SCREEN_WIDTH];
+        assert!(row[..64].iter().all(|pixel| pixel.color == 0));
+        assert!(row[96..].iter().all(|pixel| pixel.color == 3));
+    }
+}
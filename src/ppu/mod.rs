@@ -0,0 +1,1424 @@
+pub mod fifo;
+pub mod palette;
+pub mod sprites;
+pub mod tiles;
+pub mod viewer;
+
+use core::ops::Range;
+use log::debug;
+
+use crate::interrupts::Interrupt;
+use crate::ppu::fifo::PixelFifo;
+use crate::ppu::palette::{
+    shade, ColorPalettes, Shades, BCPD, BCPS, BGP, GRAYSCALE, OBP0, OBP1, OCPD, OCPS,
+};
+use crate::ppu::sprites::{render_sprites, select_sprites};
+use crate::ppu::tiles::{
+    tile_address, tile_pixel, TileAttributes, TILE_MAP_0, TILE_MAP_1, TILE_MAP_WIDTH, VRAM_BANK_1,
+};
+use crate::prelude::*;
+use crate::savestate::{save_state_enum, save_state_fields, SaveState};
+
+/// LCD registers
+pub const LCDC: u16 = 0xFF40;
+pub const STAT: u16 = 0xFF41;
+pub const SCY: u16 = 0xFF42;
+pub const SCX: u16 = 0xFF43;
+pub const LY: u16 = 0xFF44;
+pub const LYC: u16 = 0xFF45;
+pub const WY: u16 = 0xFF4A;
+pub const WX: u16 = 0xFF4B;
+
+/// Screen size in pixels
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// LCDC bits
+const LCDC_BG_ENABLE: u8 = 1 << 0;
+const LCDC_OBJ_ENABLE: u8 = 1 << 1;
+const LCDC_OBJ_SIZE: u8 = 1 << 2;
+const LCDC_BG_TILE_MAP: u8 = 1 << 3;
+const LCDC_TILE_DATA: u8 = 1 << 4;
+const LCDC_WINDOW_ENABLE: u8 = 1 << 5;
+const LCDC_WINDOW_TILE_MAP: u8 = 1 << 6;
+const LCDC_LCD_ENABLE: u8 = 1 << 7;
+/// What the boot ROM leaves in LCDC: LCD and background on, unsigned tile data
+const LCDC_POST_BOOT: u8 = 0x91;
+
+/// The window is off-screen for WX values past this
+const WX_MAX: u8 = 166;
+
+/// Timing, in dots (one dot = one T-cycle)
+pub const DOTS_PER_LINE: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+/// Dots at the start of mode 3 before the first pixel is output
+const DRAWING_DELAY_DOTS: u32 = 12;
+pub const VISIBLE_LINES: u8 = 144;
+pub const LINES_PER_FRAME: u8 = 154;
+pub const DOTS_PER_FRAME: u32 = DOTS_PER_LINE * LINES_PER_FRAME as u32;
+
+/// STAT bits
+const STAT_COINCIDENCE: u8 = 1 << 2;
+const STAT_HBLANK_INTERRUPT: u8 = 1 << 3;
+const STAT_VBLANK_INTERRUPT: u8 = 1 << 4;
+const STAT_OAM_INTERRUPT: u8 = 1 << 5;
+const STAT_LYC_INTERRUPT: u8 = 1 << 6;
+/// The bits of STAT that the CPU can write (the interrupt sources)
+const STAT_WRITABLE: u8 = 0b0111_1000;
+
+/// The PPU mode, as reported in the lower 2 bits of STAT
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(u8)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    #[default]
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// How mode 3 turns tile data into pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Renderer {
+    /// Draw each line in one go at the end of mode 3, which always lasts
+    /// the same number of dots. Fast, and right for most games.
+    #[default]
+    Scanline,
+    /// Emulate the pixel FIFOs dot by dot, so mode 3 varies in length
+    /// and register writes during it affect the rest of the line
+    Fifo,
+}
+
+/// A register write during mode 3, replayed when the scanline renderer
+/// draws the line so it only affects the pixels after it
+#[derive(Clone, Copy, Debug, Default)]
+struct LineWrite {
+    /// The first pixel drawn with the new value
+    x: usize,
+    address: u16,
+    old: u8,
+    new: u8,
+}
+
+/// The palette a pixel's color number is looked up in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Palette {
+    #[default]
+    Bg,
+    Obj0,
+    Obj1,
+    /// CGB background palette 0-7
+    CgbBg(u8),
+    /// CGB sprite palette 0-7
+    CgbObj(u8),
+}
+
+impl Palette {
+    pub fn is_sprite(self) -> bool {
+        matches!(self, Palette::Obj0 | Palette::Obj1 | Palette::CgbObj(_))
+    }
+}
+
+/// A pixel before its color is resolved through a palette
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pixel {
+    /// 2-bit color number from the tile data
+    pub color: u8,
+    pub palette: Palette,
+    /// CGB: the background tile is drawn over sprites
+    pub priority: bool,
+}
+
+/// The Pixel Processing Unit. Every scanline it searches OAM (mode 2),
+/// draws pixels (mode 3) and idles in HBlank (mode 0); after the visible
+/// lines it idles in VBlank (mode 1) until the next frame.
+///
+/// See `Renderer` for how the pixels of each line are drawn.
+#[derive(Default)]
+pub struct Ppu {
+    renderer: Renderer,
+    /// Running in GameBoy Color mode, with color palettes and tile attributes
+    cgb_mode: bool,
+    lcdc: u8,
+    /// Only the writable bits; the mode is tracked separately
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    wy: u8,
+    wx: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    /// The RGB values the DMG palettes map to
+    shades: Shades,
+    bg_palettes: ColorPalettes,
+    obj_palettes: ColorPalettes,
+    mode: Mode,
+    /// Dots elapsed in the current scanline
+    dot: u32,
+    /// Whether LY has matched WY this frame
+    window_triggered: bool,
+    /// The line of the window to draw next
+    window_line: u8,
+    /// Whether LY matched LYC at the last comparison
+    coincidence: bool,
+    /// The internal STAT interrupt line: the OR of every enabled STAT source.
+    /// An interrupt is only requested when it goes from low to high.
+    stat_line: bool,
+    /// Set when a frame has been completed, until the frontend takes it
+    frame_complete: bool,
+    /// Dots since the LCD was turned off. Frames are still completed
+    /// at the usual rate so the frontend keeps running.
+    lcd_off_dots: u32,
+    /// The first frame after the LCD is turned on, which isn't shown
+    first_frame: bool,
+    /// Every pixel on the screen before palettes are applied, row by row
+    framebuffer: Vec<Pixel>,
+    /// Every pixel on the screen as 0x00RRGGBB, row by row
+    screen: Vec<u32>,
+    /// Mode 3 state for the FIFO renderer
+    fifo: PixelFifo,
+    /// Writes made during mode 3 of the current line, for the scanline renderer
+    line_writes: Vec<LineWrite>,
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            framebuffer: vec![Pixel::default(); SCREEN_WIDTH * SCREEN_HEIGHT],
+            screen: vec![GRAYSCALE[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            shades: GRAYSCALE,
+            lcdc: LCDC_POST_BOOT,
+            ..Default::default()
+        }
+    }
+
+    /// A PPU for GameBoy Color games
+    pub fn new_cgb() -> Ppu {
+        Ppu {
+            cgb_mode: true,
+            ..Ppu::new()
+        }
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn framebuffer(&self) -> &[Pixel] {
+        &self.framebuffer
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// The colors on the screen, after palettes are applied
+    pub fn screen(&self) -> &[u32] {
+        &self.screen
+    }
+
+    /// Choose the colors of the four DMG shades
+    pub fn set_shades(&mut self, shades: Shades) {
+        self.shades = shades;
+    }
+
+    /// The RGB value of a pixel, looked up through the current palettes
+    fn resolve(&self, pixel: Pixel) -> u32 {
+        let palette = match pixel.palette {
+            Palette::Bg => self.bgp,
+            Palette::Obj0 => self.obp0,
+            Palette::Obj1 => self.obp1,
+            Palette::CgbBg(palette) => return self.bg_palettes.rgb(palette, pixel.color),
+            Palette::CgbObj(palette) => return self.obj_palettes.rgb(palette, pixel.color),
+        };
+        self.shades[shade(palette, pixel.color)]
+    }
+
+    /// Whether a new frame has been completed since the last call.
+    /// The framebuffer holds the whole frame until the next VBlank.
+    pub fn take_frame_complete(&mut self) -> bool {
+        core::mem::take(&mut self.frame_complete)
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            LCDC => self.lcdc,
+            // Bit 7 is unused and reads as 1
+            STAT => {
+                let coincidence = if self.coincidence {
+                    STAT_COINCIDENCE
+                } else {
+                    0
+                };
+                0b1000_0000 | self.stat | coincidence | self.mode as u8
+            }
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            BCPS => self.bg_palettes.read_index(),
+            BCPD => self.bg_palettes.read_data(),
+            OCPS => self.obj_palettes.read_index(),
+            OCPD => self.obj_palettes.read_data(),
+            _ => unreachable!("{:#06x} is not a PPU register", address),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let was_enabled = self.lcd_enabled();
+        if self.mode == Mode::Drawing
+            && self.renderer == Renderer::Scanline
+            && matches!(address, LCDC | SCY | SCX | BGP | WX)
+        {
+            let x = self
+                .dot
+                .saturating_sub(OAM_SCAN_DOTS + DRAWING_DELAY_DOTS)
+                .min(SCREEN_WIDTH as u32);
+            self.line_writes.push(LineWrite {
+                x: x as usize,
+                address,
+                old: self.read(address),
+                new: value,
+            });
+        }
+        self.set_register(address, value);
+        match (was_enabled, self.lcd_enabled()) {
+            (true, false) => self.turn_lcd_off(),
+            (false, true) => self.turn_lcd_on(),
+            _ => {}
+        }
+    }
+
+    fn set_register(&mut self, address: u16, value: u8) {
+        match address {
+            LCDC => self.lcdc = value,
+            STAT => self.stat = value & STAT_WRITABLE,
+            SCY => self.scy = value,
+            SCX => self.scx = value,
+            LY => debug!("Ignoring write to read-only LY"),
+            LYC => self.lyc = value,
+            BGP => self.bgp = value,
+            OBP0 => self.obp0 = value,
+            OBP1 => self.obp1 = value,
+            WY => self.wy = value,
+            WX => self.wx = value,
+            BCPS => self.bg_palettes.write_index(value),
+            BCPD => self.bg_palettes.write_data(value),
+            OCPS => self.obj_palettes.write_index(value),
+            OCPD => self.obj_palettes.write_data(value),
+            _ => unreachable!("{:#06x} is not a PPU register", address),
+        }
+    }
+
+    /// Advance the PPU by a number of dots (T-cycles).
+    /// `vram` is the contents of 0x8000-0x9FFF, followed by bank 1 on the CGB.
+    /// `oam` is the contents of 0xFE00-0xFE9F.
+    /// Returns the interrupts requested in that time, as IF bits.
+    pub fn tick(&mut self, cycles: u32, vram: &[u8], oam: &[u8]) -> u8 {
+        let mut interrupts = 0;
+        for _ in 0..cycles {
+            if !self.lcd_enabled() {
+                self.lcd_off_dots += 1;
+                if self.lcd_off_dots == DOTS_PER_FRAME {
+                    self.lcd_off_dots = 0;
+                    self.frame_complete = true;
+                }
+                continue;
+            }
+            if self.step(vram, oam) {
+                interrupts |= Interrupt::VBlank.mask();
+            }
+            if self.update_stat_line() {
+                interrupts |= Interrupt::Stat.mask();
+            }
+        }
+        interrupts
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCDC_LCD_ENABLE != 0
+    }
+
+    /// The color of the screen while the LCD is off
+    fn blank_color(&self) -> u32 {
+        if self.cgb_mode {
+            0xFFFFFF
+        } else {
+            self.shades[0]
+        }
+    }
+
+    fn blank_screen(&mut self) {
+        let blank = self.blank_color();
+        self.framebuffer.fill(Pixel::default());
+        self.screen.fill(blank);
+    }
+
+    /// Clearing LCDC bit 7 stops the PPU: LY goes to 0, STAT reports
+    /// mode 0 and the screen goes blank until it's turned on again
+    fn turn_lcd_off(&mut self) {
+        debug!("LCD off at LY={} dot={}", self.ly, self.dot);
+        self.ly = 0;
+        self.dot = 0;
+        self.mode = Mode::HBlank;
+        self.window_triggered = false;
+        self.window_line = 0;
+        self.stat_line = false;
+        self.line_writes.clear();
+        self.lcd_off_dots = 0;
+        self.blank_screen();
+    }
+
+    /// Setting LCDC bit 7 starts a frame from line 0. That line skips
+    /// OAM scan (mode 0 instead of mode 2), and the frame isn't shown.
+    fn turn_lcd_on(&mut self) {
+        debug!("LCD on");
+        self.ly = 0;
+        self.dot = 0;
+        self.mode = Mode::HBlank;
+        self.first_frame = true;
+    }
+
+    /// Compare LY against LYC and re-evaluate the STAT interrupt line.
+    /// Returns true on a rising edge, which requests the STAT interrupt.
+    fn update_stat_line(&mut self) -> bool {
+        self.coincidence = self.ly == self.lyc;
+
+        let enabled = |bit: u8| self.stat & bit != 0;
+        let line = (enabled(STAT_LYC_INTERRUPT) && self.coincidence)
+            || (enabled(STAT_HBLANK_INTERRUPT) && self.mode == Mode::HBlank)
+            || (enabled(STAT_VBLANK_INTERRUPT) && self.mode == Mode::VBlank)
+            || (enabled(STAT_OAM_INTERRUPT) && self.mode == Mode::OamScan);
+
+        // While the line stays high, further sources don't trigger
+        // another interrupt ("STAT blocking")
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+
+    /// Advance by one dot. Returns true when entering VBlank.
+    fn step(&mut self, vram: &[u8], oam: &[u8]) -> bool {
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+            if self.ly == 0 {
+                self.window_triggered = false;
+                self.window_line = 0;
+            }
+        }
+
+        let mode = if self.ly >= VISIBLE_LINES {
+            Mode::VBlank
+        } else if self.dot < OAM_SCAN_DOTS {
+            if self.first_frame && self.ly == 0 {
+                Mode::HBlank
+            } else {
+                Mode::OamScan
+            }
+        } else if self.mode == Mode::Drawing {
+            let done = match self.renderer {
+                Renderer::Scanline => self.dot >= OAM_SCAN_DOTS + DRAWING_DOTS,
+                Renderer::Fifo => self.fifo_step(vram),
+            };
+            if done {
+                Mode::HBlank
+            } else {
+                Mode::Drawing
+            }
+        } else if self.dot == OAM_SCAN_DOTS {
+            Mode::Drawing
+        } else {
+            Mode::HBlank
+        };
+        if mode != self.mode {
+            debug!(
+                "LY={} dot={}: {:?} -> {:?}",
+                self.ly, self.dot, self.mode, mode
+            );
+            if mode == Mode::Drawing {
+                self.start_line(oam);
+            }
+            if self.mode == Mode::Drawing {
+                match self.renderer {
+                    Renderer::Scanline => self.render_line(vram, oam),
+                    Renderer::Fifo => self.fifo_end_line(),
+                }
+            }
+            self.mode = mode;
+            if mode == Mode::VBlank {
+                if self.first_frame {
+                    self.first_frame = false;
+                    self.blank_screen();
+                }
+                self.frame_complete = true;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether the background and window are drawn. On the CGB, LCDC bit 0
+    /// instead takes away their priority over sprites.
+    fn bg_enabled(&self) -> bool {
+        self.cgb_mode || self.lcdc & LCDC_BG_ENABLE != 0
+    }
+
+    /// Whether the background can be drawn over sprites at all
+    fn master_priority(&self) -> bool {
+        !self.cgb_mode || self.lcdc & LCDC_BG_ENABLE != 0
+    }
+
+    /// The CGB attributes of a tile map entry
+    fn tile_attributes(&self, vram: &[u8], map_address: usize) -> TileAttributes {
+        if self.cgb_mode {
+            TileAttributes::from_byte(vram[VRAM_BANK_1 + map_address])
+        } else {
+            TileAttributes::default()
+        }
+    }
+
+    /// The palette background tiles with the given attributes are drawn with
+    fn bg_palette(&self, attributes: &TileAttributes) -> Palette {
+        if self.cgb_mode {
+            Palette::CgbBg(attributes.palette)
+        } else {
+            Palette::Bg
+        }
+    }
+
+    fn sprite_height(&self) -> u8 {
+        if self.lcdc & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Called when mode 3 starts
+    fn start_line(&mut self, oam: &[u8]) {
+        // The window is triggered once LY reaches WY, and stays
+        // triggered for the rest of the frame even if WY changes
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+        if self.renderer == Renderer::Fifo {
+            self.fifo_start_line(oam);
+        }
+    }
+
+    fn render_line(&mut self, vram: &[u8], oam: &[u8]) {
+        let row_start = self.ly as usize * SCREEN_WIDTH;
+        let mut line = [Pixel::default(); SCREEN_WIDTH];
+
+        // Rewind the registers to how they were at the start of mode 3,
+        // then draw up to each write made since before applying it
+        let writes = core::mem::take(&mut self.line_writes);
+        for write in writes.iter().rev() {
+            self.set_register(write.address, write.old);
+        }
+        let mut window_start = None;
+        let mut start = 0;
+        for write in writes.iter() {
+            self.render_background(&mut line[..], vram, start..write.x, &mut window_start);
+            self.set_register(write.address, write.new);
+            start = start.max(write.x);
+        }
+        self.render_background(&mut line[..], vram, start..SCREEN_WIDTH, &mut window_start);
+
+        // The window has its own line counter, which only advances
+        // on lines where the window was drawn
+        if window_start.is_some() {
+            self.window_line += 1;
+        }
+
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            let height = self.sprite_height();
+            let sprites = select_sprites(oam, self.ly, height, self.cgb_mode);
+            render_sprites(
+                &mut line,
+                vram,
+                sprites,
+                self.ly,
+                height,
+                self.master_priority(),
+            );
+            // Sprites are colored with the palettes as they are at the end of the line
+            for (x, pixel) in line.iter().enumerate() {
+                if pixel.palette.is_sprite() {
+                    self.screen[row_start + x] = self.resolve(*pixel);
+                }
+            }
+        }
+
+        self.framebuffer[row_start..row_start + SCREEN_WIDTH].copy_from_slice(&line);
+    }
+
+    /// Draw the background and window layers for the pixels in `range`.
+    /// `window_start` is the screen X the window started at on this line, if it has.
+    fn render_background(
+        &mut self,
+        line: &mut [Pixel],
+        vram: &[u8],
+        range: Range<usize>,
+        window_start: &mut Option<usize>,
+    ) {
+        if !self.bg_enabled() {
+            // On the DMG this also hides the window
+            let row_start = self.ly as usize * SCREEN_WIDTH;
+            for x in range {
+                line[x] = Pixel::default();
+                self.screen[row_start + x] = self.resolve(line[x]);
+            }
+            return;
+        }
+
+        let unsigned_addressing = self.lcdc & LCDC_TILE_DATA != 0;
+        let bg_map = if self.lcdc & LCDC_BG_TILE_MAP != 0 {
+            TILE_MAP_1
+        } else {
+            TILE_MAP_0
+        };
+        let window_map = if self.lcdc & LCDC_WINDOW_TILE_MAP != 0 {
+            TILE_MAP_1
+        } else {
+            TILE_MAP_0
+        };
+        let window_visible =
+            self.lcdc & LCDC_WINDOW_ENABLE != 0 && self.window_triggered && self.wx <= WX_MAX;
+
+        for screen_x in range {
+            // WX is the window's left edge plus 7. Once the window has
+            // started, moving WX doesn't move it for the rest of the line.
+            if window_visible && window_start.is_none() && screen_x + 7 >= self.wx as usize {
+                *window_start = Some(self.wx as usize);
+            }
+            let (map, x, y) = match window_start {
+                Some(start) if window_visible => {
+                    (window_map, (screen_x + 7 - *start) as u8, self.window_line)
+                }
+                _ => {
+                    // The background wraps around at 256x256 pixels
+                    (
+                        bg_map,
+                        self.scx.wrapping_add(screen_x as u8),
+                        self.scy.wrapping_add(self.ly),
+                    )
+                }
+            };
+            let map_address = map + (y as usize / 8) * TILE_MAP_WIDTH + x as usize / 8;
+            let attributes = self.tile_attributes(vram, map_address);
+            let tile = attributes.bank + tile_address(vram[map_address], unsigned_addressing);
+            let (x, y) = attributes.flip(x % 8, y % 8);
+            line[screen_x] = Pixel {
+                color: tile_pixel(vram, tile, x, y),
+                palette: self.bg_palette(&attributes),
+                priority: attributes.priority,
+            };
+            self.screen[self.ly as usize * SCREEN_WIDTH + screen_x] = self.resolve(line[screen_x]);
+        }
+    }
+}
+
+save_state_enum!(Mode {
+    HBlank,
+    VBlank,
+    OamScan,
+    Drawing
+});
+
+impl SaveState for Palette {
+    fn save(&self, out: &mut Vec<u8>) {
+        let (kind, number) = match *self {
+            Palette::Bg => (0u8, 0u8),
+            Palette::Obj0 => (1, 0),
+            Palette::Obj1 => (2, 0),
+            Palette::CgbBg(number) => (3, number),
+            Palette::CgbObj(number) => (4, number),
+        };
+        kind.save(out);
+        number.save(out);
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+        let (mut kind, mut number) = (0u8, 0u8);
+        kind.load(input)?;
+        number.load(input)?;
+        *self = match kind {
+            0 => Palette::Bg,
+            1 => Palette::Obj0,
+            2 => Palette::Obj1,
+            3 => Palette::CgbBg(number),
+            4 => Palette::CgbObj(number),
+            _ => return Err(format!("Unknown palette {} in the save state", kind)),
+        };
+        Ok(())
+    }
+}
+
+save_state_fields!(Pixel {
+    color,
+    palette,
+    priority
+});
+
+save_state_fields!(LineWrite {
+    x,
+    address,
+    old,
+    new
+});
+
+// The renderer and the DMG shades are settings rather than hardware
+save_state_fields!(Ppu {
+    cgb_mode,
+    lcdc,
+    stat,
+    scy,
+    scx,
+    ly,
+    lyc,
+    wy,
+    wx,
+    bgp,
+    obp0,
+    obp1,
+    bg_palettes,
+    obj_palettes,
+    mode,
+    dot,
+    window_triggered,
+    window_line,
+    coincidence,
+    stat_line,
+    frame_complete,
+    lcd_off_dots,
+    first_frame,
+    fifo,
+    line_writes,
+} fixed {
+    framebuffer,
+    screen
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::tiles::TILE_SIZE;
+    use test_case::test_case;
+
+    pub fn vram() -> Vec<u8> {
+        vec![0; 0x2000]
+    }
+
+    /// Write a tile whose rows are all `low`, `high`
+    pub fn write_tile(vram: &mut [u8], address: usize, low: u8, high: u8) {
+        for row in 0..8 {
+            vram[address + row * 2] = low;
+            vram[address + row * 2 + 1] = high;
+        }
+    }
+
+    pub fn oam() -> Vec<u8> {
+        vec![0; 0xA0]
+    }
+
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> u8 {
+        ppu.framebuffer()[y * SCREEN_WIDTH + x].color
+    }
+
+    /// Run until the first frame has been drawn
+    fn render_frame(ppu: &mut Ppu, vram: &[u8]) {
+        render_frame_with_oam(ppu, vram, &oam());
+    }
+
+    fn render_frame_with_oam(ppu: &mut Ppu, vram: &[u8], oam: &[u8]) {
+        ppu.tick(DOTS_PER_LINE * VISIBLE_LINES as u32, vram, oam);
+    }
+
+    #[test_case(0, Mode::OamScan; "start of line")]
+    #[test_case(79, Mode::OamScan; "end of oam scan")]
+    #[test_case(80, Mode::Drawing; "start of drawing")]
+    #[test_case(251, Mode::Drawing; "end of drawing")]
+    #[test_case(252, Mode::HBlank; "start of hblank")]
+    #[test_case(455, Mode::HBlank; "end of hblank")]
+    #[test_case(456, Mode::OamScan; "next line")]
+    #[test_case(144 * 456, Mode::VBlank; "start of vblank")]
+    #[test_case(154 * 456 - 1, Mode::VBlank; "end of vblank")]
+    #[test_case(154 * 456, Mode::OamScan; "next frame")]
+    fn test_modes(dots: u32, mode: Mode) {
+        let mut ppu = Ppu::new();
+        ppu.tick(dots, &vram(), &oam());
+        assert_eq!(ppu.mode, mode);
+        assert_eq!(ppu.read(STAT) & 0b11, mode as u8);
+    }
+
+    #[test]
+    fn test_ly() {
+        let vram = vram();
+        let mut ppu = Ppu::new();
+        for line in 0..LINES_PER_FRAME {
+            assert_eq!(ppu.read(LY), line);
+            ppu.tick(DOTS_PER_LINE, &vram, &oam());
+        }
+        // Wraps around to the next frame
+        assert_eq!(ppu.read(LY), 0);
+        ppu.tick(DOTS_PER_LINE * LINES_PER_FRAME as u32, &vram, &oam());
+        assert_eq!(ppu.read(LY), 0);
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut ppu = Ppu::new();
+        for (address, value) in [
+            (LCDC, 0x91),
+            (SCY, 0x12),
+            (SCX, 0x34),
+            (LYC, 0x56),
+            (WY, 0x78),
+            (WX, 0x9A),
+            (BGP, 0xE4),
+            (OBP0, 0xD2),
+            (OBP1, 0x1B),
+        ] {
+            ppu.write(address, value);
+            assert_eq!(ppu.read(address), value);
+        }
+
+        // LY is read-only
+        ppu.write(LY, 0x42);
+        assert_eq!(ppu.read(LY), 0);
+
+        // Only the interrupt enable bits of STAT are writable
+        ppu.write(STAT, 0xFF);
+        assert_eq!(ppu.read(STAT), 0b1111_1000 | Mode::OamScan as u8);
+    }
+
+    #[test]
+    fn test_background() {
+        let mut vram = vram();
+        // Tile 1 is solid color 3, tile 2 is solid color 1
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        write_tile(&mut vram, 2 * TILE_SIZE, 0xFF, 0x00);
+        // Map: top-left tile is 1, the one to its right is 2
+        vram[TILE_MAP_0] = 1;
+        vram[TILE_MAP_0 + 1] = 2;
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x91); // LCD on, unsigned tile data, map 0, BG on
+        render_frame(&mut ppu, &vram);
+
+        assert_eq!(pixel(&ppu, 0, 0), 3);
+        assert_eq!(pixel(&ppu, 7, 7), 3);
+        assert_eq!(pixel(&ppu, 8, 0), 1);
+        assert_eq!(pixel(&ppu, 16, 0), 0);
+        assert_eq!(pixel(&ppu, 0, 8), 0);
+    }
+
+    #[test]
+    fn test_background_scroll() {
+        let mut vram = vram();
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        vram[TILE_MAP_0] = 1;
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x91);
+        ppu.write(SCX, 4);
+        ppu.write(SCY, 250); // wraps around the 256 pixel map
+        render_frame(&mut ppu, &vram);
+
+        // The tile at map (0, 0) now appears at screen (-4, 6)
+        assert_eq!(pixel(&ppu, 0, 5), 0);
+        assert_eq!(pixel(&ppu, 0, 6), 3);
+        assert_eq!(pixel(&ppu, 3, 13), 3);
+        assert_eq!(pixel(&ppu, 4, 13), 0);
+        assert_eq!(pixel(&ppu, 3, 14), 0);
+    }
+
+    #[test]
+    fn test_background_signed_tile_data_and_map_1() {
+        let mut vram = vram();
+        // Tile -1 with signed addressing is at 0x8FF0
+        write_tile(&mut vram, 0x0FF0, 0x00, 0xFF);
+        vram[TILE_MAP_1] = 0xFF;
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x89); // LCD on, signed tile data, map 1, BG on
+        render_frame(&mut ppu, &vram);
+        assert_eq!(pixel(&ppu, 0, 0), 2);
+    }
+
+    #[test]
+    fn test_background_disabled() {
+        let mut vram = vram();
+        write_tile(&mut vram, 0, 0xFF, 0xFF);
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x90);
+        render_frame(&mut ppu, &vram);
+        assert_eq!(pixel(&ppu, 0, 0), 0);
+    }
+
+    /// A map filled with tile 1 (solid color 3) for the window, on top of
+    /// an empty background
+    fn window_vram() -> Vec<u8> {
+        let mut vram = vram();
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        vram[TILE_MAP_1..TILE_MAP_1 + 0x400].fill(1);
+        vram
+    }
+
+    #[test]
+    fn test_window() {
+        let vram = window_vram();
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0xF1); // window on, map 1
+        ppu.write(WY, 100);
+        ppu.write(WX, 7 + 40);
+        render_frame(&mut ppu, &vram);
+
+        assert_eq!(pixel(&ppu, 40, 99), 0);
+        assert_eq!(pixel(&ppu, 39, 100), 0);
+        assert_eq!(pixel(&ppu, 40, 100), 3);
+        assert_eq!(pixel(&ppu, 159, 143), 3);
+    }
+
+    #[test]
+    fn test_window_disabled() {
+        let vram = window_vram();
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0xD1); // window off
+        ppu.write(WY, 0);
+        ppu.write(WX, 7);
+        render_frame(&mut ppu, &vram);
+        assert_eq!(pixel(&ppu, 0, 0), 0);
+
+        // WX past the screen hides the window
+        ppu.write(LCDC, 0xF1);
+        ppu.write(WX, 167);
+        ppu.tick(DOTS_PER_LINE * LINES_PER_FRAME as u32, &vram, &oam());
+        assert_eq!(pixel(&ppu, 159, 0), 0);
+    }
+
+    /// Disabling the window for a few lines mid-frame, eg for a status bar,
+    /// pauses the window line counter instead of skipping lines of the window
+    #[test]
+    fn test_window_line_counter() {
+        let mut vram = vram();
+        // Window tile map: row 0 is tile 1 (color 3), row 1 is tile 2 (color 1)
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        write_tile(&mut vram, 2 * TILE_SIZE, 0xFF, 0x00);
+        vram[TILE_MAP_1..TILE_MAP_1 + 32].fill(1);
+        vram[TILE_MAP_1 + 32..TILE_MAP_1 + 64].fill(2);
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0xF1);
+        ppu.write(WY, 0);
+        ppu.write(WX, 7);
+        // Draw 4 lines of the window, then hide it for 10 lines
+        ppu.tick(DOTS_PER_LINE * 4, &vram, &oam());
+        ppu.write(LCDC, 0xD1);
+        ppu.tick(DOTS_PER_LINE * 10, &vram, &oam());
+        ppu.write(LCDC, 0xF1);
+        render_frame(&mut ppu, &vram);
+
+        assert_eq!(pixel(&ppu, 0, 3), 3);
+        assert_eq!(pixel(&ppu, 0, 4), 0);
+        // The window resumes from its 5th line
+        assert_eq!(pixel(&ppu, 0, 14), 3);
+        assert_eq!(pixel(&ppu, 0, 17), 3);
+        assert_eq!(pixel(&ppu, 0, 18), 1);
+    }
+
+    /// VRAM with tile 1 solid color 3, tile 2 with only its left
+    /// column set to color 1, and tile 3 solid color 2
+    fn sprite_vram() -> Vec<u8> {
+        let mut vram = vram();
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        write_tile(&mut vram, 2 * TILE_SIZE, 0x80, 0x00);
+        write_tile(&mut vram, 3 * TILE_SIZE, 0x00, 0xFF);
+        vram
+    }
+
+    fn write_sprite(oam: &mut [u8], index: usize, y: u8, x: u8, tile: u8, attributes: u8) {
+        oam[index * 4..index * 4 + 4].copy_from_slice(&[y, x, tile, attributes]);
+    }
+
+    #[test]
+    fn test_sprite() {
+        let vram = sprite_vram();
+        let mut oam = oam();
+        // Screen position (10, 20)
+        write_sprite(&mut oam, 0, 20 + 16, 10 + 8, 1, 0);
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x93); // sprites on
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+
+        assert_eq!(pixel(&ppu, 9, 20), 0);
+        assert_eq!(pixel(&ppu, 10, 20), 3);
+        assert_eq!(pixel(&ppu, 17, 27), 3);
+        assert_eq!(pixel(&ppu, 18, 27), 0);
+        assert_eq!(pixel(&ppu, 17, 28), 0);
+        assert_eq!(
+            ppu.framebuffer()[20 * SCREEN_WIDTH + 10].palette,
+            Palette::Obj0
+        );
+
+        // Sprites disabled
+        ppu.write(LCDC, 0x91);
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+        assert_eq!(pixel(&ppu, 10, 20), 0);
+    }
+
+    #[test]
+    fn test_sprite_x_flip_and_palette() {
+        let vram = sprite_vram();
+        let mut oam = oam();
+        write_sprite(&mut oam, 0, 16, 8, 2, 0);
+        write_sprite(&mut oam, 1, 16, 16, 2, 0b0011_0000); // X flip, OBP1
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x93);
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+        assert_eq!(pixel(&ppu, 7, 0), 0);
+        assert_eq!(pixel(&ppu, 8, 0), 0);
+        assert_eq!(pixel(&ppu, 15, 0), 1);
+        assert_eq!(ppu.framebuffer()[15].palette, Palette::Obj1);
+    }
+
+    #[test]
+    fn test_sprite_8x16_and_y_flip() {
+        let vram = sprite_vram();
+        let mut oam = oam();
+        // Tile 3 is ignored in 8x16 mode: tile 2 is the top, tile 3 the bottom
+        write_sprite(&mut oam, 0, 16, 8, 3, 0);
+        write_sprite(&mut oam, 1, 16, 16, 2, 0b0100_0000); // Y flip
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x97); // 8x16 sprites
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+        assert_eq!(pixel(&ppu, 1, 8), 2);
+        assert_eq!(pixel(&ppu, 1, 15), 2);
+        assert_eq!(pixel(&ppu, 1, 16), 0);
+        // Flipped: the bottom tile is now on top
+        assert_eq!(pixel(&ppu, 9, 0), 2);
+        assert_eq!(pixel(&ppu, 8, 15), 1);
+        assert_eq!(pixel(&ppu, 9, 15), 0);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let vram = sprite_vram();
+        let mut oam = oam();
+        // The sprite further left is on top even though it comes later in OAM
+        write_sprite(&mut oam, 0, 16, 12, 3, 0);
+        write_sprite(&mut oam, 1, 16, 10, 1, 0);
+        // With equal X, the one first in OAM is on top
+        write_sprite(&mut oam, 2, 32, 8, 3, 0);
+        write_sprite(&mut oam, 3, 32, 8, 1, 0);
+        // Transparent pixels of the top sprite show the one beneath
+        write_sprite(&mut oam, 4, 48, 8, 2, 0);
+        write_sprite(&mut oam, 5, 48, 9, 3, 0);
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x93);
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+
+        assert_eq!(pixel(&ppu, 4, 0), 3);
+        assert_eq!(pixel(&ppu, 9, 0), 3);
+        assert_eq!(pixel(&ppu, 10, 0), 2);
+        assert_eq!(pixel(&ppu, 0, 16), 2);
+        assert_eq!(pixel(&ppu, 0, 32), 1);
+        assert_eq!(pixel(&ppu, 1, 32), 2);
+    }
+
+    #[test]
+    fn test_sprite_bg_priority() {
+        let mut vram = sprite_vram();
+        // Background: tile 2, so only the first column of each tile is non-zero
+        vram[TILE_MAP_0..TILE_MAP_0 + 0x400].fill(2);
+        let mut oam = oam();
+        write_sprite(&mut oam, 0, 16, 8, 1, 0b1000_0000); // behind BG
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x93);
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+
+        // Hidden behind background color 1, drawn over background color 0
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+        assert_eq!(ppu.framebuffer()[0].palette, Palette::Bg);
+        assert_eq!(pixel(&ppu, 1, 0), 3);
+    }
+
+    #[test]
+    fn test_sprite_limit_per_line() {
+        let vram = sprite_vram();
+        let mut oam = oam();
+        for index in 0..11 {
+            write_sprite(&mut oam, index, 16, 8 + 8 * index as u8, 1, 0);
+        }
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x93);
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+        assert_eq!(pixel(&ppu, 79, 0), 3);
+        assert_eq!(pixel(&ppu, 80, 0), 0);
+    }
+
+    /// Tick until the given line and dot, returning the interrupts requested
+    fn tick_to(ppu: &mut Ppu, ly: u8, dot: u32) -> u8 {
+        let vram = vram();
+        let oam = oam();
+        let mut interrupts = 0;
+        while (ppu.ly, ppu.dot) != (ly, dot) {
+            interrupts |= ppu.tick(1, &vram, &oam);
+        }
+        interrupts
+    }
+
+    #[test]
+    fn test_lyc_coincidence() {
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 3);
+        tick_to(&mut ppu, 2, 0);
+        assert_eq!(ppu.read(STAT) & STAT_COINCIDENCE, 0);
+        tick_to(&mut ppu, 3, 0);
+        assert_eq!(ppu.read(STAT) & STAT_COINCIDENCE, STAT_COINCIDENCE);
+        tick_to(&mut ppu, 4, 0);
+        assert_eq!(ppu.read(STAT) & STAT_COINCIDENCE, 0);
+        // The flag is read-only
+        ppu.write(STAT, 0xFF);
+        assert_eq!(ppu.read(STAT), 0xFA);
+    }
+
+    #[test_case(STAT_LYC_INTERRUPT, 10, 0; "lyc")]
+    #[test_case(STAT_HBLANK_INTERRUPT, 0, 252; "hblank")]
+    #[test_case(STAT_OAM_INTERRUPT, 1, 0; "oam scan")]
+    #[test_case(STAT_VBLANK_INTERRUPT, 144, 0; "vblank")]
+    fn test_stat_interrupt(source: u8, ly: u8, dot: u32) {
+        let mut ppu = Ppu::new();
+        // Start in mode 3, where none of the sources are active
+        tick_to(&mut ppu, 0, 100);
+        ppu.write(LYC, 10);
+        ppu.write(STAT, source);
+
+        // Nothing is requested until the source's condition starts
+        let (previous_ly, previous_dot) = if dot == 0 {
+            (ly - 1, DOTS_PER_LINE - 1)
+        } else {
+            (ly, dot - 1)
+        };
+        let stat = Interrupt::Stat.mask();
+        assert_eq!(tick_to(&mut ppu, previous_ly, previous_dot) & stat, 0);
+        assert_eq!(tick_to(&mut ppu, ly, dot) & stat, stat);
+    }
+
+    #[test]
+    fn test_stat_interrupt_edges() {
+        let mut ppu = Ppu::new();
+        ppu.write(STAT, STAT_HBLANK_INTERRUPT);
+        assert_eq!(tick_to(&mut ppu, 0, 252), Interrupt::Stat.mask());
+        // Only once per HBlank
+        assert_eq!(tick_to(&mut ppu, 0, 455), 0);
+        assert_eq!(tick_to(&mut ppu, 1, 252), Interrupt::Stat.mask());
+    }
+
+    /// HBlank leading straight into a matching line keeps the line high,
+    /// so the LYC match doesn't request a second interrupt
+    #[test]
+    fn test_stat_blocking() {
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 1);
+        ppu.write(STAT, STAT_HBLANK_INTERRUPT | STAT_LYC_INTERRUPT);
+        assert_eq!(tick_to(&mut ppu, 0, 252), Interrupt::Stat.mask());
+        assert_eq!(tick_to(&mut ppu, 1, 100), 0);
+
+        // Without HBlank enabled, the match is an edge of its own
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 1);
+        ppu.write(STAT, STAT_LYC_INTERRUPT);
+        assert_eq!(tick_to(&mut ppu, 1, 100), Interrupt::Stat.mask());
+    }
+
+    #[test]
+    fn test_vblank_interrupt() {
+        let mut ppu = Ppu::new();
+        assert_eq!(tick_to(&mut ppu, 143, DOTS_PER_LINE - 1), 0);
+        assert!(!ppu.take_frame_complete());
+
+        assert_eq!(tick_to(&mut ppu, 144, 0), Interrupt::VBlank.mask());
+        assert!(ppu.take_frame_complete());
+        // Only reported once per frame
+        assert!(!ppu.take_frame_complete());
+        assert_eq!(tick_to(&mut ppu, 0, 0), 0);
+
+        assert_eq!(tick_to(&mut ppu, 144, 0), Interrupt::VBlank.mask());
+        assert!(ppu.take_frame_complete());
+    }
+
+    #[test]
+    fn test_lcd_off() {
+        let mut vram = vram();
+        write_tile(&mut vram, 0, 0xFF, 0xFF); // every tile is color 3
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(
+            STAT,
+            STAT_HBLANK_INTERRUPT | STAT_VBLANK_INTERRUPT | STAT_OAM_INTERRUPT,
+        );
+        render_frame(&mut ppu, &vram);
+        assert_eq!(ppu.screen()[0], GRAYSCALE[3]);
+        assert!(ppu.take_frame_complete());
+
+        ppu.write(LCDC, LCDC_POST_BOOT & !LCDC_LCD_ENABLE);
+        assert_eq!(ppu.read(LY), 0);
+        assert_eq!(ppu.read(STAT) & 0b11, Mode::HBlank as u8);
+        assert!(ppu.screen().iter().all(|pixel| *pixel == GRAYSCALE[0]));
+
+        // The PPU is stopped, so no interrupts, but frames still complete on time
+        assert_eq!(ppu.tick(DOTS_PER_FRAME - 1, &vram, &oam()), 0);
+        assert_eq!(ppu.read(LY), 0);
+        assert!(!ppu.take_frame_complete());
+        ppu.tick(1, &vram, &oam());
+        assert!(ppu.take_frame_complete());
+        assert!(ppu.screen().iter().all(|pixel| *pixel == GRAYSCALE[0]));
+    }
+
+    #[test]
+    fn test_lcd_on() {
+        let mut vram = vram();
+        write_tile(&mut vram, 0, 0xFF, 0xFF);
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(LCDC, 0);
+        ppu.tick(1000, &vram, &oam());
+        ppu.write(LCDC, LCDC_POST_BOOT);
+
+        // The first line starts in mode 0 instead of OAM scan
+        tick_to(&mut ppu, 0, 1);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        tick_to(&mut ppu, 0, OAM_SCAN_DOTS);
+        assert_eq!(ppu.mode, Mode::Drawing);
+        tick_to(&mut ppu, 1, 0);
+        assert_eq!(ppu.mode, Mode::OamScan);
+
+        // The first frame is blank, the one after is shown
+        let mut frames = 0;
+        while frames < 2 {
+            ppu.tick(1, &vram, &oam());
+            if ppu.take_frame_complete() {
+                frames += 1;
+                let shade = if frames == 1 { 0 } else { 3 };
+                assert_eq!(ppu.screen()[0], GRAYSCALE[shade], "frame {}", frames);
+            }
+        }
+    }
+
+    /// Writes during mode 3 only affect the rest of the line
+    #[test]
+    fn test_mid_line_writes() {
+        let mut vram = vram();
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        // Map columns 16-31 use tile 1 (color 3), the rest tile 0
+        for row in 0..32 {
+            let start = TILE_MAP_0 + row * 32 + 16;
+            vram[start..start + 16].fill(1);
+        }
+        let oam = oam();
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x91);
+
+        // Scroll right 64 pixels once 80 pixels of line 0 have been drawn
+        ppu.tick(OAM_SCAN_DOTS + DRAWING_DELAY_DOTS + 80, &vram, &oam);
+        ppu.write(SCX, 64);
+        assert_eq!(ppu.read(SCX), 64);
+        // Turn the background off for the last 20 pixels
+        ppu.tick(60, &vram, &oam);
+        ppu.write(LCDC, 0x90);
+        ppu.tick(DOTS_PER_LINE, &vram, &oam);
+
+        let row: Vec<u8> = (0..SCREEN_WIDTH).map(|x| pixel(&ppu, x, 0)).collect();
+        assert!(row[..80].iter().all(|color| *color == 0));
+        assert!(row[80..140].iter().all(|color| *color == 3));
+        assert!(row[140..].iter().all(|color| *color == 0));
+
+        // The next line is drawn with the final values throughout
+        ppu.tick(DOTS_PER_LINE, &vram, &oam);
+        assert_eq!(pixel(&ppu, 100, 1), 0);
+    }
+
+    /// Moving WX after the window has started doesn't move the window
+    #[test]
+    fn test_mid_line_window_move() {
+        let mut vram = vram();
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        write_tile(&mut vram, 2 * TILE_SIZE, 0x00, 0xFF);
+        vram[TILE_MAP_1..TILE_MAP_1 + 0x400].fill(1);
+        vram[TILE_MAP_1] = 2; // the window's first tile
+        let oam = oam();
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0xF1);
+        ppu.write(WX, 7);
+
+        ppu.tick(OAM_SCAN_DOTS + DRAWING_DELAY_DOTS + 50, &vram, &oam);
+        ppu.write(WX, 100);
+        ppu.tick(DOTS_PER_LINE, &vram, &oam);
+
+        assert_eq!(pixel(&ppu, 0, 0), 2);
+        assert_eq!(pixel(&ppu, 8, 0), 3);
+        assert_eq!(pixel(&ppu, 100, 0), 3);
+    }
+
+    fn screen_pixel(ppu: &Ppu, x: usize, y: usize) -> u32 {
+        ppu.screen()[y * SCREEN_WIDTH + x]
+    }
+
+    #[test]
+    fn test_palettes() {
+        let mut vram = sprite_vram();
+        vram[TILE_MAP_0] = 1; // color 3 in the top left tile
+        let mut oam = oam();
+        write_sprite(&mut oam, 0, 16, 16 + 8, 1, 0); // OBP0
+        write_sprite(&mut oam, 1, 16, 24 + 8, 1, 0b0001_0000); // OBP1
+
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x93);
+        ppu.write(BGP, 0b00_01_10_11); // inverted
+        ppu.write(OBP0, 0b01_00_00_00); // color 3 is light gray
+        ppu.write(OBP1, 0b10_00_00_00); // color 3 is dark gray
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+
+        assert_eq!(screen_pixel(&ppu, 0, 0), GRAYSCALE[0]);
+        assert_eq!(screen_pixel(&ppu, 8, 0), GRAYSCALE[3]);
+        assert_eq!(screen_pixel(&ppu, 16, 0), GRAYSCALE[1]);
+        assert_eq!(screen_pixel(&ppu, 24, 0), GRAYSCALE[2]);
+        // The color numbers are unchanged
+        assert_eq!(pixel(&ppu, 0, 0), 3);
+        assert_eq!(pixel(&ppu, 8, 0), 0);
+
+        // Custom shades
+        let shades = [0xE0F8D0, 0x88C070, 0x346856, 0x081820];
+        ppu.set_shades(shades);
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+        assert_eq!(screen_pixel(&ppu, 0, 0), shades[0]);
+        assert_eq!(screen_pixel(&ppu, 16, 0), shades[1]);
+    }
+
+    #[test_case(Renderer::Scanline; "scanline")]
+    #[test_case(Renderer::Fifo; "fifo")]
+    fn test_mid_line_palette(renderer: Renderer) {
+        let mut vram = vram();
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        vram[TILE_MAP_0..TILE_MAP_0 + 0x400].fill(1);
+        let oam = oam();
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(renderer);
+        ppu.write(LCDC, 0x91);
+        ppu.write(BGP, 0xFF);
+
+        ppu.tick(OAM_SCAN_DOTS + DRAWING_DELAY_DOTS + 80, &vram, &oam);
+        ppu.write(BGP, 0x00);
+        ppu.tick(DOTS_PER_LINE, &vram, &oam);
+
+        assert_eq!(screen_pixel(&ppu, 0, 0), GRAYSCALE[3]);
+        assert_eq!(screen_pixel(&ppu, 70, 0), GRAYSCALE[3]);
+        assert_eq!(screen_pixel(&ppu, 90, 0), GRAYSCALE[0]);
+        assert_eq!(screen_pixel(&ppu, 159, 0), GRAYSCALE[0]);
+    }
+
+    /// Write a color to CGB palette RAM through the index and data registers
+    fn write_cgb_color(ppu: &mut Ppu, index_register: u16, palette: u8, color: u8, rgb555: u16) {
+        ppu.write(index_register, 0x80 | (palette * 8 + color * 2));
+        for byte in rgb555.to_le_bytes() {
+            ppu.write(index_register + 1, byte);
+        }
+    }
+
+    fn cgb_vram() -> Vec<u8> {
+        let mut vram = vec![0; 0x4000];
+        // Tile 1 is color 3 in bank 0 and color 1 in bank 1
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        write_tile(&mut vram, VRAM_BANK_1 + TILE_SIZE, 0xFF, 0x00);
+        // Tile 2 has only its top left pixel set
+        vram[2 * TILE_SIZE] = 0x80;
+        vram[TILE_MAP_0..TILE_MAP_0 + 0x400].fill(1);
+        vram
+    }
+
+    #[test_case(Renderer::Scanline; "scanline")]
+    #[test_case(Renderer::Fifo; "fifo")]
+    fn test_cgb_background(renderer: Renderer) {
+        let mut vram = cgb_vram();
+        let attributes = VRAM_BANK_1 + TILE_MAP_0;
+        vram[attributes + 1] = 0b0000_0010; // palette 2
+        vram[attributes + 2] = 0b0000_1000; // tile data from bank 1
+        vram[TILE_MAP_0 + 3] = 2;
+        vram[attributes + 3] = 0b0110_0000; // flipped both ways
+
+        let mut ppu = Ppu::new_cgb();
+        ppu.set_renderer(renderer);
+        write_cgb_color(&mut ppu, BCPS, 0, 3, 0x001F);
+        write_cgb_color(&mut ppu, BCPS, 2, 3, 0x03E0);
+        write_cgb_color(&mut ppu, BCPS, 0, 1, 0x7C00);
+        // Unlike the DMG, LCDC bit 0 doesn't turn off the background
+        ppu.write(LCDC, 0x90);
+        render_frame(&mut ppu, &vram);
+
+        assert_eq!(screen_pixel(&ppu, 0, 0), 0xFF0000);
+        assert_eq!(ppu.framebuffer()[0].palette, Palette::CgbBg(0));
+        assert_eq!(screen_pixel(&ppu, 8, 0), 0x00FF00);
+        assert_eq!(screen_pixel(&ppu, 16, 0), 0x0000FF);
+        assert_eq!(pixel(&ppu, 24, 0), 0);
+        assert_eq!(pixel(&ppu, 31, 7), 1);
+    }
+
+    #[test_case(Renderer::Scanline; "scanline")]
+    #[test_case(Renderer::Fifo; "fifo")]
+    fn test_cgb_sprites(renderer: Renderer) {
+        let mut vram = cgb_vram();
+        // Tiles with priority over sprites
+        vram[VRAM_BANK_1 + TILE_MAP_0 + 2] = 0b1000_0000;
+        vram[VRAM_BANK_1 + TILE_MAP_0 + 3] = 0b1000_0000;
+        vram[TILE_MAP_0 + 3] = 2;
+        let mut oam = oam();
+        // The first sprite in OAM is on top even though it's further right
+        write_sprite(&mut oam, 0, 16, 8 + 4, 1, 0b0000_1101); // bank 1, palette 5
+        write_sprite(&mut oam, 1, 16, 8, 1, 0);
+        // Over a BG tile with priority: hidden except where the BG is color 0
+        write_sprite(&mut oam, 2, 16, 8 + 24, 1, 0);
+
+        let mut ppu = Ppu::new_cgb();
+        ppu.set_renderer(renderer);
+        write_cgb_color(&mut ppu, OCPS, 5, 1, 0x001F);
+        write_cgb_color(&mut ppu, OCPS, 0, 3, 0x03E0);
+        ppu.write(LCDC, 0x93);
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+
+        assert_eq!(screen_pixel(&ppu, 0, 0), 0x00FF00);
+        assert_eq!(screen_pixel(&ppu, 4, 0), 0xFF0000);
+        assert_eq!(ppu.framebuffer()[4].palette, Palette::CgbObj(5));
+        assert_eq!(pixel(&ppu, 24, 0), 1);
+        assert!(!ppu.framebuffer()[24].palette.is_sprite());
+        assert!(ppu.framebuffer()[25].palette.is_sprite());
+
+        // Clearing LCDC bit 0 puts every sprite on top
+        ppu.write(LCDC, 0x92);
+        render_frame_with_oam(&mut ppu, &vram, &oam);
+        assert!(ppu.framebuffer()[24].palette.is_sprite());
+    }
+}
@@ -0,0 +1,206 @@
+// DMG palettes: each register maps the four 2-bit color numbers
+// to one of the four shades of the screen.
+// CGB palettes: 8 background and 8 sprite palettes of 4 RGB555 colors,
+// in palette RAM that is only reachable through an index register.
+
+use core::convert::TryInto;
+
+use crate::prelude::*;
+use crate::savestate::save_state_fields;
+
+/// Background and window palette
+pub const BGP: u16 = 0xFF47;
+/// Sprite palettes; color 0 is transparent so its entry is unused
+pub const OBP0: u16 = 0xFF48;
+pub const OBP1: u16 = 0xFF49;
+
+/// CGB background palette index and data
+pub const BCPS: u16 = 0xFF68;
+pub const BCPD: u16 = 0xFF69;
+/// CGB sprite palette index and data
+pub const OCPS: u16 = 0xFF6A;
+pub const OCPD: u16 = 0xFF6B;
+
+/// 8 palettes of 4 colors, 2 bytes per color
+const PALETTE_RAM_SIZE: usize = 64;
+/// Index register bit that advances the index after each data write
+const AUTO_INCREMENT: u8 = 1 << 7;
+const INDEX_MASK: u8 = 0b0011_1111;
+
+/// The RGB values (0x00RRGGBB) of the four DMG shades, lightest first
+pub type Shades = [u32; 4];
+
+pub const GRAYSCALE: Shades = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000];
+/// The green tint of the original DMG screen
+pub const CLASSIC_GREEN: Shades = [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F];
+
+/// Parse a palette name (`grayscale` or `green`) or four comma-separated
+/// hex colors, lightest first, eg `#E0F8D0,#88C070,#346856,#081820`
+pub fn parse_shades(value: &str) -> Result<Shades, String> {
+    match value.trim() {
+        "grayscale" => return Ok(GRAYSCALE),
+        "green" => return Ok(CLASSIC_GREEN),
+        _ => {}
+    }
+
+    let colors = value
+        .split(',')
+        .map(|color| {
+            let hex = color.trim().trim_start_matches('#');
+            match u32::from_str_radix(hex, 16) {
+                Ok(rgb) if hex.len() == 6 => Ok(rgb),
+                _ => Err(format!(
+                    "{:?} is not a hex color like #E0F8D0",
+                    color.trim()
+                )),
+            }
+        })
+        .collect::<Result<Vec<u32>, String>>()?;
+    colors.try_into().map_err(|colors: Vec<u32>| {
+        format!(
+            "A palette needs 4 colors (or grayscale or green), got {}",
+            colors.len()
+        )
+    })
+}
+
+/// The shade (0-3) a palette register gives a color number
+pub fn shade(palette: u8, color: u8) -> usize {
+    ((palette >> (color * 2)) & 0b11) as usize
+}
+
+/// Convert a little-endian RGB555 color (0bbbbbgg gggrrrrr) to 0x00RRGGBB
+pub fn rgb555_to_rgb888(color: u16) -> u32 {
+    // Repeat the top bits in the bottom so 0x1F becomes 0xFF
+    let scale = |channel: u16| {
+        let channel = (channel & 0x1F) as u32;
+        (channel << 3) | (channel >> 2)
+    };
+    (scale(color) << 16) | (scale(color >> 5) << 8) | scale(color >> 10)
+}
+
+/// One of the CGB's two banks of color palette RAM
+#[derive(Clone)]
+pub struct ColorPalettes {
+    ram: [u8; PALETTE_RAM_SIZE],
+    /// The byte BCPD/OCPD reads and writes, 0-63
+    index: u8,
+    auto_increment: bool,
+}
+
+impl Default for ColorPalettes {
+    fn default() -> Self {
+        // Every color starts out white
+        ColorPalettes {
+            ram: [0xFF; PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+}
+
+impl ColorPalettes {
+    pub fn read_index(&self) -> u8 {
+        // Bit 6 is unused and reads as 1
+        let auto_increment = if self.auto_increment {
+            AUTO_INCREMENT
+        } else {
+            0
+        };
+        auto_increment | 0b0100_0000 | self.index
+    }
+
+    pub fn write_index(&mut self, value: u8) {
+        self.index = value & INDEX_MASK;
+        self.auto_increment = value & AUTO_INCREMENT != 0;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.ram[self.index as usize]
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        self.ram[self.index as usize] = value;
+        if self.auto_increment {
+            self.index = (self.index + 1) & INDEX_MASK;
+        }
+    }
+
+    /// A color in one of the 8 palettes as it's stored, in RGB555
+    pub fn color(&self, palette: u8, color: u8) -> u16 {
+        let offset = palette as usize * 8 + color as usize * 2;
+        u16::from_le_bytes([self.ram[offset], self.ram[offset + 1]])
+    }
+
+    /// The RGB value of a color in one of the 8 palettes
+    pub fn rgb(&self, palette: u8, color: u8) -> u32 {
+        rgb555_to_rgb888(self.color(palette, color))
+    }
+}
+
+save_state_fields!(ColorPalettes {
+    ram,
+    index,
+    auto_increment
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(0x0000, 0x000000; "black")]
+    #[test_case(0x7FFF, 0xFFFFFF; "white")]
+    #[test_case(0x001F, 0xFF0000; "red")]
+    #[test_case(0x03E0, 0x00FF00; "green")]
+    #[test_case(0x7C00, 0x0000FF; "blue")]
+    #[test_case(0x0010, 0x840000; "half red")]
+    fn test_rgb555_to_rgb888(color: u16, expected: u32) {
+        assert_eq!(rgb555_to_rgb888(color), expected);
+    }
+
+    #[test]
+    fn test_color_palettes() {
+        let mut palettes = ColorPalettes::default();
+        assert_eq!(palettes.rgb(7, 3), 0xFFFFFF);
+
+        // Palette 1, color 2, auto-increment
+        palettes.write_index(0x80 | 0x0C);
+        assert_eq!(palettes.read_index(), 0xCC);
+        palettes.write_data(0x1F);
+        palettes.write_data(0x00);
+        assert_eq!(palettes.read_index(), 0xCE);
+        assert_eq!(palettes.rgb(1, 2), 0xFF0000);
+
+        // Without auto-increment the index stays put
+        palettes.write_index(0x0C);
+        palettes.write_data(0xE0);
+        assert_eq!(palettes.read_data(), 0xE0);
+        assert_eq!(palettes.read_index(), 0x4C);
+
+        // The index wraps around at the end of palette RAM
+        palettes.write_index(0x80 | 0x3F);
+        palettes.write_data(0x12);
+        assert_eq!(palettes.read_index(), 0xC0);
+    }
+
+    #[test_case("grayscale", Ok(GRAYSCALE); "grayscale")]
+    #[test_case("green", Ok(CLASSIC_GREEN); "green")]
+    #[test_case("#E0F8D0,#88c070, 346856,#081820", Ok([0xE0F8D0, 0x88C070, 0x346856, 0x081820]); "custom")]
+    #[test_case("#E0F8D0,#88C070,#346856", Err(String::from("A palette needs 4 colors (or grayscale or green), got 3")); "too few")]
+    #[test_case("#E0F8D0,#88C070,#346856,#0818", Err(String::from("\"#0818\" is not a hex color like #E0F8D0")); "short color")]
+    #[test_case("sepia", Err(String::from("\"sepia\" is not a hex color like #E0F8D0")); "unknown name")]
+    fn test_parse_shades(value: &str, expected: Result<Shades, String>) {
+        assert_eq!(parse_shades(value), expected);
+    }
+
+    #[test_case(0b11_10_01_00, 0, 0; "identity color 0")]
+    #[test_case(0b11_10_01_00, 3, 3; "identity color 3")]
+    #[test_case(0b00_01_10_11, 0, 3; "inverted color 0")]
+    #[test_case(0b00_01_10_11, 2, 1; "inverted color 2")]
+    #[test_case(0xFC, 0, 0; "boot rom color 0")]
+    #[test_case(0xFC, 1, 3; "boot rom color 1")]
+    fn test_shade(palette: u8, color: u8, expected: usize) {
+        assert_eq!(shade(palette, color), expected);
+    }
+}
@@ -0,0 +1,277 @@
+use core::fmt;
+
+use crate::ppu::tiles::{tile_address, tile_pixel, VRAM_BANK_1};
+use crate::ppu::{Palette, Pixel, SCREEN_WIDTH};
+use crate::prelude::*;
+use crate::savestate::save_state_fields;
+
+/// OAM holds 40 sprites of 4 bytes each
+pub const OAM_SPRITES: usize = 40;
+const SPRITE_SIZE: usize = 4;
+
+/// At most 10 sprites are drawn per scanline
+pub const MAX_SPRITES_PER_LINE: usize = 10;
+
+/// The columns of a sprite's Display, as it's stored in OAM
+pub const SPRITE_TABLE_HEADER: &str = " #   Y   X  tile attr";
+
+/// Sprite attribute flags
+const ATTR_BG_PRIORITY: u8 = 1 << 7;
+const ATTR_Y_FLIP: u8 = 1 << 6;
+const ATTR_X_FLIP: u8 = 1 << 5;
+const ATTR_PALETTE: u8 = 1 << 4;
+const ATTR_CGB_BANK: u8 = 1 << 3;
+const ATTR_CGB_PALETTE: u8 = 0b111;
+
+/// A sprite (object) entry in OAM
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sprite {
+    /// Screen position plus 16, so 0 is fully above the screen
+    pub y: u8,
+    /// Screen position plus 8, so 0 is fully left of the screen
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    /// Position in OAM, which breaks ties in drawing priority
+    pub index: usize,
+    /// Whether the CGB attribute bits (palette 0-7, VRAM bank) are used
+    pub cgb_mode: bool,
+}
+
+impl Sprite {
+    pub fn from_oam(oam: &[u8], index: usize, cgb_mode: bool) -> Sprite {
+        let entry = &oam[index * SPRITE_SIZE..(index + 1) * SPRITE_SIZE];
+        Sprite {
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            attributes: entry[3],
+            index,
+            cgb_mode,
+        }
+    }
+
+    pub fn palette(&self) -> Palette {
+        if self.cgb_mode {
+            Palette::CgbObj(self.attributes & ATTR_CGB_PALETTE)
+        } else if self.attributes & ATTR_PALETTE != 0 {
+            Palette::Obj1
+        } else {
+            Palette::Obj0
+        }
+    }
+
+    /// Whether the sprite is hidden behind background colors 1-3
+    pub fn has_bg_priority(&self) -> bool {
+        self.attributes & ATTR_BG_PRIORITY != 0
+    }
+
+    /// The color number of the sprite at a screen position,
+    /// or None if the sprite is transparent or not there
+    pub fn pixel(&self, vram: &[u8], screen_x: usize, ly: u8, height: u8) -> Option<u8> {
+        let left = self.x as isize - 8;
+        let column = screen_x as isize - left;
+        if !(0..8).contains(&column) {
+            return None;
+        }
+        let row = ly.wrapping_add(16).wrapping_sub(self.y);
+        match self.tile_color(vram, column as u8, row, height) {
+            0 => None, // color 0 is transparent
+            color => Some(color),
+        }
+    }
+
+    /// The color number at (column, row) from the sprite's top left
+    /// corner on the screen, after it's flipped
+    pub fn tile_color(&self, vram: &[u8], column: u8, row: u8, height: u8) -> u8 {
+        let (mut column, mut row) = (column, row);
+        if self.attributes & ATTR_X_FLIP != 0 {
+            column = 7 - column;
+        }
+        if self.attributes & ATTR_Y_FLIP != 0 {
+            row = height - 1 - row;
+        }
+
+        // 8x16 sprites are two consecutive tiles, ignoring bit 0 of the index
+        let tile = if height == 16 {
+            (self.tile & 0xFE) + row / 8
+        } else {
+            self.tile
+        };
+        let bank = if self.cgb_mode && self.attributes & ATTR_CGB_BANK != 0 {
+            VRAM_BANK_1
+        } else {
+            0
+        };
+        // Sprites always use unsigned tile addressing
+        tile_pixel(vram, bank + tile_address(tile, true), column, row % 8)
+    }
+}
+
+/// The OAM index and bytes under SPRITE_TABLE_HEADER, then what the
+/// attributes mean, eg `12  80  40   2C   60  OBP0 x-flip y-flip`
+impl fmt::Display for Sprite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:2} {:3} {:3}   {:02X}   {:02X}  ",
+            self.index, self.y, self.x, self.tile, self.attributes
+        )?;
+        let mut flags = vec![match self.palette() {
+            Palette::CgbObj(palette) => format!("OBJ{}", palette),
+            Palette::Obj1 => String::from("OBP1"),
+            _ => String::from("OBP0"),
+        }];
+        if self.cgb_mode && self.attributes & ATTR_CGB_BANK != 0 {
+            flags.push(String::from("bank1"));
+        }
+        if self.attributes & ATTR_X_FLIP != 0 {
+            flags.push(String::from("x-flip"));
+        }
+        if self.attributes & ATTR_Y_FLIP != 0 {
+            flags.push(String::from("y-flip"));
+        }
+        if self.has_bg_priority() {
+            flags.push(String::from("behind-bg"));
+        }
+        write!(f, "{}", flags.join(" "))
+    }
+}
+
+/// Whether the sprite drawing order of two sprites is decided by OAM order
+/// alone (CGB) or by X coordinate first (DMG)
+fn priority_key(sprite: &Sprite) -> (u8, usize) {
+    if sprite.cgb_mode {
+        (0, sprite.index)
+    } else {
+        (sprite.x, sprite.index)
+    }
+}
+
+/// Sort sprites so the one drawn on top comes first
+pub fn sort_by_priority(sprites: &mut [Sprite]) {
+    sprites.sort_by_key(priority_key);
+}
+
+/// Whether `sprite` is drawn over `other` where they overlap
+pub fn has_priority_over(sprite: &Sprite, other: &Sprite) -> bool {
+    priority_key(sprite) < priority_key(other)
+}
+
+/// Whether a sprite pixel is drawn over a background pixel. `master_priority`
+/// is LCDC bit 0 on the CGB; when it's clear sprites are always on top.
+pub fn sprite_over_bg(bg: Pixel, sprite_bg_priority: bool, master_priority: bool) -> bool {
+    bg.color == 0 || !master_priority || !(sprite_bg_priority || bg.priority)
+}
+
+/// The sprites on a scanline, in OAM order, up to the hardware limit
+pub fn select_sprites(oam: &[u8], ly: u8, height: u8, cgb_mode: bool) -> Vec<Sprite> {
+    (0..OAM_SPRITES)
+        .map(|index| Sprite::from_oam(oam, index, cgb_mode))
+        .filter(|sprite| {
+            let top = ly as i16 + 16 - sprite.y as i16;
+            0 <= top && top < height as i16
+        })
+        .take(MAX_SPRITES_PER_LINE)
+        .collect()
+}
+
+/// Draw the sprites over a line of background pixels.
+/// On the DMG, the sprite with the smaller X coordinate is drawn on top;
+/// when X is equal the one that comes first in OAM wins.
+/// On the CGB only the OAM order counts.
+pub fn render_sprites(
+    line: &mut [Pixel],
+    vram: &[u8],
+    mut sprites: Vec<Sprite>,
+    ly: u8,
+    height: u8,
+    master_priority: bool,
+) {
+    sort_by_priority(&mut sprites);
+
+    for (screen_x, pixel) in line.iter_mut().enumerate().take(SCREEN_WIDTH) {
+        let topmost = sprites.iter().find_map(|sprite| {
+            sprite
+                .pixel(vram, screen_x, ly, height)
+                .map(|color| (sprite, color))
+        });
+        if let Some((sprite, color)) = topmost {
+            if !sprite_over_bg(*pixel, sprite.has_bg_priority(), master_priority) {
+                continue;
+            }
+            *pixel = Pixel {
+                color,
+                palette: sprite.palette(),
+                priority: false,
+            };
+        }
+    }
+}
+
+save_state_fields!(Sprite {
+    y,
+    x,
+    tile,
+    attributes,
+    index,
+    cgb_mode,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn oam_with(sprites: &[(u8, u8, u8, u8)]) -> Vec<u8> {
+        let mut oam = vec![0; OAM_SPRITES * SPRITE_SIZE];
+        for (index, (y, x, tile, attributes)) in sprites.iter().enumerate() {
+            oam[index * SPRITE_SIZE..(index + 1) * SPRITE_SIZE].copy_from_slice(&[
+                *y,
+                *x,
+                *tile,
+                *attributes,
+            ]);
+        }
+        oam
+    }
+
+    #[test_case(16, 0, true; "top row")]
+    #[test_case(16, 7, true; "bottom row")]
+    #[test_case(16, 8, false; "below")]
+    #[test_case(17, 0, false; "above")]
+    #[test_case(0, 0, false; "hidden above the screen")]
+    fn test_select_sprites_8x8(y: u8, ly: u8, selected: bool) {
+        let oam = oam_with(&[(y, 8, 0, 0)]);
+        assert_eq!(select_sprites(&oam, ly, 8, false).len(), selected as usize);
+    }
+
+    #[test]
+    fn test_select_sprites_8x16() {
+        let oam = oam_with(&[(16, 8, 0, 0)]);
+        assert_eq!(select_sprites(&oam, 15, 16, false).len(), 1);
+        assert_eq!(select_sprites(&oam, 16, 16, false).len(), 0);
+    }
+
+    #[test]
+    fn test_display() {
+        let oam = oam_with(&[(80, 40, 0x2C, 0x70), (16, 8, 1, 0x8B)]);
+        assert_eq!(
+            Sprite::from_oam(&oam, 0, false).to_string(),
+            " 0  80  40   2C   70  OBP1 x-flip y-flip"
+        );
+        assert_eq!(
+            Sprite::from_oam(&oam, 1, true).to_string(),
+            " 1  16   8   01   8B  OBJ3 bank1 behind-bg"
+        );
+    }
+
+    #[test]
+    fn test_select_sprites_limit() {
+        let oam = oam_with(&[(16, 8, 0, 0); 12]);
+        let sprites = select_sprites(&oam, 0, 8, false);
+        assert_eq!(sprites.len(), MAX_SPRITES_PER_LINE);
+        // The first 10 in OAM order are picked
+        assert_eq!(sprites.last().unwrap().index, 9);
+    }
+}
@@ -0,0 +1,131 @@
+// Helpers for decoding tile data from VRAM (as offsets from 0x8000)
+
+use crate::savestate::save_state_fields;
+
+/// Each tile is 8x8 pixels, 2 bits per pixel, 16 bytes
+pub const TILE_SIZE: usize = 16;
+
+/// Tile maps are 32x32 tile indices
+pub const TILE_MAP_WIDTH: usize = 32;
+pub const TILE_MAP_0: usize = 0x1800; // 0x9800
+pub const TILE_MAP_1: usize = 0x1C00; // 0x9C00
+
+/// Offset of VRAM bank 1, which the CGB uses for more tile data
+/// and for the attributes of each tile map entry
+pub const VRAM_BANK_1: usize = 0x2000;
+
+/// CGB tile map attribute bits
+const ATTR_PALETTE: u8 = 0b111;
+const ATTR_BANK: u8 = 1 << 3;
+const ATTR_X_FLIP: u8 = 1 << 5;
+const ATTR_Y_FLIP: u8 = 1 << 6;
+const ATTR_PRIORITY: u8 = 1 << 7;
+
+/// The attributes of a CGB tile map entry, stored in VRAM bank 1
+/// at the same offset as the tile number
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TileAttributes {
+    /// Background palette 0-7
+    pub palette: u8,
+    /// Offset of the VRAM bank holding the tile data
+    pub bank: usize,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    /// Draw the tile over sprites (unless its color is 0)
+    pub priority: bool,
+}
+
+impl TileAttributes {
+    pub fn from_byte(byte: u8) -> TileAttributes {
+        TileAttributes {
+            palette: byte & ATTR_PALETTE,
+            bank: if byte & ATTR_BANK != 0 {
+                VRAM_BANK_1
+            } else {
+                0
+            },
+            x_flip: byte & ATTR_X_FLIP != 0,
+            y_flip: byte & ATTR_Y_FLIP != 0,
+            priority: byte & ATTR_PRIORITY != 0,
+        }
+    }
+
+    /// The pixel of the tile to draw at (x, y), after flipping
+    pub fn flip(&self, x: u8, y: u8) -> (u8, u8) {
+        (
+            if self.x_flip { 7 - x } else { x },
+            if self.y_flip { 7 - y } else { y },
+        )
+    }
+}
+
+/// Offset of tile data for a tile index. With unsigned addressing
+/// tiles 0-255 are at 0x8000-0x8FFF; with signed addressing
+/// tiles -128-127 are at 0x8800-0x97FF, with tile 0 at 0x9000.
+pub fn tile_address(index: u8, unsigned_addressing: bool) -> usize {
+    if unsigned_addressing {
+        index as usize * TILE_SIZE
+    } else {
+        (0x1000 + (index as i8 as isize) * TILE_SIZE as isize) as usize
+    }
+}
+
+/// The 2-bit color number of a pixel in a tile.
+/// Each row is 2 bytes: the low bits of the 8 pixels, then the high bits.
+pub fn tile_pixel(vram: &[u8], tile_address: usize, x: u8, y: u8) -> u8 {
+    let row = tile_address + y as usize * 2;
+    let low = vram[row];
+    let high = vram[row + 1];
+    let bit = 7 - x;
+    (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+}
+
+save_state_fields!(TileAttributes {
+    palette,
+    bank,
+    x_flip,
+    y_flip,
+    priority,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(0, true, 0x0000; "first unsigned tile")]
+    #[test_case(255, true, 0x0FF0; "last unsigned tile")]
+    #[test_case(0, false, 0x1000; "signed tile zero")]
+    #[test_case(127, false, 0x17F0; "last signed tile")]
+    #[test_case(128, false, 0x0800; "signed tile -128")]
+    #[test_case(255, false, 0x0FF0; "signed tile -1")]
+    fn test_tile_address(index: u8, unsigned_addressing: bool, expected: usize) {
+        assert_eq!(tile_address(index, unsigned_addressing), expected);
+    }
+
+    #[test]
+    fn test_tile_pixel() {
+        // The example tile from the Pan Docs, first row: 0x3C 0x7E
+        // -> colors 0 2 3 3 3 3 2 0
+        let vram = [0x3C, 0x7E];
+        let row: Vec<u8> = (0..8).map(|x| tile_pixel(&vram, 0, x, 0)).collect();
+        assert_eq!(row, vec![0, 2, 3, 3, 3, 3, 2, 0]);
+    }
+
+    #[test]
+    fn test_tile_attributes() {
+        let attributes = TileAttributes::from_byte(0b1010_1101);
+        assert_eq!(
+            attributes,
+            TileAttributes {
+                palette: 5,
+                bank: VRAM_BANK_1,
+                x_flip: true,
+                y_flip: false,
+                priority: true,
+            }
+        );
+        assert_eq!(attributes.flip(1, 2), (6, 2));
+        assert_eq!(TileAttributes::from_byte(0x40).flip(1, 2), (1, 5));
+    }
+}
@@ -0,0 +1,459 @@
+// Debug views of what the PPU draws from, as images a frontend can show in
+// a window of their own. They're drawn from scratch with the palettes the
+// PPU is using now, so showing them every frame keeps them up to date.
+//
+// The VRAM view has the tile data as the background sees it with each
+// addressing mode, then the tile maps at 0x9800 and 0x9C00:
+//
+//   | tiles 0x8000 | map 0x9800 | map 0x9C00 |
+//   | tiles 0x8800 |            |            |
+//
+// The OAM view has the 40 sprites in OAM order, 8 to a row, each drawn
+// bigger with its own palette and flips.
+//
+// The palette view has a swatch for each color of BGP, OBP0 and OBP1, then
+// on the CGB the 8 background and 8 sprite palettes in their own columns.
+
+use core::fmt::Write;
+
+use crate::ppu::palette::shade;
+use crate::ppu::sprites::{Sprite, OAM_SPRITES};
+use crate::ppu::tiles::{tile_address, tile_pixel, TileAttributes, TILE_MAP_0, TILE_MAP_1};
+use crate::ppu::tiles::{TILE_MAP_WIDTH, VRAM_BANK_1};
+use crate::ppu::{
+    Palette, Pixel, Ppu, LCDC_BG_TILE_MAP, LCDC_TILE_DATA, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+use crate::prelude::*;
+
+/// Tiles across and down a tile sheet
+const SHEET_TILES: usize = 16;
+const SHEET_SIZE: usize = SHEET_TILES * 8;
+/// A tile map is 256x256 pixels
+const MAP_SIZE: usize = TILE_MAP_WIDTH * 8;
+/// Space around the parts of a view
+const GAP: usize = 4;
+/// Sprites across a row of the OAM view
+const OAM_VIEW_COLUMNS: usize = 8;
+/// How many times bigger the OAM view draws sprites
+const SPRITE_SCALE: usize = 4;
+/// The width and height of a color in the palette view
+const SWATCH_SIZE: usize = 16;
+/// The CGB has 8 background and 8 sprite palettes
+const CGB_PALETTES: u8 = 8;
+
+const BACKGROUND: u32 = 0x404040;
+/// Outlines the part of the background on the screen
+const VIEWPORT: u32 = 0xFF0000;
+
+/// Pixels as 0x00RRGGBB, row by row, like the screen
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![BACKGROUND; width * height],
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: u32) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Copy `image` in with its top left corner at (x, y)
+    pub fn draw(&mut self, image: &Image, x: usize, y: usize) {
+        for row in 0..image.height {
+            let start = (y + row) * self.width + x;
+            self.pixels[start..start + image.width]
+                .copy_from_slice(&image.pixels[row * image.width..(row + 1) * image.width]);
+        }
+    }
+}
+
+impl Ppu {
+    /// The 256 tiles a tile map can pick with the addressing mode, in the
+    /// order of their numbers, from VRAM bank 0 or bank 1 at `bank`.
+    /// They're drawn with the first background palette.
+    pub fn tile_sheet(&self, vram: &[u8], bank: usize, unsigned_addressing: bool) -> Image {
+        let palette = self.bg_palette(&TileAttributes::default());
+        let mut image = Image::new(SHEET_SIZE, SHEET_SIZE);
+        for index in 0..=255u8 {
+            let tile = bank + tile_address(index, unsigned_addressing);
+            let left = index as usize % SHEET_TILES * 8;
+            let top = index as usize / SHEET_TILES * 8;
+            for y in 0..8 {
+                for x in 0..8 {
+                    let color = tile_pixel(vram, tile, x, y);
+                    let pixel = Pixel {
+                        color,
+                        palette,
+                        priority: false,
+                    };
+                    image.set(left + x as usize, top + y as usize, self.resolve(pixel));
+                }
+            }
+        }
+        image
+    }
+
+    /// The tile map at `map` drawn whole, as the background would be with
+    /// the addressing mode in LCDC. If it's the background's map, the part
+    /// on the screen is outlined.
+    pub fn tile_map(&self, vram: &[u8], map: usize) -> Image {
+        let unsigned_addressing = self.lcdc & LCDC_TILE_DATA != 0;
+        let mut image = Image::new(MAP_SIZE, MAP_SIZE);
+        for y in 0..MAP_SIZE {
+            for x in 0..MAP_SIZE {
+                let map_address = map + (y / 8) * TILE_MAP_WIDTH + x / 8;
+                let attributes = self.tile_attributes(vram, map_address);
+                let tile = attributes.bank + tile_address(vram[map_address], unsigned_addressing);
+                let (tile_x, tile_y) = attributes.flip(x as u8 % 8, y as u8 % 8);
+                let pixel = Pixel {
+                    color: tile_pixel(vram, tile, tile_x, tile_y),
+                    palette: self.bg_palette(&attributes),
+                    priority: attributes.priority,
+                };
+                image.set(x, y, self.resolve(pixel));
+            }
+        }
+
+        let bg_map = if self.lcdc & LCDC_BG_TILE_MAP != 0 {
+            TILE_MAP_1
+        } else {
+            TILE_MAP_0
+        };
+        if map == bg_map {
+            // The screen wraps around the edges of the map like the background does
+            let (scx, scy) = (self.scx as usize, self.scy as usize);
+            for x in 0..SCREEN_WIDTH {
+                image.set((scx + x) % MAP_SIZE, scy, VIEWPORT);
+                image.set(
+                    (scx + x) % MAP_SIZE,
+                    (scy + SCREEN_HEIGHT - 1) % MAP_SIZE,
+                    VIEWPORT,
+                );
+            }
+            for y in 0..SCREEN_HEIGHT {
+                image.set(scx, (scy + y) % MAP_SIZE, VIEWPORT);
+                image.set(
+                    (scx + SCREEN_WIDTH - 1) % MAP_SIZE,
+                    (scy + y) % MAP_SIZE,
+                    VIEWPORT,
+                );
+            }
+        }
+        image
+    }
+
+    /// The tile data with both addressing modes and both tile maps, side
+    /// by side. On the CGB the tile data in VRAM bank 1 is in a second column.
+    pub fn vram_view(&self, vram: &[u8]) -> Image {
+        let banks: &[usize] = if self.cgb_mode {
+            &[0, VRAM_BANK_1]
+        } else {
+            &[0]
+        };
+        let sheets_width = banks.len() * (SHEET_SIZE + GAP);
+        let mut image = Image::new(
+            GAP + sheets_width + 2 * (MAP_SIZE + GAP),
+            GAP + 2 * (SHEET_SIZE + GAP),
+        );
+        for (column, bank) in banks.iter().enumerate() {
+            let x = GAP + column * (SHEET_SIZE + GAP);
+            image.draw(&self.tile_sheet(vram, *bank, true), x, GAP);
+            image.draw(
+                &self.tile_sheet(vram, *bank, false),
+                x,
+                2 * GAP + SHEET_SIZE,
+            );
+        }
+        for (column, map) in [TILE_MAP_0, TILE_MAP_1].iter().enumerate() {
+            let x = GAP + sheets_width + column * (MAP_SIZE + GAP);
+            image.draw(&self.tile_map(vram, *map), x, GAP);
+        }
+        image
+    }
+
+    /// All 40 sprites in OAM, in OAM order
+    pub fn sprites(&self, oam: &[u8]) -> Vec<Sprite> {
+        (0..OAM_SPRITES)
+            .map(|index| Sprite::from_oam(oam, index, self.cgb_mode))
+            .collect()
+    }
+
+    /// A sprite drawn with its palette and flips, SPRITE_SCALE times bigger.
+    /// Its transparent pixels are left as the background.
+    pub fn sprite_image(&self, vram: &[u8], sprite: &Sprite) -> Image {
+        let height = self.sprite_height();
+        let mut image = Image::new(8 * SPRITE_SCALE, height as usize * SPRITE_SCALE);
+        for row in 0..height {
+            for column in 0..8 {
+                let color = match sprite.tile_color(vram, column, row, height) {
+                    0 => continue,
+                    color => color,
+                };
+                let pixel = Pixel {
+                    color,
+                    palette: sprite.palette(),
+                    priority: false,
+                };
+                let rgb = self.resolve(pixel);
+                for y in 0..SPRITE_SCALE {
+                    for x in 0..SPRITE_SCALE {
+                        let (x, y) = (
+                            column as usize * SPRITE_SCALE + x,
+                            row as usize * SPRITE_SCALE + y,
+                        );
+                        image.set(x, y, rgb);
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// Every sprite in OAM, OAM_VIEW_COLUMNS to a row. Each has room
+    /// for an 8x16 sprite, so the view stays the same size when LCDC
+    /// switches sprite sizes.
+    pub fn oam_view(&self, vram: &[u8], oam: &[u8]) -> Image {
+        let (cell_width, cell_height) = (8 * SPRITE_SCALE + GAP, 16 * SPRITE_SCALE + GAP);
+        let rows = OAM_SPRITES / OAM_VIEW_COLUMNS;
+        let mut image = Image::new(
+            GAP + OAM_VIEW_COLUMNS * cell_width,
+            GAP + rows * cell_height,
+        );
+        for sprite in self.sprites(oam) {
+            let x = GAP + sprite.index % OAM_VIEW_COLUMNS * cell_width;
+            let y = GAP + sprite.index / OAM_VIEW_COLUMNS * cell_height;
+            image.draw(&self.sprite_image(vram, &sprite), x, y);
+        }
+        image
+    }
+}
+
+/// A row of swatches, one for each of a palette's 4 colors
+fn swatches(colors: [u32; 4]) -> Image {
+    let mut image = Image::new(4 * SWATCH_SIZE, SWATCH_SIZE);
+    for (index, color) in colors.iter().enumerate() {
+        for y in 0..SWATCH_SIZE {
+            // A line between colors, so two the same can be told apart
+            for x in 0..SWATCH_SIZE - 1 {
+                image.set(index * SWATCH_SIZE + x, y, *color);
+            }
+        }
+    }
+    image
+}
+
+impl Ppu {
+    /// The colors of a palette, looked up like a pixel's
+    fn palette_colors(&self, palette: Palette) -> [u32; 4] {
+        [0, 1, 2, 3].map(|color| {
+            self.resolve(Pixel {
+                color,
+                palette,
+                priority: false,
+            })
+        })
+    }
+
+    /// Swatches of the colors of every palette: BGP, OBP0 and OBP1, then
+    /// the CGB's background palettes and sprite palettes in two more columns
+    pub fn palette_view(&self) -> Image {
+        let columns: Vec<Vec<Palette>> = if self.cgb_mode {
+            vec![
+                vec![Palette::Bg, Palette::Obj0, Palette::Obj1],
+                (0..CGB_PALETTES).map(Palette::CgbBg).collect(),
+                (0..CGB_PALETTES).map(Palette::CgbObj).collect(),
+            ]
+        } else {
+            vec![vec![Palette::Bg, Palette::Obj0, Palette::Obj1]]
+        };
+        let (cell_width, cell_height) = (4 * SWATCH_SIZE + GAP, SWATCH_SIZE + GAP);
+        let rows = columns.iter().map(Vec::len).max().unwrap_or(0);
+        let mut image = Image::new(GAP + columns.len() * cell_width, GAP + rows * cell_height);
+        for (column, palettes) in columns.iter().enumerate() {
+            for (row, palette) in palettes.iter().enumerate() {
+                let colors = swatches(self.palette_colors(*palette));
+                image.draw(&colors, GAP + column * cell_width, GAP + row * cell_height);
+            }
+        }
+        image
+    }
+
+    /// The palette registers as they're stored: BGP, OBP0 and OBP1 with
+    /// the shade each color number gets, then the CGB palettes in RGB555
+    pub fn palette_table(&self) -> String {
+        let mut table = String::new();
+        for (name, value) in [("BGP ", self.bgp), ("OBP0", self.obp0), ("OBP1", self.obp1)] {
+            let shades: Vec<String> = (0..4)
+                .map(|color| shade(value, color).to_string())
+                .collect();
+            writeln!(table, "{} {:02X}  shades {}", name, value, shades.join(" ")).unwrap();
+        }
+        if self.cgb_mode {
+            for (name, palettes) in [("BG", &self.bg_palettes), ("OBJ", &self.obj_palettes)] {
+                for palette in 0..CGB_PALETTES {
+                    let colors: Vec<String> = (0..4)
+                        .map(|color| format!("{:04X}", palettes.color(palette, color)))
+                        .collect();
+                    let name = format!("{}{}", name, palette);
+                    writeln!(table, "{:4} {}", name, colors.join(" ")).unwrap();
+                }
+            }
+        }
+        table.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::palette::GRAYSCALE;
+    use crate::ppu::palette::{BCPD, BCPS, OBP1, OCPD, OCPS};
+    use crate::ppu::tests::{oam, vram, write_tile};
+    use crate::ppu::tiles::TILE_SIZE;
+    use crate::ppu::{BGP, LCDC, SCX, SCY};
+
+    impl Image {
+        fn pixel(&self, x: usize, y: usize) -> u32 {
+            self.pixels[y * self.width + x]
+        }
+    }
+
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0b11_10_01_00);
+        ppu
+    }
+
+    #[test]
+    fn test_tile_sheet() {
+        let ppu = ppu();
+        let mut vram = vram();
+        // Tile 1 with unsigned addressing is color 3
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        // Tile 1 with signed addressing is color 1
+        write_tile(&mut vram, 0x1000 + TILE_SIZE, 0xFF, 0x00);
+        // Tile 0x80 with signed addressing is at 0x8800
+        write_tile(&mut vram, 0x0800, 0x00, 0xFF);
+
+        let unsigned = ppu.tile_sheet(&vram, 0, true);
+        assert_eq!(unsigned.pixel(0, 0), GRAYSCALE[0]);
+        assert_eq!(unsigned.pixel(8, 7), GRAYSCALE[3]);
+        let signed = ppu.tile_sheet(&vram, 0, false);
+        assert_eq!(signed.pixel(8, 7), GRAYSCALE[1]);
+        assert_eq!(signed.pixel(0, 8 * 8), GRAYSCALE[2]);
+    }
+
+    #[test]
+    fn test_tile_map() {
+        let mut ppu = ppu();
+        ppu.write(LCDC, 0x91);
+        ppu.write(SCX, 250);
+        ppu.write(SCY, 8);
+        let mut vram = vram();
+        write_tile(&mut vram, TILE_SIZE, 0xFF, 0xFF);
+        // The second tile in the second row of the map at 0x9C00
+        vram[TILE_MAP_1 + TILE_MAP_WIDTH + 1] = 1;
+
+        let map = ppu.tile_map(&vram, TILE_MAP_1);
+        assert_eq!(map.pixel(8, 8), GRAYSCALE[3]);
+        assert_eq!(map.pixel(8, 16), GRAYSCALE[0]);
+        // Not the background's map, so there's no outline
+        assert_eq!(map.pixel(250, 8), GRAYSCALE[0]);
+
+        let map = ppu.tile_map(&vram, TILE_MAP_0);
+        assert_eq!(map.pixel(250, 8), VIEWPORT);
+        // Wrapped around to the left edge
+        assert_eq!(map.pixel(10, 8), VIEWPORT);
+        assert_eq!(map.pixel(153, 151), VIEWPORT);
+        assert_eq!(map.pixel(154, 151), GRAYSCALE[0]);
+    }
+
+    #[test]
+    fn test_oam_view() {
+        let mut ppu = ppu();
+        ppu.write(OBP1, 0b00_01_10_11);
+        let mut vram = vram();
+        // Color 1 on the left half of the tile and color 3 on the right
+        write_tile(&mut vram, 2 * TILE_SIZE, 0xFF, 0x0F);
+        let mut oam = oam();
+        // The second sprite is tile 2, flipped across, with OBP1
+        oam[4..8].copy_from_slice(&[16, 8, 2, 0x30]);
+
+        let view = ppu.oam_view(&vram, &oam);
+        assert_eq!((view.width, view.height), (GAP + 8 * 36, GAP + 5 * 68));
+        // The first sprite is tile 0, which is all transparent
+        assert_eq!(view.pixel(GAP, GAP), BACKGROUND);
+        let left = GAP + 36;
+        // Color 3 through OBP1 is shade 0, and the sprite is flipped
+        assert_eq!(view.pixel(left, GAP), GRAYSCALE[0]);
+        assert_eq!(view.pixel(left + 31, GAP + 31), GRAYSCALE[2]);
+        // An 8x8 sprite leaves the bottom half of its cell empty
+        assert_eq!(view.pixel(left, GAP + 32), BACKGROUND);
+    }
+
+    #[test]
+    fn test_palette_view() {
+        let view = ppu().palette_view();
+        assert_eq!((view.width, view.height), (GAP + 68, GAP + 3 * 20));
+        // BGP's colors are each of the shades, OBP0 is all shade 0
+        assert_eq!(view.pixel(GAP + 3 * SWATCH_SIZE, GAP), GRAYSCALE[3]);
+        assert_eq!(view.pixel(GAP + 3 * SWATCH_SIZE, GAP + 20), GRAYSCALE[0]);
+        // The line between two colors
+        assert_eq!(view.pixel(GAP + SWATCH_SIZE - 1, GAP), BACKGROUND);
+
+        let mut ppu = Ppu::new_cgb();
+        ppu.write(OCPS, 0x80 | (7 * 8 + 3 * 2));
+        ppu.write(OCPD, 0x1F);
+        ppu.write(OCPD, 0x00);
+        let view = ppu.palette_view();
+        assert_eq!((view.width, view.height), (GAP + 3 * 68, GAP + 8 * 20));
+        assert_eq!(
+            view.pixel(GAP + 2 * 68 + 3 * SWATCH_SIZE, GAP + 7 * 20),
+            0xFF0000
+        );
+    }
+
+    #[test]
+    fn test_palette_table() {
+        let mut ppu = ppu();
+        ppu.write(OBP1, 0b00_01_10_11);
+        assert_eq!(
+            ppu.palette_table(),
+            "BGP  E4  shades 0 1 2 3\n\
+             OBP0 00  shades 0 0 0 0\n\
+             OBP1 1B  shades 3 2 1 0"
+        );
+
+        let mut ppu = Ppu::new_cgb();
+        ppu.write(BCPS, 0x80 | 2);
+        ppu.write(BCPD, 0x1F);
+        ppu.write(BCPD, 0x00);
+        let table = ppu.palette_table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3 + 16);
+        assert_eq!(lines[3], "BG0  FFFF 001F FFFF FFFF");
+        assert_eq!(lines[18], "OBJ7 FFFF FFFF FFFF FFFF");
+    }
+
+    #[test]
+    fn test_vram_view() {
+        let view = ppu().vram_view(&vram());
+        assert_eq!((view.width, view.height), (GAP + 132 + 2 * 260, 268));
+        assert_eq!(view.pixel(0, 0), BACKGROUND);
+        assert_eq!(view.pixel(GAP, GAP), GRAYSCALE[0]);
+
+        let mut vram = vram();
+        vram.resize(2 * VRAM_BANK_1, 0);
+        let view = Ppu::new_cgb().vram_view(&vram);
+        assert_eq!(view.width, GAP + 2 * 132 + 2 * 260);
+    }
+}
@@ -0,0 +1,176 @@
+// Counts where the CPU spends its time: the T-cycles of every instruction
+// go to its address, and to the subroutine it's in according to the shadow
+// call stack. Subroutines get both their own time and their total, which
+// takes in the subroutines they call. The report lists the hottest of each.
+
+use alloc::collections::BTreeMap;
+use core::fmt::Write;
+
+use crate::callstack::CallStack;
+use crate::prelude::*;
+
+/// How many lines each table of the report has
+const TOP: usize = 20;
+
+/// What the CPU runs outside any subroutine is counted under this
+const TOP_LEVEL: &str = "top level";
+
+pub struct Profiler {
+    /// T-cycles by the address of the instruction
+    by_address: Vec<u64>,
+    /// T-cycles by the subroutine's entry, or None for the top level,
+    /// leaving out the subroutines it calls
+    own: BTreeMap<Option<u16>, u64>,
+    /// The same, but taking in the subroutines it calls
+    total: BTreeMap<Option<u16>, u64>,
+    cycles: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler {
+            by_address: vec![0; 0x10000],
+            own: BTreeMap::new(),
+            total: BTreeMap::new(),
+            cycles: 0,
+        }
+    }
+}
+
+fn name(entry: Option<u16>) -> String {
+    match entry {
+        Some(entry) => format!("{:04X}", entry),
+        None => String::from(TOP_LEVEL),
+    }
+}
+
+impl Profiler {
+    /// Count an instruction at `pc`, run inside the subroutines on `call_stack`
+    pub fn record(&mut self, pc: u16, cycles: u32, call_stack: &CallStack) {
+        let cycles = cycles as u64;
+        self.cycles += cycles;
+        self.by_address[pc as usize] += cycles;
+        *self
+            .own
+            .entry(call_stack.current().map(|frame| frame.entry))
+            .or_default() += cycles;
+
+        // A subroutine that's on the stack more than once, from recursion,
+        // only counts once
+        let mut entries: Vec<Option<u16>> = call_stack
+            .frames()
+            .iter()
+            .map(|frame| Some(frame.entry))
+            .collect();
+        entries.push(None);
+        entries.sort_unstable();
+        entries.dedup();
+        for entry in entries {
+            *self.total.entry(entry).or_default() += cycles;
+        }
+    }
+
+    fn percent(&self, cycles: u64) -> f64 {
+        100.0 * cycles as f64 / self.cycles.max(1) as f64
+    }
+
+    /// The hottest addresses and subroutines, as a table for people to read
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        writeln!(report, "{} T-cycles profiled", self.cycles).unwrap();
+
+        let mut addresses: Vec<(usize, u64)> = self
+            .by_address
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, cycles)| *cycles > 0)
+            .collect();
+        // Hottest first, then by address
+        addresses.sort_by_key(|(address, cycles)| (u64::MAX - cycles, *address));
+        writeln!(report, "\nHottest addresses").unwrap();
+        writeln!(report, "{:>12} {:>7}  address", "cycles", "%").unwrap();
+        for (address, cycles) in addresses.into_iter().take(TOP) {
+            writeln!(
+                report,
+                "{:>12} {:>6.2}%  {:04X}",
+                cycles,
+                self.percent(cycles),
+                address
+            )
+            .unwrap();
+        }
+
+        let mut subroutines: Vec<(Option<u16>, u64)> =
+            self.own.iter().map(|(entry, own)| (*entry, *own)).collect();
+        subroutines.sort_by_key(|(entry, own)| (u64::MAX - own, *entry));
+        writeln!(report, "\nHottest subroutines").unwrap();
+        writeln!(
+            report,
+            "{:>12} {:>7} {:>12} {:>7}  subroutine",
+            "own", "%", "total", "%"
+        )
+        .unwrap();
+        for (entry, own) in subroutines.into_iter().take(TOP) {
+            let total = self.total.get(&entry).copied().unwrap_or(own);
+            writeln!(
+                report,
+                "{:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                own,
+                self.percent(own),
+                total,
+                self.percent(total),
+                name(entry)
+            )
+            .unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_core::cpu::Registers;
+
+    fn at(pc: u16, sp: u16) -> Registers {
+        Registers {
+            pc,
+            sp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let mut profiler = Profiler::default();
+        let mut call_stack = CallStack::default();
+        profiler.record(0x0150, 4, &call_stack);
+        profiler.record(0x0151, 24, &call_stack);
+        // CALL 0x4000
+        call_stack.update(&at(0x0151, 0xFFFE), 0xCD, &at(0x4000, 0xFFFC));
+        profiler.record(0x4000, 8, &call_stack);
+        profiler.record(0x4000, 8, &call_stack);
+        profiler.record(0x4001, 16, &call_stack);
+
+        let report = profiler.report();
+        assert!(report.starts_with("60 T-cycles profiled\n"), "{}", report);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines[4].split_whitespace().collect::<Vec<_>>(),
+            ["24", "40.00%", "0151"]
+        );
+        assert_eq!(
+            lines[5].split_whitespace().collect::<Vec<_>>(),
+            ["16", "26.67%", "4000"]
+        );
+        assert_eq!(
+            lines[11].split_whitespace().collect::<Vec<_>>(),
+            ["32", "53.33%", "32", "53.33%", "4000"]
+        );
+        assert_eq!(
+            lines[12].split_whitespace().collect::<Vec<_>>(),
+            ["28", "46.67%", "60", "100.00%", "top", "level"]
+        );
+    }
+}
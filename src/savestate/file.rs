@@ -0,0 +1,261 @@
+// The save state container. After the magic bytes and the format version,
+// each part of the machine is stored in its own chunk: a 4-byte ID, a length
+// and the part's bytes. A part can change what it saves without touching
+// the others, and when it does the version is bumped and a migration added
+// to upgrade the older states, so they still load.
+//
+// | magic (8) | version (u32) | id (4) | length (u32) | bytes | id | ...
+
+use core::convert::TryInto;
+
+use crate::prelude::*;
+use crate::savestate::{take, SaveState};
+
+/// Written at the start of every save state
+const MAGIC: &[u8; 8] = b"RGB-SAVE";
+/// How save states started before they had a version
+const UNVERSIONED_MAGIC: &[u8; 8] = b"RGBSTATE";
+
+/// The version this build writes
+pub const VERSION: u32 = 2;
+
+/// Upgrades a state by one version
+type Migration = fn(&mut StateFile) -> Result<(), String>;
+
+/// The migration from each version to the next, starting from version 1.
+/// There's one fewer than VERSION.
+const MIGRATIONS: [Migration; VERSION as usize - 1] = [drop_register_count];
+
+/// Version 2 keeps the CPU's registers in a struct rather than a Vec, so
+/// they're saved without a count before them
+fn drop_register_count(file: &mut StateFile) -> Result<(), String> {
+    let mut cpu = file
+        .chunk(b"CPU ")
+        .ok_or_else(|| String::from("The save state has no CPU chunk"))?
+        .clone();
+    // The cycle count comes first, then how many registers there are
+    let mut count = 0usize;
+    count.load(&mut cpu.get(2..10).unwrap_or_default())?;
+    if count != 6 {
+        return Err(format!(
+            "Expected 6 registers in the CPU chunk, found {}",
+            count
+        ));
+    }
+    cpu.drain(2..10);
+    file.set_chunk(b"CPU ", cpu);
+    Ok(())
+}
+
+pub type ChunkId = [u8; 4];
+
+#[derive(Clone)]
+pub struct StateFile {
+    pub version: u32,
+    chunks: Vec<(ChunkId, Vec<u8>)>,
+}
+
+impl Default for StateFile {
+    fn default() -> Self {
+        StateFile {
+            version: VERSION,
+            chunks: Vec::new(),
+        }
+    }
+}
+
+/// A chunk ID as text for errors, eg `PPU `
+fn name(id: &ChunkId) -> String {
+    String::from_utf8_lossy(id).trim_end().to_string()
+}
+
+impl StateFile {
+    /// Save `part` in the chunk `id`
+    pub fn add(&mut self, id: &ChunkId, part: &impl SaveState) {
+        let mut bytes = Vec::new();
+        part.save(&mut bytes);
+        self.chunks.push((*id, bytes));
+    }
+
+    /// The bytes saved in the chunk `id`, if there is one
+    pub fn chunk(&self, id: &ChunkId) -> Option<&Vec<u8>> {
+        self.chunks
+            .iter()
+            .find(|(chunk_id, _)| chunk_id == id)
+            .map(|(_, bytes)| bytes)
+    }
+
+    /// Replace the bytes in the chunk `id`, or add it
+    pub fn set_chunk(&mut self, id: &ChunkId, bytes: Vec<u8>) {
+        match self.chunks.iter_mut().find(|(chunk_id, _)| chunk_id == id) {
+            Some((_, chunk)) => *chunk = bytes,
+            None => self.chunks.push((*id, bytes)),
+        }
+    }
+
+    /// Load `part` from the chunk `id`, which it must use all of
+    pub fn load(&self, id: &ChunkId, part: &mut impl SaveState) -> Result<(), String> {
+        let bytes = self
+            .chunk(id)
+            .ok_or_else(|| format!("The save state has no {} chunk", name(id)))?;
+        let mut input = &bytes[..];
+        part.load(&mut input)
+            .map_err(|e| format!("{} chunk: {}", name(id), e))?;
+        if !input.is_empty() {
+            return Err(format!(
+                "{} chunk: {} bytes left over",
+                name(id),
+                input.len()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        self.version.save(&mut out);
+        for (id, bytes) in &self.chunks {
+            out.extend_from_slice(id);
+            (bytes.len() as u32).save(&mut out);
+            out.extend_from_slice(bytes);
+        }
+        out
+    }
+
+    /// Read a save state written by this build or an older one,
+    /// upgrading it to the current version
+    pub fn from_bytes(bytes: &[u8]) -> Result<StateFile, String> {
+        let mut input = bytes;
+        match take(&mut input, MAGIC.len()) {
+            Ok(magic) if magic == MAGIC => {}
+            Ok(magic) if magic == UNVERSIONED_MAGIC => {
+                return Err(String::from(
+                    "The save state is from before states had a version, \
+                     and can't be loaded any more",
+                ))
+            }
+            _ => return Err(String::from("Not a save state")),
+        }
+        let mut file = StateFile::default();
+        file.version.load(&mut input)?;
+        if file.version == 0 || file.version > VERSION {
+            return Err(format!(
+                "The save state is version {}, but this build only reads versions 1 to {}",
+                file.version, VERSION
+            ));
+        }
+        while !input.is_empty() {
+            let id: ChunkId = take(&mut input, 4)?.try_into().unwrap();
+            let mut len = 0u32;
+            len.load(&mut input)?;
+            let bytes = take(&mut input, len as usize)?.to_vec();
+            file.chunks.push((id, bytes));
+        }
+
+        for migrate in &MIGRATIONS[file.version as usize - 1..] {
+            migrate(&mut file)?;
+            file.version += 1;
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_core::cpu::Cpu;
+
+    fn file() -> StateFile {
+        let mut file = StateFile::default();
+        file.add(b"ONE ", &1u8);
+        file.add(b"TWO ", &vec![2u16, 2]);
+        file
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let file = StateFile::from_bytes(&file().to_bytes()).unwrap();
+        assert_eq!(file.version, VERSION);
+        let mut two: Vec<u16> = Vec::new();
+        file.load(b"TWO ", &mut two).unwrap();
+        assert_eq!(two, vec![2, 2]);
+    }
+
+    #[test]
+    fn test_load_chunk() {
+        let mut file = file();
+        let mut one = 0u8;
+        assert_eq!(
+            file.load(b"MISS", &mut one),
+            Err(String::from("The save state has no MISS chunk"))
+        );
+        assert_eq!(
+            file.load(b"TWO ", &mut one),
+            Err(String::from("TWO chunk: 11 bytes left over"))
+        );
+
+        // Chunks nobody reads are fine
+        file.set_chunk(b"NEW ", vec![1, 2, 3]);
+        let file = StateFile::from_bytes(&file.to_bytes()).unwrap();
+        file.load(b"ONE ", &mut one).unwrap();
+        assert_eq!(one, 1);
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let mut file = file();
+        file.version = VERSION + 1;
+        let error = StateFile::from_bytes(&file.to_bytes()).err().unwrap();
+        assert!(error.contains("only reads versions"), "{}", error);
+    }
+
+    #[test]
+    fn test_not_a_state() {
+        assert_eq!(
+            StateFile::from_bytes(b"RGB").err(),
+            Some(String::from("Not a save state"))
+        );
+        let error = StateFile::from_bytes(b"RGBSTATE\x1c\0\0\0").err().unwrap();
+        assert!(error.contains("before states had a version"), "{}", error);
+    }
+
+    /// A version 1 state with the CPU at `pc`, its registers saved after
+    /// `count`
+    fn version_1(count: usize, pc: u16) -> StateFile {
+        let mut cpu = Vec::new();
+        0u16.save(&mut cpu);
+        count.save(&mut cpu);
+        [0u16, 0, 0, 0, 0xFFFE, pc].save(&mut cpu);
+        let mut file = StateFile {
+            version: 1,
+            ..Default::default()
+        };
+        file.set_chunk(b"CPU ", cpu);
+        file
+    }
+
+    #[test]
+    fn test_drop_register_count() {
+        let file = StateFile::from_bytes(&version_1(6, 0x0150).to_bytes()).unwrap();
+        assert_eq!(file.version, VERSION);
+        let mut cpu = Cpu::new();
+        file.load(b"CPU ", &mut cpu).unwrap();
+        assert_eq!((cpu.registers().sp, cpu.registers().pc), (0xFFFE, 0x0150));
+    }
+
+    #[test]
+    fn test_drop_register_count_invalid() {
+        assert_eq!(
+            StateFile::from_bytes(&version_1(5, 0x0150).to_bytes()).err(),
+            Some(String::from(
+                "Expected 6 registers in the CPU chunk, found 5"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_cut_short() {
+        let bytes = file().to_bytes();
+        assert!(StateFile::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
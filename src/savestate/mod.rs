@@ -0,0 +1,327 @@
+// Save states: the whole machine written out as bytes and read back in.
+// Each part of the hardware implements SaveState, writing its fields in
+// a fixed order, little-endian. Settings that aren't part of the hardware,
+// like the palette, the sample rate or what's plugged into the serial
+// port, are left as they are when a state is loaded. See `file` for how
+// the parts are put together.
+
+pub mod file;
+
+use alloc::collections::VecDeque;
+use core::convert::TryInto;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+/// The whole machine, held in memory rather than written to a file
+#[derive(Clone)]
+pub struct Snapshot(pub(crate) file::StateFile);
+
+/// How many numbered slots there are for states saved from a hotkey, 0-9
+pub const SLOTS: u8 = 10;
+
+/// Where the state in a numbered slot is kept: in a directory of the ROM's
+/// own next to it, eg `roms/tetris.gb` -> `roms/tetris.states/3.state`
+#[cfg(feature = "std")]
+pub fn slot_path(rom_path: &Path, slot: u8) -> PathBuf {
+    rom_path
+        .with_extension("states")
+        .join(format!("{}.state", slot))
+}
+
+/// A save state given on the command line, by slot number or path
+#[cfg(feature = "std")]
+pub fn state_path(rom_path: &Path, value: &str) -> PathBuf {
+    match value.parse::<u8>() {
+        Ok(slot) if slot < SLOTS => slot_path(rom_path, slot),
+        _ => PathBuf::from(value),
+    }
+}
+
+pub trait SaveState {
+    fn save(&self, out: &mut Vec<u8>);
+
+    /// Read back what save() wrote, consuming it from the front of `input`
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String>;
+}
+
+/// Take `count` bytes from the front of `input`
+pub fn take<'a>(input: &mut &'a [u8], count: usize) -> Result<&'a [u8], String> {
+    if input.len() < count {
+        return Err(String::from("The save state ends too soon"));
+    }
+    let (taken, rest) = input.split_at(count);
+    *input = rest;
+    Ok(taken)
+}
+
+macro_rules! save_state_int {
+    ($($int:ty),*) => {
+        $(
+            impl SaveState for $int {
+                fn save(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+                    let bytes = take(input, core::mem::size_of::<$int>())?;
+                    *self = <$int>::from_le_bytes(bytes.try_into().unwrap());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+save_state_int!(u8, u16, u32, u64);
+
+impl SaveState for usize {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u64).save(out);
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+        let mut value = 0u64;
+        value.load(input)?;
+        *self = value as usize;
+        Ok(())
+    }
+}
+
+impl SaveState for bool {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u8).save(out);
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+        let mut value = 0u8;
+        value.load(input)?;
+        *self = value != 0;
+        Ok(())
+    }
+}
+
+impl SaveState for f32 {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.to_bits().save(out);
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+        let mut bits = 0u32;
+        bits.load(input)?;
+        *self = f32::from_bits(bits);
+        Ok(())
+    }
+}
+
+impl<T: SaveState, const N: usize> SaveState for [T; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+        for item in self {
+            item.load(input)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: SaveState + Default> SaveState for Option<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.is_some().save(out);
+        if let Some(value) = self {
+            value.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+        let mut is_some = false;
+        is_some.load(input)?;
+        *self = None;
+        if is_some {
+            let mut value = T::default();
+            value.load(input)?;
+            *self = Some(value);
+        }
+        Ok(())
+    }
+}
+
+/// Read a length, which can't be more than the bytes left since every item
+/// takes at least one byte
+fn load_len(input: &mut &[u8]) -> Result<usize, String> {
+    let mut len = 0usize;
+    len.load(input)?;
+    if len > input.len() {
+        return Err(String::from("The save state ends too soon"));
+    }
+    Ok(len)
+}
+
+impl<T: SaveState + Default> SaveState for Vec<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+        let len = load_len(input)?;
+        self.clear();
+        for _ in 0..len {
+            let mut item = T::default();
+            item.load(input)?;
+            self.push(item);
+        }
+        Ok(())
+    }
+}
+
+impl<T: SaveState + Default> SaveState for VecDeque<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> Result<(), String> {
+        let len = load_len(input)?;
+        self.clear();
+        for _ in 0..len {
+            let mut item = T::default();
+            item.load(input)?;
+            self.push_back(item);
+        }
+        Ok(())
+    }
+}
+
+/// Load a memory or a buffer whose size is set by the hardware,
+/// rejecting a state where it's a different size
+pub fn load_fixed<T: SaveState + Default>(
+    items: &mut Vec<T>,
+    input: &mut &[u8],
+) -> Result<(), String> {
+    let mut loaded = Vec::new();
+    loaded.load(input)?;
+    if loaded.len() != items.len() {
+        return Err(format!(
+            "Expected {} items in the save state, found {}",
+            items.len(),
+            loaded.len()
+        ));
+    }
+    *items = loaded;
+    Ok(())
+}
+
+/// Implement SaveState for a struct by saving the given fields in order.
+/// Fields after `fixed` are memories or buffers that can't change size.
+macro_rules! save_state_fields {
+    ($type:ty { $($field:ident),* $(,)? } $(fixed { $($fixed:ident),* $(,)? })?) => {
+        impl crate::savestate::SaveState for $type {
+            fn save(&self, out: &mut crate::prelude::Vec<u8>) {
+                $(crate::savestate::SaveState::save(&self.$field, out);)*
+                $($(crate::savestate::SaveState::save(&self.$fixed, out);)*)?
+            }
+
+            fn load(&mut self, input: &mut &[u8]) -> Result<(), crate::prelude::String> {
+                $(crate::savestate::SaveState::load(&mut self.$field, input)?;)*
+                $($(crate::savestate::load_fixed(&mut self.$fixed, input)?;)*)?
+                Ok(())
+            }
+        }
+    };
+}
+
+/// Implement SaveState for an enum without data by saving which variant it is
+macro_rules! save_state_enum {
+    ($type:ident { $($variant:ident),* $(,)? }) => {
+        impl crate::savestate::SaveState for $type {
+            fn save(&self, out: &mut crate::prelude::Vec<u8>) {
+                let variants = [$($type::$variant),*];
+                let index = variants.iter().position(|variant| variant == self).unwrap();
+                crate::savestate::SaveState::save(&(index as u8), out);
+            }
+
+            fn load(&mut self, input: &mut &[u8]) -> Result<(), crate::prelude::String> {
+                let variants = [$($type::$variant),*];
+                let mut index = 0u8;
+                crate::savestate::SaveState::load(&mut index, input)?;
+                *self = *variants.get(index as usize).ok_or_else(|| {
+                    crate::prelude::format!("Unknown {} {} in the save state", stringify!($type), index)
+                })?;
+                Ok(())
+            }
+        }
+    };
+}
+
+pub(crate) use save_state_enum;
+pub(crate) use save_state_fields;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use test_case::test_case;
+
+    fn round_trip<T: SaveState + Default>(value: &T) -> T {
+        let mut out = Vec::new();
+        value.save(&mut out);
+        let mut input = &out[..];
+        let mut loaded = T::default();
+        loaded.load(&mut input).unwrap();
+        assert!(input.is_empty());
+        loaded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(round_trip(&0x1234_5678u32), 0x1234_5678);
+        assert!(round_trip(&true));
+        assert_eq!(round_trip(&-0.5f32), -0.5);
+        assert_eq!(round_trip(&Some(7u8)), Some(7));
+        assert_eq!(round_trip(&vec![1u16, 2, 3]), vec![1, 2, 3]);
+        assert_eq!(round_trip(&[4u8, 5]), [4, 5]);
+    }
+
+    #[test]
+    fn test_too_short() {
+        let mut value = 0u32;
+        assert!(value.load(&mut &[1, 2][..]).is_err());
+        // A length longer than what's left isn't allocated
+        let mut items: Vec<u8> = Vec::new();
+        let mut out = Vec::new();
+        usize::MAX.save(&mut out);
+        assert!(items.load(&mut &out[..]).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test_case("3", "roms/tetris.states/3.state"; "slot")]
+    #[test_case("10", "10"; "not a slot")]
+    #[test_case("saves/boss.state", "saves/boss.state"; "path")]
+    fn test_state_path(value: &str, expected: &str) {
+        assert_eq!(
+            state_path(Path::new("roms/tetris.gb"), value),
+            PathBuf::from(expected)
+        );
+    }
+
+    #[test]
+    fn test_load_fixed() {
+        let mut out = Vec::new();
+        vec![1u8, 2, 3].save(&mut out);
+        let mut memory = vec![0u8; 4];
+        assert!(load_fixed(&mut memory, &mut &out[..]).is_err());
+        let mut memory = vec![0u8; 3];
+        load_fixed(&mut memory, &mut &out[..]).unwrap();
+        assert_eq!(memory, vec![1, 2, 3]);
+    }
+}
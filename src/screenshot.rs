@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::gameboy::Framebuffer;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Where a screenshot of a frame is saved: next to the ROM,
+/// eg `roms/tetris.gb` -> `roms/tetris-120.png` for frame 120
+pub fn screenshot_path(rom_path: &Path, frame: u64) -> PathBuf {
+    let stem = rom_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("screenshot"));
+    rom_path.with_file_name(format!("{}-{}.png", stem, frame))
+}
+
+/// Encode a frame as an 8-bit RGB PNG
+pub fn write_png<W: Write>(writer: W, framebuffer: &Framebuffer) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data: Vec<u8> = framebuffer
+        .iter()
+        .flat_map(|pixel| {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            [r, g, b]
+        })
+        .collect();
+    encoder.write_header()?.write_image_data(&data)
+}
+
+/// Save a frame to a PNG file
+pub fn save_png(path: &Path, framebuffer: &Framebuffer) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    write_png(BufWriter::new(file), framebuffer).map_err(|e| e.to_string())
+}
+
+/// Read a PNG of the screen, like a test ROM's reference image, as
+/// 0x00RRGGBB pixels row by row
+pub fn read_png(path: &Path) -> Result<Vec<u32>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    // Palettes, grays and 16-bit channels all come out as 8-bit RGB(A)
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+    if (info.width, info.height) != (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32) {
+        return Err(format!(
+            "Expected a {}x{} image, got {}x{}",
+            SCREEN_WIDTH, SCREEN_HEIGHT, info.width, info.height
+        ));
+    }
+    let channels = info.color_type.samples();
+    Ok(data[..info.buffer_size()]
+        .chunks(channels)
+        .map(|pixel| match pixel {
+            [gray] | [gray, _] => u32::from_be_bytes([0, *gray, *gray, *gray]),
+            [r, g, b, ..] => u32::from_be_bytes([0, *r, *g, *b]),
+            [] => 0,
+        })
+        .collect())
+}
+
+/// Where the frame differs from a reference image, as x and y
+pub fn differing_pixels(framebuffer: &Framebuffer, reference: &[u32]) -> Vec<(usize, usize)> {
+    framebuffer
+        .iter()
+        .zip(reference)
+        .enumerate()
+        .filter(|(_, (pixel, expected))| pixel != expected)
+        .map(|(index, _)| (index % SCREEN_WIDTH, index / SCREEN_WIDTH))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::palette::GRAYSCALE;
+
+    #[test]
+    fn test_screenshot_path() {
+        let path = screenshot_path(Path::new("roms/tetris.gb"), 120);
+        assert_eq!(path, PathBuf::from("roms/tetris-120.png"));
+    }
+
+    #[test]
+    fn test_write_png() {
+        let mut framebuffer = [GRAYSCALE[0]; SCREEN_WIDTH * SCREEN_HEIGHT];
+        framebuffer[1] = 0x00FF_8000;
+        let mut bytes = Vec::new();
+        write_png(&mut bytes, &framebuffer).unwrap();
+
+        let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (160, 144));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(&data[0..6], &[0xFF, 0xFF, 0xFF, 0xFF, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn test_read_png() {
+        let path = std::env::temp_dir().join("rusty-gameboy-test-read-png.png");
+        let mut framebuffer = [GRAYSCALE[0]; SCREEN_WIDTH * SCREEN_HEIGHT];
+        framebuffer[SCREEN_WIDTH + 2] = GRAYSCALE[3];
+        save_png(&path, &framebuffer).unwrap();
+        let reference = read_png(&path).unwrap();
+        assert_eq!(&reference[..], &framebuffer[..]);
+
+        framebuffer[5] = GRAYSCALE[1];
+        assert_eq!(differing_pixels(&framebuffer, &reference), vec![(5, 0)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_png_wrong_size() {
+        let path = std::env::temp_dir().join("rusty-gameboy-test-read-png-size.png");
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 2, 2);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[0; 4])
+            .unwrap();
+        assert_eq!(
+            read_png(&path),
+            Err(String::from("Expected a 160x144 image, got 2x2"))
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+// Deterministic mode. A real GameBoy powers on with garbage in its RAM and
+// its divider at an arbitrary phase, and an MBC3 cartridge's clock follows
+// the time of day. With a seed, the garbage and the phase come from a
+// pseudo-random generator and the clock follows emulated time, so two runs
+// of the same ROM with the same inputs end in exactly the same state.
+
+use crate::prelude::*;
+
+/// SplitMix64: small and fast, and plenty random enough for power-on garbage
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn fill(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            let random = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&random[..chunk.len()]);
+        }
+    }
+}
+
+/// Parse a seed, in decimal or in hex with a 0x prefix
+pub fn parse_seed(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    }
+    .map_err(|_| format!("The seed must be a 64-bit number, got {:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn test_same_seed() {
+        let mut first = Rng::new(42);
+        let mut second = Rng::new(42);
+        let mut other = Rng::new(43);
+        let mut bytes = [[0; 13]; 3];
+        first.fill(&mut bytes[0]);
+        second.fill(&mut bytes[1]);
+        other.fill(&mut bytes[2]);
+        assert_eq!(bytes[0], bytes[1]);
+        assert_ne!(bytes[0], bytes[2]);
+    }
+
+    #[test_case("1234", Ok(1234); "decimal")]
+    #[test_case("0xBEEF", Ok(0xBEEF); "hex")]
+    #[test_case("-1", Err(()); "negative")]
+    #[test_case("seed", Err(()); "not a number")]
+    fn test_parse_seed(value: &str, expected: Result<u64, ()>) {
+        assert_eq!(parse_seed(value).map_err(|_| ()), expected);
+    }
+}
@@ -0,0 +1,179 @@
+// A link cable between two emulators over TCP. Each message is two bytes:
+// a kind and a data byte. The side whose GameBoy clocks a transfer sends its
+// byte and waits for the other side's byte in reply, which keeps the two in
+// step. The other side checks for transfers every so often and replies with
+// SB if its GameBoy is waiting for one, or 0xFF if it isn't.
+
+use log::{info, warn};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::serial::{SerialDevice, DISCONNECTED};
+
+/// This side clocked a transfer and sent its byte
+const TRANSFER: u8 = 0x01;
+/// The other side's byte in a transfer
+const REPLY: u8 = 0x02;
+
+/// How long to wait for the other side before treating the cable as unplugged
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct TcpLink {
+    /// None once the connection is lost
+    stream: Option<TcpStream>,
+}
+
+impl TcpLink {
+    /// Wait for the other emulator to connect
+    pub fn listen(address: &str) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind(address)?;
+        info!("Waiting for the other player on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept()?;
+        info!("Linked with {}", peer);
+        TcpLink::new(stream)
+    }
+
+    /// Connect to an emulator that's listening
+    pub fn connect(address: &str) -> io::Result<TcpLink> {
+        let stream = TcpStream::connect(address)?;
+        info!("Linked with {}", stream.peer_addr()?);
+        TcpLink::new(stream)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<TcpLink> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+        Ok(TcpLink {
+            stream: Some(stream),
+        })
+    }
+
+    /// Run `exchange` on the connection, dropping it if that fails
+    fn with_stream<T>(
+        &mut self,
+        exchange: impl FnOnce(&mut TcpStream) -> io::Result<T>,
+    ) -> Option<T> {
+        let stream = self.stream.as_mut()?;
+        match exchange(stream) {
+            Ok(result) => Some(result),
+            Err(e) => {
+                warn!("Lost the link cable connection: {}", e);
+                self.stream = None;
+                None
+            }
+        }
+    }
+}
+
+fn send(stream: &mut TcpStream, kind: u8, byte: u8) -> io::Result<()> {
+    stream.write_all(&[kind, byte])
+}
+
+fn receive(stream: &mut TcpStream) -> io::Result<(u8, u8)> {
+    let mut message = [0; 2];
+    stream.read_exact(&mut message)?;
+    Ok((message[0], message[1]))
+}
+
+/// Whether a message is waiting, without blocking
+fn has_message(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let peeked = stream.peek(&mut [0]);
+    stream.set_nonblocking(false)?;
+    match peeked {
+        Ok(0) => Err(io::Error::new(ErrorKind::UnexpectedEof, "disconnected")),
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl SerialDevice for TcpLink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.with_stream(|stream| {
+            send(stream, TRANSFER, byte)?;
+            loop {
+                match receive(stream)? {
+                    (REPLY, reply) => return Ok(reply),
+                    // Both sides clocked a transfer at once, so neither was listening
+                    (TRANSFER, _) => send(stream, REPLY, DISCONNECTED)?,
+                    (kind, _) => warn!("Unknown link cable message {:#04x}", kind),
+                }
+            }
+        })
+        .unwrap_or(DISCONNECTED)
+    }
+
+    fn poll(&mut self, reply: Option<u8>) -> Option<u8> {
+        self.with_stream(|stream| {
+            if !has_message(stream)? {
+                return Ok(None);
+            }
+            match receive(stream)? {
+                (TRANSFER, byte) => {
+                    send(stream, REPLY, reply.unwrap_or(DISCONNECTED))?;
+                    Ok(reply.map(|_| byte))
+                }
+                (kind, _) => {
+                    warn!("Unexpected link cable message {:#04x}", kind);
+                    Ok(None)
+                }
+            }
+        })
+        .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn linked() -> (TcpLink, TcpLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let client = thread::spawn(move || TcpLink::connect(&address).unwrap());
+        let (stream, _) = listener.accept().unwrap();
+        (TcpLink::new(stream).unwrap(), client.join().unwrap())
+    }
+
+    /// Poll until the other side's transfer arrives
+    fn poll_until_transfer(link: &mut TcpLink, reply: Option<u8>) -> Option<u8> {
+        while !has_message(link.stream.as_mut().unwrap()).unwrap() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        link.poll(reply)
+    }
+
+    #[test]
+    fn test_transfer() {
+        let (mut host, mut client) = linked();
+        let other = thread::spawn(move || poll_until_transfer(&mut client, Some(0x22)));
+        assert_eq!(host.transfer(0x11), 0x22);
+        assert_eq!(other.join().unwrap(), Some(0x11));
+    }
+
+    /// The other GameBoy isn't waiting for a transfer
+    #[test]
+    fn test_transfer_not_waiting() {
+        let (mut host, mut client) = linked();
+        let other = thread::spawn(move || poll_until_transfer(&mut client, None));
+        assert_eq!(host.transfer(0x11), DISCONNECTED);
+        assert_eq!(other.join().unwrap(), None);
+    }
+
+    #[test]
+    fn test_poll_nothing() {
+        let (mut host, _client) = linked();
+        assert_eq!(host.poll(Some(0x22)), None);
+    }
+
+    #[test]
+    fn test_disconnected() {
+        let (mut host, client) = linked();
+        drop(client);
+        assert_eq!(host.transfer(0x11), DISCONNECTED);
+        assert!(host.stream.is_none());
+    }
+}
@@ -0,0 +1,22 @@
+// A cable from the serial port back into itself: every transfer receives
+// the byte it sent.
+
+use crate::serial::SerialDevice;
+
+pub struct Loopback;
+
+impl SerialDevice for Loopback {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        byte
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer() {
+        assert_eq!(Loopback.transfer(0x42), 0x42);
+    }
+}
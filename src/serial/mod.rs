@@ -0,0 +1,426 @@
+// The serial port behind the link cable. A transfer swaps SB with the other
+// side one bit at a time, most significant bit first, driven by whichever
+// side provides the clock. With nothing plugged in, the bits shifted in are
+// all 1s, unless a SerialDevice is plugged in. Test ROMs print their results
+// over serial, so the bytes sent can be copied to an output as they go.
+
+#[cfg(feature = "std")]
+pub mod link;
+pub mod loopback;
+#[cfg(feature = "std")]
+pub mod script;
+
+#[cfg(feature = "std")]
+use log::warn;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+use crate::interrupts::Interrupt;
+use crate::prelude::*;
+use crate::savestate::save_state_fields;
+#[cfg(feature = "std")]
+use crate::serial::link::TcpLink;
+#[cfg(feature = "std")]
+use crate::serial::loopback::Loopback;
+#[cfg(feature = "std")]
+use crate::serial::script::Script;
+
+/// Serial data: the byte to send, and the byte received after a transfer
+pub const SB: u16 = 0xFF01;
+/// Serial control: bit 7 starts a transfer and reads 1 until it's done,
+/// bit 0 picks the internal clock, bit 1 the CGB's fast clock
+pub const SC: u16 = 0xFF02;
+
+const SC_TRANSFER: u8 = 1 << 7;
+const SC_FAST_CLOCK: u8 = 1 << 1;
+const SC_INTERNAL_CLOCK: u8 = 1 << 0;
+/// The bits of SC that aren't connected read as 1
+const SC_UNUSED: u8 = 0b0111_1110;
+const SC_UNUSED_CGB: u8 = 0b0111_1100;
+
+/// T-cycles per bit with the internal clock: 8192 Hz, or 262144 Hz
+/// with the CGB's fast clock
+const BIT_CYCLES: u32 = 512;
+const FAST_BIT_CYCLES: u32 = 16;
+
+/// What the serial port reads when nothing is plugged in
+pub const DISCONNECTED: u8 = 0xFF;
+
+/// How often a device gets to clock a transfer from its side: once per byte
+/// at the normal clock, which is as fast as the other side can send
+const POLL_CYCLES: u32 = 8 * BIT_CYCLES;
+
+/// Something plugged into the serial port
+pub trait SerialDevice {
+    /// The GameBoy clocked a transfer and sent `byte`. Returns the byte sent back.
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    /// Called every so often for the device to clock a transfer itself.
+    /// `reply` is SB if the GameBoy is waiting for one on the external clock.
+    /// Returns the byte the device sent, if there was a transfer.
+    fn poll(&mut self, _reply: Option<u8>) -> Option<u8> {
+        None
+    }
+}
+
+/// What to plug into the serial port, as picked on the command line
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Link {
+    /// Wait for another emulator to connect on an address or port
+    Listen(String),
+    /// Connect to another emulator's address
+    Connect(String),
+    /// Receive every byte sent
+    Loopback,
+    /// Answer with the bytes in a script file
+    Script(PathBuf),
+}
+
+/// Parse a link like `listen:7777`, `connect:192.168.1.2:7777`,
+/// `loopback` or `script:replies.txt`
+#[cfg(feature = "std")]
+pub fn parse_link(value: &str) -> Result<Link, String> {
+    if value == "loopback" {
+        return Ok(Link::Loopback);
+    }
+    match value.split_once(':') {
+        Some(("listen", port)) if port.parse::<u16>().is_ok() => {
+            Ok(Link::Listen(format!("0.0.0.0:{}", port)))
+        }
+        Some(("listen", address)) if !address.is_empty() => Ok(Link::Listen(address.to_string())),
+        Some(("connect", address)) if !address.is_empty() => Ok(Link::Connect(address.to_string())),
+        Some(("script", path)) if !path.is_empty() => Ok(Link::Script(PathBuf::from(path))),
+        _ => Err(format!(
+            "Expected listen:<port>, connect:<host>:<port>, loopback or script:<path>, got {:?}",
+            value
+        )),
+    }
+}
+
+/// Plug in the device for a link, eg by connecting to the other emulator
+#[cfg(feature = "std")]
+pub fn open_link(link: &Link) -> Result<Box<dyn SerialDevice>, String> {
+    Ok(match link {
+        Link::Listen(address) => Box::new(TcpLink::listen(address).map_err(|e| e.to_string())?),
+        Link::Connect(address) => Box::new(TcpLink::connect(address).map_err(|e| e.to_string())?),
+        Link::Loopback => Box::new(Loopback),
+        Link::Script(path) => Box::new(Script::load(path)?),
+    })
+}
+
+#[derive(Default)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    cgb_mode: bool,
+    /// Bits left to shift in the current transfer
+    bits_left: u8,
+    /// The rest of the byte being shifted in
+    incoming: u8,
+    /// T-cycles into the current bit
+    clock: u32,
+    /// Where the bytes sent are copied to, if anywhere
+    #[cfg(feature = "std")]
+    output: Option<Box<dyn Write>>,
+    /// The last bytes sent, if they're being kept
+    sent: Option<Vec<u8>>,
+    device: Option<Box<dyn SerialDevice>>,
+    /// T-cycles since the device was last polled
+    poll_clock: u32,
+}
+
+impl Serial {
+    pub fn new_cgb() -> Serial {
+        Serial {
+            cgb_mode: true,
+            ..Default::default()
+        }
+    }
+
+    /// Copy every byte sent from now on to `output`
+    #[cfg(feature = "std")]
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = Some(output);
+    }
+
+    /// Keep the last `count` bytes sent from now on, for sent()
+    pub fn keep_sent(&mut self, count: usize) {
+        self.sent = Some(Vec::with_capacity(count));
+    }
+
+    /// The last bytes sent, oldest first, if they're being kept
+    pub fn sent(&self) -> &[u8] {
+        self.sent.as_deref().unwrap_or_default()
+    }
+
+    pub fn plug_in(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = Some(device);
+    }
+
+    /// The DMG has no fast clock
+    fn unused_bits(&self) -> u8 {
+        if self.cgb_mode {
+            SC_UNUSED_CGB
+        } else {
+            SC_UNUSED
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            SB => self.sb,
+            SC => self.unused_bits() | self.sc,
+            _ => unreachable!("{:#06x} is not a serial register", address),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            SB => self.sb = value,
+            SC => {
+                self.sc = value & !self.unused_bits();
+                if self.sc & SC_TRANSFER != 0 {
+                    self.start_transfer();
+                }
+            }
+            _ => unreachable!("{:#06x} is not a serial register", address),
+        }
+    }
+
+    fn start_transfer(&mut self) {
+        self.bits_left = 8;
+        self.clock = 0;
+        #[cfg(feature = "std")]
+        if let Some(output) = &mut self.output {
+            if let Err(e) = output.write_all(&[self.sb]).and_then(|_| output.flush()) {
+                warn!("Stopped copying the serial output: {}", e);
+                self.output = None;
+            }
+        }
+        if let Some(sent) = &mut self.sent {
+            if sent.len() == sent.capacity() {
+                sent.remove(0);
+            }
+            sent.push(self.sb);
+        }
+        // The whole byte is swapped up front, then shifted in bit by bit
+        self.incoming = match &mut self.device {
+            Some(device) if self.sc & SC_INTERNAL_CLOCK != 0 => device.transfer(self.sb),
+            _ => DISCONNECTED,
+        };
+    }
+
+    fn bit_cycles(&self) -> u32 {
+        if self.sc & SC_FAST_CLOCK != 0 {
+            FAST_BIT_CYCLES
+        } else {
+            BIT_CYCLES
+        }
+    }
+
+    /// Advance by a number of T-cycles.
+    /// Returns the interrupts requested in that time, as IF bits.
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        // With the external clock, the other side drives the transfer
+        if self.bits_left == 0 || self.sc & SC_INTERNAL_CLOCK == 0 {
+            return self.poll_device(cycles);
+        }
+
+        self.clock += cycles;
+        let bit_cycles = self.bit_cycles();
+        while self.clock >= bit_cycles && self.bits_left > 0 {
+            self.clock -= bit_cycles;
+            self.sb = (self.sb << 1) | (self.incoming >> 7);
+            self.incoming <<= 1;
+            self.bits_left -= 1;
+        }
+        if self.bits_left > 0 {
+            return 0;
+        }
+        self.sc &= !SC_TRANSFER;
+        Interrupt::Serial.mask()
+    }
+
+    /// Let the device clock a transfer from its side
+    fn poll_device(&mut self, cycles: u32) -> u8 {
+        let device = match &mut self.device {
+            Some(device) => device,
+            None => return 0,
+        };
+        self.poll_clock += cycles;
+        if self.poll_clock < POLL_CYCLES {
+            return 0;
+        }
+        self.poll_clock %= POLL_CYCLES;
+
+        let waiting = self.bits_left > 0;
+        match device.poll(Some(self.sb).filter(|_| waiting)) {
+            Some(byte) if waiting => {
+                self.sb = byte;
+                self.bits_left = 0;
+                self.sc &= !SC_TRANSFER;
+                Interrupt::Serial.mask()
+            }
+            _ => 0,
+        }
+    }
+}
+
+save_state_fields!(Serial {
+    sb,
+    sc,
+    cgb_mode,
+    bits_left,
+    incoming,
+    clock,
+    poll_clock,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use alloc::rc::Rc;
+    #[cfg(feature = "std")]
+    use core::cell::RefCell;
+    #[cfg(feature = "std")]
+    use std::io;
+    use test_case::test_case;
+
+    /// An output the test can look at after handing it to the serial port
+    #[cfg(feature = "std")]
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    #[cfg(feature = "std")]
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test_case(Serial::default(), 0x81, 8 * BIT_CYCLES; "normal clock")]
+    #[test_case(Serial::new_cgb(), 0x83, 8 * FAST_BIT_CYCLES; "cgb fast clock")]
+    fn test_transfer(mut serial: Serial, sc: u8, cycles: u32) {
+        serial.write(SB, 0x42);
+        serial.write(SC, sc);
+        assert_eq!(serial.tick(cycles - 1), 0);
+        assert_eq!(serial.read(SC) & SC_TRANSFER, SC_TRANSFER);
+        assert_eq!(serial.tick(1), Interrupt::Serial.mask());
+        assert_eq!(serial.read(SC) & SC_TRANSFER, 0);
+        // Nothing is plugged in
+        assert_eq!(serial.read(SB), DISCONNECTED);
+    }
+
+    #[test]
+    fn test_transfer_by_bit() {
+        let mut serial = Serial::default();
+        serial.write(SB, 0x00);
+        serial.write(SC, 0x81);
+        serial.tick(3 * BIT_CYCLES);
+        assert_eq!(serial.read(SB), 0b0000_0111);
+    }
+
+    /// The DMG has no fast clock
+    #[test]
+    fn test_fast_clock_dmg() {
+        let mut serial = Serial::default();
+        serial.write(SC, 0x83);
+        assert_eq!(serial.read(SC), 0xFF);
+        assert_eq!(serial.tick(8 * FAST_BIT_CYCLES), 0);
+    }
+
+    #[test]
+    fn test_external_clock() {
+        let mut serial = Serial::default();
+        serial.write(SB, 0x42);
+        serial.write(SC, 0x80);
+        assert_eq!(serial.tick(100 * BIT_CYCLES), 0);
+        assert_eq!(serial.read(SB), 0x42);
+        assert_eq!(serial.read(SC), 0xFE);
+    }
+
+    /// Sends back the byte after the one it receives, and clocks a
+    /// transfer of 0x99 when polled
+    struct Counter;
+
+    impl SerialDevice for Counter {
+        fn transfer(&mut self, byte: u8) -> u8 {
+            byte.wrapping_add(1)
+        }
+
+        fn poll(&mut self, reply: Option<u8>) -> Option<u8> {
+            reply.map(|_| 0x99)
+        }
+    }
+
+    #[test]
+    fn test_device_transfer() {
+        let mut serial = Serial::default();
+        serial.plug_in(Box::new(Counter));
+        serial.write(SB, 0x42);
+        serial.write(SC, 0x81);
+        serial.tick(8 * BIT_CYCLES);
+        assert_eq!(serial.read(SB), 0x43);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_device_clock() {
+        let mut serial = Serial::default();
+        serial.plug_in(Box::new(Counter));
+        // Not waiting for a transfer
+        assert_eq!(serial.tick(POLL_CYCLES), 0);
+        serial.write(SB, 0x42);
+        serial.write(SC, 0x80);
+        assert_eq!(serial.tick(POLL_CYCLES - 1), 0);
+        assert_eq!(serial.tick(1), Interrupt::Serial.mask());
+        assert_eq!(serial.read(SB), 0x99);
+        assert_eq!(serial.read(SC), 0x7E);
+    }
+
+    #[cfg(feature = "std")]
+    #[test_case("listen:7777", Ok(Link::Listen(String::from("0.0.0.0:7777"))); "port")]
+    #[test_case("listen:127.0.0.1:7777", Ok(Link::Listen(String::from("127.0.0.1:7777"))); "address")]
+    #[test_case("connect:gameboy:7777", Ok(Link::Connect(String::from("gameboy:7777"))); "connect")]
+    #[test_case("loopback", Ok(Link::Loopback); "loopback")]
+    #[test_case("script:replies.txt", Ok(Link::Script(PathBuf::from("replies.txt"))); "script")]
+    #[test_case("connect:", Err(()); "no address")]
+    #[test_case("7777", Err(()); "no kind")]
+    fn test_parse_link(value: &str, expected: Result<Link, ()>) {
+        assert_eq!(parse_link(value).map_err(|_| ()), expected);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_output() {
+        let output = SharedOutput::default();
+        let mut serial = Serial::default();
+        serial.set_output(Box::new(output.clone()));
+        for byte in b"Passed".iter() {
+            serial.write(SB, *byte);
+            serial.write(SC, 0x81);
+            serial.tick(8 * BIT_CYCLES);
+        }
+        assert_eq!(output.0.borrow().as_slice(), b"Passed");
+    }
+
+    #[test]
+    fn test_keep_sent() {
+        let mut serial = Serial::default();
+        serial.keep_sent(4);
+        for byte in b"Passed".iter() {
+            serial.write(SB, *byte);
+            serial.write(SC, 0x81);
+            serial.tick(8 * BIT_CYCLES);
+        }
+        assert_eq!(serial.sent(), b"ssed");
+    }
+}
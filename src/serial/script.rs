@@ -0,0 +1,85 @@
+// A serial device that answers each transfer with the next byte of a script,
+// then with 0xFF as if it had been unplugged once the script runs out.
+// The script is text holding hex bytes separated by whitespace, with
+// anything after a '#' skipped:
+//
+//     # Answer the handshake, then send the player's name
+//     01 02
+//     80 91 8E 80 50  # "ROSA"
+
+use log::debug;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+use crate::serial::{SerialDevice, DISCONNECTED};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Script {
+    replies: VecDeque<u8>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Script, String> {
+        let mut replies = VecDeque::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            for byte in line.split_whitespace() {
+                match u8::from_str_radix(byte, 16) {
+                    Ok(byte) => replies.push_back(byte),
+                    Err(_) => {
+                        return Err(format!(
+                            "Line {}: expected a hex byte, got {:?}",
+                            number + 1,
+                            byte
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(Script { replies })
+    }
+
+    pub fn load(path: &Path) -> Result<Script, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        Script::parse(&text).map_err(|e| format!("{:?}: {}", path, e))
+    }
+}
+
+impl SerialDevice for Script {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let reply = self.replies.pop_front().unwrap_or(DISCONNECTED);
+        debug!("Serial script got {:#04x}, sent {:#04x}", byte, reply);
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let script = Script::parse("# handshake\n01 02\n\n  aB # the rest\n").unwrap();
+        assert_eq!(script.replies, [0x01, 0x02, 0xAB]);
+        assert_eq!(Script::parse("").unwrap(), Script::default());
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(
+            Script::parse("01\n02 xyz"),
+            Err(String::from("Line 2: expected a hex byte, got \"xyz\""))
+        );
+        assert!(Script::parse("100").is_err());
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut script = Script::parse("11 22").unwrap();
+        assert_eq!(script.transfer(0x00), 0x11);
+        assert_eq!(script.transfer(0x00), 0x22);
+        // Unplugged once it runs out
+        assert_eq!(script.transfer(0x00), DISCONNECTED);
+    }
+}
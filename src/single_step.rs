@@ -0,0 +1,517 @@
+// Runs the SM83 single instruction tests from SingleStepTests
+// (https://github.com/SingleStepTests/sm83): a JSON file for each opcode,
+// with cases like
+//
+//   {"name": "00 0000",
+//    "initial": {"pc": 19935, "sp": 59438, "a": 110, ..., "ram": [[19935, 0]]},
+//    "final": {"pc": 19936, ..., "ram": [[19935, 0]]},
+//    "cycles": [[19935, 0, "r-m"]]}
+//
+// Each case loads the registers and RAM onto a bus that's nothing but RAM,
+// runs the one instruction, and compares the registers, the RAM and the
+// reads and writes it made in order. IME and IE aren't compared, as the CPU
+// doesn't have interrupts yet.
+
+use core::fmt;
+
+use crate::cpu_core::bus::{Bus, FlatRam};
+use crate::cpu_core::cpu::{Cpu, CpuError, Registers};
+use crate::prelude::*;
+
+/// Just enough JSON for the test files
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            at: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.at == parser.text.len() {
+            true => Ok(value),
+            false => Err(parser.error("the end")),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// A whole number that fits in 16 bits
+    fn as_u16(&self) -> Option<u16> {
+        match self {
+            Json::Number(number) if number % 1.0 == 0.0 && (0.0..=65535.0).contains(number) => {
+                Some(*number as u16)
+            }
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, expected: &str) -> String {
+        format!("Expected {} at byte {}", expected, self.at)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.text.get(self.at).is_some_and(u8::is_ascii_whitespace) {
+            self.at += 1;
+        }
+    }
+
+    /// Skip `byte` after any whitespace, if it's next
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.text.get(self.at) == Some(&byte);
+        self.at += found as usize;
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        match self.eat(byte) {
+            true => Ok(()),
+            false => Err(self.error(&format!("{:?}", byte as char))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        let rest = &self.text[self.at..];
+        for (word, value) in [
+            ("null", Json::Null),
+            ("true", Json::Bool(true)),
+            ("false", Json::Bool(false)),
+        ] {
+            if rest.starts_with(word.as_bytes()) {
+                self.at += word.len();
+                return Ok(value);
+            }
+        }
+        match rest.first() {
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.at += 1;
+                let mut values = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        values.push(self.value()?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Json::Array(values))
+            }
+            Some(b'{') => {
+                self.at += 1;
+                let mut fields = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let name = self.string()?;
+                        self.expect(b':')?;
+                        fields.push((name, self.value()?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Json::Object(fields))
+            }
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.at;
+        while self
+            .text
+            .get(self.at)
+            .is_some_and(|&byte| byte.is_ascii_digit() || b"+-.eE".contains(&byte))
+        {
+            self.at += 1;
+        }
+        core::str::from_utf8(&self.text[start..self.at])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Json::Number)
+            .ok_or_else(|| {
+                self.at = start;
+                self.error("a value")
+            })
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self.text.get(self.at).ok_or_else(|| self.error("'\"'"))?;
+            self.at += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self
+                        .text
+                        .get(self.at)
+                        .ok_or_else(|| self.error("an escape"))?;
+                    self.at += 1;
+                    let c = match escaped {
+                        b'n' => '\n',
+                        b't' => '\t',
+                        b'r' => '\r',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let code = self
+                                .text
+                                .get(self.at..self.at + 4)
+                                .and_then(|hex| core::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("4 hex digits"))?;
+                            self.at += 4;
+                            code
+                        }
+                        other => other as char,
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                byte => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+    }
+}
+
+/// A read or write on the bus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+/// Why a case didn't pass
+#[derive(Clone, Debug, PartialEq)]
+pub enum Failure {
+    /// The CPU doesn't run the instruction yet, so the case was skipped
+    Unsupported(CpuError),
+    /// The instruction ran, but this differs from what was expected
+    Differs(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Unsupported(e) => write!(f, "Skipped: {}", e),
+            Failure::Differs(difference) => write!(f, "{}", difference),
+        }
+    }
+}
+
+/// The registers and the RAM the test sets or checks
+#[derive(Clone, Debug, PartialEq)]
+pub struct State {
+    pub registers: Registers,
+    pub ram: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn parse(json: &Json) -> Result<State, String> {
+        let field = |name: &str| {
+            json.get(name)
+                .and_then(Json::as_u16)
+                .ok_or_else(|| format!("Expected a number for {:?}", name))
+        };
+        let byte = |name: &str| field(name).map(|value| value as u8);
+        let registers = Registers {
+            a: byte("a")?,
+            f: byte("f")?,
+            b: byte("b")?,
+            c: byte("c")?,
+            d: byte("d")?,
+            e: byte("e")?,
+            h: byte("h")?,
+            l: byte("l")?,
+            sp: field("sp")?,
+            pc: field("pc")?,
+        };
+        let ram = json
+            .get("ram")
+            .and_then(Json::as_array)
+            .ok_or("Expected an array for \"ram\"")?
+            .iter()
+            .map(|entry| match entry.as_array() {
+                Some([address, value]) => Some((address.as_u16()?, value.as_u16()? as u8)),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or("Expected [address, value] pairs in \"ram\"")?;
+        Ok(State { registers, ram })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    pub name: String,
+    pub initial: State,
+    pub expected: State,
+    /// The reads and writes in order, leaving out the cycles without either
+    pub accesses: Vec<Access>,
+}
+
+impl Case {
+    fn parse(json: &Json) -> Result<Case, String> {
+        let name = json.get("name").and_then(Json::as_str).unwrap_or_default();
+        let parsed = || {
+            let state = |key| {
+                json.get(key)
+                    .ok_or(format!("Expected {:?}", key))
+                    .and_then(State::parse)
+            };
+            // Each cycle is [address, value, pins], with pins like "r-m" for a read
+            let accesses = json
+                .get("cycles")
+                .and_then(Json::as_array)
+                .ok_or("Expected an array for \"cycles\"")?
+                .iter()
+                .filter_map(|cycle| match cycle.as_array()? {
+                    [address, value, pins] => {
+                        let (address, value) = (address.as_u16()?, value.as_u16()? as u8);
+                        match pins.as_str()? {
+                            pins if pins.starts_with('r') => Some(Access::Read(address, value)),
+                            pins if pins.contains('w') => Some(Access::Write(address, value)),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                })
+                .collect();
+            Ok(Case {
+                name: name.to_string(),
+                initial: state("initial")?,
+                expected: state("final")?,
+                accesses,
+            })
+        };
+        parsed().map_err(|e: String| format!("{}: {}", name, e))
+    }
+
+    /// Run the instruction, returning what differs from what was expected
+    pub fn run(&self) -> Result<(), Failure> {
+        let mut bus = Recorder::default();
+        for &(address, value) in &self.initial.ram {
+            bus.ram.write(address, value);
+        }
+        let mut cpu = Cpu::new();
+        cpu.set_registers(&self.initial.registers);
+        cpu.try_step(&mut bus).map_err(Failure::Unsupported)?;
+
+        let registers = cpu.registers();
+        if registers != self.expected.registers {
+            return Err(Failure::Differs(format!(
+                "Expected {}\n     got {}",
+                self.expected.registers, registers
+            )));
+        }
+        for &(address, value) in &self.expected.ram {
+            let found = bus.ram.read(address);
+            if found != value {
+                return Err(Failure::Differs(format!(
+                    "Expected {:#04x} at {:#06x}, got {:#04x}",
+                    value, address, found
+                )));
+            }
+        }
+        if bus.accesses != self.accesses {
+            return Err(Failure::Differs(format!(
+                "Expected the accesses {:?}\n     got {:?}",
+                self.accesses, bus.accesses
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Parse the cases in one of the test files
+pub fn parse_cases(text: &str) -> Result<Vec<Case>, String> {
+    Json::parse(text)?
+        .as_array()
+        .ok_or("Expected an array of cases")?
+        .iter()
+        .map(Case::parse)
+        .collect()
+}
+
+/// Plain RAM that keeps the CPU's accesses, for the case to compare after
+#[derive(Default)]
+struct Recorder {
+    ram: FlatRam,
+    accesses: Vec<Access>,
+}
+
+impl Bus for Recorder {
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.ram.read(address);
+        self.accesses.push(Access::Read(address, value));
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.ram.write(address, value);
+        self.accesses.push(Access::Write(address, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    /// A state with the registers all 0 but for the ones given
+    fn state(registers: &[(&str, u16)], ram: &str) -> String {
+        let fields: Vec<String> = ["pc", "sp", "a", "b", "c", "d", "e", "f", "h", "l"]
+            .iter()
+            .map(|name| {
+                let value = registers
+                    .iter()
+                    .find(|(register, _)| register == name)
+                    .map_or(0, |(_, value)| *value);
+                format!("\"{}\": {}", name, value)
+            })
+            .collect();
+        format!("{{{}, \"ime\": 0, \"ram\": [{}]}}", fields.join(", "), ram)
+    }
+
+    fn case(initial: String, expected: String, cycles: &str) -> String {
+        format!(
+            "[{{\"name\": \"test\", \"initial\": {}, \"final\": {}, \"cycles\": [{}]}}]",
+            initial, expected, cycles
+        )
+    }
+
+    #[test_case("null", Json::Null; "null")]
+    #[test_case(" [1, -2.5e1, true]", Json::Array(vec![Json::Number(1.0), Json::Number(-25.0), Json::Bool(true)]); "array")]
+    #[test_case("{\"a\\u0062\\n\": {}}", Json::Object(vec![(String::from("ab\n"), Json::Object(Vec::new()))]); "object")]
+    fn test_parse_json(text: &str, expected: Json) {
+        assert_eq!(Json::parse(text), Ok(expected));
+    }
+
+    #[test_case("[1, 2"; "unclosed array")]
+    #[test_case("{\"a\" 1}"; "no colon")]
+    #[test_case("1 2"; "trailing value")]
+    #[test_case("\"abc"; "unclosed string")]
+    fn test_parse_json_invalid(text: &str) {
+        assert!(Json::parse(text).is_err());
+    }
+
+    #[test]
+    fn test_parse_cases() {
+        let text = case(
+            state(&[("pc", 0xC000)], "[49152, 0]"),
+            state(&[("pc", 0xC001)], "[49152, 0]"),
+            "[49152, 0, \"r-m\"], [null, null, \"---\"]",
+        );
+        let cases = parse_cases(&text).unwrap();
+        assert_eq!(cases[0].initial.registers.pc, 0xC000);
+        assert_eq!(cases[0].expected.ram, vec![(0xC000, 0x00)]);
+        assert_eq!(cases[0].accesses, vec![Access::Read(0xC000, 0x00)]);
+    }
+
+    #[test]
+    fn test_run() {
+        // LD (BC), A with BC at $C100 and A $5A
+        let registers = [("a", 0x5A), ("b", 0xC1), ("c", 0x00)];
+        let text = case(
+            state(&[&[("pc", 0xC000)], &registers[..]].concat(), "[49152, 2]"),
+            state(
+                &[&[("pc", 0xC001)], &registers[..]].concat(),
+                "[49152, 2], [49408, 90]",
+            ),
+            "[49152, 2, \"r-m\"], [49408, 90, \"-wm\"]",
+        );
+        assert_eq!(parse_cases(&text).unwrap()[0].run(), Ok(()));
+    }
+
+    #[test]
+    fn test_run_differs() {
+        // NOP, expecting A to change
+        let text = case(
+            state(&[("pc", 0xC000)], "[49152, 0]"),
+            state(&[("pc", 0xC001), ("a", 1)], "[49152, 0]"),
+            "[49152, 0, \"r-m\"]",
+        );
+        let result = parse_cases(&text).unwrap()[0].run();
+        assert!(
+            matches!(result, Err(Failure::Differs(difference)) if difference.starts_with("Expected A:01"))
+        );
+    }
+
+    #[test]
+    fn test_run_accesses() {
+        // NOP, expecting a write too
+        let text = case(
+            state(&[("pc", 0xC000)], "[49152, 0]"),
+            state(&[("pc", 0xC001)], "[49152, 0]"),
+            "[49152, 0, \"r-m\"], [49152, 0, \"-wm\"]",
+        );
+        let result = parse_cases(&text).unwrap()[0].run();
+        assert!(
+            matches!(result, Err(Failure::Differs(difference)) if difference.starts_with("Expected the accesses"))
+        );
+    }
+
+    #[test]
+    fn test_run_unsupported() {
+        // LD A, d8, which the CPU doesn't run yet
+        let text = case(
+            state(&[("pc", 0xC000)], "[49152, 62], [49153, 1]"),
+            state(&[("pc", 0xC002), ("a", 1)], "[49152, 62], [49153, 1]"),
+            "[49152, 62, \"r-m\"], [49153, 1, \"r-m\"]",
+        );
+        let result = parse_cases(&text).unwrap()[0].run();
+        assert_eq!(
+            result,
+            Err(Failure::Unsupported(CpuError::Unimplemented {
+                pc: 0xC000,
+                opcode: 0x3E
+            }))
+        );
+    }
+}
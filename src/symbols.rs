@@ -0,0 +1,151 @@
+// Symbol files, as written by RGBDS's linker: a line for each label, with
+// its bank and address in hex and then its name. Everything after a
+// semicolon is a comment.
+//
+//   ; File generated by rgblink
+//   00:0150 Main
+//   01:4000 Intro.loop
+//
+// Addresses outside ROM use the bank of the RAM they're in, so WRAM at
+// 0xC000 and HRAM are bank 0 and WRAM at 0xD000 is bank 1 or above.
+
+use alloc::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+use crate::mmu::trace::parse_address;
+use crate::prelude::*;
+
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    /// The first name given to each bank and address
+    names: BTreeMap<(usize, u16), String>,
+    addresses: BTreeMap<String, (usize, u16)>,
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line
+                .split_once(char::is_whitespace)
+                .and_then(|(location, name)| {
+                    let (bank, address) = location.split_once(':')?;
+                    let bank = usize::from_str_radix(bank, 16).ok()?;
+                    let address = u16::from_str_radix(address, 16).ok()?;
+                    Some((bank, address, name.trim()))
+                });
+            let (bank, address, name) = parsed.ok_or_else(|| {
+                format!(
+                    "Line {}: expected a bank, address and name like 00:0150 Main, got {:?}",
+                    number + 1,
+                    line
+                )
+            })?;
+            symbols
+                .names
+                .entry((bank, address))
+                .or_insert_with(|| name.to_string());
+            symbols.addresses.insert(name.to_string(), (bank, address));
+        }
+        Ok(symbols)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Symbols::parse(&text)
+    }
+
+    /// Where RGBDS puts the symbols for a ROM: next to it, ending in .sym
+    #[cfg(feature = "std")]
+    pub fn path_for(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension("sym")
+    }
+
+    /// The name of the label at an address in a bank
+    pub fn name(&self, bank: usize, address: u16) -> Option<&str> {
+        self.names.get(&(bank, address)).map(String::as_str)
+    }
+
+    /// The name of the label at an address the CPU sees, with `bank` mapped
+    /// at 0x4000. For RAM, the bank is guessed as the first one.
+    pub fn name_at(&self, address: u16, bank: usize) -> Option<&str> {
+        match address {
+            0x0000..=0x3FFF => self.name(0, address),
+            0x4000..=0x7FFF => self.name(bank, address),
+            _ => self.name(0, address).or_else(|| self.name(1, address)),
+        }
+    }
+
+    /// The bank and address of a label
+    pub fn lookup(&self, name: &str) -> Option<(usize, u16)> {
+        self.addresses.get(name).copied()
+    }
+
+    /// An address given as a label or a number
+    pub fn resolve(&self, text: &str) -> Result<u16, String> {
+        match self.lookup(text.trim()) {
+            Some((_, address)) => Ok(address),
+            None => parse_address(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: &str = "\
+; File generated by rgblink
+00:0150 Main
+01:4000 Intro ; the title screen
+01:4010 Intro.loop
+02:4000 Credits
+00:C000 wScore
+00:0150 Start
+";
+
+    #[test]
+    fn test_parse() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.name(0, 0x0150), Some("Main"));
+        assert_eq!(symbols.name(1, 0x4000), Some("Intro"));
+        assert_eq!(symbols.lookup("Intro.loop"), Some((1, 0x4010)));
+        assert_eq!(symbols.lookup("Start"), Some((0, 0x0150)));
+        assert_eq!(symbols.lookup("Outro"), None);
+    }
+
+    #[test]
+    fn test_name_at() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.name_at(0x0150, 2), Some("Main"));
+        assert_eq!(symbols.name_at(0x4000, 2), Some("Credits"));
+        assert_eq!(symbols.name_at(0x4000, 3), None);
+        assert_eq!(symbols.name_at(0xC000, 1), Some("wScore"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.resolve("Credits"), Ok(0x4000));
+        assert_eq!(symbols.resolve("0x0150"), Ok(0x0150));
+        assert!(symbols.resolve("Nowhere").is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(
+            Symbols::parse("00:0150 Main\n0150 Start").err(),
+            Some(String::from(
+                "Line 2: expected a bank, address and name like 00:0150 Main, got \"0150 Start\""
+            ))
+        );
+    }
+}
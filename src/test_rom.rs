@@ -0,0 +1,389 @@
+// Tells when a test ROM has finished and whether it passed, so CI can go by
+// the exit code. Blargg's tests print "Passed" or "Failed" over the serial
+// port. Mooneye's run LD B, B when they're done, with B, C, D, E, H and L
+// holding 3, 5, 8, 13, 21 and 34 if they passed, or 0x42 in each if not.
+//
+// run() plays one headless to the end, for the `test-roms` subcommand and
+// the integration tests.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::any::Any;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+use crate::cpu_core::cpu::Registers;
+#[cfg(feature = "std")]
+use crate::gameboy::GameBoy;
+use crate::prelude::*;
+
+/// LD B, B, which does nothing, so Mooneye's tests use it as a signal
+const LD_B_B: u8 = 0x40;
+/// How much of the serial output is kept to look for Blargg's result
+pub const SERIAL_TAIL: usize = 16;
+
+/// Blargg's tests that `test-roms` runs, where they are in gb-test-roms,
+/// with how many frames each gets to finish
+pub const BLARGG_ROMS: [(&str, u64); 3] = [
+    ("cpu_instrs/cpu_instrs.gb", 4000),
+    ("instr_timing/instr_timing.gb", 600),
+    ("mem_timing/mem_timing.gb", 600),
+];
+/// How many frames each of Mooneye's tests gets, which finish in well under one
+pub const MOONEYE_FRAMES: u64 = 300;
+
+/// A set of test ROMs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Suite {
+    /// Blargg's gb-test-roms
+    Blargg,
+    /// The acceptance tests of Mooneye's mooneye-test-suite
+    Mooneye,
+}
+
+/// Parse a suite as given on the command line
+pub fn parse_suite(value: &str) -> Result<Suite, String> {
+    match value.trim() {
+        "blargg" => Ok(Suite::Blargg),
+        "mooneye" => Ok(Suite::Mooneye),
+        other => Err(format!(
+            "Unknown test suite {:?}, expected blargg or mooneye",
+            other
+        )),
+    }
+}
+
+#[cfg(feature = "std")]
+impl Suite {
+    /// The suite's ROMs under `dir`, named by their path from it, with how
+    /// many frames each gets
+    pub fn roms(self, dir: &Path) -> io::Result<Vec<(String, PathBuf, u64)>> {
+        match self {
+            Suite::Blargg => Ok(BLARGG_ROMS
+                .iter()
+                .map(|&(name, frames)| (name.to_string(), dir.join(name), frames))
+                .collect()),
+            Suite::Mooneye => {
+                let mut paths = Vec::new();
+                find_roms(&dir.join("acceptance"), &mut paths)?;
+                paths.sort();
+                Ok(paths
+                    .into_iter()
+                    .filter(|path| runs_on_dmg(path))
+                    .map(|path| {
+                        let name = path.strip_prefix(dir).unwrap_or(&path);
+                        (name.display().to_string(), path.clone(), MOONEYE_FRAMES)
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Add the .gb files under `dir` to `paths`
+#[cfg(feature = "std")]
+fn find_roms(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, paths)?;
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a Mooneye test is for the DMG. The models one runs on are after
+/// the last dash, as groups like `-GS` (G for the DMG and MGB, S the SGBs,
+/// C the CGB and A the AGB) or models like `-dmgABCmgb`. Without them it
+/// runs on every model.
+#[cfg(feature = "std")]
+fn runs_on_dmg(path: &Path) -> bool {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match stem.rsplit_once('-') {
+        Some((_, models)) if models.chars().all(|c| "GSCA".contains(c)) => models.contains('G'),
+        Some((_, models)) if models.starts_with("dmg") => models.contains("ABC"),
+        Some((_, models)) if models.starts_with(|c: char| c.is_ascii_lowercase()) => {
+            !["mgb", "sgb", "sgb2", "cgb", "agb", "ags"]
+                .iter()
+                .any(|model| models.starts_with(model))
+        }
+        _ => true,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    Passed,
+    Failed,
+}
+
+impl Verdict {
+    /// What the process exits with
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Verdict::Passed => 0,
+            Verdict::Failed => 1,
+        }
+    }
+}
+
+/// How a run of a test ROM ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Finished(Verdict),
+    /// It hadn't said whether it passed when the time was up
+    TimedOut,
+    /// The emulator panicked, with the panic's message
+    Crashed(String),
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        *self == Outcome::Finished(Verdict::Passed)
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Finished(verdict) => write!(f, "{:?}", verdict),
+            Outcome::TimedOut => write!(f, "Timed out"),
+            Outcome::Crashed(message) => write!(f, "Crashed: {}", message),
+        }
+    }
+}
+
+/// How many of a suite's ROMs ended each way
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+    pub timed_out: usize,
+    pub crashed: usize,
+    /// ROMs that couldn't be read
+    pub missing: usize,
+}
+
+impl Summary {
+    pub fn add(&mut self, outcome: &Outcome) {
+        match outcome {
+            Outcome::Finished(Verdict::Passed) => self.passed += 1,
+            Outcome::Finished(Verdict::Failed) => self.failed += 1,
+            Outcome::TimedOut => self.timed_out += 1,
+            Outcome::Crashed(_) => self.crashed += 1,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} passed, {} failed, {} timed out, {} crashed",
+            self.passed, self.failed, self.timed_out, self.crashed
+        )?;
+        if self.missing > 0 {
+            write!(f, ", {} missing", self.missing)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => panic.downcast_ref::<&str>().map_or_else(
+            || String::from("unknown panic"),
+            |message| message.to_string(),
+        ),
+    }
+}
+
+/// Run a test ROM headless for up to `frames` frames, stopping once it says
+/// whether it passed. Returns how it ended and the frames it ran.
+#[cfg(feature = "std")]
+pub fn run(rom: Vec<u8>, frames: u64) -> (Outcome, u64) {
+    let mut frame = 0;
+    let mut gameboy = None;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let gameboy = gameboy.insert(GameBoy::new(rom));
+        gameboy.skip_boot();
+        gameboy.watch_test_result();
+        while frame < frames && gameboy.test_verdict().is_none() {
+            gameboy.step_frame();
+            frame += 1;
+        }
+    }));
+    // The rest of the frame can run into something the emulator can't do
+    // after the ROM has said whether it passed
+    let outcome = match (gameboy.and_then(|gameboy| gameboy.test_verdict()), result) {
+        (Some(verdict), _) => Outcome::Finished(verdict),
+        (None, Ok(())) => Outcome::TimedOut,
+        (None, Err(panic)) => Outcome::Crashed(panic_message(panic)),
+    };
+    (outcome, frame)
+}
+
+/// Blargg's result, from the end of what was sent over serial
+pub fn from_serial(sent: &[u8]) -> Option<Verdict> {
+    let find = |word: &[u8]| sent.windows(word.len()).rposition(|window| window == word);
+    match (find(b"Passed"), find(b"Failed")) {
+        (Some(passed), Some(failed)) if failed > passed => Some(Verdict::Failed),
+        (Some(_), _) => Some(Verdict::Passed),
+        (None, Some(_)) => Some(Verdict::Failed),
+        (None, None) => None,
+    }
+}
+
+/// Mooneye's result, if the instruction about to run is its LD B, B
+pub fn from_registers(registers: &Registers, opcode: impl FnOnce() -> u8) -> Option<Verdict> {
+    let values = [
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+    ];
+    // Look at the registers first, as they're cheaper than reading the opcode
+    let verdict = match values {
+        [3, 5, 8, 13, 21, 34] => Verdict::Passed,
+        [0x42, 0x42, 0x42, 0x42, 0x42, 0x42] => Verdict::Failed,
+        _ => return None,
+    };
+    Some(verdict).filter(|_| opcode() == LD_B_B)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(b"01-special\n\n\nPassed\n", Some(Verdict::Passed); "passed")]
+    #[test_case(b"\nFailed #2\n", Some(Verdict::Failed); "failed")]
+    #[test_case(b"Passed\nFailed", Some(Verdict::Failed); "last one counts")]
+    #[test_case(b"Pass", None; "not yet")]
+    fn test_from_serial(sent: &[u8], expected: Option<Verdict>) {
+        assert_eq!(from_serial(sent), expected);
+    }
+
+    #[test_case([3, 5, 8, 13, 21, 34], LD_B_B, Some(Verdict::Passed); "passed")]
+    #[test_case([0x42; 6], LD_B_B, Some(Verdict::Failed); "failed")]
+    #[test_case([3, 5, 8, 13, 21, 34], 0x00, None; "not ld b b")]
+    #[test_case([3, 5, 8, 13, 21, 0], LD_B_B, None; "other registers")]
+    fn test_from_registers(values: [u8; 6], opcode: u8, expected: Option<Verdict>) {
+        let [b, c, d, e, h, l] = values;
+        let registers = Registers {
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            ..Default::default()
+        };
+        assert_eq!(from_registers(&registers, || opcode), expected);
+    }
+
+    #[test_case("blargg", Ok(Suite::Blargg); "blargg")]
+    #[test_case(" mooneye", Ok(Suite::Mooneye); "mooneye")]
+    #[test_case("gambatte", Err(String::from("Unknown test suite \"gambatte\", expected blargg or mooneye")); "unknown")]
+    fn test_parse_suite(value: &str, expected: Result<Suite, String>) {
+        assert_eq!(parse_suite(value), expected);
+    }
+
+    #[cfg(feature = "std")]
+    #[test_case("acceptance/ei_timing.gb", true; "every model")]
+    #[test_case("acceptance/boot_regs-dmgABC.gb", true; "dmg abc")]
+    #[test_case("acceptance/boot_hwio-dmgABCmgb.gb", true; "dmg abc and mgb")]
+    #[test_case("acceptance/boot_regs-dmg0.gb", false; "dmg 0")]
+    #[test_case("acceptance/boot_div-S.gb", false; "sgb")]
+    #[test_case("acceptance/di_timing-GS.gb", true; "dmg and sgb")]
+    #[test_case("acceptance/boot_regs-mgb.gb", false; "mgb")]
+    #[test_case("acceptance/boot_regs-sgb2.gb", false; "sgb2")]
+    #[test_case("acceptance/timer/tim00_div_trigger.gb", true; "underscores")]
+    #[test_case("acceptance/ppu/hblank_ly_scx_timing-GS.gb", true; "dash in the name")]
+    fn test_runs_on_dmg(path: &str, expected: bool) {
+        assert_eq!(runs_on_dmg(Path::new(path)), expected);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_mooneye_roms() {
+        let dir = std::env::temp_dir().join("rusty-gameboy-test-mooneye-roms");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("acceptance/timer")).unwrap();
+        for name in [
+            "acceptance/timer/div_write.gb",
+            "acceptance/boot_div-S.gb",
+            "acceptance/add_sp_e_timing.gb",
+            "acceptance/README.md",
+        ] {
+            fs::write(dir.join(name), []).unwrap();
+        }
+        let names: Vec<String> = Suite::Mooneye
+            .roms(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "acceptance/add_sp_e_timing.gb",
+                "acceptance/timer/div_write.gb"
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut summary = Summary::default();
+        summary.add(&Outcome::Finished(Verdict::Passed));
+        summary.add(&Outcome::Finished(Verdict::Passed));
+        summary.add(&Outcome::TimedOut);
+        summary.add(&Outcome::Crashed(String::from("oops")));
+        assert_eq!(
+            summary.to_string(),
+            "2 passed, 0 failed, 1 timed out, 1 crashed"
+        );
+        summary.missing = 1;
+        assert!(summary.to_string().ends_with("1 crashed, 1 missing"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_run() {
+        // LD BC, DE and HL with 3, 5, 8, 13, 21, 34, then LD B, B
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x10A].copy_from_slice(&[0x01, 5, 3, 0x11, 13, 8, 0x21, 34, 21, 0x40]);
+        assert_eq!(run(rom, 10), (Outcome::Finished(Verdict::Passed), 0));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_run_timed_out() {
+        assert_eq!(run(vec![0; 0x8000], 1), (Outcome::TimedOut, 1));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_run_crashed() {
+        // An opcode the CPU doesn't have
+        let mut rom = vec![0; 0x8000];
+        rom[0x100] = 0xD3;
+        let (outcome, frames) = run(rom, 10);
+        assert!(matches!(outcome, Outcome::Crashed(_)), "{:?}", outcome);
+        assert_eq!(frames, 0);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::interrupts::Interrupt;
+use crate::savestate::save_state_fields;
+
+/// Divider: the upper 8 bits of a counter that runs at the T-cycle rate.
+/// Writing any value resets the whole counter.
+pub const DIV: u16 = 0xFF04;
+/// Timer counter, which requests an interrupt when it overflows
+pub const TIMA: u16 = 0xFF05;
+/// Timer modulo, loaded into TIMA when it overflows
+pub const TMA: u16 = 0xFF06;
+/// Timer control: bit 2 enables TIMA, bits 0-1 pick its rate
+pub const TAC: u16 = 0xFF07;
+
+const TAC_ENABLE: u8 = 1 << 2;
+/// Only the lower 3 bits of TAC are used, the rest read as 1
+const TAC_UNUSED: u8 = 0b1111_1000;
+
+/// The bit of the internal counter whose falling edge increments TIMA,
+/// for each TAC rate: 4096 Hz, 262144 Hz, 65536 Hz and 16384 Hz
+const TAC_COUNTER_BITS: [u16; 4] = [9, 3, 5, 7];
+
+/// After TIMA overflows it reads 0 for one M-cycle before TMA is loaded
+const RELOAD_DELAY: u8 = 4;
+
+/// The divider and the programmable timer
+#[derive(Debug, Default)]
+pub struct Timer {
+    /// Counts T-cycles; DIV is the upper byte
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// T-cycles until TMA is loaded after an overflow. Writing TIMA
+    /// in this time cancels the reload and the interrupt.
+    reload_delay: u8,
+    /// T-cycles left of the M-cycle TMA is loaded in. Writes to TIMA are
+    /// ignored, and writes to TMA go straight through to TIMA.
+    reloading: u8,
+}
+
+impl Timer {
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => TAC_UNUSED | self.tac,
+            _ => unreachable!("{:#06x} is not a timer register", address),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            DIV => {
+                // Resetting the counter can look like a falling edge
+                let was_set = self.timer_bit();
+                self.counter = 0;
+                if was_set {
+                    self.increment_tima();
+                }
+            }
+            TIMA => {
+                if self.reloading == 0 {
+                    self.tima = value;
+                    self.reload_delay = 0;
+                }
+            }
+            TMA => {
+                self.tma = value;
+                if self.reloading > 0 {
+                    self.tima = value;
+                }
+            }
+            TAC => {
+                // So can disabling the timer or switching to a bit that's clear
+                let was_set = self.timer_bit();
+                self.tac = value & !TAC_UNUSED;
+                if was_set && !self.timer_bit() {
+                    self.increment_tima();
+                }
+            }
+            _ => unreachable!("{:#06x} is not a timer register", address),
+        }
+    }
+
+    /// The counter bit TIMA follows, or false while the timer is disabled
+    fn timer_bit(&self) -> bool {
+        let bit = TAC_COUNTER_BITS[(self.tac & 0b11) as usize];
+        self.tac & TAC_ENABLE != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflowed) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflowed {
+            self.reload_delay = RELOAD_DELAY;
+        }
+    }
+
+    /// Start the internal counter at `counter`, for the divider's phase at power on
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    /// Advance by a number of T-cycles.
+    /// Returns the interrupts requested in that time, as IF bits.
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        let mut interrupts = 0;
+        for _ in 0..cycles {
+            self.reloading = self.reloading.saturating_sub(1);
+            if self.reload_delay > 0 {
+                self.reload_delay -= 1;
+                if self.reload_delay == 0 {
+                    self.tima = self.tma;
+                    self.reloading = RELOAD_DELAY;
+                    interrupts |= Interrupt::Timer.mask();
+                }
+            }
+
+            let was_set = self.timer_bit();
+            self.counter = self.counter.wrapping_add(1);
+            if was_set && !self.timer_bit() {
+                self.increment_tima();
+            }
+        }
+        interrupts
+    }
+}
+
+save_state_fields!(Timer {
+    counter,
+    tima,
+    tma,
+    tac,
+    reload_delay,
+    reloading,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn test_div() {
+        let mut timer = Timer::default();
+        timer.tick(255);
+        assert_eq!(timer.read(DIV), 0);
+        timer.tick(1);
+        assert_eq!(timer.read(DIV), 1);
+        // Writing any value resets it
+        timer.write(DIV, 0x42);
+        assert_eq!(timer.read(DIV), 0);
+    }
+
+    #[test_case(0b100, 1024; "4096 Hz")]
+    #[test_case(0b101, 16; "262144 Hz")]
+    #[test_case(0b110, 64; "65536 Hz")]
+    #[test_case(0b111, 256; "16384 Hz")]
+    fn test_tima_rate(tac: u8, period: u32) {
+        let mut timer = Timer::default();
+        timer.write(TAC, tac);
+        timer.tick(period - 1);
+        assert_eq!(timer.read(TIMA), 0);
+        timer.tick(1);
+        assert_eq!(timer.read(TIMA), 1);
+        timer.tick(period * 9);
+        assert_eq!(timer.read(TIMA), 10);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut timer = Timer::default();
+        timer.write(TAC, 0b001);
+        timer.tick(1024);
+        assert_eq!(timer.read(TIMA), 0);
+        assert_eq!(timer.read(TAC), 0xF9);
+    }
+
+    /// A timer at 262144 Hz that has just overflowed
+    fn overflowed() -> Timer {
+        let mut timer = Timer::default();
+        timer.write(TAC, 0b101);
+        timer.write(TMA, 0xA0);
+        timer.write(TIMA, 0xFF);
+        assert_eq!(timer.tick(16), 0);
+        timer
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut timer = overflowed();
+        // 0 for one M-cycle, then TMA and the interrupt
+        assert_eq!(timer.read(TIMA), 0x00);
+        assert_eq!(timer.tick(3), 0);
+        assert_eq!(timer.tick(1), Interrupt::Timer.mask());
+        assert_eq!(timer.read(TIMA), 0xA0);
+    }
+
+    #[test]
+    fn test_tima_write_cancels_reload() {
+        let mut timer = overflowed();
+        timer.write(TIMA, 0x42);
+        assert_eq!(timer.tick(4), 0);
+        assert_eq!(timer.read(TIMA), 0x42);
+    }
+
+    #[test]
+    fn test_writes_while_reloading() {
+        let mut timer = overflowed();
+        timer.tick(4);
+        // TIMA ignores writes for the M-cycle it's reloaded in
+        timer.write(TIMA, 0x42);
+        assert_eq!(timer.read(TIMA), 0xA0);
+        // And a new TMA is loaded too
+        timer.write(TMA, 0xB0);
+        assert_eq!(timer.read(TIMA), 0xB0);
+
+        timer.tick(4);
+        timer.write(TIMA, 0x42);
+        assert_eq!(timer.read(TIMA), 0x42);
+    }
+
+    #[test]
+    fn test_div_write_falling_edge() {
+        let mut timer = Timer::default();
+        timer.write(TAC, 0b101);
+        // Bit 3 is set, so resetting the counter increments TIMA
+        timer.tick(8);
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 1);
+        // Bit 3 is clear, so it doesn't
+        timer.tick(4);
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 1);
+    }
+
+    #[test_case(0b001; "disabling")]
+    #[test_case(0b110; "switching to a clear bit")]
+    fn test_tac_write_falling_edge(tac: u8) {
+        let mut timer = Timer::default();
+        timer.write(TAC, 0b101);
+        timer.tick(8);
+        timer.write(TAC, tac);
+        assert_eq!(timer.read(TIMA), 1);
+    }
+}
@@ -0,0 +1,92 @@
+// A JavaScript API, built with the `wasm` feature and wasm-pack:
+//
+//   wasm-pack build --target web --no-default-features --features wasm
+//
+// `new Emulator(rom, seed)` starts a cartridge, and each stepFrame() runs a
+// frame, after which framebuffer() has the screen as RGBA bytes for an
+// ImageData. The browser has no clock the core can read, so the
+// cartridge's clock follows emulated time from the seed.
+
+use wasm_bindgen::prelude::*;
+
+use crate::gameboy::GameBoy;
+use crate::joypad::BUTTONS;
+
+#[wasm_bindgen]
+pub struct Emulator {
+    gameboy: GameBoy,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// Start `rom` where the boot ROM would leave off
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8], seed: u64) -> Emulator {
+        let mut gameboy = GameBoy::new(rom.to_vec());
+        gameboy.seed(seed);
+        gameboy.skip_boot();
+        Emulator { gameboy }
+    }
+
+    /// Run until the next frame is drawn
+    #[wasm_bindgen(js_name = stepFrame)]
+    pub fn step_frame(&mut self) {
+        self.gameboy.step_frame();
+    }
+
+    /// The screen, 160x144 pixels row by row, 4 bytes each: red, green,
+    /// blue and an opaque alpha
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.gameboy
+            .framebuffer()
+            .iter()
+            .flat_map(|&rgb| {
+                let [_, red, green, blue] = rgb.to_be_bytes();
+                [red, green, blue, 0xFF]
+            })
+            .collect()
+    }
+
+    /// Set which buttons are held, a bit each from bit 0: right, left, up,
+    /// down, A, B, select and start
+    #[wasm_bindgen(js_name = setInput)]
+    pub fn set_input(&mut self, buttons: u8) {
+        for (bit, &button) in BUTTONS.iter().enumerate() {
+            self.gameboy.set_button(button, buttons & (1 << bit) != 0);
+        }
+    }
+
+    /// The sound made since the last call, as left and right samples in turn
+    #[wasm_bindgen(js_name = audioSamples)]
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.gameboy
+            .audio_samples()
+            .flat_map(|sample| [sample.left, sample.right])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn test_framebuffer() {
+        let mut emulator = Emulator::new(&[0; 0x8000], 0);
+        emulator.step_frame();
+        let pixels = emulator.framebuffer();
+        assert_eq!(pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+        let [_, red, green, blue] = emulator.gameboy.framebuffer()[0].to_be_bytes();
+        assert_eq!(pixels[..4], [red, green, blue, 0xFF]);
+    }
+
+    #[test]
+    fn test_set_input() {
+        let mut emulator = Emulator::new(&[0; 0x8000], 0);
+        // Start, with the buttons selected in P1
+        emulator.set_input(0x80);
+        emulator.gameboy.poke(0xFF00, 0x10);
+        assert_eq!(emulator.gameboy.peek(0xFF00) & 0x0F, 0x07);
+    }
+}
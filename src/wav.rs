@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::apu::buffer::StereoSample;
+
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const BYTES_PER_FRAME: u32 = (CHANNELS * BITS_PER_SAMPLE / 8) as u32;
+/// RIFF header, "fmt " chunk and "data" chunk header
+const HEADER_SIZE: u32 = 44;
+
+/// Writes 16-bit stereo PCM to a WAV file. The sizes in the header are
+/// updated after every write, so the file is valid even if the emulator
+/// is killed rather than closed.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    /// Bytes of sample data written so far
+    data_size: u32,
+}
+
+/// -1.0 to 1.0 as a signed 16-bit sample
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(writer: W, sample_rate: u32) -> io::Result<Self> {
+        let mut wav = WavWriter {
+            writer,
+            sample_rate,
+            data_size: 0,
+        };
+        wav.write_header()?;
+        Ok(wav)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let w = &mut self.writer;
+        w.seek(SeekFrom::Start(0))?;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+
+        w.write_all(b"fmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?; // PCM
+        w.write_all(&CHANNELS.to_le_bytes())?;
+        w.write_all(&self.sample_rate.to_le_bytes())?;
+        w.write_all(&(self.sample_rate * BYTES_PER_FRAME).to_le_bytes())?;
+        w.write_all(&(BYTES_PER_FRAME as u16).to_le_bytes())?;
+        w.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+        w.write_all(b"data")?;
+        w.write_all(&self.data_size.to_le_bytes())?;
+        w.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    /// Append samples and update the header to match
+    pub fn write_samples<'a>(
+        &mut self,
+        samples: impl Iterator<Item = &'a StereoSample>,
+    ) -> io::Result<()> {
+        for sample in samples {
+            self.writer.write_all(&to_i16(sample.left).to_le_bytes())?;
+            self.writer.write_all(&to_i16(sample.right).to_le_bytes())?;
+            self.data_size += BYTES_PER_FRAME;
+        }
+        self.write_header()?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use test_case::test_case;
+
+    #[test_case(0.0, 0; "silence")]
+    #[test_case(1.0, 32767; "max")]
+    #[test_case(-1.0, -32767; "min")]
+    #[test_case(2.0, 32767; "clipped")]
+    fn test_to_i16(sample: f32, expected: i16) {
+        assert_eq!(to_i16(sample), expected);
+    }
+
+    #[test]
+    fn test_write_samples() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 48_000).unwrap();
+        let samples = [
+            StereoSample {
+                left: 1.0,
+                right: 0.0,
+            },
+            StereoSample {
+                left: 0.0,
+                right: -1.0,
+            },
+        ];
+        wav.write_samples(samples.iter()).unwrap();
+        let bytes = wav.writer.into_inner();
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[4..8], &(36u32 + 8).to_le_bytes());
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(&bytes[22..24], &2u16.to_le_bytes());
+        assert_eq!(&bytes[24..28], &48_000u32.to_le_bytes());
+        assert_eq!(&bytes[28..32], &192_000u32.to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(&bytes[40..44], &8u32.to_le_bytes());
+        assert_eq!(&bytes[44..], &[0xFF, 0x7F, 0, 0, 0, 0, 0x01, 0x80]);
+    }
+}
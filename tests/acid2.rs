@@ -0,0 +1,43 @@
+// Runs dmg-acid2 (https://github.com/mattcurrie/dmg-acid2) and compares the
+// screen with its reference image, pixel by pixel. $DMG_ACID2 points at a
+// directory with dmg-acid2.gb and reference-dmg.png from its release. They
+// aren't in the repository, so the test only runs when asked for:
+//
+//   DMG_ACID2=path/to/dmg-acid2 cargo test --test acid2 -- --ignored
+#![cfg(feature = "screenshot")]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use rusty_gameboy::screenshot::{differing_pixels, read_png, save_png};
+use rusty_gameboy::GameBoy;
+
+/// The frame to compare, well after the test has drawn everything
+const STABLE_FRAME: u64 = 120;
+
+#[test]
+#[ignore = "needs DMG_ACID2"]
+fn test_dmg_acid2() {
+    let dir = PathBuf::from(
+        env::var_os("DMG_ACID2").expect("DMG_ACID2 should point at a directory with dmg-acid2.gb"),
+    );
+    let reference = read_png(&dir.join("reference-dmg.png")).unwrap();
+    let mut gameboy = GameBoy::new(fs::read(dir.join("dmg-acid2.gb")).unwrap());
+    gameboy.skip_boot();
+    for _ in 0..STABLE_FRAME {
+        gameboy.step_frame();
+    }
+
+    let differing = differing_pixels(gameboy.framebuffer(), &reference);
+    if !differing.is_empty() {
+        let path = env::temp_dir().join("dmg-acid2.png");
+        save_png(&path, gameboy.framebuffer()).unwrap();
+        panic!(
+            "{} pixels differ from the reference, the first at {:?}. The screen is in {:?}",
+            differing.len(),
+            differing[0],
+            path
+        );
+    }
+}
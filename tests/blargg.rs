@@ -0,0 +1,31 @@
+// Runs Blargg's test ROMs from a checkout of gb-test-roms
+// (https://github.com/retrio/gb-test-roms) at $GB_TEST_ROMS. They aren't in
+// the repository, so the test only runs when asked for:
+//
+//   GB_TEST_ROMS=path/to/gb-test-roms cargo test --test blargg -- --ignored
+#![cfg(feature = "std")]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use rusty_gameboy::test_rom::{self, Outcome, BLARGG_ROMS};
+
+#[test]
+#[ignore = "needs GB_TEST_ROMS"]
+fn test_blargg() {
+    let dir = PathBuf::from(
+        env::var_os("GB_TEST_ROMS")
+            .expect("GB_TEST_ROMS should point at a checkout of gb-test-roms"),
+    );
+    let failed: Vec<(&str, Outcome)> = BLARGG_ROMS
+        .iter()
+        .map(|&(name, frames)| {
+            let rom = fs::read(dir.join(name))
+                .unwrap_or_else(|e| panic!("Could not read {}: {}", name, e));
+            (name, test_rom::run(rom, frames).0)
+        })
+        .filter(|(_, outcome)| !outcome.passed())
+        .collect();
+    assert!(failed.is_empty(), "{:?}", failed);
+}
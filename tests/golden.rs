@@ -0,0 +1,125 @@
+// Golden traces: a few small ROMs run for a few hundred instructions from
+// power on, with every register and flag 0, and each instruction's line,
+// the registers before it as in Gameboy Doctor's logs followed by the
+// T-cycles it took and LY and DIV after it, has to match the line recorded
+// in tests/golden by a known-good build. That catches changes to flags and
+// timing the unit tests don't look at.
+//
+// After a change that's meant to alter the traces, record them again with
+//
+//   UPDATE_GOLDEN=1 cargo test --test golden
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use rusty_gameboy::GameBoy;
+
+/// Instructions traced for each ROM
+const INSTRUCTIONS: usize = 300;
+
+const LY: u16 = 0xFF44;
+const DIV: u16 = 0xFF04;
+
+/// A ROM running `body` from 0x0000 in a loop
+fn looping(body: &[u8]) -> Vec<u8> {
+    assert!(
+        body.len() <= 126,
+        "JR can't reach back over {} bytes",
+        body.len()
+    );
+    let mut rom = vec![0; 0x8000];
+    rom[..body.len()].copy_from_slice(body);
+    // JR back to 0x0000
+    let back = -(body.len() as i8 + 2);
+    rom[body.len()..body.len() + 2].copy_from_slice(&[0x18, back as u8]);
+    rom
+}
+
+fn trace(rom: Vec<u8>) -> Vec<String> {
+    let mut gameboy = GameBoy::new(rom);
+    (0..INSTRUCTIONS)
+        .map(|_| {
+            let line = gameboy.trace_line();
+            let cycles = gameboy.step_instruction();
+            format!(
+                "{} CYC:{} LY:{:02X} DIV:{:02X}",
+                line,
+                cycles,
+                gameboy.peek(LY),
+                gameboy.peek(DIV)
+            )
+        })
+        .collect()
+}
+
+/// Compare the trace of `rom` with the one recorded as `name`, or record it
+/// with $UPDATE_GOLDEN set
+fn check(name: &str, rom: Vec<u8>) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.log", name));
+    let lines = trace(rom);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        return;
+    }
+    let golden = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "Could not read {}: {}, record it with UPDATE_GOLDEN=1",
+            path.display(),
+            e
+        )
+    });
+    let golden: Vec<&str> = golden.lines().collect();
+    assert_eq!(golden.len(), lines.len(), "{} has the wrong length", name);
+    if let Some((number, (expected, got))) = golden
+        .iter()
+        .zip(&lines)
+        .enumerate()
+        .find(|(_, (expected, got))| *expected != got)
+    {
+        panic!(
+            "{} differs at instruction {}\nexpected: {}\n     got: {}",
+            name,
+            number + 1,
+            expected,
+            got
+        );
+    }
+}
+
+#[test]
+fn test_loads() {
+    // LD SP, $DFF1; LD A, ($0000) through DE, which is the $31 of LD SP;
+    // LD HL, $C0C1; LD BC, $C1C2; LD DE, $C2C3; LD ($C300), SP, then stores
+    // and loads through them, moving HL up and down
+    let mut body = vec![
+        0x31, 0xF1, 0xDF, 0x1A, 0x21, 0xC1, 0xC0, 0x01, 0xC2, 0xC1, 0x11, 0xC3, 0xC2, 0x08, 0x00,
+        0xC3,
+    ];
+    body.extend([0x22, 0x02, 0x1A, 0x32, 0x2A, 0x12, 0x0A, 0x3A].repeat(8));
+    check("loads", looping(&body));
+}
+
+#[test]
+fn test_alu() {
+    // LD BC, $0801; LD DE, $F800; LD SP, $8FFF, then ADD HL with BC, DE,
+    // HL and SP, carrying out of bits 11 and 15 and not
+    let mut body = vec![0x01, 0x01, 0x08, 0x11, 0x00, 0xF8, 0x31, 0xFF, 0x8F];
+    body.extend([0x09, 0x19, 0x29, 0x39].repeat(14));
+    check("alu", looping(&body));
+}
+
+#[test]
+fn test_branches() {
+    // LD BC, $8800; LD DE, $0801, then ADD HL with them to flip carry and
+    // half carry, and JR C, JR NC, JR NZ and JR Z over the next instruction
+    check(
+        "branches",
+        looping(&[
+            0x01, 0x00, 0x88, 0x11, 0x01, 0x08, 0x09, 0x38, 0x01, 0x19, 0x30, 0x01, 0x29, 0x20,
+            0x01, 0x00, 0x28, 0x01, 0x00,
+        ]),
+    );
+}
@@ -0,0 +1,44 @@
+// Runs the DMG's acceptance tests from a build of mooneye-test-suite
+// (https://github.com/Gekkio/mooneye-test-suite) at $MOONEYE_TEST_ROMS. They
+// aren't in the repository, so the test only runs when asked for:
+//
+//   MOONEYE_TEST_ROMS=path/to/mts cargo test --test mooneye -- --ignored
+//
+// The CPU doesn't run enough of the instruction set to pass any of them yet,
+// so for now this fails, listing how each ROM ended, to track progress.
+#![cfg(feature = "std")]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use rusty_gameboy::test_rom::{self, Outcome, Suite};
+
+#[test]
+#[ignore = "needs MOONEYE_TEST_ROMS, and the CPU doesn't pass any of them yet"]
+fn test_mooneye() {
+    let dir = PathBuf::from(
+        env::var_os("MOONEYE_TEST_ROMS")
+            .expect("MOONEYE_TEST_ROMS should point at a build of mooneye-test-suite"),
+    );
+    let roms = Suite::Mooneye.roms(&dir).unwrap();
+    assert!(!roms.is_empty(), "No ROMs in {:?}", dir.join("acceptance"));
+    let total = roms.len();
+    let failed: Vec<(String, Outcome)> = roms
+        .into_iter()
+        .map(|(name, path, frames)| {
+            let rom = fs::read(&path).unwrap_or_else(|e| panic!("Could not read {}: {}", name, e));
+            (name, test_rom::run(rom, frames).0)
+        })
+        .filter(|(_, outcome)| !outcome.passed())
+        .collect();
+    for (name, outcome) in &failed {
+        eprintln!("{}: {}", name, outcome);
+    }
+    assert!(
+        failed.is_empty(),
+        "{} of {} ROMs failed",
+        failed.len(),
+        total
+    );
+}
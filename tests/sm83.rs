@@ -0,0 +1,62 @@
+// Runs the SM83 single instruction tests from a checkout of
+// https://github.com/SingleStepTests/sm83 at $SM83_TESTS, pointing at its
+// v1 directory of a JSON file per opcode. They aren't in the repository, so
+// the test only runs when asked for:
+//
+//   SM83_TESTS=path/to/sm83/v1 cargo test --test sm83 -- --ignored
+//
+// Opcodes the CPU doesn't run yet are listed as skipped rather than failed.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use rusty_gameboy::single_step::{parse_cases, Failure};
+
+#[test]
+#[ignore = "needs SM83_TESTS"]
+fn test_sm83() {
+    let dir = PathBuf::from(
+        env::var_os("SM83_TESTS")
+            .expect("SM83_TESTS should point at the v1 directory of the tests"),
+    );
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No tests in {:?}", dir);
+
+    // The first case that didn't pass for each opcode
+    let (skipped, failed): (Vec<_>, Vec<_>) = paths
+        .iter()
+        .filter_map(|path| {
+            let text = fs::read_to_string(path).unwrap();
+            let cases = parse_cases(&text).unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+            cases
+                .iter()
+                .find_map(|case| case.run().err().map(|failure| (&case.name, failure)))
+                .map(|(name, failure)| (name.clone(), failure))
+        })
+        .partition(|(_, failure)| matches!(failure, Failure::Unsupported(_)));
+
+    for (name, failure) in skipped.iter().chain(&failed) {
+        eprintln!("{}: {}", name, failure);
+    }
+    eprintln!(
+        "{} of {} opcodes passed, {} skipped",
+        paths.len() - skipped.len() - failed.len(),
+        paths.len(),
+        skipped.len()
+    );
+    assert!(
+        failed.is_empty(),
+        "{} of {} opcodes failed",
+        failed.len(),
+        paths.len()
+    );
+}